@@ -0,0 +1,421 @@
+use clap::{Parser, ValueEnum};
+use client_resilience_rust::RoutingConfig;
+use observability_rust::{LogFormat, LOG_FORMAT_ENV, OTLP_ENDPOINT_ENV};
+use serde::{Deserialize, Serialize};
+use service_config_rust::{ConfigSource, EnvAlias, Validate};
+use std::collections::BTreeMap;
+use std::error::Error;
+use std::path::PathBuf;
+use std::time::Duration;
+use transport_rust::{AuthConfig, TlsConfig};
+
+const BROKER_ADDRESS_ENV: &str = "BROKER_ADDRESS";
+const TOPOLOGY_PROXY_ENV: &str = "TOPOLOGY_PROXY_ADDRESS";
+const DEFAULT_BROKER_ADDRESS: &str = "127.0.0.1:50051";
+const DEFAULT_TOPOLOGY_PROXY_ADDRESS: &str = "http://127.0.0.1:50055";
+const SERVICE_NAME: &str = "calculator.v1.CalculatorService";
+const DEFAULT_ROLE: &str = "default";
+const DEFAULT_RETRY_ATTEMPTS: u32 = 3;
+const DEFAULT_BREAKER_FAILURES: u32 = 3;
+const DEFAULT_BREAKER_OPEN_SECS: u64 = 10;
+const METRICS_HOST: &str = "127.0.0.1";
+
+/// Environment variables read before `RUNTIME_*`.
+const ENV_ALIASES: &[EnvAlias] = &[
+  ("broker_address", BROKER_ADDRESS_ENV),
+  ("topology_proxy", TOPOLOGY_PROXY_ENV),
+  ("log_format", LOG_FORMAT_ENV),
+  ("otlp_endpoint", OTLP_ENDPOINT_ENV),
+];
+
+#[derive(Parser)]
+#[command(name = "calculator-client-rust")]
+#[command(about = "A Rust calculator client that connects to a broker")]
+struct Args {
+  /// TOML or YAML config file (default: $RUNTIME_CONFIG)
+  #[arg(long)]
+  config: Option<PathBuf>,
+
+  /// Print the effective configuration and exit
+  #[arg(long)]
+  print_config: bool,
+
+  /// Broker address in the format host:port (default: 127.0.0.1:50051)
+  #[arg(long)]
+  broker_address: Option<String>,
+
+  /// Topology proxy address (HTTP) (default: http://127.0.0.1:50055)
+  #[arg(long)]
+  topology_proxy: Option<String>,
+
+  /// Disable topology reporting
+  #[arg(long)]
+  no_topology: bool,
+
+  /// Role of the calculator instances to call (default: default)
+  #[arg(long)]
+  role: Option<String>,
+
+  /// Maximum attempts per calculation, including the first one (default: 3)
+  #[arg(long)]
+  retry_attempts: Option<u32>,
+
+  /// Send a hedged request to a second instance after this many milliseconds
+  #[arg(long)]
+  hedge_delay_ms: Option<u64>,
+
+  /// Consecutive failures that open the circuit breaker of an instance (default: 3)
+  #[arg(long)]
+  breaker_failures: Option<u32>,
+
+  /// Seconds an open circuit breaker waits before probing the instance again (default: 10)
+  #[arg(long)]
+  breaker_open_secs: Option<u64>,
+
+  /// Serve Prometheus metrics on /metrics at this port
+  #[arg(long)]
+  metrics_port: Option<u16>,
+
+  /// Log format: pretty or json (default: pretty)
+  #[arg(long)]
+  log_format: Option<LogFormat>,
+
+  /// Export spans via OTLP/gRPC to this collector (default: disabled)
+  #[arg(long)]
+  otlp_endpoint: Option<String>,
+
+  /// Reach the broker and the calculator instances over https
+  #[arg(long)]
+  tls: bool,
+
+  /// PEM client certificate chain for mutual TLS
+  #[arg(long)]
+  tls_cert: Option<PathBuf>,
+
+  /// PEM private key of --tls-cert
+  #[arg(long)]
+  tls_key: Option<PathBuf>,
+
+  /// PEM CA bundle that server certificates are verified against (default: system roots)
+  #[arg(long)]
+  tls_ca: Option<PathBuf>,
+
+  /// Name expected in server certificates instead of the host
+  #[arg(long)]
+  tls_server_name: Option<String>,
+
+  /// File holding the bearer token presented to the broker
+  #[arg(long)]
+  broker_token_file: Option<PathBuf>,
+
+  /// Only call instances registered with this version
+  #[arg(long)]
+  filter_version: Option<String>,
+
+  /// Only call instances registered in this language
+  #[arg(long)]
+  filter_language: Option<String>,
+
+  /// Only call instances registered in this zone
+  #[arg(long)]
+  filter_zone: Option<String>,
+
+  /// Only call instances registered with this tag; repeatable
+  #[arg(long = "filter-tag")]
+  filter_tags: Vec<String>,
+
+  /// Share of calculations for a version as version=weight, e.g. 1.4.0=95; repeatable
+  #[arg(long = "split", value_parser = parse_split)]
+  splits: Vec<(String, u32)>,
+
+  /// What calculations are routed sticky by: none, operation or calculation (default: none)
+  #[arg(long, value_enum)]
+  route_key: Option<RouteKey>,
+
+  /// Error rate (0.0-1.0) above which a canary version gets no more traffic (default: 0.2)
+  #[arg(long)]
+  rollback_error_rate: Option<f64>,
+
+  /// Seconds before a rolled-back version gets its share back (default: 300)
+  #[arg(long)]
+  rollback_cooldown_secs: Option<u64>,
+}
+
+fn parse_split(value: &str) -> Result<(String, u32), String> {
+  let (version, weight) = value
+    .split_once('=')
+    .ok_or_else(|| format!("expected version=weight, got {}", value))?;
+  let weight = weight
+    .parse()
+    .map_err(|_| format!("weight of {} must be a non-negative integer", version))?;
+  Ok((version.to_string(), weight))
+}
+
+/// Settings of the client, layered from defaults, the config file, the
+/// environment and the command line.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct ClientConfig {
+  pub broker_address: String,
+  pub topology_proxy: String,
+  pub topology_enabled: bool,
+  /// gRPC service looked up in the broker.
+  pub interface_name: String,
+  /// Role of the instances to call; instances registered without a role
+  /// match any.
+  pub role: String,
+  /// Milliseconds between calculations, which also paces the topology
+  /// heartbeat and broker lookups.
+  pub interval_ms: u64,
+  /// Maximum attempts per calculation, including the first one.
+  pub retry_attempts: u32,
+  /// Delay in milliseconds before a hedged request; no hedging when `None`.
+  pub hedge_delay_ms: Option<u64>,
+  /// Consecutive failures that open the circuit breaker of an instance.
+  pub breaker_failures: u32,
+  /// Seconds an open circuit breaker waits before probing again.
+  pub breaker_open_secs: u64,
+  pub metrics_host: String,
+  pub metrics_port: Option<u16>,
+  pub log_format: Option<LogFormat>,
+  pub otlp_endpoint: Option<String>,
+  /// TLS of the connections to the broker, the calculator instances and the
+  /// topology proxy.
+  pub tls: TlsConfig,
+  /// Credentials presented to the broker.
+  pub auth: AuthConfig,
+  /// Metadata the instances to call must be registered with.
+  pub filter: LookupFilter,
+  /// How calculations are spread over the instances found.
+  pub routing: RoutingSettings,
+}
+
+/// Metadata lookups require; unset fields match any instance.
+#[derive(Debug, Default, Serialize, Deserialize)]
+pub struct LookupFilter {
+  pub version: Option<String>,
+  pub language: Option<String>,
+  pub zone: Option<String>,
+  /// Tags an instance needs all of.
+  pub tags: Vec<String>,
+}
+
+/// Traffic split between calculator versions, e.g. for a canary release.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct RoutingSettings {
+  /// Share of calculations per version, spread over its instances by their
+  /// registered weight. Unlisted versions get none; when empty, every
+  /// instance gets its registered weight.
+  pub split: BTreeMap<String, u32>,
+  /// Key each calculation is routed sticky by; calculations with the same key
+  /// go to the same instance while the instances stay the same.
+  pub key: RouteKey,
+  /// Error rate (0.0-1.0) of a canary version, measured over this client's
+  /// calls, above which it is rolled back to no traffic; never when `None`.
+  pub rollback_error_rate: Option<f64>,
+  /// Most recent calls per version the error rate is computed over.
+  pub rollback_window: usize,
+  /// Calls a version needs in the window before it can be rolled back.
+  pub rollback_min_calls: usize,
+  /// Seconds after which a rolled-back version gets its share back; never
+  /// when `None`, short of a restart.
+  pub rollback_cooldown_secs: Option<u64>,
+}
+
+/// What a calculation's sticky routing key is derived from.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize, ValueEnum)]
+#[serde(rename_all = "lowercase")]
+pub enum RouteKey {
+  /// No key; calculations are spread exactly by weight.
+  #[default]
+  None,
+  /// The operation, so all calculations of one operation share an instance.
+  Operation,
+  /// Operation and operands, so repeated calculations share an instance
+  /// while distinct ones spread by weight.
+  Calculation,
+}
+
+impl Default for RoutingSettings {
+  fn default() -> Self {
+    let defaults = RoutingConfig::default();
+    Self {
+      split: defaults.version_weights,
+      key: RouteKey::None,
+      rollback_error_rate: defaults.rollback_error_rate,
+      rollback_window: defaults.window_size,
+      rollback_min_calls: defaults.minimum_calls,
+      rollback_cooldown_secs: defaults.rollback_cooldown.map(|cooldown| cooldown.as_secs()),
+    }
+  }
+}
+
+impl RoutingSettings {
+  pub fn router_config(&self) -> RoutingConfig {
+    RoutingConfig {
+      version_weights: self.split.clone(),
+      window_size: self.rollback_window,
+      minimum_calls: self.rollback_min_calls,
+      rollback_error_rate: self.rollback_error_rate,
+      rollback_cooldown: self.rollback_cooldown_secs.map(Duration::from_secs),
+    }
+  }
+}
+
+impl Validate for RoutingSettings {
+  fn validate(&self, errors: &mut Vec<String>) {
+    if !self.split.is_empty() && self.split.values().all(|weight| *weight == 0) {
+      errors.push("routing.split must give at least one version a weight".to_string());
+    }
+    if self
+      .rollback_error_rate
+      .is_some_and(|rate| !(0.0..1.0).contains(&rate))
+    {
+      errors.push("routing.rollback_error_rate must be at least 0.0 and below 1.0".to_string());
+    }
+    if self.rollback_window == 0 {
+      errors.push("routing.rollback_window must be at least 1".to_string());
+    }
+    if self.rollback_min_calls > self.rollback_window {
+      errors.push("routing.rollback_min_calls must not exceed routing.rollback_window".to_string());
+    }
+  }
+}
+
+impl Default for ClientConfig {
+  fn default() -> Self {
+    Self {
+      broker_address: DEFAULT_BROKER_ADDRESS.to_string(),
+      topology_proxy: DEFAULT_TOPOLOGY_PROXY_ADDRESS.to_string(),
+      topology_enabled: true,
+      interface_name: SERVICE_NAME.to_string(),
+      role: DEFAULT_ROLE.to_string(),
+      interval_ms: 2000,
+      retry_attempts: DEFAULT_RETRY_ATTEMPTS,
+      hedge_delay_ms: None,
+      breaker_failures: DEFAULT_BREAKER_FAILURES,
+      breaker_open_secs: DEFAULT_BREAKER_OPEN_SECS,
+      metrics_host: METRICS_HOST.to_string(),
+      metrics_port: None,
+      log_format: None,
+      otlp_endpoint: None,
+      tls: TlsConfig::default(),
+      auth: AuthConfig::default(),
+      filter: LookupFilter::default(),
+      routing: RoutingSettings::default(),
+    }
+  }
+}
+
+impl ClientConfig {
+  /// Parses the command line over the config file and the environment.
+  pub fn load() -> Result<Self, Box<dyn Error>> {
+    let args = Args::parse();
+    let source = ConfigSource::new(args.config.clone(), args.print_config);
+    let mut config = source.load(&Self::default(), ENV_ALIASES)?;
+    config.apply(args);
+    Ok(source.finish(config)?)
+  }
+
+  fn apply(&mut self, args: Args) {
+    if let Some(broker_address) = args.broker_address {
+      self.broker_address = broker_address;
+    }
+    if let Some(topology_proxy) = args.topology_proxy {
+      self.topology_proxy = topology_proxy;
+    }
+    if args.no_topology {
+      self.topology_enabled = false;
+    }
+    if let Some(role) = args.role {
+      self.role = role;
+    }
+    if let Some(retry_attempts) = args.retry_attempts {
+      self.retry_attempts = retry_attempts;
+    }
+    if args.hedge_delay_ms.is_some() {
+      self.hedge_delay_ms = args.hedge_delay_ms;
+    }
+    if let Some(breaker_failures) = args.breaker_failures {
+      self.breaker_failures = breaker_failures;
+    }
+    if let Some(breaker_open_secs) = args.breaker_open_secs {
+      self.breaker_open_secs = breaker_open_secs;
+    }
+    if args.metrics_port.is_some() {
+      self.metrics_port = args.metrics_port;
+    }
+    if args.log_format.is_some() {
+      self.log_format = args.log_format;
+    }
+    if args.otlp_endpoint.is_some() {
+      self.otlp_endpoint = args.otlp_endpoint;
+    }
+    if args.tls {
+      self.tls.enabled = true;
+    }
+    if args.tls_cert.is_some() {
+      self.tls.cert_file = args.tls_cert;
+    }
+    if args.tls_key.is_some() {
+      self.tls.key_file = args.tls_key;
+    }
+    if args.tls_ca.is_some() {
+      self.tls.ca_file = args.tls_ca;
+    }
+    if args.tls_server_name.is_some() {
+      self.tls.server_name = args.tls_server_name;
+    }
+    if args.broker_token_file.is_some() {
+      self.auth.token_file = args.broker_token_file;
+    }
+    if args.filter_version.is_some() {
+      self.filter.version = args.filter_version;
+    }
+    if args.filter_language.is_some() {
+      self.filter.language = args.filter_language;
+    }
+    if args.filter_zone.is_some() {
+      self.filter.zone = args.filter_zone;
+    }
+    if !args.filter_tags.is_empty() {
+      self.filter.tags = args.filter_tags;
+    }
+    if !args.splits.is_empty() {
+      self.routing.split = args.splits.into_iter().collect();
+    }
+    if let Some(route_key) = args.route_key {
+      self.routing.key = route_key;
+    }
+    if args.rollback_error_rate.is_some() {
+      self.routing.rollback_error_rate = args.rollback_error_rate;
+    }
+    if args.rollback_cooldown_secs.is_some() {
+      self.routing.rollback_cooldown_secs = args.rollback_cooldown_secs;
+    }
+  }
+
+  /// Prefix of the topology target of every instance, `<interface>::<role>`.
+  pub fn service_key_prefix(&self) -> String {
+    format!("{}::{}", self.interface_name, self.role)
+  }
+}
+
+impl Validate for ClientConfig {
+  fn validate(&self, errors: &mut Vec<String>) {
+    if self.interface_name.is_empty() {
+      errors.push("interface_name must not be empty".to_string());
+    }
+    if self.interval_ms == 0 {
+      errors.push("interval_ms must be at least 1".to_string());
+    }
+    if self.retry_attempts == 0 {
+      errors.push("retry_attempts must be at least 1".to_string());
+    }
+    if self.breaker_failures == 0 {
+      errors.push("breaker_failures must be at least 1".to_string());
+    }
+    self.tls.validate(errors);
+    self.auth.validate(errors);
+    self.routing.validate(errors);
+  }
+}
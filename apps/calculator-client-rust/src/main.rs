@@ -1,27 +1,46 @@
+mod config;
 mod proto;
 
-use clap::Parser;
+use client_resilience_rust::{
+  is_endpoint_failure, AttemptRecord, AttemptTarget, BreakerState, CircuitBreakerConfig,
+  CircuitBreakerRegistry, RetryConfig, RetryLayer, RetryPolicy, RetryService, Route,
+  RoutingEvent, TrafficRouter, DEFAULT_WEIGHT,
+};
+use observability_rust::{
+  init_logging, spawn_metrics_server, trace_id, GrpcMetricsLayer, GrpcMetricsService,
+  GrpcTraceLayer, GrpcTraceService,
+};
+use config::{ClientConfig, RouteKey};
 use proto::broker::v1::{
-  broker_service_client::BrokerServiceClient, GetAvailableServicesRequest, LookupServiceRequest,
+  broker_service_client::BrokerServiceClient, GetAvailableServicesRequest, InstanceMetadata,
+  LookupServiceRequest, MetadataFilter,
 };
 use proto::calculator::v1::{
   calculator_service_client::CalculatorServiceClient, CalculateRequest, Operation,
 };
 use rand::Rng;
+use std::collections::hash_map::{Entry, HashMap};
 use std::{error::Error, time::Duration};
+use tokio::sync::{mpsc, watch};
 use tokio::time::Instant;
+use tonic::service::interceptor::InterceptedService;
 use tonic::transport::Channel;
+use tower::Layer;
+use tracing::{error, info, info_span, warn, Instrument};
 use topology_reporter_rust::{
-  ActivityReport, ActivityType, ServiceLanguage, ServiceType, TopologyProxyClient,
+  ActivityReport, ActivityType, ConnectionState, ServiceLanguage, ServiceType, TopologyProxyClient,
   TopologyProxyConfig,
 };
+use transport_rust::{connect, socket_path, AuthInterceptor};
 
-const BROKER_ADDRESS_ENV: &str = "BROKER_ADDRESS";
-const DEFAULT_BROKER_ADDRESS: &str = "127.0.0.1:50051";
-const DEFAULT_TOPOLOGY_PROXY_ADDRESS: &str = "http://127.0.0.1:50055";
-const SERVICE_NAME: &str = "calculator.v1.CalculatorService";
-const DEFAULT_ROLE: &str = "default";
-const CALCULATOR_SERVICE_KEY_PREFIX: &str = "calculator.v1.CalculatorService::default";
+const CALCULATE_METHOD_PATH: &str = "/calculator.v1.CalculatorService/Calculate";
+/// How often the instances to route to are looked up again, so that new
+/// builds join the split without a restart.
+const ROUTE_REFRESH_INTERVAL: Duration = Duration::from_secs(30);
+
+/// A client service wrapped in the gRPC span and metrics layers.
+type Instrumented<S> = GrpcTraceService<GrpcMetricsService<S>>;
+type BrokerClient = BrokerServiceClient<Instrumented<InterceptedService<Channel, AuthInterceptor>>>;
 
 struct RetryState {
   next_retry_at: Instant,
@@ -53,71 +72,100 @@ impl RetryState {
 }
 
 struct CalculatorConnection {
-  client: CalculatorServiceClient<Channel>,
+  client: CalculatorServiceClient<Instrumented<RetryService<Channel>>>,
   address: String,
   target_service_key: String,
+  hedge_address: Option<String>,
+  hedge_target_service_key: Option<String>,
 }
 
-#[derive(Parser)]
-#[command(name = "calculator-client-rust")]
-#[command(about = "A Rust calculator client that connects to a broker")]
-struct Args {
-    /// Broker address in the format host:port
-    #[arg(long, default_value = DEFAULT_BROKER_ADDRESS)]
-    broker_address: String,
-
-    /// Topology proxy address (HTTP)
-    #[arg(long, default_value = DEFAULT_TOPOLOGY_PROXY_ADDRESS)]
-    topology_proxy: String,
-
-    /// Disable topology reporting
-    #[arg(long)]
-    no_topology: bool,
+impl CalculatorConnection {
+  fn target_for(&self, target: AttemptTarget) -> Option<&str> {
+    match target {
+      AttemptTarget::Primary => Some(&self.target_service_key),
+      AttemptTarget::Hedge => self.hedge_target_service_key.as_deref(),
+    }
+  }
+
+  fn address_for(&self, target: AttemptTarget) -> Option<&str> {
+    match target {
+      AttemptTarget::Primary => Some(&self.address),
+      AttemptTarget::Hedge => self.hedge_address.as_deref(),
+    }
+  }
 }
 
 #[tokio::main]
 async fn main() -> Result<(), Box<dyn Error>> {
-  let args = Args::parse();
+  let config = ClientConfig::load()?;
+  let _tracing = init_logging(
+    "calculator-client-rust",
+    config.log_format,
+    config.otlp_endpoint.as_deref(),
+  )?;
 
-  println!("Starting Rust calculator client...");
+  info!("Starting Rust calculator client...");
 
-  let broker_address = std::env::var(BROKER_ADDRESS_ENV).unwrap_or(args.broker_address);
-  let topology_enabled = !args.no_topology;
+  let topology_enabled = config.topology_enabled;
 
-  let broker_url = normalize_broker_url(&broker_address);
-  let mut calculator: Option<CalculatorServiceClient<Channel>> = None;
-  let mut target_service_key: Option<String> = None;
+  let broker_url = config.tls.url(&config.broker_address);
+  let mut router = TrafficRouter::new(config.routing.router_config());
+  let mut connections: HashMap<String, CalculatorConnection> = HashMap::new();
+  let mut routes_refreshed_at: Option<Instant> = None;
   let mut broker_retry = RetryState::new();
 
-  let topology_proxy =
-    std::env::var("TOPOLOGY_PROXY_ADDRESS").unwrap_or_else(|_| args.topology_proxy.clone());
+  let mut retry_policy = RetryPolicy::idempotent();
+  retry_policy.max_attempts = config.retry_attempts;
+  retry_policy.hedge_delay = config.hedge_delay_ms.map(Duration::from_millis);
+  let (attempt_tx, mut attempt_rx) = mpsc::unbounded_channel();
+  let retry_layer = RetryLayer::with_observer(
+    RetryConfig::default().with_method(CALCULATE_METHOD_PATH, retry_policy),
+    attempt_tx,
+  );
+  let hedging_enabled = config.hedge_delay_ms.is_some();
+  let mut breakers = CircuitBreakerRegistry::new(CircuitBreakerConfig {
+    consecutive_failure_threshold: config.breaker_failures,
+    open_duration: Duration::from_secs(config.breaker_open_secs),
+    ..CircuitBreakerConfig::default()
+  });
+
+  let topology_proxy = config.topology_proxy.clone();
   let mut topology = if topology_enabled {
     let host = hostname::get()
       .ok()
       .and_then(|h| h.into_string().ok());
-    let mut config = TopologyProxyConfig::with_defaults(
+    let mut topology_config = TopologyProxyConfig::with_defaults(
       topology_proxy.clone(),
       "calculator-client-rust".to_string(),
       ServiceType::Client,
       ServiceLanguage::Rust,
     );
-    config.version = Some(env!("CARGO_PKG_VERSION").to_string());
-    config.host = host;
-    config.program_name = Some("calculator-client-rust".to_string());
-    Some(TopologyProxyClient::new(config))
+    topology_config.version = Some(env!("CARGO_PKG_VERSION").to_string());
+    topology_config.host = host;
+    topology_config.program_name = Some("calculator-client-rust".to_string());
+    topology_config.tls = config.tls.clone();
+    Some(TopologyProxyClient::new(topology_config))
   } else {
     None
   };
   if topology_enabled {
-    println!("Topology proxy: {}", topology_proxy);
+    info!("Topology proxy: {}", topology_proxy);
     if let Some(topology) = topology.as_mut() {
       if let Err(error) = topology.ensure_registered().await {
-        eprintln!("Topology registration failed: {}", error);
+        warn!(%error, "Topology registration failed");
       }
     }
   }
 
-  let mut interval = tokio::time::interval(Duration::from_secs(2));
+  let (shutdown_tx, shutdown_rx) = watch::channel(false);
+  let metrics_task = match config.metrics_port {
+    Some(port) => {
+      Some(spawn_metrics_server((config.metrics_host.as_str(), port), shutdown_rx).await?)
+    }
+    None => None,
+  };
+
+  let mut interval = tokio::time::interval(Duration::from_millis(config.interval_ms));
 
   let mut sigterm = tokio::signal::unix::signal(tokio::signal::unix::SignalKind::terminate())?;
   let mut sigint = tokio::signal::unix::signal(tokio::signal::unix::SignalKind::interrupt())?;
@@ -125,19 +173,19 @@ async fn main() -> Result<(), Box<dyn Error>> {
   loop {
     tokio::select! {
       _ = sigterm.recv() => {
-        println!("Received SIGTERM, shutting down.");
+        info!("Received SIGTERM, shutting down.");
         if let Some(topology) = topology.as_mut() {
           if let Err(error) = topology.unregister().await {
-            eprintln!("Topology unregister failed: {}", error);
+            warn!(%error, "Topology unregister failed");
           }
         }
         break;
       }
       _ = sigint.recv() => {
-        println!("Received SIGINT (Ctrl+C), shutting down.");
+        info!("Received SIGINT (Ctrl+C), shutting down.");
         if let Some(topology) = topology.as_mut() {
           if let Err(error) = topology.unregister().await {
-            eprintln!("Topology unregister failed: {}", error);
+            warn!(%error, "Topology unregister failed");
           }
         }
         break;
@@ -145,20 +193,23 @@ async fn main() -> Result<(), Box<dyn Error>> {
       _ = interval.tick() => {
         if let Some(topology) = topology.as_mut() {
           if let Err(error) = topology.ensure_registered().await {
-            eprintln!("Topology registration failed: {}", error);
+            warn!(%error, "Topology registration failed");
           }
         }
 
-        if calculator.is_none() && broker_retry.should_retry() {
-          match connect_calculator(&broker_url).await {
-            Ok(connection) => {
-              println!("Connecting to calculator service at {}", connection.address);
-              calculator = Some(connection.client);
-              target_service_key = Some(connection.target_service_key);
+        let refresh_due = routes_refreshed_at
+          .is_none_or(|refreshed_at| refreshed_at.elapsed() >= ROUTE_REFRESH_INTERVAL);
+        if refresh_due && broker_retry.should_retry() {
+          match resolve_calculator_routes(&config, &broker_url).await {
+            Ok(routes) => {
+              if routes != router.routes() {
+                update_routes(&mut router, &mut connections, routes);
+              }
+              routes_refreshed_at = Some(Instant::now());
               broker_retry.reset();
             }
             Err(error) => {
-              eprintln!("Calculator service not available: {}", error);
+              warn!(%error, "Calculator service not available");
               broker_retry.schedule_retry();
             }
           }
@@ -171,146 +222,426 @@ async fn main() -> Result<(), Box<dyn Error>> {
           operation: op as i32,
         };
 
-        let started_at = Instant::now();
-        let calculator_client = match calculator.as_mut() {
-          Some(client) => client,
-          None => continue,
+        for event in router.readmit_expired() {
+          if let RoutingEvent::Readmitted { version } = event {
+            info!("Calculator version {} is back after its rollback cooldown", version);
+          }
+          log_version_shares(&router);
+        }
+        let key = route_key(config.routing.key, &request);
+        let picked =
+          router.pick(key.as_deref(), |route| breakers.breaker(&route.address).try_acquire());
+        let Some(route) = picked.cloned() else {
+          if !router.is_empty() {
+            warn!("All calculator instances have an open circuit breaker");
+            routes_refreshed_at = None;
+          }
+          continue;
         };
-
-        match calculator_client.calculate(request).await {
-          Ok(response) => {
-            let latency_ms = started_at.elapsed().as_millis() as i32;
-            let result = response.into_inner().result;
-            println!("calculate({:.6} {} {:.6}) => {:.6}", a, operation_symbol(op), b, result);
-
-            if let Some(topology) = topology.as_mut() {
-              let Some(target_service) = target_service_key.as_ref() else {
+        let connection = match connections.entry(route.address.clone()) {
+          Entry::Occupied(entry) => entry.into_mut(),
+          Entry::Vacant(entry) => {
+            let connected = connect_calculator(
+              &config,
+              &route,
+              &router,
+              &retry_layer,
+              &mut breakers,
+              hedging_enabled,
+            )
+            .await;
+            match connected {
+              Ok(connection) => {
+                info!(
+                  "Connecting to calculator service at {} (version {})",
+                  connection.address, route.version
+                );
+                if let Some(hedge_address) = connection.hedge_address.as_ref() {
+                  info!("Hedging calculations to {}", hedge_address);
+                }
+                entry.insert(connection)
+              }
+              Err(error) => {
+                warn!(%error, "Calculator instance {} not reachable", route.address);
+                routes_refreshed_at = None;
                 continue;
-              };
-              let report = ActivityReport {
-                target_service: target_service.to_string(),
-                activity_type: ActivityType::RequestSent,
-                timestamp_ms: None,
-                latency_ms: Some(latency_ms),
-                method: Some("CalculatorService/Calculate".to_string()),
-                success: Some(true),
-                error_message: None,
-              };
-              if let Err(error) = topology.report_activity(report).await {
-                eprintln!("Topology activity report failed: {}", error);
               }
             }
           }
+        };
+
+        // Each calculation is the root of a trace that its retries, hedges
+        // and the server spans handling them join.
+        let span = info_span!("calculation", operation = op.as_str_name());
+        let calculation_trace = trace_id(&span);
+        let result = connection.client.calculate(request).instrument(span).await;
+
+        let mut attempts = Vec::new();
+        while let Ok(record) = attempt_rx.try_recv() {
+          let transition = connection
+            .address_for(record.target)
+            .and_then(|address| record_breaker_outcome(&mut breakers, address, &record));
+          record_routing_outcome(&mut router, connection, &record);
+          attempts.push((record, transition));
+        }
+        for (record, _) in attempts.iter().filter(|(record, _)| record.will_retry) {
+          warn!(
+            attempt = record.attempt,
+            error = record.message.as_deref().unwrap_or("unknown error"),
+            "Calculation attempt failed, retrying"
+          );
+        }
+        if let Some(topology) = topology.as_mut() {
+          report_attempts(topology, connection, &attempts, calculation_trace).await;
+        }
+        let primary_open = breakers.breaker(&connection.address).state() == BreakerState::Open;
+
+        match result {
+          Ok(response) => {
+            let result = response.into_inner().result;
+            info!("calculate({:.6} {} {:.6}) => {:.6}", a, operation_symbol(op), b, result);
+          }
           Err(error) => {
-            let latency_ms = started_at.elapsed().as_millis() as i32;
-            eprintln!("Calculation failed: {}", error.message());
-            calculator = None;
-            target_service_key = None;
+            warn!(error = error.message(), "Calculation failed");
+            connections.remove(&route.address);
+            routes_refreshed_at = None;
             broker_retry.schedule_retry();
-
-            if let Some(topology) = topology.as_mut() {
-              let Some(target_service) = target_service_key.as_ref() else {
-                continue;
-              };
-              let report = ActivityReport {
-                target_service: target_service.to_string(),
-                activity_type: ActivityType::Error,
-                timestamp_ms: None,
-                latency_ms: Some(latency_ms),
-                method: Some("CalculatorService/Calculate".to_string()),
-                success: Some(false),
-                error_message: Some(error.message().to_string()),
-              };
-              if let Err(error) = topology.report_activity(report).await {
-                eprintln!("Topology activity report failed: {}", error);
-              }
-            }
           }
         }
+
+        if primary_open {
+          // The open breaker keeps this instance out of routing until it probes again.
+          connections.remove(&route.address);
+        }
       }
     }
   }
 
+  let _ = shutdown_tx.send(true);
+  if let Some(task) = metrics_task {
+    if let Err(error) = task.await {
+      error!(%error, "Metrics task failed");
+    }
+  }
+
   Ok(())
 }
 
+/// Feeds an attempt into the breaker of the instance at `address` that
+/// served it and returns the edge state to report when the breaker opened or
+/// closed. Outcomes that say nothing about the instance, such as a lost hedge
+/// or a rejected argument, give back a half-open probe slot.
+fn record_breaker_outcome(
+  breakers: &mut CircuitBreakerRegistry,
+  address: &str,
+  record: &AttemptRecord,
+) -> Option<ConnectionState> {
+  let breaker = breakers.breaker(address);
+  let before = breaker.state();
+  let after = if record.is_success() {
+    breaker.record_success()
+  } else if is_endpoint_failure(record.code) {
+    breaker.record_failure()
+  } else {
+    breaker.release_probe();
+    return None;
+  };
+
+  match (before, after) {
+    (BreakerState::Open, _) => None,
+    (_, BreakerState::Open) => {
+      warn!("Circuit breaker opened for {}", address);
+      Some(ConnectionState::Failed)
+    }
+    (BreakerState::HalfOpen, BreakerState::Closed) => {
+      info!("Circuit breaker closed for {}", address);
+      Some(ConnectionState::Active)
+    }
+    _ => None,
+  }
+}
+
+/// Feeds an attempt into the error rate of the version that served it, with
+/// the same failures that count for the circuit breakers.
+fn record_routing_outcome(
+  router: &mut TrafficRouter,
+  connection: &CalculatorConnection,
+  record: &AttemptRecord,
+) {
+  let Some(address) = connection.address_for(record.target) else {
+    return;
+  };
+  if !record.is_success() && !is_endpoint_failure(record.code) {
+    return;
+  }
+  if let Some(RoutingEvent::RolledBack {
+    version,
+    error_rate,
+    baseline,
+  }) = router.record(address, record.is_success())
+  {
+    warn!(
+      "Rolled back calculator version {} at an error rate of {:.0}%, its calculations go to {}",
+      version,
+      error_rate * 100.0,
+      baseline
+    );
+    log_version_shares(router);
+  }
+}
+
+/// Takes the instances of the latest lookup and drops connections to
+/// instances that are gone.
+fn update_routes(
+  router: &mut TrafficRouter,
+  connections: &mut HashMap<String, CalculatorConnection>,
+  routes: Vec<Route>,
+) {
+  let known = |address: &str| routes.iter().any(|route| route.address == address);
+  connections.retain(|address, connection| {
+    known(address) && connection.hedge_address.as_deref().is_none_or(known)
+  });
+  router.update(routes);
+  log_version_shares(router);
+}
+
+fn log_version_shares(router: &TrafficRouter) {
+  for (version, share) in router.version_shares() {
+    let version = if version.is_empty() { "unversioned" } else { version.as_str() };
+    info!("Calculator version {} gets {:.1}% of calculations", version, share * 100.0);
+  }
+}
+
+/// Sends one topology activity report per attempt made by the retry layer,
+/// linked to the trace of the calculation.
+async fn report_attempts(
+  topology: &mut TopologyProxyClient,
+  connection: &CalculatorConnection,
+  attempts: &[(AttemptRecord, Option<ConnectionState>)],
+  trace_id: Option<String>,
+) {
+  for (record, connection_state) in attempts {
+    let Some(target_service) = connection.target_for(record.target) else {
+      continue;
+    };
+    let report = ActivityReport {
+      source_service: None,
+      target_service: target_service.to_string(),
+      activity_type: if record.is_success() {
+        ActivityType::RequestSent
+      } else {
+        ActivityType::Error
+      },
+      timestamp_ms: None,
+      latency_ms: Some(record.latency.as_millis() as i32),
+      method: Some("CalculatorService/Calculate".to_string()),
+      success: Some(record.is_success()),
+      batch_size: None,
+      error_message: record
+        .message
+        .as_ref()
+        .map(|message| format!("attempt {}: {}", record.attempt, message)),
+      connection_state: *connection_state,
+      trace_id: trace_id.clone(),
+      stall_ms: None,
+    };
+    if let Err(error) = topology.report_activity(report).await {
+      warn!(%error, "Topology activity report failed");
+    }
+  }
+}
+
+/// Connects to the instance of `route`, hedging to another instance of the
+/// same version so that hedges do not shift traffic between versions.
 async fn connect_calculator(
-  broker_url: &str,
+  config: &ClientConfig,
+  route: &Route,
+  router: &TrafficRouter,
+  retry_layer: &RetryLayer,
+  breakers: &mut CircuitBreakerRegistry,
+  hedging_enabled: bool,
 ) -> Result<CalculatorConnection, Box<dyn Error>> {
-  let mut broker = BrokerServiceClient::connect(broker_url.to_string()).await?;
-  let calculator_url = resolve_calculator_url(&mut broker).await?;
-  let client = CalculatorServiceClient::connect(calculator_url.clone()).await?;
-  let normalized = calculator_url
-    .trim_start_matches("http://")
-    .trim_start_matches("https://");
-  let target_service_key = format!("{CALCULATOR_SERVICE_KEY_PREFIX}@{normalized}");
+  let calculator_url = route.address.clone();
+  let channel = match connect(&calculator_url, &config.tls).await {
+    Ok(channel) => channel,
+    Err(error) => {
+      breakers.breaker(&calculator_url).record_failure();
+      return Err(error.into());
+    }
+  };
+
+  let mut hedge = None;
+  if hedging_enabled {
+    let hedge_candidates: Vec<String> = router
+      .routes()
+      .iter()
+      .filter(|other| other.version == route.version && other.address != calculator_url)
+      .map(|other| other.address.clone())
+      .collect();
+    for hedge_url in breakers.available(&hedge_candidates) {
+      match connect(hedge_url, &config.tls).await {
+        Ok(hedge_channel) => {
+          hedge = Some((hedge_url.clone(), hedge_channel));
+          break;
+        }
+        Err(error) => warn!(%error, "Hedge instance {} not reachable", hedge_url),
+      }
+    }
+  }
+
+  let (service, hedge_address) = match hedge {
+    Some((hedge_url, hedge_channel)) => (
+      retry_layer.layer_with_hedge(channel, hedge_channel),
+      Some(hedge_url),
+    ),
+    None => (retry_layer.layer(channel), None),
+  };
+
+  let key_prefix = config.service_key_prefix();
   Ok(CalculatorConnection {
-    client,
+    client: CalculatorServiceClient::new(instrument(service)),
+    target_service_key: target_service_key(&key_prefix, &calculator_url),
+    hedge_target_service_key: hedge_address
+      .as_deref()
+      .map(|hedge_url| target_service_key(&key_prefix, hedge_url)),
     address: calculator_url,
-    target_service_key,
+    hedge_address,
   })
 }
 
-fn normalize_broker_url(address: &str) -> String {
-  if address.starts_with("http://") || address.starts_with("https://") {
-    address.to_string()
-  } else {
-    format!("http://{}", address)
-  }
+fn instrument<S>(service: S) -> Instrumented<S> {
+  GrpcTraceLayer::client().layer(GrpcMetricsLayer::client().layer(service))
 }
 
-async fn resolve_calculator_url(
-  broker: &mut BrokerServiceClient<Channel>,
-) -> Result<String, Box<dyn Error>> {
-  if let Some((url, port)) = lookup_service_via_list(broker).await? {
-    return Ok(format!("http://{}:{}", url, port));
+fn target_service_key(key_prefix: &str, calculator_url: &str) -> String {
+  let normalized = calculator_url
+    .trim_start_matches("http://")
+    .trim_start_matches("https://");
+  format!("{key_prefix}@{normalized}")
+}
+
+/// Resolves all calculator instances with the version and weight they
+/// registered.
+async fn resolve_calculator_routes(
+  config: &ClientConfig,
+  broker_url: &str,
+) -> Result<Vec<Route>, Box<dyn Error>> {
+  let broker_channel = connect(broker_url, &config.tls).await?;
+  let broker_channel = InterceptedService::new(broker_channel, config.auth.interceptor()?);
+  let mut broker = BrokerServiceClient::new(instrument(broker_channel));
+  let instances = lookup_services_via_list(&mut broker, config).await?;
+  if !instances.is_empty() {
+    return Ok(
+      instances
+        .into_iter()
+        .map(|(url, port, metadata)| calculator_route(config, &url, port, metadata))
+        .collect(),
+    );
   }
 
   let response = broker
     .lookup_service(LookupServiceRequest {
-      interface_name: SERVICE_NAME.to_string(),
-      role: DEFAULT_ROLE.to_string(),
+      interface_name: config.interface_name.clone(),
+      role: config.role.clone(),
+      filter: Some(metadata_filter(config)),
     })
     .await?
     .into_inner();
 
-  if response.error.is_empty() && response.port > 0 && !response.url.is_empty() {
-    return Ok(format!("http://{}:{}", response.url, response.port));
+  let reachable = response.port > 0 || socket_path(&response.url).is_some();
+  if response.error.is_empty() && reachable && !response.url.is_empty() {
+    return Ok(vec![calculator_route(
+      config,
+      &response.url,
+      response.port,
+      response.metadata,
+    )]);
   }
 
   Err(format!("Calculator service not found: {}", response.error).into())
 }
 
-async fn lookup_service_via_list(
-  broker: &mut BrokerServiceClient<Channel>,
-) -> Result<Option<(String, i32)>, Box<dyn Error>> {
+/// Route to the instance at `url` and `port`; instances registered without
+/// metadata are unversioned and get the default weight.
+fn calculator_route(
+  config: &ClientConfig,
+  url: &str,
+  port: i32,
+  metadata: Option<InstanceMetadata>,
+) -> Route {
+  let address = config.tls.service_url(url, port);
+  let metadata = metadata.unwrap_or_default();
+  Route {
+    instance_id: if metadata.instance_id.is_empty() {
+      address.clone()
+    } else {
+      metadata.instance_id
+    },
+    version: metadata.version,
+    weight: metadata.weight.unwrap_or(DEFAULT_WEIGHT),
+    address,
+  }
+}
+
+async fn lookup_services_via_list(
+  broker: &mut BrokerClient,
+  config: &ClientConfig,
+) -> Result<Vec<(String, i32, Option<InstanceMetadata>)>, Box<dyn Error>> {
   let response = broker
-    .get_available_services(GetAvailableServicesRequest {})
+    .get_available_services(GetAvailableServicesRequest {
+      filter: Some(metadata_filter(config)),
+    })
     .await?
     .into_inner();
 
+  let mut instances = Vec::new();
   for service in response.services {
     let info = match service.info {
       Some(info) => info,
       None => continue,
     };
 
-    if info.interface_name != SERVICE_NAME {
+    if info.interface_name != config.interface_name {
       continue;
     }
 
-    if !role_matches(&info.role) {
+    if !role_matches(&info.role, &config.role) {
       continue;
     }
 
-    return Ok(Some((service.url, service.port)));
+    instances.push((service.url, service.port, service.metadata));
   }
 
-  Ok(None)
+  Ok(instances)
+}
+
+/// Broker filter for the instances `config` asks for.
+fn metadata_filter(config: &ClientConfig) -> MetadataFilter {
+  let filter = &config.filter;
+  MetadataFilter {
+    version: filter.version.clone().unwrap_or_default(),
+    language: filter.language.clone().unwrap_or_default(),
+    zone: filter.zone.clone().unwrap_or_default(),
+    tags: filter.tags.clone(),
+  }
 }
 
-fn role_matches(role: &str) -> bool {
-  role.is_empty() || role == DEFAULT_ROLE
+fn role_matches(role: &str, wanted: &str) -> bool {
+  role.is_empty() || role == wanted
+}
+
+/// Sticky routing key of `request`; `None` routes it by weight alone.
+fn route_key(key: RouteKey, request: &CalculateRequest) -> Option<String> {
+  match key {
+    RouteKey::None => None,
+    RouteKey::Operation => Some(request.operation.to_string()),
+    RouteKey::Calculation => Some(format!(
+      "{}:{:x}:{:x}",
+      request.operation,
+      request.operand1.to_bits(),
+      request.operand2.to_bits()
+    )),
+  }
 }
 
 fn random_calculation() -> (f64, f64, Operation) {
@@ -335,3 +666,74 @@ fn operation_symbol(operation: Operation) -> &'static str {
     Operation::Unspecified => "?",
   }
 }
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use tonic::Code;
+
+  const ADDRESS: &str = "http://127.0.0.1:1";
+
+  /// Breakers that open on the first failure and turn half-open right away.
+  fn half_open_breakers() -> CircuitBreakerRegistry {
+    let mut breakers = CircuitBreakerRegistry::new(CircuitBreakerConfig {
+      consecutive_failure_threshold: 1,
+      open_duration: Duration::ZERO,
+      ..CircuitBreakerConfig::default()
+    });
+    breakers.breaker(ADDRESS).record_failure();
+    assert_eq!(breakers.breaker(ADDRESS).state(), BreakerState::HalfOpen);
+    breakers
+  }
+
+  fn attempt(code: Code) -> AttemptRecord {
+    AttemptRecord {
+      method: "/calculator.v1.CalculatorService/Calculate".to_string(),
+      attempt: 1,
+      target: AttemptTarget::Primary,
+      latency: Duration::from_millis(1),
+      code,
+      message: None,
+      will_retry: false,
+    }
+  }
+
+  #[test]
+  fn neutral_outcomes_leave_the_breaker_probing() {
+    let mut breakers = half_open_breakers();
+    for code in [Code::Cancelled, Code::InvalidArgument, Code::NotFound] {
+      assert!(breakers.breaker(ADDRESS).try_acquire(), "probe after {:?}", code);
+      assert_eq!(record_breaker_outcome(&mut breakers, ADDRESS, &attempt(code)), None);
+    }
+
+    assert!(breakers.breaker(ADDRESS).try_acquire());
+    let closed = record_breaker_outcome(&mut breakers, ADDRESS, &attempt(Code::Ok));
+    assert_eq!(closed, Some(ConnectionState::Active));
+  }
+
+  #[tokio::test]
+  async fn failed_connects_leave_the_breaker_probing() {
+    let mut breakers = half_open_breakers();
+    let route = Route {
+      address: ADDRESS.to_string(),
+      instance_id: "calculator-1".to_string(),
+      version: "1.0.0".to_string(),
+      weight: DEFAULT_WEIGHT,
+    };
+    let router = TrafficRouter::new(Default::default());
+    let retry_layer = RetryLayer::new(RetryConfig::default());
+
+    assert!(breakers.breaker(ADDRESS).try_acquire());
+    let connected = connect_calculator(
+      &ClientConfig::default(),
+      &route,
+      &router,
+      &retry_layer,
+      &mut breakers,
+      false,
+    )
+    .await;
+    assert!(connected.is_err());
+    assert!(breakers.breaker(ADDRESS).try_acquire());
+  }
+}
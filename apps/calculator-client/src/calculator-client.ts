@@ -23,6 +23,10 @@ const buildServiceKey = (address?: string): string => {
   return `${CALCULATOR_SERVICE_INTERFACE}::${CALCULATOR_SERVICE_ROLE}`
 }
 
+// Unix domain sockets are registered as a full unix:// URL with port 0
+const endpointAddress = (url: string, port: number): string =>
+  url.startsWith('unix:') ? url : `${url}:${port}`
+
 const parseArgs = () => {
   const args = process.argv.slice(2)
   let brokerAddress = '127.0.0.1:50051'
@@ -151,7 +155,7 @@ async function prepareClient() {
   const s = await brokerManager?.getService(CalculatorServiceClient, 'default')
   console.log('getService:', s)
   if (s != null) {
-    const address = `${s.url}:${s.port}`
+    const address = endpointAddress(s.url, s.port)
     calculatorClient = new CalculatorServiceClient(address, credentials.createInsecure())
     targetServiceKey = buildServiceKey(address)
   }
@@ -163,6 +167,18 @@ function brokerManagerInstance() {
 
   brokerManager.onChanges = (changes) => {
     console.log('notifyServiceChanges:', changes)
+    // Drop the client when the instance it calls goes away; the lookup then finds another one
+    const removed =
+      changes.changeType === 'removed' &&
+      changes.info?.interfaceName === CALCULATOR_SERVICE_INTERFACE &&
+      changes.info.role === CALCULATOR_SERVICE_ROLE
+    const address = endpointAddress(changes.url, changes.port)
+    if (removed && targetServiceKey === buildServiceKey(address)) {
+      calculatorClient?.close()
+      calculatorClient = null
+      targetServiceKey = buildServiceKey()
+      if (changes.remaining === 0) console.log('Last calculator instance unregistered')
+    }
     prepareClient()
   }
   brokerManager.onConnected = async () => {
@@ -0,0 +1,303 @@
+use clap::Parser;
+use observability_rust::{check_log_filter, LogFormat, LOG_FORMAT_ENV, OTLP_ENDPOINT_ENV};
+use serde::{Deserialize, Serialize};
+use service_config_rust::{ConfigSource, EnvAlias, Reload, Validate};
+use service_registration_rust::{InstanceOptions, RegistrationOptions};
+use std::error::Error;
+use std::path::PathBuf;
+use transport_rust::{AuthConfig, TlsConfig};
+
+const BROKER_ADDRESS_ENV: &str = "BROKER_ADDRESS";
+const TOPOLOGY_PROXY_ENV: &str = "TOPOLOGY_PROXY_ADDRESS";
+const DEFAULT_BROKER_ADDRESS: &str = "127.0.0.1:50051";
+const DEFAULT_TOPOLOGY_PROXY: &str = "http://127.0.0.1:50055";
+const DEFAULT_ADDRESS: &str = "127.0.0.1:5556";
+const SERVICE_NAME: &str = "calculator.v1.CalculatorService";
+const DEFAULT_ROLE: &str = "default";
+
+/// Environment variables read before `RUNTIME_*`.
+const ENV_ALIASES: &[EnvAlias] = &[
+  ("broker_address", BROKER_ADDRESS_ENV),
+  ("topology_proxy", TOPOLOGY_PROXY_ENV),
+  ("log_format", LOG_FORMAT_ENV),
+  ("otlp_endpoint", OTLP_ENDPOINT_ENV),
+];
+
+#[derive(Parser)]
+#[command(name = "calculator-server-rust")]
+#[command(about = "A Rust calculator gRPC server with broker registration")]
+struct Args {
+  /// TOML or YAML config file (default: $RUNTIME_CONFIG)
+  #[arg(long)]
+  config: Option<PathBuf>,
+
+  /// Print the effective configuration and exit
+  #[arg(long)]
+  print_config: bool,
+
+  /// Bind address in the format host:port (default: 127.0.0.1:5556)
+  #[arg(long)]
+  address: Option<String>,
+
+  /// Broker address in the format host:port (default: 127.0.0.1:50051)
+  #[arg(long)]
+  broker_address: Option<String>,
+
+  /// Topology proxy address (HTTP) (default: http://127.0.0.1:50055)
+  #[arg(long)]
+  topology_proxy: Option<String>,
+
+  /// Disable topology reporting
+  #[arg(long)]
+  no_topology: bool,
+
+  /// Role registered with the broker (default: default)
+  #[arg(long)]
+  role: Option<String>,
+
+  /// Serve Prometheus metrics on /metrics at this port
+  #[arg(long)]
+  metrics_port: Option<u16>,
+
+  /// Log format: pretty or json (default: pretty)
+  #[arg(long)]
+  log_format: Option<LogFormat>,
+
+  /// Level filter in RUST_LOG syntax (default: $RUST_LOG or info)
+  #[arg(long)]
+  log_level: Option<String>,
+
+  /// Export spans via OTLP/gRPC to this collector (default: disabled)
+  #[arg(long)]
+  otlp_endpoint: Option<String>,
+
+  /// Serve TLS and reach the broker over https
+  #[arg(long)]
+  tls: bool,
+
+  /// PEM certificate chain presented to peers
+  #[arg(long)]
+  tls_cert: Option<PathBuf>,
+
+  /// PEM private key of --tls-cert
+  #[arg(long)]
+  tls_key: Option<PathBuf>,
+
+  /// PEM CA bundle that peer certificates are verified against
+  #[arg(long)]
+  tls_ca: Option<PathBuf>,
+
+  /// Reject clients without a certificate signed by --tls-ca
+  #[arg(long)]
+  tls_require_client_cert: bool,
+
+  /// Name expected in server certificates instead of the host
+  #[arg(long)]
+  tls_server_name: Option<String>,
+
+  /// File holding the bearer token presented to the broker
+  #[arg(long)]
+  broker_token_file: Option<PathBuf>,
+
+  /// Instance id registered with the broker (default: calculator-server-rust@hostname:port)
+  #[arg(long)]
+  instance_id: Option<String>,
+
+  /// Traffic weight registered with the broker (default: 100)
+  #[arg(long)]
+  weight: Option<u32>,
+
+  /// Tag registered with the broker; repeatable
+  #[arg(long = "tag")]
+  tags: Vec<String>,
+
+  /// Zone registered with the broker
+  #[arg(long)]
+  zone: Option<String>,
+
+  /// Serve on a Unix domain socket instead of --address
+  #[arg(long)]
+  unix_socket: Option<PathBuf>,
+}
+
+/// Settings of the server, layered from defaults, the config file, the
+/// environment and the command line. `role`, `topology_enabled`, the two
+/// intervals and `log_level` are reloaded while the server runs.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct ServerConfig {
+  /// Bind address in the format host:port.
+  pub address: String,
+  pub broker_address: String,
+  pub topology_proxy: String,
+  pub topology_enabled: bool,
+  /// gRPC service registered with the broker.
+  pub interface_name: String,
+  pub role: String,
+  /// Topology heartbeat interval in milliseconds.
+  pub heartbeat_interval_ms: u64,
+  /// Interval in milliseconds at which a registered server checks that the
+  /// broker still lists it.
+  pub registration_interval_ms: u64,
+  pub metrics_port: Option<u16>,
+  pub log_format: Option<LogFormat>,
+  /// Level filter in `RUST_LOG` syntax; `RUST_LOG`, then `info` when `None`.
+  pub log_level: Option<String>,
+  pub otlp_endpoint: Option<String>,
+  /// TLS of the served endpoint and of the connections to the broker and the
+  /// topology proxy.
+  pub tls: TlsConfig,
+  /// Credentials presented to the broker.
+  pub auth: AuthConfig,
+  /// Instance id and metadata registered with the broker.
+  pub instance: InstanceOptions,
+  /// Serve on this Unix domain socket instead of `address`, for clients on
+  /// the same host. Registered as a `unix://` URL; never TLS.
+  pub unix_socket: Option<PathBuf>,
+}
+
+impl Default for ServerConfig {
+  fn default() -> Self {
+    Self {
+      address: DEFAULT_ADDRESS.to_string(),
+      broker_address: DEFAULT_BROKER_ADDRESS.to_string(),
+      topology_proxy: DEFAULT_TOPOLOGY_PROXY.to_string(),
+      topology_enabled: true,
+      interface_name: SERVICE_NAME.to_string(),
+      role: DEFAULT_ROLE.to_string(),
+      heartbeat_interval_ms: 2000,
+      registration_interval_ms: 5000,
+      metrics_port: None,
+      log_format: None,
+      log_level: None,
+      otlp_endpoint: None,
+      tls: TlsConfig::default(),
+      auth: AuthConfig::default(),
+      instance: InstanceOptions::default(),
+      unix_socket: None,
+    }
+  }
+}
+
+impl ServerConfig {
+  /// Parses the command line over the config file and the environment and
+  /// returns where the config came from, so it can be reloaded.
+  pub fn load() -> Result<(Self, ConfigSource), Box<dyn Error>> {
+    let args = Args::parse();
+    let source = ConfigSource::new(args.config.clone(), args.print_config);
+    let mut config = source.load(&Self::default(), ENV_ALIASES)?;
+    config.apply(args);
+    Ok((source.finish(config)?, source))
+  }
+
+  fn apply(&mut self, args: Args) {
+    if let Some(address) = args.address {
+      self.address = address;
+    }
+    if let Some(broker_address) = args.broker_address {
+      self.broker_address = broker_address;
+    }
+    if let Some(topology_proxy) = args.topology_proxy {
+      self.topology_proxy = topology_proxy;
+    }
+    if args.no_topology {
+      self.topology_enabled = false;
+    }
+    if let Some(role) = args.role {
+      self.role = role;
+    }
+    if args.metrics_port.is_some() {
+      self.metrics_port = args.metrics_port;
+    }
+    if args.log_format.is_some() {
+      self.log_format = args.log_format;
+    }
+    if args.log_level.is_some() {
+      self.log_level = args.log_level;
+    }
+    if args.otlp_endpoint.is_some() {
+      self.otlp_endpoint = args.otlp_endpoint;
+    }
+    if args.tls {
+      self.tls.enabled = true;
+    }
+    if args.tls_cert.is_some() {
+      self.tls.cert_file = args.tls_cert;
+    }
+    if args.tls_key.is_some() {
+      self.tls.key_file = args.tls_key;
+    }
+    if args.tls_ca.is_some() {
+      self.tls.ca_file = args.tls_ca;
+    }
+    if args.tls_require_client_cert {
+      self.tls.require_client_cert = true;
+    }
+    if args.tls_server_name.is_some() {
+      self.tls.server_name = args.tls_server_name;
+    }
+    if args.broker_token_file.is_some() {
+      self.auth.token_file = args.broker_token_file;
+    }
+    if args.instance_id.is_some() {
+      self.instance.id = args.instance_id;
+    }
+    if args.weight.is_some() {
+      self.instance.weight = args.weight;
+    }
+    if !args.tags.is_empty() {
+      self.instance.tags = args.tags;
+    }
+    if args.zone.is_some() {
+      self.instance.zone = args.zone;
+    }
+    if args.unix_socket.is_some() {
+      self.unix_socket = args.unix_socket;
+    }
+  }
+}
+
+impl Validate for ServerConfig {
+  fn validate(&self, errors: &mut Vec<String>) {
+    if crate::parse_host_port(&self.address).is_err() {
+      errors.push(format!("address '{}' must be in host:port format", self.address));
+    }
+    if self.interface_name.is_empty() {
+      errors.push("interface_name must not be empty".to_string());
+    }
+    if self.role.is_empty() {
+      errors.push("role must not be empty".to_string());
+    }
+    if self.heartbeat_interval_ms == 0 {
+      errors.push("heartbeat_interval_ms must be at least 1".to_string());
+    }
+    if self.registration_interval_ms == 0 {
+      errors.push("registration_interval_ms must be at least 1".to_string());
+    }
+    if let Some(Err(error)) = self.log_level.as_deref().map(check_log_filter) {
+      errors.push(error);
+    }
+    self.tls.validate_server(errors);
+    self.auth.validate(errors);
+    self.instance.validate(errors);
+  }
+}
+
+impl RegistrationOptions for ServerConfig {
+  fn role(&self) -> &str {
+    &self.role
+  }
+
+  fn registration_interval_ms(&self) -> u64 {
+    self.registration_interval_ms
+  }
+}
+
+impl Reload for ServerConfig {
+  fn reload_from(&mut self, next: &Self) {
+    self.role = next.role.clone();
+    self.topology_enabled = next.topology_enabled;
+    self.heartbeat_interval_ms = next.heartbeat_interval_ms;
+    self.registration_interval_ms = next.registration_interval_ms;
+    self.log_level = next.log_level.clone();
+  }
+}
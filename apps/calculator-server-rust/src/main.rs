@@ -1,52 +1,33 @@
+mod config;
 mod proto;
 
-use clap::Parser;
-use proto::broker::v1::{
-  broker_service_client::BrokerServiceClient, GetAvailableServicesRequest, RegisterServiceRequest,
-  ServiceInfo, UnregisterServiceRequest,
+use config::ServerConfig;
+use observability_rust::{
+  init_logging, set_log_filter, spawn_metrics_server, GrpcMetricsLayer, GrpcTraceLayer,
 };
 use proto::calculator::v1::calculator_service_server::{
   CalculatorService, CalculatorServiceServer,
 };
 use proto::calculator::v1::{CalculateRequest, CalculateResponse, Operation};
+use service_registration_rust::{run_broker_registration, BrokerRegistration};
 use std::{error::Error, io::Error as IoError, io::ErrorKind as IoErrorKind, time::Duration};
 use tokio::net::TcpListener;
 use tokio::sync::watch;
 use tokio::time::sleep;
 use tonic::{Request, Response, Status};
 use tonic::transport::Server;
-use tokio_stream::wrappers::TcpListenerStream;
+use tracing::{error, info, info_span, warn, Instrument};
 use topology_reporter_rust::{
   ServiceLanguage, ServiceType, TopologyProxyClient, TopologyProxyConfig,
 };
+use transport_rust::{
+  remove_socket, tcp_incoming, tls_incoming, unix_incoming, unix_url, UnixIncoming,
+};
 
-const BROKER_ADDRESS_ENV: &str = "BROKER_ADDRESS";
-const TOPOLOGY_PROXY_ENV: &str = "TOPOLOGY_PROXY_ADDRESS";
-const DEFAULT_BROKER_ADDRESS: &str = "127.0.0.1:50051";
-const DEFAULT_TOPOLOGY_PROXY: &str = "http://127.0.0.1:50055";
-const DEFAULT_ADDRESS: &str = "127.0.0.1:5556";
-const SERVICE_NAME: &str = "calculator.v1.CalculatorService";
-const DEFAULT_ROLE: &str = "default";
-
-#[derive(Parser)]
-#[command(name = "calculator-server-rust")]
-#[command(about = "A Rust calculator gRPC server with broker registration")]
-struct Args {
-  /// Bind address in the format host:port
-  #[arg(long, default_value = DEFAULT_ADDRESS)]
-  address: String,
-
-  /// Broker address in the format host:port
-  #[arg(long, default_value = DEFAULT_BROKER_ADDRESS)]
-  broker_address: String,
-
-  /// Topology proxy address (HTTP)
-  #[arg(long, default_value = DEFAULT_TOPOLOGY_PROXY)]
-  topology_proxy: String,
-
-  /// Disable topology reporting
-  #[arg(long)]
-  no_topology: bool,
+/// Where the server accepts connections.
+enum Listener {
+  Tcp(TcpListener),
+  Unix(UnixIncoming),
 }
 
 #[derive(Default)]
@@ -79,92 +60,167 @@ impl CalculatorService for CalculatorServiceImpl {
 
 #[tokio::main]
 async fn main() -> Result<(), Box<dyn Error>> {
-  let args = Args::parse();
-
-  let broker_address =
-    std::env::var(BROKER_ADDRESS_ENV).unwrap_or_else(|_| args.broker_address.clone());
-  let topology_proxy =
-    std::env::var(TOPOLOGY_PROXY_ENV).unwrap_or_else(|_| args.topology_proxy.clone());
-  let topology_enabled = !args.no_topology;
+  let (config, source) = ServerConfig::load()?;
+  let _tracing = init_logging(
+    "calculator-server-rust",
+    config.log_format,
+    config.otlp_endpoint.as_deref(),
+  )?;
+  if let Some(log_level) = config.log_level.as_deref() {
+    set_log_filter(Some(log_level))?;
+  }
+  let live_config = source.watch(config.clone(), || Ok(ServerConfig::load()?.0));
 
-  let (service_host, service_port) = parse_host_port(&args.address)?;
+  let (service_host, service_port) = parse_host_port(&config.address)?;
 
   let (shutdown_tx, shutdown_rx) = watch::channel(false);
 
-  let listener = match bind_with_retry(&args.address, shutdown_rx.clone()).await {
-    Ok(listener) => listener,
-    Err(error) => {
-      eprintln!("Failed to bind {}: {}", args.address, error);
-      return Ok(());
+  let (listener, address, (host, port)) = match &config.unix_socket {
+    Some(path) => {
+      let incoming = match unix_incoming(path) {
+        Ok(incoming) => incoming,
+        Err(error) => {
+          error!(%error, "Failed to bind {}", path.display());
+          return Ok(());
+        }
+      };
+      let url = unix_url(path);
+      info!("Listening on {}", url);
+      (Listener::Unix(incoming), url.clone(), (url, 0))
     }
+    None => match bind_with_retry(&config.address, shutdown_rx.clone()).await {
+      Ok(listener) => (
+        Listener::Tcp(listener),
+        config.address.clone(),
+        (service_host.clone(), service_port),
+      ),
+      Err(error) => {
+        error!(%error, "Failed to bind {}", config.address);
+        return Ok(());
+      }
+    },
   };
 
-  let broker_task = tokio::spawn(run_broker_registration(
-    broker_address,
-    service_host.clone(),
-    service_port,
-    shutdown_rx.clone(),
-  ));
+  let hostname = hostname::get().ok().and_then(|h| h.into_string().ok());
+  let registration = BrokerRegistration {
+    interface_name: config.interface_name.clone(),
+    role: config.role.clone(),
+    metadata: config.instance.metadata(
+      "calculator-server-rust",
+      env!("CARGO_PKG_VERSION"),
+      hostname.as_deref().unwrap_or(&host),
+      &host,
+      port,
+    ),
+    host,
+    port,
+  };
+  let broker_span = info_span!(
+    "broker_registration",
+    broker = %config.broker_address,
+    interface = %registration.interface_name,
+    role = %registration.role,
+  );
+  let broker_task = tokio::spawn(
+    run_broker_registration(
+      config.tls.url(&config.broker_address),
+      config.tls.clone(),
+      config.auth.clone(),
+      registration,
+      live_config.clone(),
+      shutdown_rx.clone(),
+    )
+    .instrument(broker_span),
+  );
 
-  let topology_task = if topology_enabled {
-    let host = hostname::get().ok().and_then(|h| h.into_string().ok());
-    let mut config = TopologyProxyConfig::with_defaults(
-      topology_proxy.clone(),
+  let topology_task = {
+    let mut topology_config = TopologyProxyConfig::with_defaults(
+      config.topology_proxy.clone(),
       "calculator-server-rust".to_string(),
       ServiceType::Server,
       ServiceLanguage::Rust,
     );
-    config.version = Some(env!("CARGO_PKG_VERSION").to_string());
-    config.address = Some(args.address.clone());
-    config.host = host;
-    config.service_interface = Some(SERVICE_NAME.to_string());
-    config.service_role = Some(DEFAULT_ROLE.to_string());
-    config.program_name = Some("calculator-server-rust".to_string());
-    let topology_client = TopologyProxyClient::new(config);
-    Some(tokio::spawn(run_topology_heartbeat(
-      topology_client,
-      shutdown_rx.clone(),
-    )))
-  } else {
-    None
+    topology_config.version = Some(env!("CARGO_PKG_VERSION").to_string());
+    topology_config.address = Some(address);
+    topology_config.host = hostname;
+    topology_config.service_interface = Some(config.interface_name.clone());
+    topology_config.service_role = Some(config.role.clone());
+    topology_config.program_name = Some("calculator-server-rust".to_string());
+    topology_config.heartbeat_interval = Duration::from_millis(config.heartbeat_interval_ms);
+    topology_config.tls = config.tls.clone();
+    let topology_client = TopologyProxyClient::new(topology_config);
+    let topology_span = info_span!("topology_heartbeat", proxy = %config.topology_proxy);
+    tokio::spawn(
+      run_topology_heartbeat(topology_client, live_config.clone(), shutdown_rx.clone())
+        .instrument(topology_span),
+    )
   };
+  let log_level_task = tokio::spawn(follow_log_level(live_config));
 
-  let server_task = tokio::spawn(async move {
-    let service = CalculatorServiceImpl::default();
-    Server::builder()
-      .add_service(CalculatorServiceServer::new(service))
-      .serve_with_incoming_shutdown(
-        TcpListenerStream::new(listener),
-        wait_for_shutdown(shutdown_rx.clone()),
-      )
-      .await
-  });
+  let metrics_task = match config.metrics_port {
+    Some(port) => {
+      let metrics_addr = (service_host.as_str(), port);
+      Some(spawn_metrics_server(metrics_addr, shutdown_rx.clone()).await?)
+    }
+    None => None,
+  };
+
+  let router = Server::builder()
+    .layer(GrpcTraceLayer::server())
+    .layer(GrpcMetricsLayer::server())
+    .add_service(CalculatorServiceServer::new(CalculatorServiceImpl));
+  let shutdown = wait_for_shutdown(shutdown_rx);
+  let server_task = match listener {
+    Listener::Unix(incoming) => {
+      tokio::spawn(router.serve_with_incoming_shutdown(incoming, shutdown))
+    }
+    Listener::Tcp(listener) if config.tls.enabled => {
+      info!(require_client_cert = config.tls.require_client_cert, "Serving TLS");
+      let incoming = tls_incoming(listener, &config.tls)?;
+      tokio::spawn(router.serve_with_incoming_shutdown(incoming, shutdown))
+    }
+    Listener::Tcp(listener) => {
+      tokio::spawn(router.serve_with_incoming_shutdown(tcp_incoming(listener), shutdown))
+    }
+  };
 
   wait_for_signal().await;
   let _ = shutdown_tx.send(true);
 
   if let Err(error) = broker_task.await {
-    eprintln!("Broker task error: {}", error);
+    error!(%error, "Broker task failed");
   }
-  if let Some(task) = topology_task {
+  if let Err(error) = topology_task.await {
+    error!(%error, "Topology task failed");
+  }
+  log_level_task.abort();
+  if let Some(task) = metrics_task {
     if let Err(error) = task.await {
-      eprintln!("Topology task error: {}", error);
+      error!(%error, "Metrics task failed");
     }
   }
   match server_task.await {
     Ok(Ok(())) => {}
-    Ok(Err(error)) => eprintln!("Server error: {}", error),
-    Err(error) => eprintln!("Server task error: {}", error),
+    Ok(Err(error)) => error!(%error, "Server failed"),
+    Err(error) => error!(%error, "Server task failed"),
+  }
+  if let Some(path) = &config.unix_socket {
+    remove_socket(path);
   }
 
   Ok(())
 }
 
+/// Heartbeats every `heartbeat_interval_ms` of the live config. While
+/// `topology_enabled` is off the server stays unregistered; a reloaded role
+/// registers again under it.
 async fn run_topology_heartbeat(
   mut topology: TopologyProxyClient,
+  mut config: watch::Receiver<ServerConfig>,
   mut shutdown: watch::Receiver<bool>,
 ) {
-  let mut interval = tokio::time::interval(Duration::from_secs(2));
+  let mut current = config.borrow_and_update().clone();
+  let mut interval = heartbeat_timer(&current);
   loop {
     tokio::select! {
       _ = shutdown.changed() => {
@@ -172,124 +228,58 @@ async fn run_topology_heartbeat(
           break;
         }
       }
-      _ = interval.tick() => {
-        if let Err(error) = topology.ensure_registered().await {
-          eprintln!("Topology registration failed: {}", error);
+      Ok(()) = config.changed() => {
+        let next = config.borrow_and_update().clone();
+        if next.heartbeat_interval_ms != current.heartbeat_interval_ms {
+          interval = heartbeat_timer(&next);
+          topology.set_heartbeat_interval(Duration::from_millis(next.heartbeat_interval_ms));
         }
-      }
-    }
-  }
-
-  if let Err(error) = topology.unregister().await {
-    eprintln!("Topology unregister failed: {}", error);
-  }
-}
-
-async fn run_broker_registration(
-  broker_address: String,
-  service_host: String,
-  service_port: i32,
-  mut shutdown: watch::Receiver<bool>,
-) {
-  let broker_url = normalize_broker_url(&broker_address);
-  let mut delay = Duration::from_secs(1);
-  let mut registered = false;
-
-  loop {
-    if *shutdown.borrow() {
-      break;
-    }
-
-    match BrokerServiceClient::connect(broker_url.clone()).await {
-      Ok(mut client) => {
-        match ensure_broker_registration(&mut client, &service_host, service_port).await {
-          Ok(is_registered) => {
-            registered = is_registered;
-            delay = Duration::from_secs(5);
+        if next.role != current.role {
+          if let Err(error) = topology.set_role(Some(next.role.clone())).await {
+            warn!(%error, "Topology unregister failed");
           }
-          Err(error) => {
-            eprintln!("Broker registration failed: {}", error);
-            delay = next_backoff(delay);
+        }
+        if current.topology_enabled && !next.topology_enabled {
+          info!("Topology reporting disabled");
+          if let Err(error) = topology.unregister().await {
+            warn!(%error, "Topology unregister failed");
           }
+        } else if !current.topology_enabled && next.topology_enabled {
+          info!("Topology reporting enabled");
         }
+        current = next;
       }
-      Err(error) => {
-        eprintln!("Broker connection failed: {}", error);
-        delay = next_backoff(delay);
-      }
-    }
-
-    tokio::select! {
-      _ = shutdown.changed() => {
-        if *shutdown.borrow() {
-          break;
+      _ = interval.tick(), if current.topology_enabled => {
+        if let Err(error) = topology.ensure_registered().await {
+          warn!(%error, "Topology registration failed");
         }
       }
-      _ = sleep(delay) => {}
     }
   }
 
-  if !registered {
-    return;
-  }
-
-  if let Ok(mut client) = BrokerServiceClient::connect(broker_url).await {
-    let request = UnregisterServiceRequest {
-      interface_name: SERVICE_NAME.to_string(),
-      role: DEFAULT_ROLE.to_string(),
-    };
-    let _ = client.unregister_service(request).await;
+  if let Err(error) = topology.unregister().await {
+    warn!(%error, "Topology unregister failed");
   }
 }
 
-async fn ensure_broker_registration(
-  client: &mut BrokerServiceClient<tonic::transport::Channel>,
-  service_host: &str,
-  service_port: i32,
-) -> Result<bool, Status> {
-  if is_registered(client, service_host, service_port).await? {
-    return Ok(true);
-  }
-
-  let request = RegisterServiceRequest {
-    info: Some(ServiceInfo {
-      interface_name: SERVICE_NAME.to_string(),
-      role: DEFAULT_ROLE.to_string(),
-    }),
-    url: service_host.to_string(),
-    port: service_port,
-  };
-  client.register_service(request).await?;
-  Ok(true)
+fn heartbeat_timer(config: &ServerConfig) -> tokio::time::Interval {
+  tokio::time::interval(Duration::from_millis(config.heartbeat_interval_ms))
 }
 
-async fn is_registered(
-  client: &mut BrokerServiceClient<tonic::transport::Channel>,
-  service_host: &str,
-  service_port: i32,
-) -> Result<bool, Status> {
-  let response = client
-    .get_available_services(GetAvailableServicesRequest {})
-    .await?
-    .into_inner();
-
-  for service in response.services {
-    let info = match service.info {
-      Some(info) => info,
-      None => continue,
-    };
-    if info.interface_name != SERVICE_NAME {
-      continue;
-    }
-    if info.role != DEFAULT_ROLE {
+/// Applies reloaded log levels to the global subscriber.
+async fn follow_log_level(mut config: watch::Receiver<ServerConfig>) {
+  let mut current = config.borrow_and_update().log_level.clone();
+  while config.changed().await.is_ok() {
+    let next = config.borrow_and_update().log_level.clone();
+    if next == current {
       continue;
     }
-    if service.url == service_host && service.port == service_port {
-      return Ok(true);
+    match set_log_filter(next.as_deref()) {
+      Ok(()) => info!(log_level = next.as_deref().unwrap_or("default"), "Log level changed"),
+      Err(error) => warn!(%error, "Log level change failed"),
     }
+    current = next;
   }
-
-  Ok(false)
 }
 
 async fn wait_for_signal() {
@@ -306,10 +296,10 @@ async fn wait_for_signal() {
 
   tokio::select! {
     _ = sigterm.recv() => {
-      println!("Received SIGTERM, shutting down.");
+      info!("Received SIGTERM, shutting down.");
     }
     _ = sigint.recv() => {
-      println!("Received SIGINT, shutting down.");
+      info!("Received SIGINT, shutting down.");
     }
   }
 }
@@ -330,16 +320,8 @@ fn parse_host_port(address: &str) -> Result<(String, i32), Box<dyn Error>> {
   Ok((host.to_string(), port))
 }
 
-fn normalize_broker_url(address: &str) -> String {
-  if address.starts_with("http://") || address.starts_with("https://") {
-    address.to_string()
-  } else {
-    format!("http://{}", address)
-  }
-}
-
 fn next_backoff(current: Duration) -> Duration {
-  let next = current.as_secs().saturating_mul(2).max(1).min(15);
+  let next = current.as_secs().saturating_mul(2).clamp(1, 15);
   Duration::from_secs(next)
 }
 
@@ -359,11 +341,11 @@ async fn bind_with_retry(
 
     match TcpListener::bind(address).await {
       Ok(listener) => {
-        println!("Listening on {}", address);
+        info!("Listening on {}", address);
         return Ok(listener);
       }
       Err(error) => {
-        eprintln!("Failed to bind {}: {}", address, error);
+        warn!(%error, "Failed to bind {}", address);
         delay = next_backoff(delay);
       }
     }
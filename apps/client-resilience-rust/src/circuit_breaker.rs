@@ -0,0 +1,308 @@
+use std::collections::{HashMap, VecDeque};
+use std::time::Duration;
+use tokio::time::Instant;
+use tonic::Code;
+
+/// Circuit breaker state for one endpoint.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum BreakerState {
+  Closed,
+  Open,
+  HalfOpen,
+}
+
+/// Thresholds that trip and reset a circuit breaker.
+#[derive(Clone, Debug)]
+pub struct CircuitBreakerConfig {
+  /// Number of most recent calls used for the failure rate.
+  pub window_size: usize,
+  /// Calls required in the window before the failure rate is evaluated.
+  pub minimum_calls: usize,
+  /// Failure rate (0.0-1.0) that opens the breaker.
+  pub failure_rate_threshold: f64,
+  /// Consecutive failures that open the breaker regardless of the rate.
+  pub consecutive_failure_threshold: u32,
+  /// How long the breaker stays open before probing the endpoint again.
+  pub open_duration: Duration,
+  /// Successful probe requests required to close a half-open breaker.
+  pub half_open_probes: u32,
+}
+
+impl Default for CircuitBreakerConfig {
+  fn default() -> Self {
+    Self {
+      window_size: 20,
+      minimum_calls: 10,
+      failure_rate_threshold: 0.5,
+      consecutive_failure_threshold: 3,
+      open_duration: Duration::from_secs(10),
+      half_open_probes: 1,
+    }
+  }
+}
+
+/// Returns true when a status code says something about endpoint health.
+/// Client-side errors such as `INVALID_ARGUMENT` do not count as failures.
+pub fn is_endpoint_failure(code: Code) -> bool {
+  matches!(
+    code,
+    Code::Unavailable
+      | Code::DeadlineExceeded
+      | Code::ResourceExhausted
+      | Code::Internal
+      | Code::Unknown
+      | Code::DataLoss
+  )
+}
+
+/// Closed/open/half-open circuit breaker for a single endpoint.
+#[derive(Debug)]
+pub struct CircuitBreaker {
+  config: CircuitBreakerConfig,
+  state: BreakerState,
+  outcomes: VecDeque<bool>,
+  consecutive_failures: u32,
+  opened_at: Option<Instant>,
+  probes_started: u32,
+  probes_succeeded: u32,
+}
+
+impl CircuitBreaker {
+  pub fn new(config: CircuitBreakerConfig) -> Self {
+    Self {
+      config,
+      state: BreakerState::Closed,
+      outcomes: VecDeque::new(),
+      consecutive_failures: 0,
+      opened_at: None,
+      probes_started: 0,
+      probes_succeeded: 0,
+    }
+  }
+
+  /// Returns the current state, moving an expired open breaker to half-open.
+  pub fn state(&mut self) -> BreakerState {
+    self.refresh(Instant::now());
+    self.state
+  }
+
+  /// Returns true when a request may be sent. In half-open state this
+  /// reserves one of the probe slots.
+  pub fn try_acquire(&mut self) -> bool {
+    self.refresh(Instant::now());
+    match self.state {
+      BreakerState::Closed => true,
+      BreakerState::Open => false,
+      BreakerState::HalfOpen => {
+        if self.probes_started >= self.config.half_open_probes.max(1) {
+          return false;
+        }
+        self.probes_started += 1;
+        true
+      }
+    }
+  }
+
+  /// Gives back the probe slot of a half-open breaker whose request ended
+  /// without saying anything about the endpoint, e.g. it was never sent or
+  /// failed on the client's side.
+  pub fn release_probe(&mut self) {
+    if self.state == BreakerState::HalfOpen {
+      self.probes_started = self.probes_started.saturating_sub(1);
+    }
+  }
+
+  /// Records a successful call and returns the new state.
+  pub fn record_success(&mut self) -> BreakerState {
+    self.consecutive_failures = 0;
+    match self.state {
+      BreakerState::HalfOpen => {
+        self.probes_succeeded += 1;
+        if self.probes_succeeded >= self.config.half_open_probes.max(1) {
+          self.close();
+        }
+      }
+      BreakerState::Closed => self.push_outcome(true),
+      BreakerState::Open => {}
+    }
+    self.state
+  }
+
+  /// Records a failed call and returns the new state.
+  pub fn record_failure(&mut self) -> BreakerState {
+    self.consecutive_failures = self.consecutive_failures.saturating_add(1);
+    match self.state {
+      BreakerState::HalfOpen => self.open(Instant::now()),
+      BreakerState::Closed => {
+        self.push_outcome(false);
+        if self.should_trip() {
+          self.open(Instant::now());
+        }
+      }
+      BreakerState::Open => {}
+    }
+    self.state
+  }
+
+  fn push_outcome(&mut self, success: bool) {
+    self.outcomes.push_back(success);
+    while self.outcomes.len() > self.config.window_size.max(1) {
+      self.outcomes.pop_front();
+    }
+  }
+
+  fn should_trip(&self) -> bool {
+    if self.consecutive_failures >= self.config.consecutive_failure_threshold.max(1) {
+      return true;
+    }
+    if self.outcomes.len() < self.config.minimum_calls.max(1) {
+      return false;
+    }
+    let failures = self.outcomes.iter().filter(|success| !**success).count();
+    failures as f64 / self.outcomes.len() as f64 >= self.config.failure_rate_threshold
+  }
+
+  fn refresh(&mut self, now: Instant) {
+    if self.state != BreakerState::Open {
+      return;
+    }
+    let Some(opened_at) = self.opened_at else {
+      return;
+    };
+    if now.duration_since(opened_at) >= self.config.open_duration {
+      self.state = BreakerState::HalfOpen;
+      self.probes_started = 0;
+      self.probes_succeeded = 0;
+    }
+  }
+
+  fn open(&mut self, now: Instant) {
+    self.state = BreakerState::Open;
+    self.opened_at = Some(now);
+    self.probes_started = 0;
+    self.probes_succeeded = 0;
+  }
+
+  fn close(&mut self) {
+    self.state = BreakerState::Closed;
+    self.opened_at = None;
+    self.outcomes.clear();
+    self.consecutive_failures = 0;
+  }
+}
+
+/// Circuit breakers keyed by endpoint address.
+#[derive(Debug)]
+pub struct CircuitBreakerRegistry {
+  config: CircuitBreakerConfig,
+  breakers: HashMap<String, CircuitBreaker>,
+}
+
+impl CircuitBreakerRegistry {
+  pub fn new(config: CircuitBreakerConfig) -> Self {
+    Self {
+      config,
+      breakers: HashMap::new(),
+    }
+  }
+
+  /// Returns the breaker for an endpoint, creating a closed one on first use.
+  pub fn breaker(&mut self, endpoint: &str) -> &mut CircuitBreaker {
+    self
+      .breakers
+      .entry(endpoint.to_string())
+      .or_insert_with(|| CircuitBreaker::new(self.config.clone()))
+  }
+
+  /// Returns the first endpoint whose breaker admits a request. Endpoints
+  /// with an open breaker are skipped; a half-open one is used as a probe.
+  pub fn select<'a>(&mut self, endpoints: &'a [String]) -> Option<&'a String> {
+    endpoints
+      .iter()
+      .find(|endpoint| self.breaker(endpoint).try_acquire())
+  }
+
+  /// Returns the endpoints whose breakers are not open, without reserving probes.
+  pub fn available<'a>(&mut self, endpoints: &'a [String]) -> Vec<&'a String> {
+    endpoints
+      .iter()
+      .filter(|endpoint| self.breaker(endpoint).state() != BreakerState::Open)
+      .collect()
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  fn config() -> CircuitBreakerConfig {
+    CircuitBreakerConfig {
+      window_size: 4,
+      minimum_calls: 4,
+      failure_rate_threshold: 0.5,
+      consecutive_failure_threshold: 3,
+      open_duration: Duration::from_secs(5),
+      half_open_probes: 1,
+    }
+  }
+
+  #[test]
+  fn opens_after_consecutive_failures() {
+    let mut breaker = CircuitBreaker::new(config());
+    assert_eq!(breaker.record_failure(), BreakerState::Closed);
+    assert_eq!(breaker.record_failure(), BreakerState::Closed);
+    assert_eq!(breaker.record_failure(), BreakerState::Open);
+    assert!(!breaker.try_acquire());
+  }
+
+  #[test]
+  fn opens_on_failure_rate() {
+    let mut breaker = CircuitBreaker::new(config());
+    breaker.record_success();
+    breaker.record_failure();
+    breaker.record_success();
+    assert_eq!(breaker.record_failure(), BreakerState::Open);
+  }
+
+  #[tokio::test(start_paused = true)]
+  async fn half_open_probe_closes_or_reopens() {
+    let mut breaker = CircuitBreaker::new(config());
+    for _ in 0..3 {
+      breaker.record_failure();
+    }
+    tokio::time::advance(Duration::from_secs(5)).await;
+    assert_eq!(breaker.state(), BreakerState::HalfOpen);
+    assert!(breaker.try_acquire());
+    assert!(!breaker.try_acquire());
+    assert_eq!(breaker.record_failure(), BreakerState::Open);
+
+    tokio::time::advance(Duration::from_secs(5)).await;
+    assert!(breaker.try_acquire());
+    assert_eq!(breaker.record_success(), BreakerState::Closed);
+  }
+
+  #[tokio::test(start_paused = true)]
+  async fn released_probe_can_be_taken_again() {
+    let mut breaker = CircuitBreaker::new(config());
+    for _ in 0..3 {
+      breaker.record_failure();
+    }
+    tokio::time::advance(Duration::from_secs(5)).await;
+    assert!(breaker.try_acquire());
+    breaker.release_probe();
+    assert_eq!(breaker.state(), BreakerState::HalfOpen);
+    assert!(breaker.try_acquire());
+    assert!(!breaker.try_acquire());
+  }
+
+  #[test]
+  fn registry_skips_open_endpoints() {
+    let mut registry = CircuitBreakerRegistry::new(config());
+    let endpoints = vec!["a:1".to_string(), "b:2".to_string()];
+    for _ in 0..3 {
+      registry.breaker("a:1").record_failure();
+    }
+    assert_eq!(registry.select(&endpoints), Some(&endpoints[1]));
+    assert_eq!(registry.available(&endpoints), vec![&endpoints[1]]);
+  }
+}
@@ -0,0 +1,13 @@
+//! Client-side resilience middleware shared by the Rust gRPC clients.
+
+pub mod circuit_breaker;
+pub mod retry;
+pub mod routing;
+
+pub use circuit_breaker::{
+  is_endpoint_failure, BreakerState, CircuitBreaker, CircuitBreakerConfig, CircuitBreakerRegistry,
+};
+pub use retry::{
+  AttemptRecord, AttemptTarget, RetryConfig, RetryLayer, RetryPolicy, RetryService,
+};
+pub use routing::{Route, RoutingConfig, RoutingEvent, TrafficRouter, DEFAULT_WEIGHT};
@@ -0,0 +1,521 @@
+use bytes::Bytes;
+use http_body_util::{BodyExt, Full};
+use std::collections::HashMap;
+use std::future::Future;
+use std::pin::Pin;
+use std::sync::Arc;
+use std::task::{Context, Poll};
+use std::time::Duration;
+use tokio::sync::mpsc::UnboundedSender;
+use tokio::time::{sleep, Instant};
+use tonic::body::BoxBody;
+use tonic::{Code, Status};
+use tower::retry::budget::Budget;
+use tower::{Layer, Service, ServiceExt};
+
+type BoxError = Box<dyn std::error::Error + Send + Sync>;
+type ResponseResult = Result<http::Response<BoxBody>, BoxError>;
+
+/// Retry behaviour for a single gRPC method.
+#[derive(Clone, Debug)]
+pub struct RetryPolicy {
+  /// Total attempts including the first one.
+  pub max_attempts: u32,
+  pub retryable_codes: Vec<Code>,
+  pub initial_backoff: Duration,
+  pub max_backoff: Duration,
+  pub backoff_multiplier: u32,
+  /// Sends a hedged attempt to the second instance when the primary has not
+  /// answered within this delay. Requires a hedge service on the layer.
+  pub hedge_delay: Option<Duration>,
+}
+
+impl RetryPolicy {
+  /// Creates a policy for idempotent calls: 3 attempts, 100ms doubling backoff.
+  pub fn idempotent() -> Self {
+    Self {
+      max_attempts: 3,
+      retryable_codes: vec![Code::Unavailable, Code::DeadlineExceeded, Code::ResourceExhausted],
+      initial_backoff: Duration::from_millis(100),
+      max_backoff: Duration::from_secs(2),
+      backoff_multiplier: 2,
+      hedge_delay: None,
+    }
+  }
+
+  /// Returns true when a failed attempt with this code may be retried.
+  pub fn is_retryable(&self, code: Code) -> bool {
+    code != Code::Ok && self.retryable_codes.contains(&code)
+  }
+
+  /// Returns the delay before the given retry (1 = first retry).
+  pub fn backoff(&self, retry: u32) -> Duration {
+    let factor = self
+      .backoff_multiplier
+      .max(1)
+      .saturating_pow(retry.saturating_sub(1));
+    self
+      .initial_backoff
+      .saturating_mul(factor)
+      .min(self.max_backoff)
+  }
+}
+
+/// Per-method retry policies plus the shared retry budget.
+#[derive(Clone, Debug)]
+pub struct RetryConfig {
+  methods: HashMap<String, RetryPolicy>,
+  /// How long deposits stay in the budget.
+  pub budget_ttl: Duration,
+  /// Retries per second that are always allowed, independent of traffic.
+  pub budget_min_per_sec: u32,
+  /// Extra retries allowed as a fraction of regular requests.
+  pub budget_retry_percent: f32,
+}
+
+impl Default for RetryConfig {
+  fn default() -> Self {
+    Self {
+      methods: HashMap::new(),
+      budget_ttl: Duration::from_secs(10),
+      budget_min_per_sec: 10,
+      budget_retry_percent: 0.2,
+    }
+  }
+}
+
+impl RetryConfig {
+  /// Registers a policy for a full gRPC path such as `/pkg.Service/Method`.
+  /// Methods without a policy are passed through untouched.
+  pub fn with_method(mut self, path: impl Into<String>, policy: RetryPolicy) -> Self {
+    self.methods.insert(path.into(), policy);
+    self
+  }
+
+  /// Returns the policy registered for a gRPC path.
+  pub fn policy_for(&self, path: &str) -> Option<&RetryPolicy> {
+    self.methods.get(path)
+  }
+}
+
+/// Which instance served an attempt.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum AttemptTarget {
+  Primary,
+  Hedge,
+}
+
+/// Outcome of a single attempt, emitted to the layer observer.
+#[derive(Clone, Debug)]
+pub struct AttemptRecord {
+  pub method: String,
+  pub attempt: u32,
+  pub target: AttemptTarget,
+  pub latency: Duration,
+  /// `Code::Ok` on success, `Code::Cancelled` for a hedge that lost the race.
+  pub code: Code,
+  pub message: Option<String>,
+  pub will_retry: bool,
+}
+
+impl AttemptRecord {
+  pub fn is_success(&self) -> bool {
+    self.code == Code::Ok
+  }
+}
+
+struct Shared {
+  config: RetryConfig,
+  budget: Budget,
+  observer: Option<UnboundedSender<AttemptRecord>>,
+}
+
+impl Shared {
+  fn observe(&self, record: AttemptRecord) {
+    if let Some(observer) = self.observer.as_ref() {
+      let _ = observer.send(record);
+    }
+  }
+}
+
+/// Tower layer that retries and optionally hedges unary gRPC calls.
+#[derive(Clone)]
+pub struct RetryLayer {
+  shared: Arc<Shared>,
+}
+
+impl RetryLayer {
+  /// Creates a layer without an attempt observer.
+  pub fn new(config: RetryConfig) -> Self {
+    Self::build(config, None)
+  }
+
+  /// Creates a layer that sends every attempt to `observer`.
+  pub fn with_observer(config: RetryConfig, observer: UnboundedSender<AttemptRecord>) -> Self {
+    Self::build(config, Some(observer))
+  }
+
+  /// Wraps a primary service together with a second instance for hedged attempts.
+  pub fn layer_with_hedge<S>(&self, primary: S, hedge: S) -> RetryService<S> {
+    RetryService {
+      primary,
+      hedge: Some(hedge),
+      shared: self.shared.clone(),
+    }
+  }
+
+  fn build(config: RetryConfig, observer: Option<UnboundedSender<AttemptRecord>>) -> Self {
+    let budget = Budget::new(
+      config.budget_ttl,
+      config.budget_min_per_sec,
+      config.budget_retry_percent,
+    );
+    Self {
+      shared: Arc::new(Shared {
+        config,
+        budget,
+        observer,
+      }),
+    }
+  }
+}
+
+impl<S> Layer<S> for RetryLayer {
+  type Service = RetryService<S>;
+
+  fn layer(&self, inner: S) -> Self::Service {
+    RetryService {
+      primary: inner,
+      hedge: None,
+      shared: self.shared.clone(),
+    }
+  }
+}
+
+/// Service produced by [`RetryLayer`].
+#[derive(Clone)]
+pub struct RetryService<S> {
+  primary: S,
+  hedge: Option<S>,
+  shared: Arc<Shared>,
+}
+
+impl<S> Service<http::Request<BoxBody>> for RetryService<S>
+where
+  S: Service<http::Request<BoxBody>, Response = http::Response<BoxBody>> + Clone + Send + 'static,
+  S::Future: Send,
+  S::Error: Into<BoxError>,
+{
+  type Response = http::Response<BoxBody>;
+  type Error = BoxError;
+  type Future = Pin<Box<dyn Future<Output = ResponseResult> + Send>>;
+
+  fn poll_ready(&mut self, _cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+    // Every attempt drives readiness of its own clone via `oneshot`.
+    Poll::Ready(Ok(()))
+  }
+
+  fn call(&mut self, request: http::Request<BoxBody>) -> Self::Future {
+    let primary = self.primary.clone();
+    let policy = self
+      .shared
+      .config
+      .policy_for(request.uri().path())
+      .cloned();
+
+    let Some(policy) = policy else {
+      return Box::pin(async move { primary.oneshot(request).await.map_err(Into::into) });
+    };
+
+    let hedge = self.hedge.clone();
+    let shared = self.shared.clone();
+    Box::pin(call_with_retries(primary, hedge, shared, policy, request))
+  }
+}
+
+/// Buffered copy of a request that can be replayed for every attempt.
+struct RequestTemplate {
+  method: http::Method,
+  uri: http::Uri,
+  version: http::Version,
+  headers: http::HeaderMap,
+  extensions: http::Extensions,
+  body: Bytes,
+}
+
+impl RequestTemplate {
+  async fn buffer(request: http::Request<BoxBody>) -> Result<Self, BoxError> {
+    let (parts, body) = request.into_parts();
+    let body = body.collect().await?.to_bytes();
+    Ok(Self {
+      method: parts.method,
+      uri: parts.uri,
+      version: parts.version,
+      headers: parts.headers,
+      extensions: parts.extensions,
+      body,
+    })
+  }
+
+  fn build(&self) -> http::Request<BoxBody> {
+    let body = Full::new(self.body.clone())
+      .map_err(|never| match never {})
+      .boxed_unsync();
+    let mut request = http::Request::new(body);
+    *request.method_mut() = self.method.clone();
+    *request.uri_mut() = self.uri.clone();
+    *request.version_mut() = self.version;
+    *request.headers_mut() = self.headers.clone();
+    *request.extensions_mut() = self.extensions.clone();
+    request
+  }
+}
+
+struct AttemptOutcome {
+  result: ResponseResult,
+  target: AttemptTarget,
+  latency: Duration,
+}
+
+async fn call_with_retries<S>(
+  primary: S,
+  hedge: Option<S>,
+  shared: Arc<Shared>,
+  policy: RetryPolicy,
+  request: http::Request<BoxBody>,
+) -> ResponseResult
+where
+  S: Service<http::Request<BoxBody>, Response = http::Response<BoxBody>> + Clone + Send + 'static,
+  S::Future: Send,
+  S::Error: Into<BoxError>,
+{
+  let method = request.uri().path().to_string();
+  let template = RequestTemplate::buffer(request).await?;
+  shared.budget.deposit();
+
+  let mut attempt = 0;
+  loop {
+    attempt += 1;
+    let outcome = match (policy.hedge_delay, hedge.as_ref()) {
+      (Some(delay), Some(hedge)) => {
+        let hedge = hedge.clone();
+        hedged_attempt(primary.clone(), hedge, delay, &template, &shared, &policy, &method, attempt)
+          .await
+      }
+      _ => single_attempt(primary.clone(), AttemptTarget::Primary, &template).await,
+    };
+
+    let (code, message) = classify(&outcome.result);
+    let will_retry = policy.is_retryable(code)
+      && attempt < policy.max_attempts
+      && shared.budget.withdraw().is_ok();
+
+    shared.observe(AttemptRecord {
+      method: method.clone(),
+      attempt,
+      target: outcome.target,
+      latency: outcome.latency,
+      code,
+      message,
+      will_retry,
+    });
+
+    if !will_retry {
+      return outcome.result;
+    }
+
+    sleep(policy.backoff(attempt)).await;
+  }
+}
+
+async fn single_attempt<S>(
+  service: S,
+  target: AttemptTarget,
+  template: &RequestTemplate,
+) -> AttemptOutcome
+where
+  S: Service<http::Request<BoxBody>, Response = http::Response<BoxBody>>,
+  S::Error: Into<BoxError>,
+{
+  let started_at = Instant::now();
+  let result = service
+    .oneshot(template.build())
+    .await
+    .map_err(Into::into);
+  let result = match result {
+    Ok(response) => buffer_response(response).await,
+    Err(error) => Err(error),
+  };
+  AttemptOutcome {
+    result,
+    target,
+    latency: started_at.elapsed(),
+  }
+}
+
+/// Races the primary against a delayed hedge and keeps the first usable answer.
+/// A retryable failure on one side waits for the other instead of winning.
+#[allow(clippy::too_many_arguments)]
+async fn hedged_attempt<S>(
+  primary: S,
+  hedge: S,
+  delay: Duration,
+  template: &RequestTemplate,
+  shared: &Shared,
+  policy: &RetryPolicy,
+  method: &str,
+  attempt: u32,
+) -> AttemptOutcome
+where
+  S: Service<http::Request<BoxBody>, Response = http::Response<BoxBody>>,
+  S::Error: Into<BoxError>,
+{
+  let primary_call = single_attempt(primary, AttemptTarget::Primary, template);
+  tokio::pin!(primary_call);
+
+  tokio::select! {
+    outcome = &mut primary_call => return outcome,
+    _ = sleep(delay) => {}
+  }
+
+  if shared.budget.withdraw().is_err() {
+    return primary_call.await;
+  }
+
+  let hedge_call = single_attempt(hedge, AttemptTarget::Hedge, template);
+  tokio::pin!(hedge_call);
+  let hedge_started_at = Instant::now();
+
+  let (first, other_target) = tokio::select! {
+    outcome = &mut primary_call => (outcome, AttemptTarget::Hedge),
+    outcome = &mut hedge_call => (outcome, AttemptTarget::Primary),
+  };
+
+  let (code, message) = classify(&first.result);
+  if !policy.is_retryable(code) {
+    let other_latency = match other_target {
+      AttemptTarget::Primary => first.latency + delay,
+      AttemptTarget::Hedge => hedge_started_at.elapsed(),
+    };
+    shared.observe(AttemptRecord {
+      method: method.to_string(),
+      attempt,
+      target: other_target,
+      latency: other_latency,
+      code: Code::Cancelled,
+      message: Some("Hedged attempt superseded".to_string()),
+      will_retry: false,
+    });
+    return first;
+  }
+
+  shared.observe(AttemptRecord {
+    method: method.to_string(),
+    attempt,
+    target: first.target,
+    latency: first.latency,
+    code,
+    message,
+    will_retry: false,
+  });
+
+  match other_target {
+    AttemptTarget::Primary => primary_call.await,
+    AttemptTarget::Hedge => hedge_call.await,
+  }
+}
+
+/// Trailers of a buffered response, kept where `classify` can read them.
+#[derive(Clone)]
+struct ResponseTrailers(http::HeaderMap);
+
+/// Reads a unary response to its end so that a `grpc-status` sent in the
+/// trailers can be classified. The caller gets the same body and trailers.
+async fn buffer_response(response: http::Response<BoxBody>) -> ResponseResult {
+  let (mut parts, body) = response.into_parts();
+  let collected = body.collect().await?;
+  if let Some(trailers) = collected.trailers() {
+    parts.extensions.insert(ResponseTrailers(trailers.clone()));
+  }
+  let body = collected.map_err(|never| match never {}).boxed_unsync();
+  Ok(http::Response::from_parts(parts, body))
+}
+
+/// Maps an attempt result to a gRPC code. Trailers-only error responses carry
+/// `grpc-status` in the headers, all others in the trailers; transport
+/// failures count as `UNAVAILABLE`.
+fn classify(result: &ResponseResult) -> (Code, Option<String>) {
+  match result {
+    Ok(response) => {
+      let trailers = response.extensions().get::<ResponseTrailers>();
+      let status = Status::from_header_map(response.headers())
+        .or_else(|| trailers.and_then(|trailers| Status::from_header_map(&trailers.0)));
+      match status {
+        Some(status) if status.code() != Code::Ok => {
+          (status.code(), Some(status.message().to_string()))
+        }
+        _ => (Code::Ok, None),
+      }
+    }
+    Err(error) => match error.downcast_ref::<Status>() {
+      Some(status) => (status.code(), Some(status.message().to_string())),
+      None => (Code::Unavailable, Some(error.to_string())),
+    },
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn backoff_grows_and_caps() {
+    let policy = RetryPolicy::idempotent();
+    assert_eq!(policy.backoff(1), Duration::from_millis(100));
+    assert_eq!(policy.backoff(2), Duration::from_millis(200));
+    assert_eq!(policy.backoff(3), Duration::from_millis(400));
+    assert_eq!(policy.backoff(20), Duration::from_secs(2));
+  }
+
+  #[test]
+  fn only_listed_codes_are_retryable() {
+    let policy = RetryPolicy::idempotent();
+    assert!(policy.is_retryable(Code::Unavailable));
+    assert!(!policy.is_retryable(Code::InvalidArgument));
+    assert!(!policy.is_retryable(Code::Ok));
+  }
+
+  #[test]
+  fn trailers_only_status_is_classified() {
+    let mut response = http::Response::new(BoxBody::default());
+    response
+      .headers_mut()
+      .insert("grpc-status", http::HeaderValue::from_static("14"));
+    assert_eq!(classify(&Ok(response)).0, Code::Unavailable);
+
+    let response = http::Response::new(BoxBody::default());
+    assert_eq!(classify(&Ok(response)).0, Code::Ok);
+  }
+
+  #[tokio::test]
+  async fn trailer_status_is_classified_and_passed_on() {
+    let mut trailers = http::HeaderMap::new();
+    trailers.insert("grpc-status", http::HeaderValue::from_static("8"));
+    trailers.insert("grpc-message", http::HeaderValue::from_static("quota"));
+    let body = Full::new(Bytes::from_static(b"partial"))
+      .map_err(|never| match never {})
+      .with_trailers(std::future::ready(Some(Ok(trailers))))
+      .boxed_unsync();
+
+    let result = buffer_response(http::Response::new(body)).await;
+    assert_eq!(
+      classify(&result),
+      (Code::ResourceExhausted, Some("quota".to_string()))
+    );
+
+    let collected = result.unwrap().into_body().collect().await.unwrap();
+    let status = collected.trailers().and_then(Status::from_header_map);
+    assert_eq!(status.map(|status| status.code()), Some(Code::ResourceExhausted));
+    assert_eq!(collected.to_bytes(), Bytes::from_static(b"partial"));
+  }
+}
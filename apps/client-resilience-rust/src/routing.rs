@@ -0,0 +1,475 @@
+use std::collections::{BTreeMap, HashMap, VecDeque};
+use std::time::Duration;
+use tokio::time::Instant;
+
+/// Weight of an instance registered without one.
+pub const DEFAULT_WEIGHT: u32 = 100;
+
+/// One instance a router can send calls to.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Route {
+  /// Endpoint address, also the key outcomes are recorded under.
+  pub address: String,
+  pub instance_id: String,
+  pub version: String,
+  /// Weight the instance registered with.
+  pub weight: u32,
+}
+
+/// Traffic split between versions and when to take a canary out of it.
+#[derive(Clone, Debug)]
+pub struct RoutingConfig {
+  /// Share of traffic per version, e.g. `1.4.0 = 95` and `1.5.0 = 5`, spread
+  /// over the instances of a version by their weight. Versions not listed get
+  /// no traffic. When empty, every instance gets its own weight.
+  pub version_weights: BTreeMap<String, u32>,
+  /// Number of most recent calls per version used for the error rate.
+  pub window_size: usize,
+  /// Calls required in the window before the error rate is evaluated.
+  pub minimum_calls: usize,
+  /// Error rate (0.0-1.0) above which a canary version is rolled back to no
+  /// traffic; never when `None`.
+  pub rollback_error_rate: Option<f64>,
+  /// Time after which `readmit_expired` gives a rolled-back version its
+  /// share back, with a fresh error window. When `None`, only `readmit` does.
+  pub rollback_cooldown: Option<Duration>,
+}
+
+impl Default for RoutingConfig {
+  fn default() -> Self {
+    Self {
+      version_weights: BTreeMap::new(),
+      window_size: 50,
+      minimum_calls: 20,
+      rollback_error_rate: Some(0.2),
+      rollback_cooldown: Some(Duration::from_secs(300)),
+    }
+  }
+}
+
+/// Change a router made on its own.
+#[derive(Clone, Debug, PartialEq)]
+pub enum RoutingEvent {
+  /// `version` went over the error threshold and gets no more traffic; its
+  /// share goes to the other versions.
+  RolledBack {
+    version: String,
+    error_rate: f64,
+    baseline: String,
+  },
+  /// The cooldown of a rolled-back `version` ran out; it has its share back.
+  Readmitted { version: String },
+}
+
+/// Picks the instance for each call by weight, sticky by request key when one
+/// is given, and rolls back canary versions whose calls fail too often. The
+/// baseline, the version with the largest share, is never rolled back. A
+/// rolled-back version returns after `rollback_cooldown`, checked by
+/// `readmit_expired`, or when `readmit` is called.
+#[derive(Debug)]
+pub struct TrafficRouter {
+  config: RoutingConfig,
+  routes: Vec<Route>,
+  /// Effective weight of each route after the split and rollbacks.
+  weights: Vec<f64>,
+  /// Smooth weighted round-robin state of each route.
+  current: Vec<f64>,
+  baseline: Option<String>,
+  outcomes: HashMap<String, VecDeque<bool>>,
+  /// Rolled-back versions and when they were rolled back.
+  rolled_back: BTreeMap<String, Instant>,
+}
+
+impl TrafficRouter {
+  pub fn new(config: RoutingConfig) -> Self {
+    Self {
+      config,
+      routes: Vec::new(),
+      weights: Vec::new(),
+      current: Vec::new(),
+      baseline: None,
+      outcomes: HashMap::new(),
+      rolled_back: BTreeMap::new(),
+    }
+  }
+
+  /// Replaces the instances to route to, e.g. after a broker lookup. Rolled
+  /// back versions stay rolled back; the outcomes of the others are kept.
+  pub fn update(&mut self, routes: Vec<Route>) {
+    self.routes = routes;
+    self.current = vec![0.0; self.routes.len()];
+    self.baseline = self
+      .shares(false)
+      .into_iter()
+      .filter(|(_, share)| *share > 0.0)
+      .max_by(|(_, a), (_, b)| a.total_cmp(b))
+      .map(|(version, _)| version);
+    self.reweigh();
+  }
+
+  pub fn routes(&self) -> &[Route] {
+    &self.routes
+  }
+
+  pub fn is_empty(&self) -> bool {
+    self.routes.is_empty()
+  }
+
+  /// Version that keeps its traffic when canaries are rolled back.
+  pub fn baseline(&self) -> Option<&str> {
+    self.baseline.as_deref()
+  }
+
+  /// Share of traffic (0.0-1.0) each version currently gets.
+  pub fn version_shares(&self) -> BTreeMap<String, f64> {
+    self.shares(true)
+  }
+
+  /// Returns the route for the next call. With a `key`, calls with the same
+  /// key go to the same instance while the routes and weights stay the same;
+  /// without one, calls are spread exactly by weight. Routes `admit` rejects,
+  /// e.g. behind an open circuit breaker, are skipped; it is called in order
+  /// of preference until it accepts one.
+  pub fn pick(
+    &mut self,
+    key: Option<&str>,
+    mut admit: impl FnMut(&Route) -> bool,
+  ) -> Option<&Route> {
+    let mut eligible: Vec<usize> = (0..self.routes.len())
+      .filter(|&index| self.weights[index] > 0.0)
+      .collect();
+    let chosen = match key {
+      Some(key) => {
+        // Weighted rendezvous hashing: a route keeps its keys when others
+        // come and go.
+        let score = |index: usize| {
+          let hash = fnv1a(key.as_bytes(), self.routes[index].address.as_bytes());
+          let unit = ((hash >> 11) as f64 + 1.0) / ((1u64 << 53) as f64 + 1.0);
+          -self.weights[index] / unit.ln()
+        };
+        eligible.sort_by(|a, b| score(*b).total_cmp(&score(*a)));
+        eligible
+          .into_iter()
+          .find(|&index| admit(&self.routes[index]))?
+      }
+      None => {
+        let total: f64 = eligible.iter().map(|&index| self.weights[index]).sum();
+        for &index in &eligible {
+          self.current[index] += self.weights[index];
+        }
+        loop {
+          let (position, &index) = eligible
+            .iter()
+            .enumerate()
+            .max_by(|(_, a), (_, b)| self.current[**a].total_cmp(&self.current[**b]))?;
+          if admit(&self.routes[index]) {
+            self.current[index] -= total;
+            break index;
+          }
+          eligible.swap_remove(position);
+        }
+      }
+    };
+    Some(&self.routes[chosen])
+  }
+
+  /// Records the outcome of a call to `address`. Returns the rollback it
+  /// caused, if any.
+  pub fn record(&mut self, address: &str, success: bool) -> Option<RoutingEvent> {
+    let version = self
+      .routes
+      .iter()
+      .find(|route| route.address == address)?
+      .version
+      .clone();
+    let outcomes = self.outcomes.entry(version.clone()).or_default();
+    outcomes.push_back(success);
+    while outcomes.len() > self.config.window_size.max(1) {
+      outcomes.pop_front();
+    }
+
+    let threshold = self.config.rollback_error_rate?;
+    let baseline = self.baseline.clone()?;
+    if version == baseline
+      || self.rolled_back.contains_key(&version)
+      || outcomes.len() < self.config.minimum_calls.max(1)
+    {
+      return None;
+    }
+    let failures = outcomes.iter().filter(|success| !**success).count();
+    let error_rate = failures as f64 / outcomes.len() as f64;
+    if error_rate <= threshold {
+      return None;
+    }
+    self.rolled_back.insert(version.clone(), Instant::now());
+    self.reweigh();
+    Some(RoutingEvent::RolledBack {
+      version,
+      error_rate,
+      baseline,
+    })
+  }
+
+  /// Gives the versions whose rollback cooldown ran out their share back.
+  pub fn readmit_expired(&mut self) -> Vec<RoutingEvent> {
+    let Some(cooldown) = self.config.rollback_cooldown else {
+      return Vec::new();
+    };
+    let expired: Vec<String> = self
+      .rolled_back
+      .iter()
+      .filter(|(_, rolled_back_at)| rolled_back_at.elapsed() >= cooldown)
+      .map(|(version, _)| version.clone())
+      .collect();
+    expired
+      .into_iter()
+      .filter(|version| self.readmit(version))
+      .map(|version| RoutingEvent::Readmitted { version })
+      .collect()
+  }
+
+  /// Gives a rolled-back `version` its share back right away, with a fresh
+  /// error window. Returns false when it was not rolled back.
+  pub fn readmit(&mut self, version: &str) -> bool {
+    if self.rolled_back.remove(version).is_none() {
+      return false;
+    }
+    self.outcomes.remove(version);
+    self.reweigh();
+    true
+  }
+
+  fn reweigh(&mut self) {
+    let mut version_totals: HashMap<&str, u64> = HashMap::new();
+    for route in &self.routes {
+      *version_totals.entry(&route.version).or_default() += u64::from(route.weight);
+    }
+    self.weights = self
+      .routes
+      .iter()
+      .map(|route| {
+        if self.rolled_back.contains_key(&route.version) {
+          return 0.0;
+        }
+        if self.config.version_weights.is_empty() {
+          return f64::from(route.weight);
+        }
+        let share = self.config.version_weights.get(&route.version).copied().unwrap_or(0);
+        match version_totals[route.version.as_str()] {
+          0 => 0.0,
+          total => f64::from(share) * f64::from(route.weight) / total as f64,
+        }
+      })
+      .collect();
+  }
+
+  /// Share of each version, before rollbacks unless `effective`.
+  fn shares(&self, effective: bool) -> BTreeMap<String, f64> {
+    let mut shares = BTreeMap::new();
+    let mut router = TrafficRouter::new(self.config.clone());
+    router.routes = self.routes.clone();
+    if effective {
+      router.rolled_back = self.rolled_back.clone();
+    }
+    router.reweigh();
+    let total: f64 = router.weights.iter().sum();
+    for (route, weight) in router.routes.iter().zip(&router.weights) {
+      let share = if total > 0.0 { weight / total } else { 0.0 };
+      *shares.entry(route.version.clone()).or_insert(0.0) += share;
+    }
+    shares
+  }
+}
+
+/// FNV-1a over `key` and `address`, stable across builds and processes. The
+/// final mix spreads keys that differ in one character over the high bits.
+fn fnv1a(key: &[u8], address: &[u8]) -> u64 {
+  let mut hash: u64 = 0xcbf2_9ce4_8422_2325;
+  for byte in key.iter().chain(&[0]).chain(address) {
+    hash ^= u64::from(*byte);
+    hash = hash.wrapping_mul(0x0000_0100_0000_01b3);
+  }
+  hash ^= hash >> 33;
+  hash = hash.wrapping_mul(0xff51_afd7_ed55_8ccd);
+  hash ^= hash >> 33;
+  hash = hash.wrapping_mul(0xc4ce_b9fe_1a85_ec53);
+  hash ^ (hash >> 33)
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  fn route(address: &str, version: &str, weight: u32) -> Route {
+    Route {
+      address: address.to_string(),
+      instance_id: address.to_string(),
+      version: version.to_string(),
+      weight,
+    }
+  }
+
+  fn canary_split() -> RoutingConfig {
+    RoutingConfig {
+      version_weights: BTreeMap::from([("1.0".to_string(), 95), ("1.1".to_string(), 5)]),
+      window_size: 10,
+      minimum_calls: 10,
+      rollback_error_rate: Some(0.2),
+      rollback_cooldown: Some(Duration::from_secs(60)),
+    }
+  }
+
+  fn picks(router: &mut TrafficRouter, calls: usize, key: Option<&str>) -> HashMap<String, usize> {
+    let mut counts = HashMap::new();
+    for _ in 0..calls {
+      let address = router.pick(key, |_| true).unwrap().address.clone();
+      *counts.entry(address).or_default() += 1;
+    }
+    counts
+  }
+
+  #[test]
+  fn splits_traffic_between_versions_by_share() {
+    let mut router = TrafficRouter::new(canary_split());
+    router.update(vec![
+      route("a:1", "1.0", 100),
+      route("b:1", "1.0", 100),
+      route("c:1", "1.1", 100),
+      route("d:1", "2.0", 100),
+    ]);
+    assert_eq!(router.baseline(), Some("1.0"));
+
+    let counts = picks(&mut router, 1000, None);
+    assert_eq!(counts["a:1"], 475);
+    assert_eq!(counts["b:1"], 475);
+    assert_eq!(counts["c:1"], 50);
+    assert!(!counts.contains_key("d:1"));
+  }
+
+  #[test]
+  fn instance_weights_apply_without_a_split() {
+    let mut router = TrafficRouter::new(RoutingConfig::default());
+    router.update(vec![route("a:1", "1.0", 300), route("b:1", "1.0", 100), route("c:1", "1.0", 0)]);
+    let counts = picks(&mut router, 400, None);
+    assert_eq!(counts["a:1"], 300);
+    assert_eq!(counts["b:1"], 100);
+    assert!(!counts.contains_key("c:1"));
+  }
+
+  #[test]
+  fn keys_stick_to_one_instance() {
+    let mut router = TrafficRouter::new(canary_split());
+    router.update(vec![
+      route("a:1", "1.0", 100),
+      route("b:1", "1.0", 100),
+      route("c:1", "1.1", 100),
+    ]);
+    for key in ["tenant-1", "tenant-2", "tenant-3"] {
+      assert_eq!(picks(&mut router, 20, Some(key)).len(), 1);
+    }
+
+    let mut canary_keys = 0;
+    for key in 0..2000 {
+      let key = format!("session-{}", key);
+      if router.pick(Some(&key), |_| true).unwrap().version == "1.1" {
+        canary_keys += 1;
+      }
+    }
+    assert!((50..150).contains(&canary_keys), "{} keys on the canary", canary_keys);
+  }
+
+  #[test]
+  fn many_keys_follow_the_configured_weights() {
+    let mut router = TrafficRouter::new(RoutingConfig {
+      version_weights: BTreeMap::from([("1.0".to_string(), 70), ("1.1".to_string(), 30)]),
+      ..canary_split()
+    });
+    router.update(vec![
+      route("a:1", "1.0", 100),
+      route("b:1", "1.0", 300),
+      route("c:1", "1.1", 100),
+    ]);
+    let mut counts: HashMap<String, usize> = HashMap::new();
+    for request in 0..20_000 {
+      let key = format!("request-{}", request);
+      let address = router.pick(Some(&key), |_| true).unwrap().address.clone();
+      *counts.entry(address).or_default() += 1;
+    }
+    // 17.5%, 52.5% and 30% of 20000, within 1.5 percentage points.
+    for (address, expected) in [("a:1", 3500), ("b:1", 10_500), ("c:1", 6000)] {
+      let count = counts[address];
+      assert!(count.abs_diff(expected) < 300, "{} got {} keys", address, count);
+    }
+  }
+
+  #[test]
+  fn rejected_routes_are_skipped() {
+    let mut router = TrafficRouter::new(RoutingConfig::default());
+    router.update(vec![route("a:1", "1.0", 100), route("b:1", "1.0", 100)]);
+    for key in [None, Some("tenant-1")] {
+      for _ in 0..5 {
+        let picked = router.pick(key, |route| route.address != "a:1").unwrap();
+        assert_eq!(picked.address, "b:1");
+      }
+    }
+    assert!(router.pick(None, |_| false).is_none());
+  }
+
+  #[test]
+  fn failing_canary_is_rolled_back() {
+    let mut router = TrafficRouter::new(canary_split());
+    router.update(vec![route("a:1", "1.0", 100), route("c:1", "1.1", 100)]);
+
+    for _ in 0..8 {
+      assert_eq!(router.record("c:1", true), None);
+    }
+    assert_eq!(router.record("c:1", false), None);
+    let event = router.record("c:1", false);
+    assert_eq!(event, None, "an error rate of exactly 0.2 is tolerated");
+    let event = router.record("c:1", false).expect("rolled back");
+    assert_eq!(
+      event,
+      RoutingEvent::RolledBack {
+        version: "1.1".to_string(),
+        error_rate: 0.3,
+        baseline: "1.0".to_string(),
+      }
+    );
+    assert_eq!(router.version_shares()["1.1"], 0.0);
+    assert_eq!(picks(&mut router, 100, None)["a:1"], 100);
+
+    // The baseline is never rolled back, and rollbacks survive updates.
+    for _ in 0..10 {
+      assert_eq!(router.record("a:1", false), None);
+    }
+    router.update(vec![route("a:1", "1.0", 100), route("c:1", "1.1", 100)]);
+    assert_eq!(router.version_shares()["1.0"], 1.0);
+  }
+
+  #[tokio::test(start_paused = true)]
+  async fn rolled_back_versions_return_after_the_cooldown() {
+    let mut router = TrafficRouter::new(canary_split());
+    router.update(vec![route("a:1", "1.0", 100), route("c:1", "1.1", 100)]);
+    let events: Vec<_> = (0..10).filter_map(|_| router.record("c:1", false)).collect();
+    assert_eq!(events.len(), 1);
+
+    tokio::time::advance(Duration::from_secs(59)).await;
+    assert!(router.readmit_expired().is_empty());
+    assert_eq!(router.version_shares()["1.1"], 0.0);
+    tokio::time::advance(Duration::from_secs(1)).await;
+    assert_eq!(
+      router.readmit_expired(),
+      vec![RoutingEvent::Readmitted { version: "1.1".to_string() }]
+    );
+    assert_eq!(router.version_shares()["1.1"], 0.05);
+
+    // The error window starts over, and a manual readmit skips the cooldown.
+    for _ in 0..9 {
+      assert_eq!(router.record("c:1", false), None);
+    }
+    assert!(router.record("c:1", false).is_some());
+    assert!(router.readmit("1.1"));
+    assert!(!router.readmit("1.1"));
+    assert_eq!(router.version_shares()["1.1"], 0.05);
+  }
+}
@@ -1,4 +1,4 @@
-import { Badge, Table } from '@mantine/core'
+import { Anchor, Badge, Table, Text } from '@mantine/core'
 import type { ServiceEdge } from '../types/topology'
 import { getConnectionStateColor, getConnectionStateLabel } from '../utils/topologyFormat'
 
@@ -8,6 +8,30 @@ interface ConnectionsTableProps {
 
 const edgeKey = (edge: ServiceEdge): string => `${edge.sourceServiceId}::${edge.targetService}`
 
+/** Trace viewer URL with a `{traceId}` placeholder, e.g. `http://127.0.0.1:16686/trace/{traceId}`. */
+const TRACE_URL_TEMPLATE: string | undefined = import.meta.env.VITE_TRACE_URL_TEMPLATE
+
+/** Renders the latest trace of an edge, linked to the trace viewer when one is configured. */
+const TraceCell = ({ traceId }: { traceId?: string }): JSX.Element => {
+  if (!traceId) {
+    return <Text c="dimmed">-</Text>
+  }
+  const label = traceId.slice(0, 8)
+  if (!TRACE_URL_TEMPLATE) {
+    return <Text title={traceId}>{label}</Text>
+  }
+  return (
+    <Anchor
+      href={TRACE_URL_TEMPLATE.replace('{traceId}', traceId)}
+      target="_blank"
+      rel="noreferrer"
+      title={traceId}
+    >
+      {label}
+    </Anchor>
+  )
+}
+
 /** Renders the simple runtime connections table view. */
 export const ConnectionsTable = ({ edges }: ConnectionsTableProps): JSX.Element => {
   const sorted = [...edges].sort((a, b) => edgeKey(a).localeCompare(edgeKey(b)))
@@ -22,6 +46,8 @@ export const ConnectionsTable = ({ edges }: ConnectionsTableProps): JSX.Element
           <Table.Th>RPS</Table.Th>
           <Table.Th>Avg Latency</Table.Th>
           <Table.Th>Total Requests</Table.Th>
+          <Table.Th>Backpressure</Table.Th>
+          <Table.Th>Last Trace</Table.Th>
         </Table.Tr>
       </Table.Thead>
       <Table.Tbody>
@@ -46,6 +72,10 @@ export const ConnectionsTable = ({ edges }: ConnectionsTableProps): JSX.Element
             <Table.Td>{edge.rps.toFixed(1)}</Table.Td>
             <Table.Td>{edge.avgLatencyMs.toFixed(1)} ms</Table.Td>
             <Table.Td>{edge.totalRequests}</Table.Td>
+            <Table.Td>{((edge.backpressureRatio ?? 0) * 100).toFixed(0)}%</Table.Td>
+            <Table.Td>
+              <TraceCell traceId={edge.lastTraceId} />
+            </Table.Td>
           </Table.Tr>
         ))}
       </Table.Tbody>
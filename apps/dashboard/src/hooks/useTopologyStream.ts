@@ -75,7 +75,9 @@ const areServiceEdgesEqual = (left: ServiceEdge, right: ServiceEdge): boolean =>
   left.totalRequests === right.totalRequests &&
   left.totalErrors === right.totalErrors &&
   left.avgLatencyMs === right.avgLatencyMs &&
-  left.rps === right.rps
+  left.rps === right.rps &&
+  left.lastTraceId === right.lastTraceId &&
+  left.backpressureRatio === right.backpressureRatio
 
 const areSnapshotsEqual = (left: TopologySnapshot, right: TopologySnapshot): boolean => {
   if (left === right) {
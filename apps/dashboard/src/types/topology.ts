@@ -44,6 +44,10 @@ export interface ServiceEdge {
   totalErrors: string
   avgLatencyMs: number
   rps: number
+  /** Trace id of the latest traced activity, empty if none. */
+  lastTraceId?: string
+  /** Stall time per elapsed time over the last flush window. */
+  backpressureRatio?: number
 }
 
 /** Snapshot containing all nodes and edges in the topology. */
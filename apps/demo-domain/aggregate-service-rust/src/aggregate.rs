@@ -0,0 +1,148 @@
+use pipeline_common_rust::proto::pipeline::v1::enriched_event::Payload;
+use pipeline_common_rust::proto::pipeline::v1::{AggregateResult, EnrichedEvent};
+use pipeline_common_rust::workitem::{
+  finalize_work_item, EnrichedWorkItem, WorkItemResult, WORK_ITEM_EVENT_TYPE,
+};
+use std::collections::BTreeMap;
+use tracing::warn;
+
+#[derive(Default)]
+struct AggregateStats {
+  count: i64,
+  sum: i64,
+}
+
+/// In-memory aggregation of one stream: per-type counts and sums for events,
+/// one result per work item.
+#[derive(Default)]
+pub struct Aggregator {
+  stats: BTreeMap<String, AggregateStats>,
+  work_items: Vec<WorkItemResult>,
+}
+
+impl Aggregator {
+  pub fn add(&mut self, enriched: &EnrichedEvent) {
+    if !enriched.passed_rules {
+      return;
+    }
+    if let Some(Payload::WorkItem(item)) = enriched.payload.as_ref() {
+      let item = EnrichedWorkItem::from(item.clone());
+      self.work_items.push(finalize_work_item(&item));
+      return;
+    }
+    let Some(event) = enriched.event.as_ref() else {
+      return;
+    };
+
+    if event.r#type == WORK_ITEM_EVENT_TYPE {
+      match serde_json::from_str::<EnrichedWorkItem>(&event.user) {
+        Ok(item) => self.work_items.push(finalize_work_item(&item)),
+        Err(error) => warn!(%error, "Failed to process WorkItem"),
+      }
+      return;
+    }
+
+    let entry = self.stats.entry(event.r#type.clone()).or_default();
+    entry.count += 1;
+    entry.sum += event.value;
+  }
+
+  /// Work-item results first, followed by the per-type aggregates in key order.
+  pub fn results(self) -> Vec<AggregateResult> {
+    let work_items = self.work_items.into_iter().map(|item| AggregateResult {
+      key: item.id,
+      count: 0,
+      sum: item.final_score.round() as i64,
+      avg: item.final_score,
+    });
+
+    let aggregates = self.stats.into_iter().map(|(key, value)| AggregateResult {
+      key,
+      count: value.count,
+      sum: value.sum,
+      avg: if value.count == 0 {
+        0.0
+      } else {
+        value.sum as f64 / value.count as f64
+      },
+    });
+
+    work_items.chain(aggregates).collect()
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use pipeline_common_rust::proto::pipeline::v1 as pb;
+  use pipeline_common_rust::proto::pipeline::v1::ParsedEvent;
+
+  fn enriched(event_type: &str, value: i64, passed_rules: bool) -> EnrichedEvent {
+    EnrichedEvent {
+      event: Some(ParsedEvent {
+        r#type: event_type.to_string(),
+        user: "u1".to_string(),
+        value,
+        timestamp: 0,
+        sequence: 0,
+        payload: None,
+      }),
+      metadata: Default::default(),
+      passed_rules,
+      payload: None,
+    }
+  }
+
+  #[test]
+  fn aggregates_by_event_type() {
+    let mut aggregator = Aggregator::default();
+    aggregator.add(&enriched("purchase", 10, true));
+    aggregator.add(&enriched("purchase", 15, true));
+    aggregator.add(&enriched("click", 20, true));
+    aggregator.add(&enriched("click", 99, false));
+
+    let results = aggregator.results();
+    assert_eq!(results.len(), 2);
+    assert_eq!(results[0].key, "click");
+    assert_eq!(results[0].count, 1);
+    assert_eq!(results[1].key, "purchase");
+    assert_eq!(results[1].sum, 25);
+    assert_eq!(results[1].avg, 12.5);
+  }
+
+  #[test]
+  fn emits_one_result_per_work_item() {
+    let mut work_item = enriched(WORK_ITEM_EVENT_TYPE, 0, true);
+    work_item.event.as_mut().unwrap().user =
+      r#"{"id":"w-000001","eigenvalues":[1.0,2.0],"score":10.0}"#.to_string();
+
+    let mut aggregator = Aggregator::default();
+    aggregator.add(&work_item);
+    aggregator.add(&enriched("purchase", 10, true));
+
+    let results = aggregator.results();
+    assert_eq!(results.len(), 2);
+    assert_eq!(results[0].key, "w-000001");
+    assert_eq!(results[0].count, 0);
+    assert!(results[0].avg > 0.0);
+    assert_eq!(results[1].key, "purchase");
+  }
+
+  #[test]
+  fn finalizes_typed_work_item_payloads() {
+    let mut work_item = enriched(WORK_ITEM_EVENT_TYPE, 0, true);
+    work_item.payload = Some(Payload::WorkItem(pb::EnrichedWorkItem {
+      id: "w-000002".to_string(),
+      eigenvalues: vec![1.0, 2.0],
+      score: 10.0,
+    }));
+
+    let mut aggregator = Aggregator::default();
+    aggregator.add(&work_item);
+
+    let results = aggregator.results();
+    assert_eq!(results.len(), 1);
+    assert_eq!(results[0].key, "w-000002");
+    assert!(results[0].avg > 0.0);
+  }
+}
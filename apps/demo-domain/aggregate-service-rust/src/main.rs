@@ -0,0 +1,223 @@
+mod aggregate;
+
+use aggregate::Aggregator;
+use pipeline_common_rust::metrics::ServiceMetrics;
+use pipeline_common_rust::proto::pipeline::v1::aggregate_service_server::{
+  AggregateService,
+  AggregateServiceServer,
+};
+use pipeline_common_rust::proto::pipeline::v1::{
+  AggregateBatchRequest,
+  AggregateBatchResponse,
+  AggregateRequest,
+  AggregateResponse,
+};
+use pipeline_common_rust::sequence::SequenceTracker;
+use pipeline_common_rust::service::{
+  batch_span, run_service, spawn_stream, stream_activity, ActivitySender, ConfigSource,
+  ServiceDescriptor, ServiceOptions, ENV_ALIASES,
+};
+use std::error::Error;
+use std::time::Instant;
+use tokio::sync::mpsc;
+use tokio_stream::wrappers::ReceiverStream;
+use tonic::{Request, Response, Status};
+use tracing::warn;
+
+const DEFAULT_PORT: u16 = 6004;
+
+const DESCRIPTOR: ServiceDescriptor = ServiceDescriptor {
+  program_name: "aggregate-service-rust",
+  interface_name: "pipeline.v1.AggregateService",
+  display_name: "Aggregate service",
+  version: env!("CARGO_PKG_VERSION"),
+};
+
+struct AggregateServiceImpl {
+  activity: ActivitySender,
+  sequence_window: usize,
+}
+
+#[tonic::async_trait]
+impl AggregateService for AggregateServiceImpl {
+  type AggregateBatchStream = ReceiverStream<Result<AggregateBatchResponse, Status>>;
+  type AggregateStream = ReceiverStream<Result<AggregateResponse, Status>>;
+
+  async fn aggregate_batch(
+    &self,
+    request: Request<tonic::Streaming<AggregateBatchRequest>>,
+  ) -> Result<Response<Self::AggregateBatchStream>, Status> {
+    let mut activity =
+      stream_activity(&self.activity, &DESCRIPTOR, &request, "AggregateService/AggregateBatch");
+    let mut input = request.into_inner();
+    let (tx, rx) = mpsc::channel(1);
+    let metrics = ServiceMetrics::new("AggregateService/AggregateBatch");
+    let mut sequences = SequenceTracker::new(self.sequence_window, &metrics);
+
+    spawn_stream(async move {
+      let mut aggregator = Aggregator::default();
+      loop {
+        let recv_start = Instant::now();
+        match input.message().await {
+          Ok(Some(mut message)) => {
+            metrics.record_recv(recv_start.elapsed().as_secs_f64() * 1000.0);
+
+            // Duplicates are dropped before they can be counted twice.
+            message.events.retain(|enriched| {
+              enriched
+                .event
+                .as_ref()
+                .is_none_or(|event| sequences.accept(event.sequence))
+            });
+            if let Some(upstream) = message.upstream_committed_sequence {
+              sequences.settle_through(upstream);
+            }
+            if message.events.is_empty() {
+              continue;
+            }
+
+            let process_start = Instant::now();
+            batch_span(message.events.len()).in_scope(|| {
+              for event in &message.events {
+                aggregator.add(event);
+              }
+            });
+            let processing_ms = process_start.elapsed().as_secs_f64() * 1000.0;
+            metrics.record_processing_count(processing_ms, message.events.len() as u64);
+            activity.record(message.events.len(), processing_ms);
+          }
+          Ok(None) => {
+            sequences.finish();
+            let send_start = Instant::now();
+            let response = AggregateBatchResponse {
+              results: aggregator.results(),
+              committed_sequence: sequences.ack(),
+            };
+            let _ = tx.send(Ok(response)).await;
+            metrics.record_send(send_start.elapsed().as_secs_f64() * 1000.0);
+            metrics.log_summary("aggregate-service");
+            break;
+          }
+          Err(error) => {
+            warn!(%error, "Stream failed");
+            activity.record_error(error.to_string());
+            let _ = tx.send(Err(Status::internal(error.to_string()))).await;
+            break;
+          }
+        }
+      }
+    });
+
+    Ok(Response::new(ReceiverStream::new(rx)))
+  }
+
+  async fn aggregate(
+    &self,
+    request: Request<tonic::Streaming<AggregateRequest>>,
+  ) -> Result<Response<Self::AggregateStream>, Status> {
+    let mut activity =
+      stream_activity(&self.activity, &DESCRIPTOR, &request, "AggregateService/Aggregate");
+    let mut input = request.into_inner();
+    let (tx, rx) = mpsc::channel(128);
+    let metrics = ServiceMetrics::new("AggregateService/Aggregate");
+    let mut sequences = SequenceTracker::new(self.sequence_window, &metrics);
+
+    spawn_stream(async move {
+      let mut aggregator = Aggregator::default();
+      loop {
+        let recv_start = Instant::now();
+        match input.message().await {
+          Ok(Some(message)) => {
+            metrics.record_recv(recv_start.elapsed().as_secs_f64() * 1000.0);
+
+            let event = message.event.filter(|enriched| {
+              enriched
+                .event
+                .as_ref()
+                .is_none_or(|event| sequences.accept(event.sequence))
+            });
+            if let Some(upstream) = message.upstream_committed_sequence {
+              sequences.settle_through(upstream);
+            }
+            if let Some(event) = event {
+              let process_start = Instant::now();
+              aggregator.add(&event);
+              let processing_ms = process_start.elapsed().as_secs_f64() * 1000.0;
+              metrics.record_processing(processing_ms);
+              activity.record(1, processing_ms);
+            }
+          }
+          Ok(None) => {
+            sequences.finish();
+            let committed_sequence = sequences.ack();
+            for result in aggregator.results() {
+              let send_start = Instant::now();
+              let send_result = tx
+                .send(Ok(AggregateResponse {
+                  result: Some(result),
+                  committed_sequence,
+                }))
+                .await;
+              metrics.record_send(send_start.elapsed().as_secs_f64() * 1000.0);
+
+              if send_result.is_err() {
+                break;
+              }
+            }
+            metrics.log_summary("aggregate-service");
+            break;
+          }
+          Err(error) => {
+            warn!(%error, "Stream failed");
+            activity.record_error(error.to_string());
+            let _ = tx.send(Err(Status::internal(error.to_string()))).await;
+            break;
+          }
+        }
+      }
+    });
+
+    Ok(Response::new(ReceiverStream::new(rx)))
+  }
+}
+
+/// Returns the options and where they came from, so they can be reloaded.
+fn parse_args() -> Result<(ServiceOptions, ConfigSource), Box<dyn Error>> {
+  let args: Vec<String> = std::env::args().skip(1).collect();
+  let source = ConfigSource::from_args(&args)?;
+  let mut options = source.load(&ServiceOptions::new(DEFAULT_PORT), ENV_ALIASES)?;
+  let mut args = args.into_iter();
+
+  while let Some(arg) = args.next() {
+    if options.parse_arg(&arg, &mut args)? {
+      continue;
+    }
+    match arg.as_str() {
+      "-h" | "--help" => {
+        println!(
+          "Usage: aggregate-service-rust [options]\n\nOptions:\n{}  -h, --help                  Show this help message",
+          ServiceOptions::new(DEFAULT_PORT).usage()
+        );
+        std::process::exit(0);
+      }
+      _ => return Err(format!("Unknown argument: {}", arg).into()),
+    }
+  }
+
+  Ok((source.finish(options)?, source))
+}
+
+#[tokio::main]
+async fn main() -> Result<(), Box<dyn Error>> {
+  let (options, source) = parse_args()?;
+  let _tracing = options.init_logging(&DESCRIPTOR)?;
+  let sequence_window = options.sequence_window;
+  let options = source.watch(options, || Ok(parse_args()?.0));
+  run_service(options, DESCRIPTOR, |mut server, activity| {
+    server.add_service(AggregateServiceServer::new(AggregateServiceImpl {
+      activity,
+      sequence_window,
+    }))
+  })
+  .await
+}
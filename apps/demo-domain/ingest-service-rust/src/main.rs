@@ -0,0 +1,312 @@
+mod push;
+mod workload;
+
+use pipeline_common_rust::metrics::ServiceMetrics;
+use pipeline_common_rust::proto::pipeline::v1::ingest_service_server::{
+  IngestService,
+  IngestServiceServer,
+};
+use pipeline_common_rust::proto::pipeline::v1::{
+  Event,
+  GetStatusRequest,
+  GetStatusResponse,
+  IngestStatus,
+  StreamEventsRequest,
+  StreamEventsResponse,
+};
+use pipeline_common_rust::registration::StreamActivity;
+use pipeline_common_rust::service::{
+  project, run_service, spawn_stream, stream_activity, ActivitySender, ConfigSource, ServiceDescriptor,
+  ServiceOptions, Reload, Validate, ENV_ALIASES,
+};
+use pipeline_common_rust::workitem::generate_work_item;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::error::Error;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use tokio::fs::File;
+use tokio::io::{AsyncBufReadExt, BufReader, Lines};
+use tokio::sync::mpsc;
+use tokio_stream::wrappers::ReceiverStream;
+use tonic::{Request, Response, Status};
+use tracing::{info, warn};
+use workload::{EventKind, StreamPlan, WorkloadMixer};
+
+const DEFAULT_PORT: u16 = 6001;
+const DEFAULT_INPUT_FILE: &str = "events.ndjson";
+const BATCH_FLUSH_INTERVAL: Duration = Duration::from_millis(10);
+
+const DESCRIPTOR: ServiceDescriptor = ServiceDescriptor {
+  program_name: "ingest-service-rust",
+  interface_name: "pipeline.v1.IngestService",
+  display_name: "Ingest service",
+  version: env!("CARGO_PKG_VERSION"),
+};
+
+type ResponseSender = mpsc::Sender<Result<StreamEventsResponse, Status>>;
+
+#[derive(Clone, Serialize, Deserialize)]
+struct IngestConfig {
+  #[serde(flatten)]
+  service: ServiceOptions,
+  default_input_file: String,
+  /// Stream the default input to the parse service of the same role and
+  /// exit, instead of serving `StreamEvents`.
+  push_to_parse: bool,
+  /// Share of pushed streams per parse version, e.g. `1.4.0 = 95` and
+  /// `1.5.0 = 5`. Each ingest instance sticks to the version its instance id
+  /// hashes to. When empty, the local parse instance is preferred.
+  parse_split: BTreeMap<String, u32>,
+}
+
+impl Reload for IngestConfig {
+  fn reload_from(&mut self, next: &Self) {
+    self.service.reload_from(&next.service);
+  }
+}
+
+impl Validate for IngestConfig {
+  fn validate(&self, errors: &mut Vec<String>) {
+    self.service.validate(errors);
+    if self.parse_split.keys().any(String::is_empty) {
+      errors.push("parse_split must not contain empty versions".to_string());
+    }
+  }
+}
+
+struct IngestServiceImpl {
+  default_input_file: String,
+  streamed_events: Arc<AtomicU64>,
+  activity: ActivitySender,
+}
+
+/// Sends responses to the client and keeps the per-stream counters.
+struct EventWriter {
+  tx: ResponseSender,
+  streamed_events: Arc<AtomicU64>,
+  metrics: ServiceMetrics,
+  activity: StreamActivity,
+}
+
+impl EventWriter {
+  /// Returns false once the client has gone away.
+  async fn send(&mut self, response: StreamEventsResponse) -> bool {
+    let send_start = Instant::now();
+    let sent = self.tx.send(Ok(response)).await.is_ok();
+    let send_ms = send_start.elapsed().as_secs_f64() * 1000.0;
+    self.metrics.record_send(send_ms);
+    if sent {
+      self.streamed_events.fetch_add(1, Ordering::Relaxed);
+      self.activity.record(1, send_ms);
+    }
+    sent
+  }
+
+  async fn send_all(&mut self, batch: &mut Vec<StreamEventsResponse>) -> bool {
+    for response in batch.drain(..) {
+      if !self.send(response).await {
+        return false;
+      }
+    }
+    true
+  }
+}
+
+#[tonic::async_trait]
+impl IngestService for IngestServiceImpl {
+  type StreamEventsStream = ReceiverStream<Result<StreamEventsResponse, Status>>;
+
+  async fn stream_events(
+    &self,
+    request: Request<StreamEventsRequest>,
+  ) -> Result<Response<Self::StreamEventsStream>, Status> {
+    let activity =
+      stream_activity(&self.activity, &DESCRIPTOR, &request, "IngestService/StreamEvents");
+    let plan = StreamPlan::from_request(request.into_inner(), &self.default_input_file);
+    info!(
+      workload_mode = plan.mode.as_str_name(),
+      input = %plan.input_file,
+      "Streaming events"
+    );
+
+    let lines = open_input(&plan).await?;
+
+    let (tx, rx) = mpsc::channel(128);
+    let mut writer = EventWriter {
+      tx,
+      streamed_events: self.streamed_events.clone(),
+      metrics: ServiceMetrics::new("IngestService/StreamEvents"),
+      activity,
+    };
+
+    spawn_stream(async move {
+      if let Err(error) = stream_plan(&plan, lines, &mut writer).await {
+        warn!(%error, "Stream failed");
+        writer.activity.record_error(error.to_string());
+        let _ = writer.tx.send(Err(Status::internal(error.to_string()))).await;
+        return;
+      }
+      writer.metrics.log_summary("ingest-service");
+    });
+
+    Ok(Response::new(ReceiverStream::new(rx)))
+  }
+
+  async fn get_status(
+    &self,
+    _request: Request<GetStatusRequest>,
+  ) -> Result<Response<GetStatusResponse>, Status> {
+    let streamed = self.streamed_events.load(Ordering::Relaxed);
+    Ok(Response::new(GetStatusResponse {
+      status: Some(IngestStatus {
+        queued_events: 0,
+        streamed_events: i64::try_from(streamed).unwrap_or(i64::MAX),
+        healthy: true,
+      }),
+    }))
+  }
+}
+
+/// Opens the input of `plan`, if it reads one, up front so a missing file
+/// fails the call instead of an empty stream.
+async fn open_input(plan: &StreamPlan) -> Result<Option<Lines<BufReader<File>>>, Status> {
+  if !plan.needs_input() {
+    return Ok(None);
+  }
+  let file = File::open(&plan.input_file)
+    .await
+    .map_err(|error| Status::not_found(format!("Cannot open {}: {}", plan.input_file, error)))?;
+  Ok(Some(BufReader::new(file).lines()))
+}
+
+/// Streams events and generated work items until `max_events`, the end of the
+/// input file, or a disconnected client.
+async fn stream_plan(
+  plan: &StreamPlan,
+  mut lines: Option<Lines<BufReader<File>>>,
+  writer: &mut EventWriter,
+) -> Result<(), Box<dyn Error + Send + Sync>> {
+  let mut mixer = WorkloadMixer::new(plan.work_ratio());
+  let mut batch = Vec::with_capacity(if plan.enable_batching { plan.batch_size } else { 0 });
+  let mut batch_start = Instant::now();
+  let mut sequence: i64 = 0;
+
+  while plan.max_events.is_none_or(|max| sequence < max) {
+    let process_start = Instant::now();
+    let raw_json = match mixer.next_kind() {
+      EventKind::WorkItem => {
+        let item = generate_work_item(format!("w-{:06}", sequence), &plan.workload);
+        serde_json::to_string(&item)?
+      }
+      EventKind::Event => {
+        let next = match lines.as_mut() {
+          Some(lines) => lines.next_line().await?,
+          None => None,
+        };
+        match next {
+          Some(line) => line,
+          None => break,
+        }
+      }
+    };
+    let response = StreamEventsResponse {
+      event: Some(Event { raw_json, sequence }),
+    };
+    writer.metrics.record_processing(process_start.elapsed().as_secs_f64() * 1000.0);
+
+    if plan.enable_batching {
+      batch.push(response);
+      let should_flush =
+        batch.len() >= plan.batch_size || batch_start.elapsed() > BATCH_FLUSH_INTERVAL;
+      if should_flush {
+        if !writer.send_all(&mut batch).await {
+          return Ok(());
+        }
+        batch_start = Instant::now();
+      }
+    } else if !writer.send(response).await {
+      return Ok(());
+    }
+
+    sequence += 1;
+  }
+
+  writer.send_all(&mut batch).await;
+  Ok(())
+}
+
+/// Returns the config and where it came from, so it can be reloaded.
+fn parse_args() -> Result<(IngestConfig, ConfigSource), Box<dyn Error>> {
+  let defaults = IngestConfig {
+    service: ServiceOptions::new(DEFAULT_PORT),
+    default_input_file: DEFAULT_INPUT_FILE.to_string(),
+    push_to_parse: false,
+    parse_split: BTreeMap::new(),
+  };
+  let args: Vec<String> = std::env::args().skip(1).collect();
+  let source = ConfigSource::from_args(&args)?;
+  let mut config = source.load(&defaults, ENV_ALIASES)?;
+  let mut args = args.into_iter();
+
+  while let Some(arg) = args.next() {
+    if config.service.parse_arg(&arg, &mut args)? {
+      continue;
+    }
+    match arg.as_str() {
+      "--input" => {
+        config.default_input_file = args.next().ok_or("Missing value for --input")?;
+      }
+      "--push-to-parse" => {
+        config.push_to_parse = true;
+      }
+      "--parse-split" => {
+        let value = args.next().ok_or("Missing value for --parse-split")?;
+        let (version, weight) = value
+          .split_once('=')
+          .ok_or_else(|| format!("Invalid --parse-split {}: expected version=weight", value))?;
+        config.parse_split.insert(version.to_string(), weight.parse()?);
+      }
+      "-h" | "--help" => {
+        println!(
+          "Usage: ingest-service-rust [options]\n\nOptions:\n{}  --input <file>              Default input file (default: {})\n  --push-to-parse             Stream the input to parse, over shared memory on this host, and exit\n  --parse-split <v=weight>    Share of pushed streams for parse version v; repeatable\n  -h, --help                  Show this help message",
+          ServiceOptions::new(DEFAULT_PORT).usage(),
+          DEFAULT_INPUT_FILE
+        );
+        std::process::exit(0);
+      }
+      _ => return Err(format!("Unknown argument: {}", arg).into()),
+    }
+  }
+
+  Ok((source.finish(config)?, source))
+}
+
+#[tokio::main]
+async fn main() -> Result<(), Box<dyn Error>> {
+  let (config, source) = parse_args()?;
+  let _tracing = config.service.init_logging(&DESCRIPTOR)?;
+  if config.push_to_parse {
+    let request = StreamEventsRequest {
+      enable_batching: true,
+      ..StreamEventsRequest::default()
+    };
+    let plan = StreamPlan::from_request(request, &config.default_input_file);
+    return push::push_to_parse(&config.service, &config.parse_split, plan)
+      .await
+      .map_err(|error| error as Box<dyn Error>);
+  }
+  let default_input_file = config.default_input_file.clone();
+  let config = source.watch(config, || Ok(parse_args()?.0));
+  let options = project(config, |config| config.service.clone());
+  run_service(options, DESCRIPTOR, |mut server, activity| {
+    let ingest_service = IngestServiceImpl {
+      default_input_file,
+      streamed_events: Arc::new(AtomicU64::new(0)),
+      activity,
+    };
+    server.add_service(IngestServiceServer::new(ingest_service))
+  })
+  .await
+}
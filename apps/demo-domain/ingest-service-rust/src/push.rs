@@ -0,0 +1,132 @@
+//! `--push-to-parse`: ingest streams its input to the parse service itself
+//! instead of serving it to the orchestrator. `connect_stage` negotiates the
+//! hop through the broker, over shared memory when parse runs on this host
+//! and over gRPC otherwise, so the demo can put the transports side by side.
+//! With a parse split configured, `connect_routed_stage` picks the parse
+//! version instead, sticky per ingest instance id, or per host without one.
+//! A push is a single stream before the process exits, too few outcomes for
+//! an error-rate rollback, so this hop splits traffic but does not roll back.
+
+use crate::workload::StreamPlan;
+use crate::{open_input, stream_plan, EventWriter, DESCRIPTOR};
+use client_resilience_rust::{RoutingConfig, TrafficRouter};
+use parse_service_rust::link::{ParseLink, ParseSender};
+use pipeline_common_rust::fast_path::{connect_routed_stage, connect_stage, local_host, StageLink};
+use pipeline_common_rust::metrics::ServiceMetrics;
+use pipeline_common_rust::proto::pipeline::v1::{Event, ParseEventsBatchRequest};
+use pipeline_common_rust::registration::StreamActivity;
+use pipeline_common_rust::service::ServiceOptions;
+use std::collections::BTreeMap;
+use std::error::Error;
+use std::sync::atomic::AtomicU64;
+use std::sync::Arc;
+use std::time::Instant;
+use tokio::sync::mpsc;
+use tonic::Status;
+use tracing::info;
+
+const PARSE_INTERFACE: &str = "pipeline.v1.ParseService";
+const PUSH_RPC: &str = "ParseService/ParseEventsBatch (push)";
+
+/// Streams `plan` to the parse service registered under the role of
+/// `options` and returns once parse has answered every batch. A non-empty
+/// `parse_split` spreads ingest instances over the parse versions it names.
+pub async fn push_to_parse(
+  options: &ServiceOptions,
+  parse_split: &BTreeMap<String, u32>,
+  plan: StreamPlan,
+) -> Result<(), Box<dyn Error + Send + Sync>> {
+  let broker_url = options.tls.url(&options.broker_address);
+  let (tls, auth, role) = (&options.tls, &options.auth, &options.role);
+  if parse_split.is_empty() {
+    let stage = connect_stage(&broker_url, tls, auth, PARSE_INTERFACE, role).await?;
+    return push(stage, plan).await;
+  }
+
+  let mut router = TrafficRouter::new(RoutingConfig {
+    version_weights: parse_split.clone(),
+    ..RoutingConfig::default()
+  });
+  // Stable across restarts, so an instance keeps its parse version
+  let key = options
+    .instance
+    .id
+    .clone()
+    .unwrap_or_else(|| format!("{}@{}", DESCRIPTOR.program_name, local_host()));
+  let (stage, _route) =
+    connect_routed_stage(&broker_url, tls, auth, PARSE_INTERFACE, role, &mut router, Some(&key))
+      .await?;
+  push(stage, plan).await
+}
+
+/// Streams `plan` over `stage`, batching events as the orchestrator would.
+async fn push(stage: StageLink, plan: StreamPlan) -> Result<(), Box<dyn Error + Send + Sync>> {
+  let transport = match stage {
+    StageLink::Shm(_) => "shm",
+    StageLink::Grpc(_) => "grpc",
+  };
+  let (mut sender, mut receiver) = ParseLink::open(stage, DESCRIPTOR.program_name).await?.split();
+
+  let lines = open_input(&plan).await?;
+  let batch_size = plan.batch_size;
+  let (tx, mut rx) = mpsc::channel(128);
+  let mut writer = EventWriter {
+    tx,
+    streamed_events: Arc::new(AtomicU64::new(0)),
+    metrics: ServiceMetrics::new(PUSH_RPC),
+    activity: StreamActivity::new(None, PARSE_INTERFACE.to_string(), PUSH_RPC),
+  };
+  let start = Instant::now();
+  let producer = tokio::spawn(async move { stream_plan(&plan, lines, &mut writer).await });
+
+  let forward = async {
+    let mut batch: Vec<Event> = Vec::with_capacity(batch_size);
+    let mut sent = 0;
+    while let Some(response) = rx.recv().await {
+      batch.extend(response?.event);
+      if batch.len() >= batch_size {
+        sent += send_batch(&mut sender, &mut batch).await?;
+      }
+    }
+    sent += send_batch(&mut sender, &mut batch).await?;
+    sender.finish().await?;
+    Ok::<_, Status>(sent)
+  };
+  let receive = async {
+    let (mut parsed, mut committed) = (0, None);
+    while let Some(response) = receiver.recv().await? {
+      parsed += response.events.len();
+      committed = response.committed_sequence.or(committed);
+    }
+    Ok::<_, Status>((parsed, committed))
+  };
+  let (sent, (parsed, committed)) = tokio::try_join!(forward, receive)?;
+  producer.await??;
+
+  let elapsed = start.elapsed().as_secs_f64();
+  info!(
+    transport,
+    events = sent,
+    parsed,
+    committed_sequence = ?committed,
+    events_per_sec = (sent as f64 / elapsed.max(f64::EPSILON)) as u64,
+    "Pushed input to parse"
+  );
+  Ok(())
+}
+
+/// Sends the pending events as one batch; returns how many there were.
+async fn send_batch(sender: &mut ParseSender, batch: &mut Vec<Event>) -> Result<usize, Status> {
+  if batch.is_empty() {
+    return Ok(0);
+  }
+  let events = std::mem::take(batch);
+  let count = events.len();
+  sender
+    .send(ParseEventsBatchRequest {
+      batch_size: count as i32,
+      events,
+    })
+    .await?;
+  Ok(count)
+}
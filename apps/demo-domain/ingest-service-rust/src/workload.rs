@@ -0,0 +1,144 @@
+use pipeline_common_rust::proto::pipeline::v1::{
+  PayloadSize,
+  StreamEventsRequest,
+  WorkloadConfig,
+  WorkloadMode,
+};
+use pipeline_common_rust::workitem::DEFAULT_COMPUTE_ITERATIONS;
+
+const DEFAULT_BATCH_SIZE: usize = 100;
+
+/// Normalized view of a `StreamEventsRequest`.
+#[derive(Clone, Debug)]
+pub struct StreamPlan {
+  pub input_file: String,
+  /// `None` streams until the input is exhausted (or forever for work items).
+  pub max_events: Option<i64>,
+  pub enable_batching: bool,
+  pub batch_size: usize,
+  pub mode: WorkloadMode,
+  pub workload: WorkloadConfig,
+}
+
+impl StreamPlan {
+  pub fn from_request(request: StreamEventsRequest, default_input_file: &str) -> Self {
+    let input_file = if request.input_file.is_empty() {
+      default_input_file.to_string()
+    } else {
+      request.input_file
+    };
+    let batch_size = usize::try_from(request.batch_size)
+      .ok()
+      .filter(|size| *size > 0)
+      .unwrap_or(DEFAULT_BATCH_SIZE);
+
+    Self {
+      input_file,
+      max_events: (request.max_events > 0).then_some(request.max_events),
+      enable_batching: request.enable_batching,
+      batch_size,
+      mode: WorkloadMode::try_from(request.workload_mode).unwrap_or(WorkloadMode::Events),
+      workload: request.workload_config.unwrap_or(WorkloadConfig {
+        work_ratio: 0.0,
+        payload_size: PayloadSize::Medium as i32,
+        compute_iterations: DEFAULT_COMPUTE_ITERATIONS,
+      }),
+    }
+  }
+
+  /// Returns true when events are read from the input file.
+  pub fn needs_input(&self) -> bool {
+    self.work_ratio() < 1.0
+  }
+
+  /// Fraction of the stream made up of generated work items.
+  pub fn work_ratio(&self) -> f32 {
+    match self.mode {
+      WorkloadMode::Events => 0.0,
+      WorkloadMode::WorkItems => 1.0,
+      WorkloadMode::Mixed => self.workload.work_ratio.clamp(0.0, 1.0),
+    }
+  }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum EventKind {
+  Event,
+  WorkItem,
+}
+
+/// Interleaves file events and work items so that any prefix of the stream
+/// stays close to the configured work ratio.
+pub struct WorkloadMixer {
+  ratio: f32,
+  credit: f32,
+}
+
+impl WorkloadMixer {
+  pub fn new(ratio: f32) -> Self {
+    Self {
+      ratio: ratio.clamp(0.0, 1.0),
+      credit: 0.0,
+    }
+  }
+
+  pub fn next_kind(&mut self) -> EventKind {
+    self.credit += self.ratio;
+    if self.credit >= 1.0 {
+      self.credit -= 1.0;
+      EventKind::WorkItem
+    } else {
+      EventKind::Event
+    }
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn plan_applies_request_defaults() {
+    let plan = StreamPlan::from_request(StreamEventsRequest::default(), "events.ndjson");
+    assert_eq!(plan.input_file, "events.ndjson");
+    assert_eq!(plan.max_events, None);
+    assert_eq!(plan.batch_size, DEFAULT_BATCH_SIZE);
+    assert_eq!(plan.mode, WorkloadMode::Events);
+    assert!(plan.needs_input());
+  }
+
+  #[test]
+  fn work_items_mode_skips_the_input_file() {
+    let request = StreamEventsRequest {
+      workload_mode: WorkloadMode::WorkItems as i32,
+      max_events: 10,
+      ..Default::default()
+    };
+    let plan = StreamPlan::from_request(request, "events.ndjson");
+    assert_eq!(plan.max_events, Some(10));
+    assert!(!plan.needs_input());
+  }
+
+  #[test]
+  fn mixer_follows_the_work_ratio() {
+    let mut mixer = WorkloadMixer::new(0.25);
+    let kinds: Vec<EventKind> = (0..8).map(|_| mixer.next_kind()).collect();
+    let work_items = kinds.iter().filter(|kind| **kind == EventKind::WorkItem).count();
+    assert_eq!(work_items, 2);
+    assert_eq!(kinds[3], EventKind::WorkItem);
+  }
+
+  #[test]
+  fn events_mode_ignores_the_configured_ratio() {
+    let request = StreamEventsRequest {
+      workload_config: Some(WorkloadConfig {
+        work_ratio: 1.0,
+        ..Default::default()
+      }),
+      ..Default::default()
+    };
+    let plan = StreamPlan::from_request(request, "events.ndjson");
+    let mut mixer = WorkloadMixer::new(plan.work_ratio());
+    assert!((0..4).all(|_| mixer.next_kind() == EventKind::Event));
+  }
+}
@@ -0,0 +1,94 @@
+//! Compares the single-pass borrowed decoder with the previous two-pass path
+//! (`WorkItem` attempt, then a `serde_json::Value` tree) on payloads shaped
+//! like the event generator's output, with and without work items mixed in.
+//!
+//! Run with `cargo bench --bench parse_event`.
+
+use criterion::{black_box, criterion_group, criterion_main, BenchmarkId, Criterion, Throughput};
+use parse_service_rust::parser::{parse_event, WorkItemFormat};
+use parse_service_rust::record::decode;
+use parse_service_rust::schema::SchemaRegistry;
+use pipeline_common_rust::proto::pipeline::v1::{Event, PayloadSize, WorkloadConfig};
+use pipeline_common_rust::workitem::{generate_work_item, WorkItem};
+use serde_json::Value;
+
+const PAYLOADS: usize = 1_000;
+const EVENT_TYPES: [&str; 3] = ["click", "view", "purchase"];
+
+/// Generator-style events with every `work_every`-th payload replaced by a
+/// work item (`0` for events only).
+fn payloads(work_every: usize) -> Vec<String> {
+  let workload = WorkloadConfig {
+    work_ratio: 0.0,
+    payload_size: PayloadSize::Small as i32,
+    compute_iterations: 1,
+  };
+  (0..PAYLOADS)
+    .map(|index| {
+      if work_every > 0 && index % work_every == work_every - 1 {
+        let item = generate_work_item(format!("w-{:06}", index), &workload);
+        return serde_json::to_string(&item).expect("work item serializes");
+      }
+      format!(
+        r#"{{"ts":"2024-01-01T00:{:02}:{:02}.000Z","type":"{}","user":"u{:04}","value":{}}}"#,
+        index / 60 % 60,
+        index % 60,
+        EVENT_TYPES[index * 7 % EVENT_TYPES.len()],
+        index * 31 % 10_000,
+        index * 13 % 100 + 1
+      )
+    })
+    .collect()
+}
+
+/// The decoding done by `parse_event` before the single-pass decoder.
+fn two_pass(raw: &str) -> bool {
+  if serde_json::from_str::<WorkItem>(raw).is_ok() {
+    return true;
+  }
+  serde_json::from_str::<Value>(raw).is_ok()
+}
+
+fn bench_decode(c: &mut Criterion) {
+  let mut group = c.benchmark_group("decode");
+  for (mix, work_every) in [("events", 0), ("mixed-10pct", 10)] {
+    let payloads = payloads(work_every);
+    group.throughput(Throughput::Elements(payloads.len() as u64));
+    group.bench_with_input(BenchmarkId::new("two_pass_value", mix), &payloads, |b, payloads| {
+      b.iter(|| payloads.iter().filter(|raw| two_pass(black_box(raw))).count())
+    });
+    group.bench_with_input(
+      BenchmarkId::new("single_pass_borrowed", mix),
+      &payloads,
+      |b, payloads| b.iter(|| payloads.iter().filter(|raw| decode(black_box(raw)).is_ok()).count()),
+    );
+  }
+  group.finish();
+}
+
+fn bench_parse_event(c: &mut Criterion) {
+  let schemas = SchemaRegistry::builtin();
+  let events: Vec<Event> = payloads(0)
+    .into_iter()
+    .enumerate()
+    .map(|(sequence, raw_json)| Event {
+      raw_json,
+      sequence: sequence as i64,
+    })
+    .collect();
+
+  let mut group = c.benchmark_group("parse_event");
+  group.throughput(Throughput::Elements(events.len() as u64));
+  group.bench_function("events", |b| {
+    b.iter(|| {
+      events
+        .iter()
+        .filter(|event| parse_event(black_box(event), &schemas, WorkItemFormat::Typed).is_ok())
+        .count()
+    })
+  });
+  group.finish();
+}
+
+criterion_group!(benches, bench_decode, bench_parse_event);
+criterion_main!(benches);
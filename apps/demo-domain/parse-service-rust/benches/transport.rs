@@ -0,0 +1,209 @@
+//! Measures the IPC overhead of a pipeline hop: batches sent through a
+//! `ParseEventsBatch` stream to a server that answers without parsing, over
+//! TCP loopback, a Unix domain socket and shared-memory rings. The
+//! difference between them is what the `transport` label of the recv/send
+//! histograms shows in a live pipeline. `protobuf` encodes and decodes the
+//! same messages without any transport, the floor every hop pays.
+//!
+//! Run with `cargo bench --bench transport`.
+
+use criterion::{criterion_group, criterion_main, BenchmarkId, Criterion, Throughput};
+use pipeline_common_rust::proto::pipeline::v1::parse_service_server::{
+  ParseService, ParseServiceServer,
+};
+use pipeline_common_rust::proto::pipeline::v1::{
+  Event, ParseEventsBatchRequest, ParseEventsBatchResponse, ParseEventsRequest,
+  ParseEventsResponse, ParsedEvent,
+};
+use parse_service_rust::link::ParseLink;
+use pipeline_common_rust::fast_path::StageLink;
+use prost::Message;
+use std::path::Path;
+use tokio::net::TcpListener;
+use tokio::runtime::Runtime;
+use tokio::sync::mpsc;
+use tokio_stream::wrappers::ReceiverStream;
+use tokio_stream::StreamExt;
+use tonic::transport::Server;
+use tonic::{Request, Response, Status, Streaming};
+use transport_rust::{
+  connect, remove_socket, shm_connect, shm_url, tcp_incoming, unix_incoming, unix_url, ShmConfig,
+  ShmListener, ShmSession, TlsConfig,
+};
+
+const BATCH_SIZES: [usize; 3] = [1, 100, 1_000];
+
+/// Answers every batch with one parsed event per input, leaving only the
+/// transport and the protobuf coding to measure.
+struct Echo;
+
+#[tonic::async_trait]
+impl ParseService for Echo {
+  type ParseEventsStream = ReceiverStream<Result<ParseEventsResponse, Status>>;
+  type ParseEventsBatchStream = ReceiverStream<Result<ParseEventsBatchResponse, Status>>;
+
+  async fn parse_events(
+    &self,
+    _request: Request<Streaming<ParseEventsRequest>>,
+  ) -> Result<Response<Self::ParseEventsStream>, Status> {
+    Err(Status::unimplemented("batches only"))
+  }
+
+  async fn parse_events_batch(
+    &self,
+    request: Request<Streaming<ParseEventsBatchRequest>>,
+  ) -> Result<Response<Self::ParseEventsBatchStream>, Status> {
+    let mut batches = request.into_inner();
+    let (tx, rx) = mpsc::channel(1);
+    tokio::spawn(async move {
+      while let Some(Ok(batch)) = batches.next().await {
+        if tx.send(Ok(echo(batch))).await.is_err() {
+          break;
+        }
+      }
+    });
+    Ok(Response::new(ReceiverStream::new(rx)))
+  }
+}
+
+/// One parsed event per input event.
+fn echo(batch: ParseEventsBatchRequest) -> ParseEventsBatchResponse {
+  let events = batch
+    .events
+    .into_iter()
+    .map(|event| ParsedEvent {
+      r#type: "click".to_string(),
+      user: "u0001".to_string(),
+      value: event.raw_json.len() as i64,
+      timestamp: 0,
+      sequence: event.sequence,
+      payload: None,
+    })
+    .collect();
+  ParseEventsBatchResponse {
+    events,
+    ..ParseEventsBatchResponse::default()
+  }
+}
+
+async fn round_trip(link: &mut ParseLink, batch: ParseEventsBatchRequest) -> usize {
+  link.send(batch).await.expect("stream is open");
+  let response = link.recv().await.expect("response").expect("stream is open");
+  response.events.len()
+}
+
+/// The coding every hop does: the request and the response are each encoded
+/// by one side and decoded by the other.
+fn protobuf_round_trip(batch: &ParseEventsBatchRequest) -> usize {
+  let request = ParseEventsBatchRequest::decode(batch.encode_to_vec().as_slice()).expect("decode");
+  let response = echo(request).encode_to_vec();
+  ParseEventsBatchResponse::decode(response.as_slice()).expect("decode").events.len()
+}
+
+async fn grpc_link(url: &str) -> ParseLink {
+  let channel = connect(url, &TlsConfig::default()).await.expect("connect");
+  ParseLink::open(StageLink::Grpc(channel), "transport-bench").await.expect("stream opens")
+}
+
+async fn tcp_hop() -> ParseLink {
+  let listener = TcpListener::bind("127.0.0.1:0").await.expect("bind loopback");
+  let url = format!("http://{}", listener.local_addr().expect("local address"));
+  tokio::spawn(
+    Server::builder()
+      .add_service(ParseServiceServer::new(Echo))
+      .serve_with_incoming(tcp_incoming(listener)),
+  );
+  grpc_link(&url).await
+}
+
+async fn unix_hop(path: &Path) -> ParseLink {
+  let incoming = unix_incoming(path).expect("bind socket");
+  tokio::spawn(
+    Server::builder()
+      .add_service(ParseServiceServer::new(Echo))
+      .serve_with_incoming(incoming),
+  );
+  grpc_link(&unix_url(path)).await
+}
+
+/// Answers the batches of one shared-memory session like `Echo`.
+async fn echo_session(session: ShmSession) {
+  let ShmSession {
+    mut sender,
+    mut receiver,
+  } = session;
+  while let Ok(Some(batch)) = receiver.recv::<ParseEventsBatchRequest>().await {
+    if sender.send(&echo(batch)).await.is_err() {
+      return;
+    }
+  }
+  let _ = sender.finish().await;
+}
+
+async fn shm_hop(socket: &Path) -> ParseLink {
+  let config = ShmConfig {
+    socket: Some(socket.to_path_buf()),
+    ..ShmConfig::default()
+  };
+  let listener = ShmListener::bind(&config).expect("bind control socket");
+  tokio::spawn(async move {
+    while let Ok(handshake) = listener.accept().await {
+      if let Ok(session) = handshake.complete().await {
+        tokio::spawn(echo_session(session));
+      }
+    }
+  });
+  let session = shm_connect(&shm_url("localhost", socket)).await.expect("shm connect");
+  ParseLink::from(session)
+}
+
+fn batch(size: usize) -> ParseEventsBatchRequest {
+  let events = (0..size)
+    .map(|index| Event {
+      raw_json: format!(
+        r#"{{"ts":"2024-01-01T00:00:{:02}.000Z","type":"click","user":"u{:04}","value":{}}}"#,
+        index % 60,
+        index % 10_000,
+        index % 100 + 1
+      ),
+      sequence: index as i64,
+    })
+    .collect();
+  ParseEventsBatchRequest {
+    events,
+    batch_size: size as i32,
+  }
+}
+
+fn bench_round_trip(c: &mut Criterion) {
+  let runtime = Runtime::new().expect("runtime");
+  let socket = std::env::temp_dir().join(format!("parse-bench-{}.sock", std::process::id()));
+  let shm_socket = std::env::temp_dir().join(format!("parse-bench-{}.shm", std::process::id()));
+  let mut tcp = runtime.block_on(tcp_hop());
+  let mut unix = runtime.block_on(unix_hop(&socket));
+  let mut shm = runtime.block_on(shm_hop(&shm_socket));
+
+  let mut group = c.benchmark_group("round_trip");
+  for size in BATCH_SIZES {
+    let request = batch(size);
+    group.throughput(Throughput::Elements(size as u64));
+    group.bench_with_input(BenchmarkId::new("protobuf", size), &request, |b, request| {
+      b.iter(|| protobuf_round_trip(request))
+    });
+    group.bench_with_input(BenchmarkId::new("tcp", size), &request, |b, request| {
+      b.iter(|| runtime.block_on(round_trip(&mut tcp, request.clone())))
+    });
+    group.bench_with_input(BenchmarkId::new("unix", size), &request, |b, request| {
+      b.iter(|| runtime.block_on(round_trip(&mut unix, request.clone())))
+    });
+    group.bench_with_input(BenchmarkId::new("shm", size), &request, |b, request| {
+      b.iter(|| runtime.block_on(round_trip(&mut shm, request.clone())))
+    });
+  }
+  group.finish();
+  remove_socket(&socket);
+  remove_socket(&shm_socket);
+}
+
+criterion_group!(benches, bench_round_trip);
+criterion_main!(benches);
@@ -0,0 +1,328 @@
+use std::collections::BTreeMap;
+use std::error::Error;
+use std::fmt;
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+use tokio::sync::Notify;
+use tonic::metadata::{MetadataMap, MetadataValue};
+use tonic::Status;
+use tracing::{info, warn};
+
+/// Request metadata with the last `committed_sequence` the client received.
+/// The stream resumes after it; replayed events up to it are dropped as
+/// duplicates. Echoed in the response metadata with the effective value.
+pub const RESUME_TOKEN_METADATA: &str = "resume-token";
+
+/// Request metadata naming the stream for checkpointing. Streams without it
+/// resume from their token only.
+pub const STREAM_ID_METADATA: &str = "stream-id";
+
+/// Commit offsets of named streams, optionally persisted to a JSON file so a
+/// restarted service can resume them. A background task rewrites the file as
+/// offsets change and only logs failures; `flush` and `close` report them.
+#[derive(Clone, Debug, Default)]
+pub struct CheckpointStore {
+  checkpoints: Option<Arc<Checkpoints>>,
+}
+
+#[derive(Debug, Default)]
+struct Checkpoints {
+  /// Checkpoint file; offsets are kept in memory only when `None`.
+  path: Option<PathBuf>,
+  sequences: Mutex<BTreeMap<String, i64>>,
+  changed: Notify,
+  closed: AtomicBool,
+  /// Serializes writes of the background task and `flush`.
+  writing: tokio::sync::Mutex<()>,
+}
+
+impl CheckpointStore {
+  /// Loads the checkpoint file, if configured, and starts its writer task.
+  /// A missing file starts out empty.
+  pub async fn start(file: Option<PathBuf>) -> Result<Self, Box<dyn Error>> {
+    let Some(path) = file else {
+      return Ok(Self::default());
+    };
+    let sequences = match tokio::fs::read_to_string(&path).await {
+      Ok(text) => serde_json::from_str::<BTreeMap<String, i64>>(&text)
+        .map_err(|error| format!("Invalid checkpoint file {}: {}", path.display(), error))?,
+      Err(error) if error.kind() == std::io::ErrorKind::NotFound => BTreeMap::new(),
+      Err(error) => {
+        return Err(format!("Cannot read checkpoint file {}: {}", path.display(), error).into())
+      }
+    };
+    info!("Loaded {} stream checkpoints from {}", sequences.len(), path.display());
+    let checkpoints = Arc::new(Checkpoints {
+      path: Some(path),
+      sequences: Mutex::new(sequences),
+      ..Checkpoints::default()
+    });
+    tokio::spawn(write_checkpoints(checkpoints.clone()));
+    Ok(Self {
+      checkpoints: Some(checkpoints),
+    })
+  }
+
+  /// Works out where the stream of a request resumes. The resume token wins
+  /// over the checkpoint, since only the client knows which responses it
+  /// received, and replaces the checkpoint of a named stream.
+  pub fn resume(&self, metadata: &MetadataMap) -> Result<StreamResume, InvalidResumeToken> {
+    let token = match metadata.get(RESUME_TOKEN_METADATA) {
+      Some(value) => Some(
+        value
+          .to_str()
+          .ok()
+          .and_then(|token| token.parse::<i64>().ok())
+          .ok_or(InvalidResumeToken)?,
+      ),
+      None => None,
+    };
+    let stream_id = metadata
+      .get(STREAM_ID_METADATA)
+      .and_then(|value| value.to_str().ok())
+      .filter(|stream_id| !stream_id.is_empty())
+      .map(str::to_string);
+    let checkpoint = match (&self.checkpoints, &stream_id) {
+      (Some(checkpoints), Some(stream_id)) => {
+        checkpoints.sequences.lock().unwrap().get(stream_id).copied()
+      }
+      _ => None,
+    };
+    let after = token.or(checkpoint);
+    if let Some(after) = after {
+      info!(stream_id, after, from_token = token.is_some(), "Resuming stream");
+    }
+    let resume = StreamResume {
+      after,
+      stream_id,
+      store: self.clone(),
+    };
+    if token.is_some() {
+      resume.save(token);
+    }
+    Ok(resume)
+  }
+
+  /// Writes the checkpoint file now, reporting a failed write.
+  pub async fn flush(&self) -> Result<(), Box<dyn Error>> {
+    match &self.checkpoints {
+      Some(checkpoints) => checkpoints.write().await,
+      None => Ok(()),
+    }
+  }
+
+  /// Stops the background writer and writes the checkpoint file a last time,
+  /// reporting a failed write. Offsets saved afterwards stay in memory.
+  pub async fn close(&self) -> Result<(), Box<dyn Error>> {
+    if let Some(checkpoints) = &self.checkpoints {
+      checkpoints.closed.store(true, Ordering::SeqCst);
+      checkpoints.changed.notify_one();
+    }
+    self.flush().await
+  }
+
+  /// Resume point of a stream without metadata, such as a shared-memory
+  /// session: it starts fresh and is not checkpointed.
+  pub fn fresh(&self) -> StreamResume {
+    StreamResume {
+      after: None,
+      stream_id: None,
+      store: self.clone(),
+    }
+  }
+}
+
+/// The `resume-token` metadata is not a sequence number.
+#[derive(Debug, PartialEq)]
+pub struct InvalidResumeToken;
+
+impl fmt::Display for InvalidResumeToken {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    write!(f, "{} must be a sequence number", RESUME_TOKEN_METADATA)
+  }
+}
+
+impl Error for InvalidResumeToken {}
+
+impl From<InvalidResumeToken> for Status {
+  fn from(error: InvalidResumeToken) -> Self {
+    Status::invalid_argument(error.to_string())
+  }
+}
+
+/// Resume point and checkpoint target of one stream.
+#[derive(Debug)]
+pub struct StreamResume {
+  /// Sequence the stream resumes after; `None` for a fresh stream.
+  pub after: Option<i64>,
+  stream_id: Option<String>,
+  store: CheckpointStore,
+}
+
+impl StreamResume {
+  /// Checkpoints the commit offset of a named stream. Call it once the
+  /// response carrying `committed` was handed to the client's stream.
+  pub fn save(&self, committed: Option<i64>) {
+    let (Some(checkpoints), Some(stream_id), Some(committed)) =
+      (&self.store.checkpoints, &self.stream_id, committed)
+    else {
+      return;
+    };
+    checkpoints
+      .sequences
+      .lock()
+      .unwrap()
+      .insert(stream_id.clone(), committed);
+    checkpoints.changed.notify_one();
+  }
+
+  /// Drops the checkpoint of a named stream that ran to its end, so a later
+  /// stream reusing its id starts from the beginning instead of dropping its
+  /// events as duplicates.
+  pub fn complete(&self) {
+    let (Some(checkpoints), Some(stream_id)) = (&self.store.checkpoints, &self.stream_id) else {
+      return;
+    };
+    if checkpoints.sequences.lock().unwrap().remove(stream_id).is_some() {
+      checkpoints.changed.notify_one();
+    }
+  }
+
+  /// Echoes the effective resume point so the client can skip its replay.
+  pub fn annotate(&self, metadata: &mut MetadataMap) {
+    if let Some(after) = self.after {
+      metadata.insert(RESUME_TOKEN_METADATA, MetadataValue::from(after));
+    }
+  }
+}
+
+impl Checkpoints {
+  /// Writes all checkpoints to a temporary file that replaces the old one, so
+  /// a crash never leaves a truncated file behind.
+  async fn write(&self) -> Result<(), Box<dyn Error>> {
+    let Some(path) = &self.path else {
+      return Ok(());
+    };
+    let _writing = self.writing.lock().await;
+    let text = {
+      let sequences = self.sequences.lock().unwrap();
+      serde_json::to_string_pretty(&*sequences)?
+    };
+    let mut temporary = path.clone().into_os_string();
+    temporary.push(".tmp");
+    let written = match tokio::fs::write(&temporary, text).await {
+      Ok(()) => tokio::fs::rename(&temporary, path).await,
+      Err(error) => Err(error),
+    };
+    written
+      .map_err(|error| format!("Cannot write checkpoint file {}: {}", path.display(), error).into())
+  }
+}
+
+/// Rewrites the checkpoint file whenever checkpoints changed, until the store
+/// is closed.
+async fn write_checkpoints(checkpoints: Arc<Checkpoints>) {
+  loop {
+    checkpoints.changed.notified().await;
+    if checkpoints.closed.load(Ordering::SeqCst) {
+      return;
+    }
+    if let Err(error) = checkpoints.write().await {
+      warn!(%error, "Checkpoint write failed");
+    }
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  fn metadata(entries: &[(&'static str, &'static str)]) -> MetadataMap {
+    let mut metadata = MetadataMap::new();
+    for (key, value) in entries {
+      metadata.insert(*key, value.parse().unwrap());
+    }
+    metadata
+  }
+
+  #[test]
+  fn resume_token_wins_over_the_checkpoint() {
+    let store = CheckpointStore {
+      checkpoints: Some(Arc::default()),
+    };
+    store
+      .resume(&metadata(&[(STREAM_ID_METADATA, "orders")]))
+      .unwrap()
+      .save(Some(41));
+
+    let resume = store.resume(&metadata(&[(STREAM_ID_METADATA, "orders")])).unwrap();
+    assert_eq!(resume.after, Some(41));
+    let resume = store
+      .resume(&metadata(&[(STREAM_ID_METADATA, "orders"), (RESUME_TOKEN_METADATA, "17")]))
+      .unwrap();
+    assert_eq!(resume.after, Some(17));
+    // The client acknowledged only 17, so that is where a reconnect without a
+    // token resumes too.
+    let resume = store.resume(&metadata(&[(STREAM_ID_METADATA, "orders")])).unwrap();
+    assert_eq!(resume.after, Some(17));
+    assert_eq!(store.resume(&MetadataMap::new()).unwrap().after, None);
+
+    let error = store
+      .resume(&metadata(&[(RESUME_TOKEN_METADATA, "latest")]))
+      .unwrap_err();
+    assert_eq!(Status::from(error).code(), tonic::Code::InvalidArgument);
+  }
+
+  #[tokio::test]
+  async fn completed_stream_ids_start_over() {
+    let path = std::env::temp_dir().join(format!("parse-completed-{}.json", std::process::id()));
+    let _ = std::fs::remove_file(&path);
+    let stream = metadata(&[(STREAM_ID_METADATA, "nightly-job")]);
+
+    let store = CheckpointStore::start(Some(path.clone())).await.unwrap();
+    let first = store.resume(&stream).unwrap();
+    first.save(Some(500));
+    first.complete();
+    assert_eq!(store.resume(&stream).unwrap().after, None);
+    store.close().await.unwrap();
+
+    let restarted = CheckpointStore::start(Some(path.clone())).await.unwrap();
+    assert_eq!(restarted.resume(&stream).unwrap().after, None);
+    std::fs::remove_file(&path).unwrap();
+  }
+
+  #[tokio::test]
+  async fn restarted_store_resumes_from_the_file() {
+    let path = std::env::temp_dir().join(format!("parse-checkpoints-{}.json", std::process::id()));
+    let _ = std::fs::remove_file(&path);
+    let stream = metadata(&[(STREAM_ID_METADATA, "orders")]);
+
+    let store = CheckpointStore::start(Some(path.clone())).await.unwrap();
+    store.resume(&stream).unwrap().save(Some(99));
+    store.close().await.unwrap();
+
+    let restarted = CheckpointStore::start(Some(path.clone())).await.unwrap();
+    let resume = restarted.resume(&stream).unwrap();
+    assert_eq!(resume.after, Some(99));
+    let mut echoed = MetadataMap::new();
+    resume.annotate(&mut echoed);
+    assert_eq!(echoed.get(RESUME_TOKEN_METADATA).unwrap(), "99");
+    std::fs::remove_file(&path).unwrap();
+  }
+
+  #[tokio::test]
+  async fn failed_writes_are_reported_on_close() {
+    let dir = std::env::temp_dir().join(format!("parse-checkpoints-{}", std::process::id()));
+    std::fs::create_dir_all(&dir).unwrap();
+    let path = dir.join("checkpoints.json");
+    let store = CheckpointStore::start(Some(path.clone())).await.unwrap();
+    store.flush().await.unwrap();
+    assert!(path.exists());
+
+    std::fs::remove_dir_all(&dir).unwrap();
+    store.resume(&metadata(&[(STREAM_ID_METADATA, "orders")])).unwrap().save(Some(5));
+    let error = store.close().await.unwrap_err();
+    assert!(error.to_string().contains("Cannot write checkpoint file"), "{}", error);
+  }
+}
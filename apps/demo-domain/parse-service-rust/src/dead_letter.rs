@@ -0,0 +1,194 @@
+use crate::schema::ValidationError;
+use pipeline_common_rust::proto::pipeline::v1::{Event, RejectReason, RejectedEvent};
+use serde::{Deserialize, Serialize};
+use std::error::Error;
+use std::path::PathBuf;
+use tokio::fs::OpenOptions;
+use tokio::io::{AsyncWriteExt, BufWriter};
+use tokio::sync::mpsc;
+use tracing::warn;
+
+/// Why `parse_event` did not produce a `ParsedEvent`.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Rejection {
+  pub reason: RejectReason,
+  pub field: String,
+  pub detail: String,
+}
+
+impl Rejection {
+  pub fn new(reason: RejectReason, detail: impl Into<String>) -> Self {
+    Self {
+      reason,
+      field: String::new(),
+      detail: detail.into(),
+    }
+  }
+
+  /// Builds the dead-letter record for `event`.
+  pub fn into_record(self, event: &Event) -> RejectedEvent {
+    RejectedEvent {
+      sequence: event.sequence,
+      reason: self.reason as i32,
+      field: self.field,
+      raw_json: event.raw_json.clone(),
+      detail: self.detail,
+    }
+  }
+}
+
+impl From<ValidationError> for Rejection {
+  fn from(error: ValidationError) -> Self {
+    let detail = error.to_string();
+    let (reason, field) = match error {
+      ValidationError::MissingField(field) => (RejectReason::MissingField, field),
+      ValidationError::InvalidType { field, .. } => (RejectReason::InvalidFieldType, field),
+      ValidationError::NotAllowed(field) => (RejectReason::ValueNotAllowed, field),
+      ValidationError::UnknownEventType(_) => (RejectReason::UnknownEventType, String::new()),
+    };
+    Self {
+      reason,
+      field,
+      detail,
+    }
+  }
+}
+
+/// Where reject records go besides the per-reason counters.
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+pub struct DeadLetterConfig {
+  /// NDJSON file that reject records are appended to.
+  pub file: Option<PathBuf>,
+  /// Return reject records to the caller alongside the parsed events.
+  pub stream: bool,
+}
+
+/// Fans reject records out to the dead-letter file and the response side stream.
+#[derive(Clone, Debug, Default)]
+pub struct DeadLetterSink {
+  file: Option<mpsc::Sender<RejectedEvent>>,
+  stream: bool,
+}
+
+impl DeadLetterSink {
+  /// Opens the dead-letter file, if configured, and starts its writer task.
+  pub async fn start(config: DeadLetterConfig) -> Result<Self, Box<dyn Error>> {
+    let file = match config.file {
+      Some(path) => {
+        let file = OpenOptions::new()
+          .create(true)
+          .append(true)
+          .open(&path)
+          .await
+          .map_err(|error| format!("Cannot open dead-letter file {}: {}", path.display(), error))?;
+        let (tx, rx) = mpsc::channel(1024);
+        tokio::spawn(write_records(BufWriter::new(file), rx));
+        Some(tx)
+      }
+      None => None,
+    };
+    Ok(Self {
+      file,
+      stream: config.stream,
+    })
+  }
+
+  /// Writes `record` to the dead-letter file and returns it when it should
+  /// also be streamed back to the caller.
+  pub async fn dispatch(&self, record: RejectedEvent) -> Option<RejectedEvent> {
+    let Some(file) = self.file.as_ref() else {
+      return self.stream.then_some(record);
+    };
+    if !self.stream {
+      let _ = file.send(record).await;
+      return None;
+    }
+    let _ = file.send(record.clone()).await;
+    Some(record)
+  }
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+struct DeadLetterLine<'a> {
+  sequence: i64,
+  reason: &'a str,
+  #[serde(skip_serializing_if = "str::is_empty")]
+  field: &'a str,
+  detail: &'a str,
+  raw_json: &'a str,
+}
+
+/// Appends records as NDJSON, flushing whenever the queue runs empty.
+async fn write_records(
+  mut output: BufWriter<tokio::fs::File>,
+  mut records: mpsc::Receiver<RejectedEvent>,
+) {
+  while let Some(record) = records.recv().await {
+    let mut next = Some(record);
+    while let Some(record) = next {
+      if let Err(error) = output.write_all(format_record(&record).as_bytes()).await {
+        warn!(%error, "Dead-letter write failed");
+      }
+      next = records.try_recv().ok();
+    }
+    if let Err(error) = output.flush().await {
+      warn!(%error, "Dead-letter flush failed");
+    }
+  }
+}
+
+fn format_record(record: &RejectedEvent) -> String {
+  let line = DeadLetterLine {
+    sequence: record.sequence,
+    reason: record.reason().as_str_name(),
+    field: &record.field,
+    detail: &record.detail,
+    raw_json: &record.raw_json,
+  };
+  let mut text = serde_json::to_string(&line).unwrap_or_default();
+  text.push('\n');
+  text
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn maps_validation_errors_to_reason_codes() {
+    let rejection = Rejection::from(ValidationError::MissingField("user".to_string()));
+    assert_eq!(rejection.reason, RejectReason::MissingField);
+    assert_eq!(rejection.field, "user");
+
+    let rejection = Rejection::from(ValidationError::UnknownEventType("scroll".to_string()));
+    assert_eq!(rejection.reason, RejectReason::UnknownEventType);
+    assert!(rejection.field.is_empty());
+  }
+
+  #[test]
+  fn formats_records_as_ndjson() {
+    let event = Event {
+      raw_json: "{\"type\":\"click\"}".to_string(),
+      sequence: 7,
+    };
+    let record = Rejection::from(ValidationError::MissingField("user".to_string())).into_record(&event);
+    assert_eq!(
+      format_record(&record),
+      "{\"sequence\":7,\"reason\":\"MISSING_FIELD\",\"field\":\"user\",\"detail\":\"missing required field 'user'\",\"rawJson\":\"{\\\"type\\\":\\\"click\\\"}\"}\n"
+    );
+  }
+
+  #[tokio::test]
+  async fn streams_records_only_when_enabled() {
+    let record = RejectedEvent::default();
+    let disabled = DeadLetterSink::default();
+    assert!(disabled.dispatch(record.clone()).await.is_none());
+
+    let streaming = DeadLetterSink {
+      file: None,
+      stream: true,
+    };
+    assert_eq!(streaming.dispatch(record.clone()).await, Some(record));
+  }
+}
@@ -0,0 +1,12 @@
+//! Parse stage of the Rust event pipeline: payload decoding, schema
+//! validation, work-item processing, dead-letter handling, stream
+//! checkpoints and the client end of its batch stream. The gRPC service in
+//! `main.rs` and the benchmarks in `benches/` build on it.
+
+pub mod checkpoint;
+pub mod dead_letter;
+pub mod link;
+pub mod parser;
+pub mod record;
+pub mod schema;
+pub mod worker_pool;
@@ -0,0 +1,136 @@
+//! Client end of a `ParseEventsBatch` stream over a `StageLink`, so a stage
+//! feeding the parse service makes the same calls whether `connect_stage`
+//! found it on this host (shared memory) or not (gRPC).
+
+use pipeline_common_rust::fast_path::StageLink;
+use pipeline_common_rust::service::name_source;
+use pipeline_common_rust::proto::pipeline::v1::parse_service_client::ParseServiceClient;
+use pipeline_common_rust::proto::pipeline::v1::{
+  ParseEventsBatchRequest, ParseEventsBatchResponse,
+};
+use tokio::sync::mpsc;
+use tokio_stream::wrappers::ReceiverStream;
+use tonic::{Request, Status, Streaming};
+use transport_rust::{ShmError, ShmReceiver, ShmSender, ShmSession};
+
+/// Batches sent ahead of the responses before `send` waits, over gRPC.
+const GRPC_BUFFER: usize = 1;
+
+/// One open `ParseEventsBatch` stream. `split` separates the halves so
+/// batches can be sent while responses are read.
+pub struct ParseLink {
+  sender: ParseSender,
+  receiver: ParseReceiver,
+}
+
+/// Sending half of a `ParseLink`.
+pub enum ParseSender {
+  Shm(ShmSender),
+  /// `None` once the request stream is finished.
+  Grpc(Option<mpsc::Sender<ParseEventsBatchRequest>>),
+}
+
+/// Receiving half of a `ParseLink`.
+pub enum ParseReceiver {
+  Shm(ShmReceiver),
+  Grpc(Box<Streaming<ParseEventsBatchResponse>>),
+}
+
+impl ParseLink {
+  /// Opens the stream on `link` as `source_service`, the name the parse
+  /// service reports the stream's activity from. Shared memory cannot carry it.
+  pub async fn open(link: StageLink, source_service: &str) -> Result<Self, Status> {
+    match link {
+      StageLink::Shm(session) => Ok(Self::from(session)),
+      StageLink::Grpc(channel) => {
+        let (requests, rx) = mpsc::channel(GRPC_BUFFER);
+        let mut request = Request::new(ReceiverStream::new(rx));
+        name_source(&mut request, source_service);
+        let responses = ParseServiceClient::new(channel)
+          .parse_events_batch(request)
+          .await?
+          .into_inner();
+        Ok(ParseLink {
+          sender: ParseSender::Grpc(Some(requests)),
+          receiver: ParseReceiver::Grpc(Box::new(responses)),
+        })
+      }
+    }
+  }
+
+  /// Separates the halves, e.g. to read responses on another task.
+  pub fn split(self) -> (ParseSender, ParseReceiver) {
+    (self.sender, self.receiver)
+  }
+
+  /// Sends a batch, waiting while the parse service is behind.
+  pub async fn send(&mut self, batch: ParseEventsBatchRequest) -> Result<(), Status> {
+    self.sender.send(batch).await
+  }
+
+  /// Ends the request stream; responses to the batches sent so far still
+  /// arrive.
+  pub async fn finish(&mut self) -> Result<(), Status> {
+    self.sender.finish().await
+  }
+
+  /// Next response, or `None` once the parse service ended the stream.
+  pub async fn recv(&mut self) -> Result<Option<ParseEventsBatchResponse>, Status> {
+    self.receiver.recv().await
+  }
+}
+
+impl ParseSender {
+  /// Sends a batch, waiting while the parse service is behind.
+  pub async fn send(&mut self, batch: ParseEventsBatchRequest) -> Result<(), Status> {
+    match self {
+      ParseSender::Shm(sender) => sender.send(&batch).await.map_err(shm_status),
+      ParseSender::Grpc(requests) => {
+        let requests = requests
+          .as_ref()
+          .ok_or_else(|| Status::failed_precondition("stream already finished"))?;
+        requests
+          .send(batch)
+          .await
+          .map_err(|_| Status::unavailable("parse service closed the stream"))
+      }
+    }
+  }
+
+  /// Ends the request stream.
+  pub async fn finish(&mut self) -> Result<(), Status> {
+    match self {
+      ParseSender::Shm(sender) => sender.finish().await.map_err(shm_status),
+      ParseSender::Grpc(requests) => {
+        requests.take();
+        Ok(())
+      }
+    }
+  }
+}
+
+impl ParseReceiver {
+  /// Next response, or `None` once the parse service ended the stream.
+  pub async fn recv(&mut self) -> Result<Option<ParseEventsBatchResponse>, Status> {
+    match self {
+      ParseReceiver::Shm(receiver) => receiver.recv().await.map_err(shm_status),
+      ParseReceiver::Grpc(responses) => responses.message().await,
+    }
+  }
+}
+
+impl From<ShmSession> for ParseLink {
+  fn from(session: ShmSession) -> Self {
+    ParseLink {
+      sender: ParseSender::Shm(session.sender),
+      receiver: ParseReceiver::Shm(session.receiver),
+    }
+  }
+}
+
+fn shm_status(error: ShmError) -> Status {
+  match error {
+    ShmError::FrameTooLarge { .. } => Status::resource_exhausted(error.to_string()),
+    _ => Status::unavailable(error.to_string()),
+  }
+}
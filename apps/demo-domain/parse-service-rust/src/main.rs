@@ -1,84 +1,102 @@
-mod proto;
-mod workitem;
-
-use chrono::DateTime;
-use chrono::FixedOffset;
-use proto::pipeline::v1::{
+use parse_service_rust::checkpoint::{CheckpointStore, StreamResume};
+use parse_service_rust::dead_letter::{DeadLetterConfig, DeadLetterSink, Rejection};
+use parse_service_rust::parser::{parse_event, WorkItemFormat};
+use parse_service_rust::schema::SchemaRegistry;
+use parse_service_rust::worker_pool::{self, WorkerPool};
+use pipeline_common_rust::flow::{flow_channel, DEFAULT_CHANNEL_CAPACITY};
+use pipeline_common_rust::metrics::ServiceMetrics;
+use pipeline_common_rust::proto::pipeline::v1::{
   Event,
   ParseEventsBatchRequest,
   ParseEventsBatchResponse,
   ParsedEvent,
   ParseEventsRequest,
   ParseEventsResponse,
+  RejectedEvent,
+};
+use pipeline_common_rust::proto::pipeline::v1::parse_service_server::{
+  ParseService,
+  ParseServiceServer,
+};
+use pipeline_common_rust::sequence::SequenceTracker;
+use pipeline_common_rust::registration::StreamActivity;
+use pipeline_common_rust::service::{
+  batch_span, project, run_service_with_shm, spawn_stream, stream_activity, ActivitySender,
+  ConfigSource, Reload, ServiceDescriptor, ServiceOptions, ShmHandler, Validate, ENV_ALIASES,
+  UNKNOWN_SOURCE,
 };
-use proto::pipeline::v1::parse_service_server::{ParseService, ParseServiceServer};
-use serde_json::Value;
+use serde::{Deserialize, Serialize};
 use std::error::Error;
-use std::net::SocketAddr;
+use std::path::{Path, PathBuf};
 use std::sync::Arc;
-use std::sync::Mutex;
 use std::time::Instant;
-use tokio::sync::mpsc;
+use tokio::sync::{mpsc, watch};
 use tokio_stream::wrappers::ReceiverStream;
-use tonic::transport::Server;
+use tokio_stream::{Stream, StreamExt};
+use tonic::metadata::MetadataMap;
 use tonic::{Request, Response, Status};
-use workitem::{WorkItem, process_work_item};
+use tracing::{info, warn, Instrument};
+use transport_rust::{ShmSession, Transport};
 
-const DEFAULT_HOST: &str = "127.0.0.1";
 const DEFAULT_PORT: u16 = 6002;
 
-#[derive(Clone, Debug)]
-struct ParseConfig {
-  host: String,
-  port: u16,
-}
+/// Metrics and activity name of `ParseEventsBatch` over shared memory.
+const SHM_RPC: &str = "ParseService/ParseEventsBatch (shm)";
 
-#[derive(Default)]
-struct ParseServiceImpl;
+const DESCRIPTOR: ServiceDescriptor = ServiceDescriptor {
+  program_name: "parse-service-rust",
+  interface_name: "pipeline.v1.ParseService",
+  display_name: "Parse service",
+  version: env!("CARGO_PKG_VERSION"),
+};
 
-#[derive(Default, Clone)]
-struct ServiceMetrics {
-  events_processed: Arc<Mutex<u64>>,
-  processing_time_ms: Arc<Mutex<f64>>,
-  ipc_send_time_ms: Arc<Mutex<f64>>,
-  ipc_recv_time_ms: Arc<Mutex<f64>>,
+#[derive(Clone, Serialize, Deserialize)]
+struct ParseConfig {
+  #[serde(flatten)]
+  service: ServiceOptions,
+  schema_file: Option<PathBuf>,
+  dead_letter: DeadLetterConfig,
+  work_item_format: WorkItemFormat,
+  workers: usize,
+  channel_capacity: usize,
+  checkpoint_file: Option<PathBuf>,
 }
 
-impl ServiceMetrics {
-  fn record_recv(&self, duration_ms: f64) {
-    *self.ipc_recv_time_ms.lock().unwrap() += duration_ms;
+/// Besides the shared options, the schema file is reloaded: pointing
+/// `schema_file` elsewhere or editing the file swaps the registry for new
+/// messages.
+impl Reload for ParseConfig {
+  fn reload_from(&mut self, next: &Self) {
+    self.service.reload_from(&next.service);
+    self.schema_file = next.schema_file.clone();
   }
 
-  fn record_processing(&self, duration_ms: f64) {
-    self.record_processing_count(duration_ms, 1);
-  }
-
-  fn record_processing_count(&self, duration_ms: f64, count: u64) {
-    *self.processing_time_ms.lock().unwrap() += duration_ms;
-    *self.events_processed.lock().unwrap() += count;
+  fn watched_files(&self) -> Vec<PathBuf> {
+    self.schema_file.iter().cloned().collect()
   }
+}
 
-  fn record_send(&self, duration_ms: f64) {
-    *self.ipc_send_time_ms.lock().unwrap() += duration_ms;
+impl Validate for ParseConfig {
+  fn validate(&self, errors: &mut Vec<String>) {
+    self.service.validate(errors);
+    if self.workers == 0 {
+      errors.push("workers must be at least 1".to_string());
+    }
+    if self.channel_capacity == 0 {
+      errors.push("channel_capacity must be at least 1".to_string());
+    }
   }
+}
 
-  fn print_summary(&self, service_name: &str) {
-    let events = *self.events_processed.lock().unwrap();
-    let processing = *self.processing_time_ms.lock().unwrap();
-    let send = *self.ipc_send_time_ms.lock().unwrap();
-    let recv = *self.ipc_recv_time_ms.lock().unwrap();
-    let total = processing + send + recv;
-
-    println!("\n=== {} Metrics ===", service_name);
-    println!("Events processed: {}", events);
-    println!("Processing time: {:.2}ms ({:.1}%)", processing, (processing / total) * 100.0);
-    println!("IPC Send time: {:.2}ms ({:.1}%)", send, (send / total) * 100.0);
-    println!("IPC Recv time: {:.2}ms ({:.1}%)", recv, (recv / total) * 100.0);
-    println!("Avg per event:");
-    println!("  Processing: {:.4}ms", processing / events as f64);
-    println!("  IPC Send: {:.4}ms", send / events as f64);
-    println!("  IPC Recv: {:.4}ms", recv / events as f64);
-  }
+struct ParseServiceImpl {
+  activity: ActivitySender,
+  schemas: watch::Receiver<Arc<SchemaRegistry>>,
+  dead_letter: DeadLetterSink,
+  work_item_format: WorkItemFormat,
+  workers: WorkerPool,
+  channel_capacity: usize,
+  sequence_window: usize,
+  checkpoints: CheckpointStore,
 }
 
 #[tonic::async_trait]
@@ -90,11 +108,25 @@ impl ParseService for ParseServiceImpl {
     &self,
     request: Request<tonic::Streaming<ParseEventsRequest>>,
   ) -> Result<Response<Self::ParseEventsStream>, Status> {
+    let resume = self.checkpoints.resume(request.metadata())?;
+    let mut activity =
+      stream_activity(&self.activity, &DESCRIPTOR, &request, "ParseService/ParseEvents");
     let mut input = request.into_inner();
-    let (tx, rx) = mpsc::channel(128);
-    let metrics = ServiceMetrics::default();
+    let metrics = ServiceMetrics::new("ParseService/ParseEvents");
+    let (tx, rx) = flow_channel(self.channel_capacity, &metrics);
+    let mut sequences = SequenceTracker::new(self.sequence_window, &metrics);
+    if let Some(after) = resume.after {
+      sequences.settle_through(after);
+    }
+    let schemas = self.schemas.clone();
+    let dead_letter = self.dead_letter.clone();
+    let work_item_format = self.work_item_format;
+    let workers = self.workers.clone();
 
-    tokio::spawn(async move {
+    let mut response = Response::new(ReceiverStream::new(rx));
+    resume.annotate(response.metadata_mut());
+
+    spawn_stream(async move {
       loop {
         let recv_start = Instant::now();
         match input.message().await {
@@ -102,30 +134,79 @@ impl ParseService for ParseServiceImpl {
             metrics.record_recv(recv_start.elapsed().as_secs_f64() * 1000.0);
 
             if let Some(event) = message.event {
+              if !sequences.accept(event.sequence) {
+                continue;
+              }
+              // Work items are CPU-bound, so they are parsed on the worker pool
+              // instead of the runtime thread serving the stream.
               let process_start = Instant::now();
-              let parsed = parse_event(&event);
-              metrics.record_processing(process_start.elapsed().as_secs_f64() * 1000.0);
-
-              if let Some(parsed) = parsed {
-                let send_start = Instant::now();
-                let send_result = tx
-                  .send(Ok(ParseEventsResponse {
-                    event: Some(parsed),
-                  }))
-                  .await;
-                metrics.record_send(send_start.elapsed().as_secs_f64() * 1000.0);
-
-                if send_result.is_err() {
+              let job_schemas = schemas.borrow().clone();
+              let outcome = workers
+                .run(event, move |event| parse_event(event, &job_schemas, work_item_format))
+                .await;
+              let (event, parsed) = match outcome {
+                Ok(outcome) => outcome,
+                Err(error) => {
+                  warn!(%error, "Stream failed");
+                  activity.record_error(error.to_string());
+                  let _ = tx.send(Err(Status::internal(error.to_string()))).await;
                   break;
                 }
+              };
+              let processing_ms = process_start.elapsed().as_secs_f64() * 1000.0;
+              metrics.record_processing(processing_ms);
+              activity.record(1, processing_ms);
+
+              let response = match parsed {
+                Ok(parsed) => Some(ParseEventsResponse {
+                  event: Some(parsed),
+                  rejected: None,
+                  committed_sequence: None,
+                }),
+                Err(rejection) => reject(&event, rejection, &metrics, &dead_letter)
+                  .await
+                  .map(|rejected| ParseEventsResponse {
+                    event: None,
+                    rejected: Some(rejected),
+                    committed_sequence: None,
+                  }),
+              };
+
+              if let Some(mut response) = response {
+                let committed = sequences.ack();
+                response.committed_sequence = committed;
+                match tx.send(Ok(response)).await {
+                  Some(stall) => {
+                    resume.save(committed);
+                    activity.record_stall(stall);
+                  }
+                  None => break,
+                }
               }
             }
           }
           Ok(None) => {
-            metrics.print_summary("parse-service");
+            // Acknowledges trailing events that produced no response.
+            sequences.finish();
+            let mut delivered = true;
+            if sequences.has_unacked() {
+              let ack = ParseEventsResponse {
+                event: None,
+                rejected: None,
+                committed_sequence: sequences.ack(),
+              };
+              delivered = tx.send(Ok(ack)).await.is_some();
+            }
+            // The stream ran to its end; a rerun under its id starts over.
+            if delivered {
+              resume.complete();
+            }
+            metrics.log_summary("parse-service");
             break;
           }
           Err(error) => {
+            warn!(%error, "Stream failed");
+            activity.record_error(error.to_string());
             let _ = tx.send(Err(Status::internal(error.to_string()))).await;
             break;
           }
@@ -133,56 +214,143 @@ impl ParseService for ParseServiceImpl {
       }
     });
 
-    Ok(Response::new(ReceiverStream::new(rx)))
+    Ok(response)
   }
 
   async fn parse_events_batch(
     &self,
     request: Request<tonic::Streaming<ParseEventsBatchRequest>>,
   ) -> Result<Response<Self::ParseEventsBatchStream>, Status> {
-    let mut input = request.into_inner();
-    let (tx, rx) = mpsc::channel(128);
-    let metrics = ServiceMetrics::default();
+    let resume = self.checkpoints.resume(request.metadata())?;
+    let activity =
+      stream_activity(&self.activity, &DESCRIPTOR, &request, "ParseService/ParseEventsBatch");
+    let mut metadata = MetadataMap::new();
+    resume.annotate(&mut metadata);
+    let metrics = ServiceMetrics::new("ParseService/ParseEventsBatch");
+    let rx = self.parse_batches(request.into_inner(), resume, activity, metrics);
+
+    let mut response = Response::new(ReceiverStream::new(rx));
+    *response.metadata_mut() = metadata;
+    Ok(response)
+  }
+}
 
-    tokio::spawn(async move {
+impl ParseServiceImpl {
+  /// Parses the batches of one `ParseEventsBatch` stream, whether it came
+  /// in over gRPC or shared memory, and returns the queue of its responses.
+  fn parse_batches<S>(
+    &self,
+    mut input: S,
+    resume: StreamResume,
+    mut activity: StreamActivity,
+    metrics: ServiceMetrics,
+  ) -> mpsc::Receiver<Result<ParseEventsBatchResponse, Status>>
+  where
+    S: Stream<Item = Result<ParseEventsBatchRequest, Status>> + Send + Unpin + 'static,
+  {
+    let (tx, rx) = flow_channel(self.channel_capacity, &metrics);
+    let mut sequences = SequenceTracker::new(self.sequence_window, &metrics);
+    if let Some(after) = resume.after {
+      sequences.settle_through(after);
+    }
+    let schemas = self.schemas.clone();
+    let dead_letter = self.dead_letter.clone();
+    let work_item_format = self.work_item_format;
+    let workers = self.workers.clone();
+
+    spawn_stream(async move {
       loop {
         let recv_start = Instant::now();
-        match input.message().await {
-          Ok(Some(message)) => {
+        match input.next().await {
+          Some(Ok(mut message)) => {
             metrics.record_recv(recv_start.elapsed().as_secs_f64() * 1000.0);
 
+            message.events.retain(|event| sequences.accept(event.sequence));
             if message.events.is_empty() {
               continue;
             }
 
+            // Work items are CPU-bound, so the batch is parsed on the worker
+            // pool; results come back in input (sequence) order.
             let process_start = Instant::now();
-            let parsed: Vec<ParsedEvent> = message
-              .events
-              .iter()
-              .filter_map(parse_event)
-              .collect();
-            metrics.record_processing_count(
-              process_start.elapsed().as_secs_f64() * 1000.0,
-              message.events.len() as u64,
-            );
-
-            if !parsed.is_empty() {
-              let send_start = Instant::now();
-              let send_result = tx
-                .send(Ok(ParseEventsBatchResponse { events: parsed }))
+            let count = message.events.len();
+            let batch = batch_span(count);
+            let job_schemas = schemas.borrow().clone();
+            let outcome = workers
+              .map(message.events, move |event| {
+                parse_event(event, &job_schemas, work_item_format)
+              })
+              .instrument(batch.clone())
+              .await;
+            let (events, results) = match outcome {
+              Ok(outcome) => outcome,
+              Err(error) => {
+                warn!(%error, "Stream failed");
+                activity.record_error(error.to_string());
+                let _ = tx.send(Err(Status::internal(error.to_string()))).await;
+                break;
+              }
+            };
+            let mut parsed: Vec<ParsedEvent> = Vec::with_capacity(count);
+            let mut rejections = Vec::new();
+            for (event, result) in events.iter().zip(results) {
+              match result {
+                Ok(event) => parsed.push(event),
+                Err(rejection) => rejections.push((event, rejection)),
+              }
+            }
+            let processing_ms = process_start.elapsed().as_secs_f64() * 1000.0;
+            metrics.record_processing_count(processing_ms, count as u64);
+            activity.record(count, processing_ms);
+
+            let mut rejected = Vec::new();
+            for (event, rejection) in rejections {
+              let record = reject(event, rejection, &metrics, &dead_letter)
+                .instrument(batch.clone())
                 .await;
-              metrics.record_send(send_start.elapsed().as_secs_f64() * 1000.0);
+              if let Some(record) = record {
+                rejected.push(record);
+              }
+            }
 
-              if send_result.is_err() {
-                break;
+            // Batches without output still acknowledge their sequences.
+            if !parsed.is_empty() || !rejected.is_empty() || sequences.has_unacked() {
+              let committed = sequences.ack();
+              let response = ParseEventsBatchResponse {
+                events: parsed,
+                rejected,
+                committed_sequence: committed,
+              };
+              match tx.send(Ok(response)).await {
+                Some(stall) => {
+                  resume.save(committed);
+                  activity.record_stall(stall);
+                }
+                None => break,
               }
             }
           }
-          Ok(None) => {
-            metrics.print_summary("parse-service");
+          None => {
+            sequences.finish();
+            let mut delivered = true;
+            if sequences.has_unacked() {
+              let ack = ParseEventsBatchResponse {
+                events: Vec::new(),
+                rejected: Vec::new(),
+                committed_sequence: sequences.ack(),
+              };
+              delivered = tx.send(Ok(ack)).await.is_some();
+            }
+            // The stream ran to its end; a rerun under its id starts over.
+            if delivered {
+              resume.complete();
+            }
+            metrics.log_summary("parse-service");
             break;
           }
-          Err(error) => {
+          Some(Err(error)) => {
+            warn!(%error, "Stream failed");
+            activity.record_error(error.to_string());
             let _ = tx.send(Err(Status::internal(error.to_string()))).await;
             break;
           }
@@ -190,83 +358,150 @@ impl ParseService for ParseServiceImpl {
       }
     });
 
-    Ok(Response::new(ReceiverStream::new(rx)))
+    rx
   }
-}
 
-fn parse_event(event: &Event) -> Option<ParsedEvent> {
-  if event.raw_json.trim().is_empty() {
-    return None;
-  }
+  /// Serves one shared-memory session as a `ParseEventsBatch` stream.
+  /// Sessions carry no metadata, so they always start fresh. A failed stream
+  /// ends the session without an end marker, which the client sees as the
+  /// session closing.
+  fn serve_shm(&self, session: ShmSession) {
+    let ShmSession {
+      mut sender,
+      mut receiver,
+    } = session;
+    let (requests, input) = mpsc::channel(1);
+    spawn_stream(async move {
+      loop {
+        let request = match receiver.recv::<ParseEventsBatchRequest>().await {
+          Ok(Some(batch)) => Ok(batch),
+          Ok(None) => break,
+          Err(error) => Err(Status::unavailable(error.to_string())),
+        };
+        let failed = request.is_err();
+        if requests.send(request).await.is_err() || failed {
+          break;
+        }
+      }
+    });
 
-  // Try to parse as WorkItem first (single parse attempt)
-  if let Ok(work_item) = serde_json::from_str::<WorkItem>(&event.raw_json) {
-    let processed = process_work_item(&work_item);
-    let processed_json = serde_json::to_string(&processed).ok()?;
-    return Some(ParsedEvent {
-      r#type: String::from("work-item"),
-      user: processed_json,
-      value: 0,
-      timestamp: chrono::Utc::now().timestamp_millis(),
-      sequence: event.sequence,
+    // The shared-memory handshake does not name the caller
+    let activity =
+      StreamActivity::new(self.activity.clone(), DESCRIPTOR.program_name.to_string(), SHM_RPC)
+        .with_source(UNKNOWN_SOURCE.to_string());
+    let metrics = ServiceMetrics::with_transport(SHM_RPC, Transport::Shm);
+    let mut responses =
+      self.parse_batches(ReceiverStream::new(input), self.checkpoints.fresh(), activity, metrics);
+    spawn_stream(async move {
+      while let Some(response) = responses.recv().await {
+        let outcome = match response {
+          Ok(response) => sender.send(&response).await,
+          Err(status) => {
+            warn!(%status, "Shared-memory stream failed");
+            return;
+          }
+        };
+        if let Err(error) = outcome {
+          warn!(%error, "Shared-memory stream failed");
+          return;
+        }
+      }
+      if let Err(error) = sender.finish().await {
+        warn!(%error, "Shared-memory stream failed");
+      }
     });
   }
+}
 
-  // Fall back to normal event parsing
-  let parsed: Value = serde_json::from_str(&event.raw_json).ok()?;
-  let object = parsed.as_object()?;
-
-  // Normal event parsing
-  let ts = object.get("ts")?.as_str()?;
-  let event_type = object.get("type")?.as_str()?;
-  let user = object.get("user")?.as_str()?;
-  let value = object.get("value")?;
+/// Counts the rejection and hands its record to the dead-letter sink. Returns
+/// the record when it should be streamed back to the caller.
+async fn reject(
+  event: &Event,
+  rejection: Rejection,
+  metrics: &ServiceMetrics,
+  dead_letter: &DeadLetterSink,
+) -> Option<RejectedEvent> {
+  metrics.record_rejected(rejection.reason.as_str_name());
+  dead_letter.dispatch(rejection.into_record(event)).await
+}
 
-  if !matches!(event_type, "click" | "view" | "purchase") {
-    return None;
+fn load_schemas(schema_file: Option<&Path>) -> Result<SchemaRegistry, Box<dyn Error>> {
+  match schema_file {
+    Some(path) => SchemaRegistry::load(path),
+    None => Ok(SchemaRegistry::builtin()),
   }
+}
 
-  let value_num = if let Some(number) = value.as_i64() {
-    number
-  } else if let Some(number) = value.as_f64() {
-    number.trunc() as i64
-  } else {
-    return None;
-  };
-
-  let timestamp = DateTime::parse_from_rfc3339(ts)
-    .map(|dt: DateTime<FixedOffset>| dt.timestamp_millis())
-    .unwrap_or(0);
-
-  Some(ParsedEvent {
-    r#type: event_type.to_string(),
-    user: user.to_string(),
-    value: value_num,
-    timestamp,
-    sequence: event.sequence,
-  })
+/// Loads the schemas again on every config reload. A file that fails to load
+/// keeps the current registry.
+async fn reload_schemas(
+  mut config: watch::Receiver<ParseConfig>,
+  schemas: watch::Sender<Arc<SchemaRegistry>>,
+) {
+  while config.changed().await.is_ok() {
+    let schema_file = config.borrow_and_update().schema_file.clone();
+    match load_schemas(schema_file.as_deref()) {
+      Ok(registry) => {
+        info!("Schemas reloaded, accepted event types: {}", registry.event_types().join(", "));
+        schemas.send_replace(Arc::new(registry));
+      }
+      Err(error) => warn!(%error, "Schema reload failed, keeping the current schemas"),
+    }
+  }
 }
 
-fn parse_args() -> Result<ParseConfig, Box<dyn Error>> {
-  let mut config = ParseConfig {
-    host: DEFAULT_HOST.to_string(),
-    port: DEFAULT_PORT,
+/// Returns the config and where it came from, so it can be reloaded.
+fn parse_args() -> Result<(ParseConfig, ConfigSource), Box<dyn Error>> {
+  let defaults = ParseConfig {
+    service: ServiceOptions::new(DEFAULT_PORT),
+    schema_file: None,
+    dead_letter: DeadLetterConfig::default(),
+    work_item_format: WorkItemFormat::Typed,
+    workers: worker_pool::default_threads(),
+    channel_capacity: DEFAULT_CHANNEL_CAPACITY,
+    checkpoint_file: None,
   };
+  let args: Vec<String> = std::env::args().skip(1).collect();
+  let source = ConfigSource::from_args(&args)?;
+  let mut config = source.load(&defaults, ENV_ALIASES)?;
+  let mut args = args.into_iter();
 
-  let mut args = std::env::args().skip(1).peekable();
   while let Some(arg) = args.next() {
+    if config.service.parse_arg(&arg, &mut args)? {
+      continue;
+    }
     match arg.as_str() {
-      "--host" => {
-        config.host = args.next().ok_or("Missing value for --host")?;
+      "--schema" => {
+        config.schema_file = Some(args.next().ok_or("Missing value for --schema")?.into());
+      }
+      "--dead-letter-file" => {
+        config.dead_letter.file =
+          Some(args.next().ok_or("Missing value for --dead-letter-file")?.into());
+      }
+      "--dead-letter-stream" => {
+        config.dead_letter.stream = true;
+      }
+      "--work-item-json" => {
+        config.work_item_format = WorkItemFormat::Json;
       }
-      "--port" => {
-        let value = args.next().ok_or("Missing value for --port")?;
-        config.port = value.parse()?;
+      "--workers" => {
+        let value = args.next().ok_or("Missing value for --workers")?;
+        config.workers = value.parse()?;
+      }
+      "--channel-capacity" => {
+        let value = args.next().ok_or("Missing value for --channel-capacity")?;
+        config.channel_capacity = value.parse()?;
+      }
+      "--checkpoint-file" => {
+        config.checkpoint_file =
+          Some(args.next().ok_or("Missing value for --checkpoint-file")?.into());
       }
       "-h" | "--help" => {
         println!(
-          "Usage: parse-service-rust [options]\n\nOptions:\n  --host <host>       Bind host (default: {})\n  --port <port>       Bind port (default: {})\n  -h, --help          Show this help message",
-          DEFAULT_HOST, DEFAULT_PORT
+          "Usage: parse-service-rust [options]\n\nOptions:\n{}  --schema <file>             Event schema file (default: built-in click/view/purchase)\n  --dead-letter-file <file>   Append rejected events to an NDJSON file\n  --dead-letter-stream        Return rejected events in the response stream\n  --work-item-json            Return work items as JSON in `user` instead of `payload`\n  --workers <n>               Worker threads for parsing (default: {})\n  --channel-capacity <n>      Responses queued per stream before sends stall (default: {})\n  --checkpoint-file <file>    Persist commit offsets of named streams for resuming (default: disabled)\n  -h, --help                  Show this help message",
+          ServiceOptions::new(DEFAULT_PORT).usage(),
+          worker_pool::default_threads(),
+          DEFAULT_CHANNEL_CAPACITY
         );
         std::process::exit(0);
       }
@@ -274,26 +509,48 @@ fn parse_args() -> Result<ParseConfig, Box<dyn Error>> {
     }
   }
 
-  Ok(config)
+  Ok((source.finish(config)?, source))
 }
 
 #[tokio::main]
 async fn main() -> Result<(), Box<dyn Error>> {
-  let config = parse_args()?;
-  let addr: SocketAddr = format!("{}:{}", config.host, config.port).parse()?;
-
-  let parse_service = ParseServiceImpl;
-  let server = Server::builder().add_service(ParseServiceServer::new(parse_service));
-
-  println!("Parse service listening on {}", addr);
-
-  server
-    .serve_with_shutdown(addr, async {
-      if tokio::signal::ctrl_c().await.is_ok() {
-        println!("Shutdown signal received");
-      }
-    })
-    .await?;
-
-  Ok(())
+  let (config, source) = parse_args()?;
+  let _tracing = config.service.init_logging(&DESCRIPTOR)?;
+  let schemas = load_schemas(config.schema_file.as_deref())?;
+  info!("Accepted event types: {}", schemas.event_types().join(", "));
+
+  let (schemas_tx, schemas) = watch::channel(Arc::new(schemas));
+  let dead_letter = DeadLetterSink::start(config.dead_letter.clone()).await?;
+  let checkpoints = CheckpointStore::start(config.checkpoint_file.clone()).await?;
+  let workers = WorkerPool::new(config.workers)?;
+  info!("Parsing workers: {}", workers.threads());
+  info!("Response queue capacity per stream: {}", config.channel_capacity);
+  let sequence_window = config.service.sequence_window;
+  let work_item_format = config.work_item_format;
+  let channel_capacity = config.channel_capacity;
+  let config = source.watch(config, || Ok(parse_args()?.0));
+  tokio::spawn(reload_schemas(config.clone(), schemas_tx));
+  let options = project(config, |config| config.service.clone());
+  let store = checkpoints.clone();
+  let served = run_service_with_shm(options, DESCRIPTOR, |mut server, activity| {
+    let service = Arc::new(ParseServiceImpl {
+      activity,
+      schemas,
+      dead_letter,
+      work_item_format,
+      workers,
+      channel_capacity,
+      sequence_window,
+      checkpoints,
+    });
+    let router = server.add_service(ParseServiceServer::from_arc(service.clone()));
+    let shm: ShmHandler = Arc::new(move |session| service.serve_shm(session));
+    (router, Some(shm))
+  })
+  .await;
+  // Streams still running after shutdown may save later offsets; the file
+  // keeps the last ones written here.
+  let closed = store.close().await;
+  served?;
+  closed
 }
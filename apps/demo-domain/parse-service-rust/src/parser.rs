@@ -0,0 +1,131 @@
+use crate::dead_letter::Rejection;
+use crate::record::{decode, Record};
+use crate::schema::SchemaRegistry;
+use pipeline_common_rust::proto::pipeline::v1::parsed_event::Payload;
+use pipeline_common_rust::proto::pipeline::v1::{Event, ParsedEvent, RejectReason};
+use pipeline_common_rust::workitem::{process_work_item, WorkItem, WORK_ITEM_EVENT_TYPE};
+use serde::{Deserialize, Serialize};
+
+/// How processed work items are attached to their `ParsedEvent`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum WorkItemFormat {
+  /// Typed `ProcessedWorkItem` in `payload`.
+  Typed,
+  /// JSON in `user`, for downstream stages that do not read `payload` yet.
+  Json,
+}
+
+/// Turns a raw event into a `ParsedEvent`, or explains why it was rejected.
+pub fn parse_event(
+  event: &Event,
+  schemas: &SchemaRegistry,
+  work_item_format: WorkItemFormat,
+) -> Result<ParsedEvent, Rejection> {
+  if event.raw_json.trim().is_empty() {
+    return Err(Rejection::new(RejectReason::EmptyPayload, "empty payload"));
+  }
+
+  // A single pass tells work items from events by their shape.
+  let fields = match decode(&event.raw_json) {
+    Ok(Record::WorkItem(work_item)) => return parse_work_item(event, &work_item, work_item_format),
+    Ok(Record::Event(fields)) => fields,
+    Ok(Record::NotAnObject) => {
+      return Err(Rejection::new(RejectReason::NotAnObject, "payload is not a JSON object"))
+    }
+    Err(error) => return Err(Rejection::new(RejectReason::InvalidJson, error.to_string())),
+  };
+
+  let mapped = schemas.map_event(&fields).map_err(|error| {
+    let mut rejection = Rejection::from(error);
+    if rejection.reason == RejectReason::UnknownEventType {
+      rejection.field = schemas.type_field().to_string();
+    }
+    rejection
+  })?;
+
+  Ok(ParsedEvent {
+    r#type: mapped.event_type,
+    user: mapped.user,
+    value: mapped.value,
+    timestamp: mapped.timestamp,
+    sequence: event.sequence,
+    payload: None,
+  })
+}
+
+fn parse_work_item(
+  event: &Event,
+  work_item: &WorkItem,
+  work_item_format: WorkItemFormat,
+) -> Result<ParsedEvent, Rejection> {
+  let processed = process_work_item(work_item);
+  let (user, payload) = match work_item_format {
+    WorkItemFormat::Typed => (String::new(), Some(Payload::WorkItem(processed.into()))),
+    WorkItemFormat::Json => {
+      let processed_json = serde_json::to_string(&processed)
+        .map_err(|error| Rejection::new(RejectReason::WorkItemFailed, error.to_string()))?;
+      (processed_json, None)
+    }
+  };
+  Ok(ParsedEvent {
+    r#type: WORK_ITEM_EVENT_TYPE.to_string(),
+    user,
+    value: 0,
+    timestamp: chrono::Utc::now().timestamp_millis(),
+    sequence: event.sequence,
+    payload,
+  })
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  const WORK_ITEM: &str =
+    r#"{"id":"w-1","vectors":[{"values":[3,4]}],"matrix":{"rows":[]},"text":"","iterations":2}"#;
+
+  fn event(raw_json: &str) -> Event {
+    Event {
+      raw_json: raw_json.to_string(),
+      sequence: 3,
+    }
+  }
+
+  #[test]
+  fn maps_events_through_the_schema() {
+    let raw = r#"{"ts":"2024-01-01T00:00:01Z","type":"purchase","user":"u0001","value":42}"#;
+    let parsed = parse_event(&event(raw), &SchemaRegistry::builtin(), WorkItemFormat::Typed).unwrap();
+    assert_eq!(parsed.r#type, "purchase");
+    assert_eq!(parsed.user, "u0001");
+    assert_eq!(parsed.value, 42);
+    assert_eq!(parsed.sequence, 3);
+  }
+
+  #[test]
+  fn attaches_work_items_in_the_requested_format() {
+    let schemas = SchemaRegistry::builtin();
+    let typed = parse_event(&event(WORK_ITEM), &schemas, WorkItemFormat::Typed).unwrap();
+    assert_eq!(typed.r#type, WORK_ITEM_EVENT_TYPE);
+    assert!(typed.user.is_empty());
+    assert!(matches!(typed.payload, Some(Payload::WorkItem(ref item)) if item.id == "w-1"));
+
+    let json = parse_event(&event(WORK_ITEM), &schemas, WorkItemFormat::Json).unwrap();
+    assert!(json.payload.is_none());
+    assert!(json.user.contains(r#""normalized_vectors":[{"values":[0.6,0.8]}]"#));
+  }
+
+  #[test]
+  fn classifies_rejections() {
+    let schemas = SchemaRegistry::builtin();
+    let reason = |raw: &str| {
+      parse_event(&event(raw), &schemas, WorkItemFormat::Typed)
+        .unwrap_err()
+        .reason
+    };
+    assert_eq!(reason("  "), RejectReason::EmptyPayload);
+    assert_eq!(reason("{\"type\":"), RejectReason::InvalidJson);
+    assert_eq!(reason("[1,2]"), RejectReason::NotAnObject);
+    assert_eq!(reason(r#"{"type":"scroll"}"#), RejectReason::UnknownEventType);
+  }
+}
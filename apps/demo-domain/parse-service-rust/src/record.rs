@@ -0,0 +1,322 @@
+//! Single-pass decoding of raw payloads.
+//!
+//! A payload is read once and classified by its shape: an object with `id`,
+//! `vectors`, `matrix`, `text` and `iterations` is a work item, any other
+//! object is an event whose top-level fields are kept as borrowed scalars for
+//! schema validation. `vectors` and `matrix` are always decoded as work-item
+//! data, so payloads that use these keys for anything else are invalid.
+
+use pipeline_common_rust::workitem::{Matrix, Vector, WorkItem};
+use serde::de::{self, Deserialize, Deserializer, IgnoredAny, MapAccess, SeqAccess, Visitor};
+use serde_json::Value;
+use std::borrow::Cow;
+use std::fmt;
+
+/// Top-level field value of an event, borrowed from the payload unless it
+/// contains escapes.
+#[derive(Clone, Debug, PartialEq)]
+pub enum FieldValue<'a> {
+  Null,
+  Bool(bool),
+  Integer(i64),
+  Float(f64),
+  String(Cow<'a, str>),
+  /// Arrays and objects; schemas only describe scalar fields.
+  Nested,
+}
+
+impl FieldValue<'_> {
+  /// Converts a value from a schema file (enum entries, defaults).
+  pub fn from_json(value: &Value) -> FieldValue<'static> {
+    match value {
+      Value::Null => FieldValue::Null,
+      Value::Bool(flag) => FieldValue::Bool(*flag),
+      Value::Number(number) => match number.as_i64() {
+        Some(integer) => FieldValue::Integer(integer),
+        None => FieldValue::Float(number.as_f64().unwrap_or(f64::NAN)),
+      },
+      Value::String(text) => FieldValue::String(Cow::Owned(text.clone())),
+      Value::Array(_) | Value::Object(_) => FieldValue::Nested,
+    }
+  }
+
+  pub fn is_null(&self) -> bool {
+    matches!(self, FieldValue::Null)
+  }
+
+  pub fn as_str(&self) -> Option<&str> {
+    match self {
+      FieldValue::String(text) => Some(text),
+      _ => None,
+    }
+  }
+
+  pub fn as_f64(&self) -> Option<f64> {
+    match self {
+      FieldValue::Integer(integer) => Some(*integer as f64),
+      FieldValue::Float(number) => Some(*number),
+      _ => None,
+    }
+  }
+}
+
+/// Top-level fields of an event object in payload order.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct EventFields<'a> {
+  fields: Vec<(Cow<'a, str>, FieldValue<'a>)>,
+}
+
+impl<'a> EventFields<'a> {
+  /// Value of `name`; the last one wins for duplicate keys, as in `serde_json`.
+  pub fn get(&self, name: &str) -> Option<&FieldValue<'a>> {
+    self
+      .fields
+      .iter()
+      .rev()
+      .find(|(key, _)| key == name)
+      .map(|(_, value)| value)
+  }
+}
+
+impl<'a> FromIterator<(Cow<'a, str>, FieldValue<'a>)> for EventFields<'a> {
+  fn from_iter<I: IntoIterator<Item = (Cow<'a, str>, FieldValue<'a>)>>(iter: I) -> Self {
+    Self {
+      fields: iter.into_iter().collect(),
+    }
+  }
+}
+
+/// A decoded payload.
+#[derive(Debug)]
+pub enum Record<'a> {
+  WorkItem(WorkItem),
+  Event(EventFields<'a>),
+  /// Valid JSON that is not an object.
+  NotAnObject,
+}
+
+/// Decodes `raw` in a single pass, borrowing event strings from it.
+pub fn decode(raw: &str) -> Result<Record<'_>, serde_json::Error> {
+  let mut deserializer = serde_json::Deserializer::from_str(raw);
+  let record = Record::deserialize(&mut deserializer)?;
+  deserializer.end()?;
+  Ok(record)
+}
+
+impl<'de> Deserialize<'de> for Record<'de> {
+  fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+    deserializer.deserialize_any(RecordVisitor)
+  }
+}
+
+struct RecordVisitor;
+
+impl<'de> Visitor<'de> for RecordVisitor {
+  type Value = Record<'de>;
+
+  fn expecting(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    f.write_str("a JSON value")
+  }
+
+  fn visit_map<A: MapAccess<'de>>(self, mut map: A) -> Result<Self::Value, A::Error> {
+    let mut fields = Vec::new();
+    let mut vectors = None;
+    let mut matrix = None;
+
+    while let Some(Key(key)) = map.next_key()? {
+      let value = match key.as_ref() {
+        "vectors" => {
+          vectors = Some(map.next_value::<Vec<Vector>>()?);
+          FieldValue::Nested
+        }
+        "matrix" => {
+          matrix = Some(map.next_value::<Matrix>()?);
+          FieldValue::Nested
+        }
+        _ => map.next_value()?,
+      };
+      fields.push((key, value));
+    }
+
+    let fields = EventFields { fields };
+    Ok(match (vectors, matrix) {
+      (Some(vectors), Some(matrix)) => match work_item(&fields, vectors, matrix) {
+        Some(item) => Record::WorkItem(item),
+        None => Record::Event(fields),
+      },
+      _ => Record::Event(fields),
+    })
+  }
+
+  fn visit_seq<A: SeqAccess<'de>>(self, mut seq: A) -> Result<Self::Value, A::Error> {
+    while seq.next_element::<IgnoredAny>()?.is_some() {}
+    Ok(Record::NotAnObject)
+  }
+
+  fn visit_bool<E: de::Error>(self, _: bool) -> Result<Self::Value, E> {
+    Ok(Record::NotAnObject)
+  }
+
+  fn visit_i64<E: de::Error>(self, _: i64) -> Result<Self::Value, E> {
+    Ok(Record::NotAnObject)
+  }
+
+  fn visit_u64<E: de::Error>(self, _: u64) -> Result<Self::Value, E> {
+    Ok(Record::NotAnObject)
+  }
+
+  fn visit_f64<E: de::Error>(self, _: f64) -> Result<Self::Value, E> {
+    Ok(Record::NotAnObject)
+  }
+
+  fn visit_str<E: de::Error>(self, _: &str) -> Result<Self::Value, E> {
+    Ok(Record::NotAnObject)
+  }
+
+  fn visit_unit<E: de::Error>(self) -> Result<Self::Value, E> {
+    Ok(Record::NotAnObject)
+  }
+}
+
+/// Completes a work item from the scalar fields, or `None` when the object
+/// does not have the full work-item shape.
+fn work_item(fields: &EventFields<'_>, vectors: Vec<Vector>, matrix: Matrix) -> Option<WorkItem> {
+  let id = fields.get("id")?.as_str()?;
+  let text = fields.get("text")?.as_str()?;
+  let iterations = match fields.get("iterations")? {
+    FieldValue::Integer(iterations) => i32::try_from(*iterations).ok()?,
+    _ => return None,
+  };
+  Some(WorkItem {
+    id: id.to_string(),
+    vectors,
+    matrix,
+    text: text.to_string(),
+    iterations,
+  })
+}
+
+/// Object key, borrowed unless it contains escapes.
+struct Key<'a>(Cow<'a, str>);
+
+impl<'de> Deserialize<'de> for Key<'de> {
+  fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+    deserializer.deserialize_str(KeyVisitor)
+  }
+}
+
+struct KeyVisitor;
+
+impl<'de> Visitor<'de> for KeyVisitor {
+  type Value = Key<'de>;
+
+  fn expecting(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    f.write_str("an object key")
+  }
+
+  fn visit_borrowed_str<E: de::Error>(self, key: &'de str) -> Result<Self::Value, E> {
+    Ok(Key(Cow::Borrowed(key)))
+  }
+
+  fn visit_str<E: de::Error>(self, key: &str) -> Result<Self::Value, E> {
+    Ok(Key(Cow::Owned(key.to_string())))
+  }
+}
+
+impl<'de> Deserialize<'de> for FieldValue<'de> {
+  fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+    deserializer.deserialize_any(FieldValueVisitor)
+  }
+}
+
+struct FieldValueVisitor;
+
+impl<'de> Visitor<'de> for FieldValueVisitor {
+  type Value = FieldValue<'de>;
+
+  fn expecting(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    f.write_str("a JSON value")
+  }
+
+  fn visit_bool<E: de::Error>(self, flag: bool) -> Result<Self::Value, E> {
+    Ok(FieldValue::Bool(flag))
+  }
+
+  fn visit_i64<E: de::Error>(self, integer: i64) -> Result<Self::Value, E> {
+    Ok(FieldValue::Integer(integer))
+  }
+
+  fn visit_u64<E: de::Error>(self, integer: u64) -> Result<Self::Value, E> {
+    Ok(match i64::try_from(integer) {
+      Ok(integer) => FieldValue::Integer(integer),
+      Err(_) => FieldValue::Float(integer as f64),
+    })
+  }
+
+  fn visit_f64<E: de::Error>(self, number: f64) -> Result<Self::Value, E> {
+    Ok(FieldValue::Float(number))
+  }
+
+  fn visit_borrowed_str<E: de::Error>(self, text: &'de str) -> Result<Self::Value, E> {
+    Ok(FieldValue::String(Cow::Borrowed(text)))
+  }
+
+  fn visit_str<E: de::Error>(self, text: &str) -> Result<Self::Value, E> {
+    Ok(FieldValue::String(Cow::Owned(text.to_string())))
+  }
+
+  fn visit_unit<E: de::Error>(self) -> Result<Self::Value, E> {
+    Ok(FieldValue::Null)
+  }
+
+  fn visit_seq<A: SeqAccess<'de>>(self, mut seq: A) -> Result<Self::Value, A::Error> {
+    while seq.next_element::<IgnoredAny>()?.is_some() {}
+    Ok(FieldValue::Nested)
+  }
+
+  fn visit_map<A: MapAccess<'de>>(self, mut map: A) -> Result<Self::Value, A::Error> {
+    while map.next_entry::<IgnoredAny, IgnoredAny>()?.is_some() {}
+    Ok(FieldValue::Nested)
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn borrows_event_fields_from_the_payload() {
+    let raw = r#"{"ts":"2024-01-01T00:00:00Z","type":"click","user":"u\"1","value":12,"tags":[1,2]}"#;
+    let Record::Event(fields) = decode(raw).unwrap() else {
+      panic!("expected an event");
+    };
+    assert!(matches!(fields.get("type"), Some(FieldValue::String(Cow::Borrowed("click")))));
+    assert_eq!(fields.get("user").and_then(FieldValue::as_str), Some("u\"1"));
+    assert_eq!(fields.get("value"), Some(&FieldValue::Integer(12)));
+    assert_eq!(fields.get("tags"), Some(&FieldValue::Nested));
+    assert_eq!(fields.get("missing"), None);
+  }
+
+  #[test]
+  fn recognizes_work_items_by_shape() {
+    let raw = r#"{"id":"w-1","vectors":[{"values":[3,4]}],"matrix":{"rows":[]},"text":"","iterations":2}"#;
+    let Record::WorkItem(item) = decode(raw).unwrap() else {
+      panic!("expected a work item");
+    };
+    assert_eq!(item.id, "w-1");
+    assert_eq!(item.vectors[0].values, vec![3.0, 4.0]);
+    assert_eq!(item.iterations, 2);
+
+    // Without the full shape the object stays an event.
+    let partial = r#"{"id":"w-1","vectors":[],"matrix":{"rows":[]}}"#;
+    assert!(matches!(decode(partial).unwrap(), Record::Event(_)));
+  }
+
+  #[test]
+  fn separates_non_objects_from_invalid_json() {
+    assert!(matches!(decode("[1, {\"a\": 2}]").unwrap(), Record::NotAnObject));
+    assert!(matches!(decode("\"text\"").unwrap(), Record::NotAnObject));
+    assert!(decode("not json").is_err());
+    assert!(decode("{\"type\":\"click\"} trailing").is_err());
+  }
+}
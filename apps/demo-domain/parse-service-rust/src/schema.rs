@@ -0,0 +1,505 @@
+//! Event schemas loaded from a JSON file (see `schemas/events.json`).
+//!
+//! A schema file names the discriminator field (`typeField`), fields shared by
+//! all event types (`commonFields`) and the accepted `eventTypes`. Each field
+//! has a `type` (string, integer, number, boolean, timestamp), optional
+//! `required`, `enum` and `default` settings, and may be mapped into the
+//! `user`, `value` or `timestamp` field of `ParsedEvent` with `mapTo`.
+
+use crate::record::{EventFields, FieldValue};
+use chrono::DateTime;
+use serde::Deserialize;
+use serde_json::Value;
+use std::collections::HashMap;
+use std::error::Error;
+use std::fmt;
+use std::path::Path;
+
+/// Schema used when no `--schema` file is given; matches the demo event generator.
+const BUILTIN_SCHEMA: &str = include_str!("../schemas/events.json");
+
+#[derive(Clone, Copy, Debug, Deserialize, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum FieldType {
+  String,
+  Integer,
+  Number,
+  Boolean,
+  /// RFC 3339 string or integer epoch milliseconds.
+  Timestamp,
+}
+
+impl fmt::Display for FieldType {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    let name = match self {
+      FieldType::String => "string",
+      FieldType::Integer => "integer",
+      FieldType::Number => "number",
+      FieldType::Boolean => "boolean",
+      FieldType::Timestamp => "timestamp",
+    };
+    f.write_str(name)
+  }
+}
+
+/// `ParsedEvent` field a schema field is mapped into.
+#[derive(Clone, Copy, Debug, Deserialize, PartialEq, Eq, Hash)]
+#[serde(rename_all = "lowercase")]
+pub enum MapTarget {
+  User,
+  Value,
+  Timestamp,
+}
+
+#[derive(Clone, Debug, Deserialize)]
+#[serde(rename_all = "camelCase", deny_unknown_fields)]
+struct FieldSpec {
+  name: String,
+  #[serde(rename = "type")]
+  field_type: FieldType,
+  #[serde(default)]
+  required: bool,
+  #[serde(default, rename = "enum")]
+  allowed: Vec<Value>,
+  #[serde(default)]
+  default: Option<Value>,
+  #[serde(default)]
+  map_to: Option<MapTarget>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase", deny_unknown_fields)]
+struct EventTypeSpec {
+  name: String,
+  #[serde(default)]
+  fields: Vec<FieldSpec>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase", deny_unknown_fields)]
+struct SchemaFile {
+  #[serde(default = "default_type_field")]
+  type_field: String,
+  #[serde(default)]
+  common_fields: Vec<FieldSpec>,
+  event_types: Vec<EventTypeSpec>,
+}
+
+fn default_type_field() -> String {
+  "type".to_string()
+}
+
+/// Why an event did not match its schema.
+#[derive(Clone, Debug, PartialEq)]
+pub enum ValidationError {
+  MissingField(String),
+  InvalidType { field: String, expected: FieldType },
+  NotAllowed(String),
+  UnknownEventType(String),
+}
+
+impl fmt::Display for ValidationError {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    match self {
+      ValidationError::MissingField(field) => write!(f, "missing required field '{}'", field),
+      ValidationError::InvalidType { field, expected } => {
+        write!(f, "field '{}' is not a valid {}", field, expected)
+      }
+      ValidationError::NotAllowed(field) => write!(f, "field '{}' has a value outside its enum", field),
+      ValidationError::UnknownEventType(event_type) => {
+        write!(f, "unknown event type '{}'", event_type)
+      }
+    }
+  }
+}
+
+impl Error for ValidationError {}
+
+/// Event fields extracted by a schema, ready to become a `ParsedEvent`.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct MappedEvent {
+  pub event_type: String,
+  pub user: String,
+  pub value: i64,
+  pub timestamp: i64,
+}
+
+/// A `FieldSpec` with its enum entries and default converted for validation.
+#[derive(Debug)]
+struct Field {
+  name: String,
+  field_type: FieldType,
+  required: bool,
+  allowed: Vec<FieldValue<'static>>,
+  default: Option<FieldValue<'static>>,
+  map_to: Option<MapTarget>,
+}
+
+#[derive(Debug)]
+struct EventSchema {
+  fields: Vec<Field>,
+}
+
+/// Event schemas keyed by event type.
+#[derive(Debug)]
+pub struct SchemaRegistry {
+  type_field: String,
+  schemas: HashMap<String, EventSchema>,
+}
+
+impl SchemaRegistry {
+  /// Registry with the built-in click/view/purchase schema.
+  pub fn builtin() -> Self {
+    Self::from_json(BUILTIN_SCHEMA).expect("built-in event schema is valid")
+  }
+
+  /// Loads and validates a JSON schema file.
+  pub fn load(path: &Path) -> Result<Self, Box<dyn Error>> {
+    let text = std::fs::read_to_string(path)
+      .map_err(|error| format!("Cannot read schema file {}: {}", path.display(), error))?;
+    Self::from_json(&text)
+      .map_err(|error| format!("Invalid schema file {}: {}", path.display(), error).into())
+  }
+
+  pub fn from_json(text: &str) -> Result<Self, Box<dyn Error>> {
+    let file: SchemaFile = serde_json::from_str(text)?;
+    if file.type_field.is_empty() {
+      return Err("typeField must not be empty".into());
+    }
+    if file.event_types.is_empty() {
+      return Err("at least one event type is required".into());
+    }
+
+    let mut schemas = HashMap::new();
+    for event_type in file.event_types {
+      if event_type.name.is_empty() {
+        return Err("event type names must not be empty".into());
+      }
+      // Type-specific fields replace common fields of the same name.
+      let mut specs: Vec<FieldSpec> = file
+        .common_fields
+        .iter()
+        .filter(|common| event_type.fields.iter().all(|field| field.name != common.name))
+        .cloned()
+        .collect();
+      specs.extend(event_type.fields);
+      let fields = compile_fields(specs)
+        .map_err(|error| format!("event type '{}': {}", event_type.name, error))?;
+
+      if schemas
+        .insert(event_type.name.clone(), EventSchema { fields })
+        .is_some()
+      {
+        return Err(format!("duplicate event type '{}'", event_type.name).into());
+      }
+    }
+
+    Ok(Self {
+      type_field: file.type_field,
+      schemas,
+    })
+  }
+
+  pub fn event_types(&self) -> Vec<&str> {
+    let mut names: Vec<&str> = self.schemas.keys().map(String::as_str).collect();
+    names.sort_unstable();
+    names
+  }
+
+  /// Name of the field that selects the event type.
+  pub fn type_field(&self) -> &str {
+    &self.type_field
+  }
+
+  /// Validates the fields of a decoded event against the schema of its event type.
+  pub fn map_event(&self, event: &EventFields<'_>) -> Result<MappedEvent, ValidationError> {
+    let event_type = match event.get(&self.type_field) {
+      None | Some(FieldValue::Null) => {
+        return Err(ValidationError::MissingField(self.type_field.clone()))
+      }
+      Some(FieldValue::String(event_type)) => event_type.as_ref(),
+      Some(_) => {
+        return Err(ValidationError::InvalidType {
+          field: self.type_field.clone(),
+          expected: FieldType::String,
+        })
+      }
+    };
+    let schema = self
+      .schemas
+      .get(event_type)
+      .ok_or_else(|| ValidationError::UnknownEventType(event_type.to_string()))?;
+
+    let mut mapped = MappedEvent {
+      event_type: event_type.to_string(),
+      ..MappedEvent::default()
+    };
+
+    for field in &schema.fields {
+      let value = match event.get(&field.name).filter(|value| !value.is_null()) {
+        Some(value) => value,
+        None => match field.default.as_ref() {
+          Some(default) => default,
+          None if field.required => return Err(ValidationError::MissingField(field.name.clone())),
+          None => continue,
+        },
+      };
+      check_field(field, value)?;
+      if let Some(target) = field.map_to {
+        apply_target(&mut mapped, target, field, value)?;
+      }
+    }
+
+    Ok(mapped)
+  }
+}
+
+/// Checks the field specs of one event type and converts them for validation.
+fn compile_fields(specs: Vec<FieldSpec>) -> Result<Vec<Field>, String> {
+  let mut fields: Vec<Field> = Vec::with_capacity(specs.len());
+  let mut targets = HashMap::new();
+  for spec in specs {
+    if spec.name.is_empty() {
+      return Err("field names must not be empty".to_string());
+    }
+    if fields.iter().any(|other| other.name == spec.name) {
+      return Err(format!("duplicate field '{}'", spec.name));
+    }
+    let mut allowed = Vec::with_capacity(spec.allowed.len());
+    for entry in &spec.allowed {
+      let value = FieldValue::from_json(entry);
+      if !matches_type(spec.field_type, &value) {
+        return Err(format!("enum value {} of '{}' is not a {}", entry, spec.name, spec.field_type));
+      }
+      allowed.push(value);
+    }
+    let field = Field {
+      name: spec.name,
+      field_type: spec.field_type,
+      required: spec.required,
+      allowed,
+      default: spec.default.as_ref().map(FieldValue::from_json),
+      map_to: spec.map_to,
+    };
+    if let Some(default) = field.default.as_ref() {
+      if field.required {
+        return Err(format!("required field '{}' cannot have a default", field.name));
+      }
+      check_field(&field, default).map_err(|error| format!("invalid default: {}", error))?;
+    }
+    if let Some(target) = field.map_to {
+      if !target_accepts(target, field.field_type) {
+        return Err(format!(
+          "field '{}' of type {} cannot be mapped to {:?}",
+          field.name, field.field_type, target
+        ));
+      }
+      if let Some(previous) = targets.insert(target, field.name.clone()) {
+        return Err(format!(
+          "fields '{}' and '{}' are both mapped to {:?}",
+          previous, field.name, target
+        ));
+      }
+    }
+    fields.push(field);
+  }
+  Ok(fields)
+}
+
+fn target_accepts(target: MapTarget, field_type: FieldType) -> bool {
+  match target {
+    MapTarget::User => field_type == FieldType::String,
+    MapTarget::Value => matches!(
+      field_type,
+      FieldType::Integer | FieldType::Number | FieldType::Boolean
+    ),
+    MapTarget::Timestamp => matches!(field_type, FieldType::Timestamp | FieldType::Integer),
+  }
+}
+
+fn check_field(field: &Field, value: &FieldValue<'_>) -> Result<(), ValidationError> {
+  if !matches_type(field.field_type, value) {
+    return Err(ValidationError::InvalidType {
+      field: field.name.clone(),
+      expected: field.field_type,
+    });
+  }
+  if !field.allowed.is_empty() && !field.allowed.iter().any(|allowed| same_value(allowed, value)) {
+    return Err(ValidationError::NotAllowed(field.name.clone()));
+  }
+  Ok(())
+}
+
+fn matches_type(field_type: FieldType, value: &FieldValue<'_>) -> bool {
+  match field_type {
+    FieldType::String => matches!(value, FieldValue::String(_)),
+    FieldType::Integer => as_integer(value).is_some(),
+    FieldType::Number => value.as_f64().is_some(),
+    FieldType::Boolean => matches!(value, FieldValue::Bool(_)),
+    FieldType::Timestamp => timestamp_millis(value).is_some(),
+  }
+}
+
+/// Compares numbers by value so that `1` matches an enum entry of `1.0`.
+fn same_value(allowed: &FieldValue<'_>, value: &FieldValue<'_>) -> bool {
+  match (allowed.as_f64(), value.as_f64()) {
+    (Some(left), Some(right)) => left == right,
+    _ => allowed == value,
+  }
+}
+
+fn as_integer(value: &FieldValue<'_>) -> Option<i64> {
+  match value {
+    FieldValue::Integer(number) => Some(*number),
+    FieldValue::Float(number) if number.fract() == 0.0 && number.abs() < i64::MAX as f64 => {
+      Some(*number as i64)
+    }
+    _ => None,
+  }
+}
+
+fn timestamp_millis(value: &FieldValue<'_>) -> Option<i64> {
+  match value {
+    FieldValue::String(text) => DateTime::parse_from_rfc3339(text)
+      .ok()
+      .map(|dt| dt.timestamp_millis()),
+    _ => as_integer(value),
+  }
+}
+
+fn apply_target(
+  mapped: &mut MappedEvent,
+  target: MapTarget,
+  field: &Field,
+  value: &FieldValue<'_>,
+) -> Result<(), ValidationError> {
+  let invalid = || ValidationError::InvalidType {
+    field: field.name.clone(),
+    expected: field.field_type,
+  };
+  match target {
+    MapTarget::User => mapped.user = value.as_str().ok_or_else(invalid)?.to_string(),
+    MapTarget::Value => {
+      mapped.value = match value {
+        FieldValue::Bool(flag) => i64::from(*flag),
+        _ => as_integer(value)
+          .or_else(|| value.as_f64().map(|number| number.trunc() as i64))
+          .ok_or_else(invalid)?,
+      }
+    }
+    MapTarget::Timestamp => mapped.timestamp = timestamp_millis(value).ok_or_else(invalid)?,
+  }
+  Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use serde_json::json;
+  use std::borrow::Cow;
+
+  fn object(value: Value) -> EventFields<'static> {
+    value
+      .as_object()
+      .unwrap()
+      .iter()
+      .map(|(key, value)| (Cow::Owned(key.clone()), FieldValue::from_json(value)))
+      .collect()
+  }
+
+  #[test]
+  fn builtin_schema_maps_demo_events() {
+    let registry = SchemaRegistry::builtin();
+    assert_eq!(registry.event_types(), vec!["click", "purchase", "view"]);
+
+    let mapped = registry
+      .map_event(&object(json!({
+        "ts": "2024-01-01T00:00:01Z",
+        "type": "purchase",
+        "user": "u0001",
+        "value": 42.9
+      })))
+      .unwrap();
+    assert_eq!(
+      mapped,
+      MappedEvent {
+        event_type: "purchase".to_string(),
+        user: "u0001".to_string(),
+        value: 42,
+        timestamp: 1_704_067_201_000,
+      }
+    );
+  }
+
+  #[test]
+  fn builtin_schema_rejects_unknown_types_and_missing_fields() {
+    let registry = SchemaRegistry::builtin();
+    let unknown = json!({"ts": "2024-01-01T00:00:00Z", "type": "scroll", "user": "u1", "value": 1});
+    assert_eq!(
+      registry.map_event(&object(unknown)),
+      Err(ValidationError::UnknownEventType("scroll".to_string()))
+    );
+    let missing = json!({"ts": "2024-01-01T00:00:00Z", "type": "click", "value": 1});
+    assert_eq!(
+      registry.map_event(&object(missing)),
+      Err(ValidationError::MissingField("user".to_string()))
+    );
+  }
+
+  #[test]
+  fn custom_schema_applies_defaults_and_enums() {
+    let registry = SchemaRegistry::from_json(
+      r#"{
+        "eventTypes": [{
+          "name": "signup",
+          "fields": [
+            { "name": "account", "type": "string", "required": true, "mapTo": "user" },
+            { "name": "plan", "type": "string", "enum": ["free", "pro"], "default": "free" },
+            { "name": "seats", "type": "integer", "default": 1, "mapTo": "value" },
+            { "name": "at", "type": "timestamp", "mapTo": "timestamp" }
+          ]
+        }]
+      }"#,
+    )
+    .unwrap();
+
+    let mapped = registry
+      .map_event(&object(json!({"type": "signup", "account": "a1", "at": 1000})))
+      .unwrap();
+    assert_eq!(mapped.user, "a1");
+    assert_eq!(mapped.value, 1);
+    assert_eq!(mapped.timestamp, 1000);
+
+    let invalid_plan = json!({"type": "signup", "account": "a1", "plan": "gold"});
+    assert_eq!(
+      registry.map_event(&object(invalid_plan)),
+      Err(ValidationError::NotAllowed("plan".to_string()))
+    );
+    let invalid_seats = json!({"type": "signup", "account": "a1", "seats": 1.5});
+    assert_eq!(
+      registry.map_event(&object(invalid_seats)),
+      Err(ValidationError::InvalidType {
+        field: "seats".to_string(),
+        expected: FieldType::Integer,
+      })
+    );
+  }
+
+  #[test]
+  fn rejects_inconsistent_schema_files() {
+    let bad_default = r#"{"eventTypes": [{"name": "a", "fields": [
+      {"name": "x", "type": "integer", "default": "one"}
+    ]}]}"#;
+    assert!(SchemaRegistry::from_json(bad_default).is_err());
+
+    let bad_target = r#"{"eventTypes": [{"name": "a", "fields": [
+      {"name": "x", "type": "boolean", "mapTo": "user"}
+    ]}]}"#;
+    assert!(SchemaRegistry::from_json(bad_target).is_err());
+
+    let unknown_key = r#"{"eventTypes": [{"name": "a", "fields": [
+      {"name": "x", "type": "string", "requried": true}
+    ]}]}"#;
+    assert!(SchemaRegistry::from_json(unknown_key).is_err());
+  }
+}
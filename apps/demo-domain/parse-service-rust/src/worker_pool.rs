@@ -0,0 +1,110 @@
+use rayon::prelude::*;
+use rayon::{ThreadPool, ThreadPoolBuildError, ThreadPoolBuilder};
+use std::sync::Arc;
+use tokio::sync::oneshot;
+use tracing::error;
+
+/// Dedicated rayon pool for CPU-heavy parsing, so work items neither block
+/// the tokio runtime nor stay on a single core.
+#[derive(Clone)]
+pub struct WorkerPool {
+  pool: Arc<ThreadPool>,
+}
+
+/// The pool dropped a job because it panicked.
+#[derive(Debug)]
+pub struct WorkerPanicked;
+
+impl std::fmt::Display for WorkerPanicked {
+  fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+    write!(f, "parse worker panicked")
+  }
+}
+
+impl std::error::Error for WorkerPanicked {}
+
+impl WorkerPool {
+  pub fn new(threads: usize) -> Result<Self, ThreadPoolBuildError> {
+    let pool = ThreadPoolBuilder::new()
+      .num_threads(threads)
+      .thread_name(|index| format!("parse-worker-{}", index))
+      .panic_handler(|_| error!("Parse worker panicked"))
+      .build()?;
+    Ok(Self {
+      pool: Arc::new(pool),
+    })
+  }
+
+  pub fn threads(&self) -> usize {
+    self.pool.current_num_threads()
+  }
+
+  /// Applies `f` to every item in parallel and hands the items back together
+  /// with the results, both in input order.
+  pub async fn map<T, R, F>(&self, items: Vec<T>, f: F) -> Result<(Vec<T>, Vec<R>), WorkerPanicked>
+  where
+    T: Send + Sync + 'static,
+    R: Send + 'static,
+    F: Fn(&T) -> R + Send + Sync + 'static,
+  {
+    let (tx, rx) = oneshot::channel();
+    self.pool.spawn(move || {
+      let results = items.par_iter().map(&f).collect();
+      let _ = tx.send((items, results));
+    });
+    rx.await.map_err(|_| WorkerPanicked)
+  }
+
+  /// Applies `f` to a single item on a worker and hands the item back
+  /// together with the result.
+  pub async fn run<T, R, F>(&self, item: T, f: F) -> Result<(T, R), WorkerPanicked>
+  where
+    T: Send + 'static,
+    R: Send + 'static,
+    F: FnOnce(&T) -> R + Send + 'static,
+  {
+    let (tx, rx) = oneshot::channel();
+    self.pool.spawn(move || {
+      let result = f(&item);
+      let _ = tx.send((item, result));
+    });
+    rx.await.map_err(|_| WorkerPanicked)
+  }
+}
+
+/// Default parallelism: one worker per available core.
+pub fn default_threads() -> usize {
+  std::thread::available_parallelism()
+    .map(|threads| threads.get())
+    .unwrap_or(1)
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[tokio::test]
+  async fn keeps_input_order() {
+    let pool = WorkerPool::new(4).expect("pool");
+    let items: Vec<u64> = (0..1000).collect();
+    let (items, results) = pool.map(items, |value| value * 2).await.expect("map");
+    assert_eq!(items.len(), 1000);
+    assert!(results.iter().enumerate().all(|(index, value)| *value == index as u64 * 2));
+  }
+
+  #[tokio::test]
+  async fn reports_panicking_jobs() {
+    let pool = WorkerPool::new(1).expect("pool");
+    let result = pool.map(vec![1], |_: &i32| -> i32 { panic!("boom") }).await;
+    assert!(result.is_err());
+  }
+
+  #[tokio::test]
+  async fn runs_single_items() {
+    let pool = WorkerPool::new(1).expect("pool");
+    let (item, result) = pool.run(21u64, |value| value * 2).await.expect("run");
+    assert_eq!((item, result), (21, 42));
+    let result = pool.run(1, |_: &i32| -> i32 { panic!("boom") }).await;
+    assert!(result.is_err());
+  }
+}
@@ -0,0 +1,335 @@
+//! Client side of the shared-memory fast path. A stage serving shared memory
+//! registers its control socket with the broker under `shm_interface` of its
+//! gRPC interface; `connect_stage` uses that entry when it names this host
+//! and falls back to the gRPC entry otherwise, or when the session cannot be
+//! set up. `connect_routed_stage` picks one of several instances by weight
+//! instead, e.g. to send a share of the traffic to a canary build.
+
+use crate::proto::broker::v1::{GetAvailableServicesRequest, RegisterServiceRequest};
+use crate::registration::connect_broker;
+use crate::service::LOCAL_HOST;
+use client_resilience_rust::{Route, TrafficRouter, DEFAULT_WEIGHT};
+use std::collections::HashMap;
+use std::error::Error;
+use tonic::transport::Channel;
+use tracing::{info, warn};
+use transport_rust::{connect, parse_shm_url, shm_connect, AuthConfig, ShmSession, TlsConfig};
+
+/// Suffix of the broker interface a shared-memory endpoint registers under.
+pub const SHM_INTERFACE_SUFFIX: &str = "/shm";
+
+/// Broker interface of the shared-memory endpoint of `interface`.
+pub fn shm_interface(interface: &str) -> String {
+  format!("{}{}", interface, SHM_INTERFACE_SUFFIX)
+}
+
+/// Connection to a downstream stage.
+pub enum StageLink {
+  Shm(ShmSession),
+  Grpc(Channel),
+}
+
+/// Endpoints of one stage found in the broker's service list.
+#[derive(Debug, Default, PartialEq, Eq)]
+struct StageEndpoints {
+  /// `shm://` URL, only when it names `host`.
+  shm: Option<String>,
+  /// gRPC URL as registered, and its port.
+  grpc: Option<(String, i32)>,
+}
+
+fn find_endpoints(
+  services: &[RegisterServiceRequest],
+  interface: &str,
+  role: &str,
+  host: &str,
+) -> StageEndpoints {
+  let shm_name = shm_interface(interface);
+  let mut endpoints = StageEndpoints::default();
+  for service in services {
+    let Some(info) = service.info.as_ref() else {
+      continue;
+    };
+    if info.role != role {
+      continue;
+    }
+    if info.interface_name == shm_name {
+      let local = parse_shm_url(&service.url).is_some_and(|(shm_host, _)| shm_host == host);
+      if local && endpoints.shm.is_none() {
+        endpoints.shm = Some(service.url.clone());
+      }
+    } else if info.interface_name == interface && endpoints.grpc.is_none() {
+      endpoints.grpc = Some((service.url.clone(), service.port));
+    }
+  }
+  endpoints
+}
+
+/// Instances of one stage a `TrafficRouter` picks from, and the `shm://`
+/// URL of each instance that serves shared memory on `host`, by instance id.
+fn find_routes(
+  services: &[RegisterServiceRequest],
+  interface: &str,
+  role: &str,
+  host: &str,
+  tls: &TlsConfig,
+) -> (Vec<Route>, HashMap<String, String>) {
+  let shm_name = shm_interface(interface);
+  let mut routes = Vec::new();
+  let mut shm_urls = HashMap::new();
+  for service in services {
+    let Some(info) = service.info.as_ref() else {
+      continue;
+    };
+    if info.role != role {
+      continue;
+    }
+    let metadata = service.metadata.clone().unwrap_or_default();
+    if info.interface_name == shm_name {
+      let local = parse_shm_url(&service.url).is_some_and(|(shm_host, _)| shm_host == host);
+      if local && !metadata.instance_id.is_empty() {
+        shm_urls.insert(metadata.instance_id, service.url.clone());
+      }
+    } else if info.interface_name == interface {
+      let address = tls.service_url(&service.url, service.port);
+      routes.push(Route {
+        instance_id: if metadata.instance_id.is_empty() {
+          address.clone()
+        } else {
+          metadata.instance_id
+        },
+        version: metadata.version,
+        weight: metadata.weight.unwrap_or(DEFAULT_WEIGHT),
+        address,
+      });
+    }
+  }
+  (routes, shm_urls)
+}
+
+async fn available_services(
+  broker_url: &str,
+  tls: &TlsConfig,
+  auth: &AuthConfig,
+) -> Result<Vec<RegisterServiceRequest>, Box<dyn Error + Send + Sync>> {
+  Ok(
+    connect_broker(broker_url, tls, auth)
+      .await?
+      .get_available_services(GetAvailableServicesRequest::default())
+      .await?
+      .into_inner()
+      .services,
+  )
+}
+
+/// Name of this host as it appears in broker entries and `shm://` URLs.
+pub fn local_host() -> String {
+  hostname::get()
+    .ok()
+    .and_then(|h| h.into_string().ok())
+    .unwrap_or_else(|| LOCAL_HOST.to_string())
+}
+
+/// Connects to the stage registered as `interface` and `role` with the
+/// broker at `broker_url`, over shared memory when it runs on this host.
+/// The broker lookup carries the token of `auth`.
+pub async fn connect_stage(
+  broker_url: &str,
+  tls: &TlsConfig,
+  auth: &AuthConfig,
+  interface: &str,
+  role: &str,
+) -> Result<StageLink, Box<dyn Error + Send + Sync>> {
+  let services = available_services(broker_url, tls, auth).await?;
+  let endpoints = find_endpoints(&services, interface, role, &local_host());
+  let grpc = endpoints.grpc.map(|(url, port)| tls.service_url(&url, port));
+  connect_endpoints(endpoints.shm.as_deref(), grpc, tls, interface, role).await
+}
+
+/// Opens a shared-memory session on `shm` when given and a gRPC channel to
+/// `grpc` otherwise, or when the session cannot be set up.
+async fn connect_endpoints(
+  shm: Option<&str>,
+  grpc: Option<String>,
+  tls: &TlsConfig,
+  interface: &str,
+  role: &str,
+) -> Result<StageLink, Box<dyn Error + Send + Sync>> {
+  if let Some(url) = shm {
+    match shm_connect(url).await {
+      Ok(session) => {
+        info!("Connected to {} over shared memory at {}", interface, url);
+        return Ok(StageLink::Shm(session));
+      }
+      Err(error) => warn!(%error, "Shared memory unavailable, falling back to gRPC"),
+    }
+  }
+  let url =
+    grpc.ok_or_else(|| format!("{} ({}) is not registered with the broker", interface, role))?;
+  Ok(StageLink::Grpc(connect(&url, tls).await?))
+}
+
+/// Like `connect_stage`, but connects to the instance `router` picks for
+/// `key` among all instances of the stage, refreshing its routes from the
+/// broker and readmitting rolled-back versions whose cooldown ran out first.
+/// Returns the route taken; the caller reports the outcome of its calls with
+/// `TrafficRouter::record` under the route's address, so a failing canary
+/// version is rolled back.
+pub async fn connect_routed_stage(
+  broker_url: &str,
+  tls: &TlsConfig,
+  auth: &AuthConfig,
+  interface: &str,
+  role: &str,
+  router: &mut TrafficRouter,
+  key: Option<&str>,
+) -> Result<(StageLink, Route), Box<dyn Error + Send + Sync>> {
+  let services = available_services(broker_url, tls, auth).await?;
+  let (routes, shm_urls) = find_routes(&services, interface, role, &local_host(), tls);
+  if routes != router.routes() {
+    router.update(routes);
+  }
+  for event in router.readmit_expired() {
+    info!(?event, "{} version readmitted", interface);
+  }
+  let route = router
+    .pick(key, |_| true)
+    .cloned()
+    .ok_or_else(|| format!("no instance of {} ({}) takes traffic", interface, role))?;
+  info!("Routed to {} {} ({})", interface, route.version, route.instance_id);
+
+  let shm = shm_urls.get(&route.instance_id).map(String::as_str);
+  let link = connect_endpoints(shm, Some(route.address.clone()), tls, interface, role).await?;
+  Ok((link, route))
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::proto::broker::v1::{InstanceMetadata, ServiceInfo};
+  use transport_rust::{shm_url, ShmConfig, ShmListener};
+
+  fn entry(interface: &str, role: &str, url: &str, port: i32) -> RegisterServiceRequest {
+    RegisterServiceRequest {
+      info: Some(ServiceInfo {
+        interface_name: interface.to_string(),
+        role: role.to_string(),
+      }),
+      url: url.to_string(),
+      port,
+      metadata: None,
+    }
+  }
+
+  #[test]
+  fn shared_memory_is_only_offered_on_the_same_host() {
+    let interface = "pipeline.v1.ParseService";
+    let services = vec![
+      entry(interface, "default", "127.0.0.1", 6002),
+      entry("pipeline.v1.ParseService/shm", "canary", "shm://node-1/run/canary.shm", 0),
+      entry("pipeline.v1.ParseService/shm", "default", "shm://node-1/run/parse.shm", 0),
+    ];
+
+    let local = find_endpoints(&services, interface, "default", "node-1");
+    assert_eq!(local.shm.as_deref(), Some("shm://node-1/run/parse.shm"));
+    assert_eq!(local.grpc, Some(("127.0.0.1".to_string(), 6002)));
+
+    let remote = find_endpoints(&services, interface, "default", "node-2");
+    assert_eq!(remote.shm, None);
+    assert_eq!(remote.grpc, Some(("127.0.0.1".to_string(), 6002)));
+
+    let canary = find_endpoints(&services, interface, "canary", "node-1");
+    assert_eq!(canary.shm.as_deref(), Some("shm://node-1/run/canary.shm"));
+    assert_eq!(canary.grpc, None);
+  }
+
+  #[tokio::test]
+  async fn shared_memory_falls_back_to_grpc() {
+    let dir = std::env::temp_dir();
+    let name = |suffix: &str| dir.join(format!("fast-path-{}-{}", std::process::id(), suffix));
+    let grpc_socket = name("grpc.sock");
+    let _grpc = std::os::unix::net::UnixListener::bind(&grpc_socket).unwrap();
+    let grpc = Some(format!("unix://{}", grpc_socket.display()));
+    let interface = "pipeline.v1.ParseService";
+
+    let shm_socket = name("parse.shm");
+    let listener = ShmListener::bind(&ShmConfig {
+      socket: Some(shm_socket.clone()),
+      dir: dir.clone(),
+      ring_bytes: 1 << 16,
+    })
+    .unwrap();
+    let server = tokio::spawn(async move { listener.accept().await.unwrap().complete().await });
+    let shm = shm_url("node-1", &shm_socket);
+    let tls = TlsConfig::default();
+    let link = connect_endpoints(Some(&shm), grpc.clone(), &tls, interface, "default").await;
+    assert!(matches!(link, Ok(StageLink::Shm(_))));
+    server.await.unwrap().unwrap();
+    std::fs::remove_file(&shm_socket).unwrap();
+
+    // The stage went away, or never served shared memory on this socket.
+    let link = connect_endpoints(Some(&shm), grpc, &tls, interface, "default").await;
+    assert!(matches!(link, Ok(StageLink::Grpc(_))));
+    let link = connect_endpoints(Some(&shm), None, &tls, interface, "default").await;
+    assert!(link.is_err());
+    std::fs::remove_file(&grpc_socket).unwrap();
+  }
+
+  #[test]
+  fn routes_pair_instances_with_their_shared_memory() {
+    let interface = "pipeline.v1.ParseService";
+    let metadata = |id: &str, version: &str, weight: Option<u32>| InstanceMetadata {
+      instance_id: id.to_string(),
+      version: version.to_string(),
+      weight,
+      ..InstanceMetadata::default()
+    };
+    let with = |mut entry: RegisterServiceRequest, metadata: InstanceMetadata| {
+      entry.metadata = Some(metadata);
+      entry
+    };
+    let services = vec![
+      with(
+        entry(interface, "default", "10.0.0.1", 6002),
+        metadata("a", "1.4.0", None),
+      ),
+      with(
+        entry(
+          "pipeline.v1.ParseService/shm",
+          "default",
+          "shm://node-1/run/b.shm",
+          0,
+        ),
+        metadata("b", "1.5.0", Some(10)),
+      ),
+      with(entry(interface, "default", "10.0.0.2", 6002), metadata("b", "1.5.0", Some(10))),
+      entry(interface, "default", "10.0.0.3", 6002),
+    ];
+
+    let (routes, shm_urls) =
+      find_routes(&services, interface, "default", "node-1", &TlsConfig::default());
+    let summary: Vec<_> = routes
+      .iter()
+      .map(|route| {
+        (
+          route.instance_id.as_str(),
+          route.version.as_str(),
+          route.weight,
+        )
+      })
+      .collect();
+    assert_eq!(
+      summary,
+      vec![
+        ("a", "1.4.0", DEFAULT_WEIGHT),
+        ("b", "1.5.0", 10),
+        ("http://10.0.0.3:6002", "", DEFAULT_WEIGHT),
+      ]
+    );
+    assert_eq!(shm_urls.len(), 1);
+    assert_eq!(shm_urls["b"], "shm://node-1/run/b.shm");
+
+    let (_, remote) = find_routes(&services, interface, "default", "node-2", &TlsConfig::default());
+    assert!(remote.is_empty());
+  }
+}
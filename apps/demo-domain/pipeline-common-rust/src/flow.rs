@@ -0,0 +1,95 @@
+//! Bounded outbound queues of response streams. A send that finds the queue
+//! full waits for the consumer; that wait is a backpressure stall and is
+//! counted separately from the plain send time, so a slow consumer shows up
+//! as `downstream` rather than as slow processing.
+
+use crate::metrics::{QueueWatch, ServiceMetrics};
+use std::time::{Duration, Instant};
+use tokio::sync::mpsc::{self, error::TrySendError};
+
+/// Outbound queue capacity used when `--channel-capacity` is not given.
+pub const DEFAULT_CHANNEL_CAPACITY: usize = 128;
+
+/// Sending half of a stream's outbound queue.
+pub struct FlowSender<T> {
+  tx: mpsc::Sender<T>,
+  metrics: ServiceMetrics,
+  _watch: QueueWatch,
+}
+
+/// Creates the outbound queue of the stream measured by `metrics`. Its depth
+/// is published as a gauge for as long as the sender lives.
+pub fn flow_channel<T: Send + 'static>(
+  capacity: usize,
+  metrics: &ServiceMetrics,
+) -> (FlowSender<T>, mpsc::Receiver<T>) {
+  let (tx, rx) = mpsc::channel(capacity);
+  let probe = tx.downgrade();
+  let watch = metrics.watch_queue(Box::new(move || {
+    probe
+      .upgrade()
+      .map(|tx| (tx.max_capacity() - tx.capacity(), tx.max_capacity()))
+  }));
+  let sender = FlowSender {
+    tx,
+    metrics: metrics.clone(),
+    _watch: watch,
+  };
+  (sender, rx)
+}
+
+impl<T> FlowSender<T> {
+  /// Queues `item`, waiting while the queue is full. Returns how long the
+  /// send stalled on backpressure, or `None` once the consumer is gone.
+  pub async fn send(&self, item: T) -> Option<Duration> {
+    let start = Instant::now();
+    let (sent, stall) = match self.tx.try_send(item) {
+      Ok(()) => (true, Duration::ZERO),
+      Err(TrySendError::Full(item)) => {
+        let sent = self.tx.send(item).await.is_ok();
+        (sent, start.elapsed())
+      }
+      Err(TrySendError::Closed(_)) => (false, Duration::ZERO),
+    };
+    self.metrics.record_send(start.elapsed().as_secs_f64() * 1000.0);
+    if !sent {
+      return None;
+    }
+    if !stall.is_zero() {
+      self.metrics.record_stall(stall.as_secs_f64() * 1000.0);
+    }
+    self.metrics.record_queue_depth(self.depth());
+    Some(stall)
+  }
+
+  /// Responses currently waiting for the consumer.
+  pub fn depth(&self) -> usize {
+    self.tx.max_capacity() - self.tx.capacity()
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[tokio::test]
+  async fn full_queue_stalls_until_the_consumer_drains_it() {
+    let metrics = ServiceMetrics::new("Test/FlowControl");
+    let (tx, mut rx) = flow_channel(1, &metrics);
+    assert_eq!(tx.send(1).await, Some(Duration::ZERO));
+    assert_eq!(tx.depth(), 1);
+
+    let consumer = tokio::spawn(async move {
+      tokio::time::sleep(Duration::from_millis(20)).await;
+      let first = rx.recv().await;
+      (first, rx)
+    });
+    let stall = tx.send(2).await.expect("consumer is alive");
+    assert!(stall >= Duration::from_millis(15));
+
+    let (first, rx) = consumer.await.unwrap();
+    assert_eq!(first, Some(1));
+    drop(rx);
+    assert_eq!(tx.send(3).await, None);
+  }
+}
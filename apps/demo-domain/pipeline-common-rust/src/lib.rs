@@ -0,0 +1,14 @@
+//! Shared building blocks for the Rust pipeline services: generated protos,
+//! topology registration next to the broker registration of
+//! `service-registration-rust`, service bootstrap, metrics, flow-controlled
+//! response queues, sequence tracking, work items and the shared-memory fast
+//! path between stages on one host.
+
+pub mod fast_path;
+pub mod flow;
+pub mod metrics;
+pub mod proto;
+pub mod registration;
+pub mod sequence;
+pub mod service;
+pub mod workitem;
@@ -0,0 +1,593 @@
+//! Stream metrics: a summary logged when each stream ends, and process-wide
+//! per-RPC counters and latency histograms rendered in the Prometheus text
+//! format by the observability endpoint. Recording only touches atomics.
+//! Outbound queues of live streams are probed for their depth at render time.
+//! Sequence checks count duplicates, reordering and gaps per stream and RPC.
+//! Latency histograms carry the transport the process serves on, so runs
+//! over TCP, TLS and Unix domain sockets can be compared; RPCs served over
+//! shared memory next to gRPC carry their own.
+
+use observability_rust::exposition::{escape_label, write_header};
+use observability_rust::Histogram;
+use std::collections::BTreeMap;
+use std::fmt::{Display, Write};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, OnceLock, RwLock};
+use tracing::info;
+use transport_rust::Transport;
+
+fn millis_to_nanos(duration_ms: f64) -> u64 {
+  (duration_ms.max(0.0) * 1_000_000.0) as u64
+}
+
+fn nanos_to_millis(nanos: u64) -> f64 {
+  nanos as f64 / 1_000_000.0
+}
+
+/// Counters keyed by a static reason. Increments take a shared lock; the
+/// exclusive lock is only needed the first time a reason shows up.
+#[derive(Default)]
+struct ReasonCounters {
+  counts: RwLock<BTreeMap<&'static str, AtomicU64>>,
+}
+
+impl ReasonCounters {
+  fn increment(&self, reason: &'static str) {
+    if let Some(count) = self.counts.read().unwrap().get(reason) {
+      count.fetch_add(1, Ordering::Relaxed);
+      return;
+    }
+    self
+      .counts
+      .write()
+      .unwrap()
+      .entry(reason)
+      .or_default()
+      .fetch_add(1, Ordering::Relaxed);
+  }
+
+  fn snapshot(&self) -> Vec<(&'static str, u64)> {
+    self
+      .counts
+      .read()
+      .unwrap()
+      .iter()
+      .map(|(reason, count)| (*reason, count.load(Ordering::Relaxed)))
+      .collect()
+  }
+}
+
+/// Returns the current depth and capacity of an outbound queue, or `None`
+/// once the queue is closed.
+pub type QueueProbe = Box<dyn Fn() -> Option<(usize, usize)> + Send + Sync>;
+
+/// Process-wide metrics of one streaming RPC, summed over all its streams.
+#[derive(Default)]
+pub struct RpcMetrics {
+  streams: AtomicU64,
+  events: AtomicU64,
+  recv: Histogram,
+  processing: Histogram,
+  send: Histogram,
+  rejected: ReasonCounters,
+  stalls: AtomicU64,
+  stall_nanos: AtomicU64,
+  duplicates: AtomicU64,
+  reordered: AtomicU64,
+  gaps: AtomicU64,
+  missing: AtomicU64,
+  /// Outbound queues of the live streams, keyed by stream id.
+  queues: RwLock<BTreeMap<u64, QueueProbe>>,
+  /// Transport of the RPC when it differs from the process's.
+  transport: OnceLock<Transport>,
+}
+
+/// All `RpcMetrics` of the process, keyed by RPC name.
+#[derive(Default)]
+pub struct MetricsRegistry {
+  rpcs: RwLock<BTreeMap<&'static str, Arc<RpcMetrics>>>,
+  transport: OnceLock<Transport>,
+}
+
+impl MetricsRegistry {
+  /// Returns the metrics of `rpc`, creating them on first use.
+  pub fn rpc(&self, rpc: &'static str) -> Arc<RpcMetrics> {
+    if let Some(metrics) = self.rpcs.read().unwrap().get(rpc) {
+      return metrics.clone();
+    }
+    self.rpcs.write().unwrap().entry(rpc).or_default().clone()
+  }
+
+  /// Records the transport the process serves on; the first call wins.
+  pub fn set_transport(&self, transport: Transport) {
+    let _ = self.transport.set(transport);
+  }
+
+  /// The transport the process serves on, TCP until one is set.
+  pub fn transport(&self) -> Transport {
+    self.transport.get().copied().unwrap_or(Transport::Tcp)
+  }
+
+  /// Appends all RPCs in the Prometheus text exposition format.
+  pub fn render(&self, out: &mut String) {
+    let transport = self.transport();
+    let rpcs: Vec<(&'static str, Arc<RpcMetrics>)> = self
+      .rpcs
+      .read()
+      .unwrap()
+      .iter()
+      .map(|(rpc, metrics)| (*rpc, metrics.clone()))
+      .collect();
+
+    write_counter(out, "pipeline_streams_total", "Streams opened per RPC.", &rpcs, |m| {
+      m.streams.load(Ordering::Relaxed)
+    });
+    write_counter(
+      out,
+      "pipeline_events_processed_total",
+      "Events processed per RPC.",
+      &rpcs,
+      |m| m.events.load(Ordering::Relaxed),
+    );
+
+    let name = "pipeline_rejected_events_total";
+    write_header(out, name, "Events rejected per RPC and reason.", "counter");
+    for (rpc, metrics) in &rpcs {
+      for (reason, count) in metrics.rejected.snapshot() {
+        let _ = writeln!(
+          out,
+          "{}{{rpc=\"{}\",reason=\"{}\"}} {}",
+          name,
+          escape_label(rpc),
+          escape_label(reason),
+          count
+        );
+      }
+    }
+
+    write_histogram(
+      out,
+      "pipeline_recv_duration_seconds",
+      "Time spent waiting for inbound stream messages.",
+      &rpcs,
+      transport,
+      |m| &m.recv,
+    );
+    write_histogram(
+      out,
+      "pipeline_processing_duration_seconds",
+      "Time spent processing one inbound message.",
+      &rpcs,
+      transport,
+      |m| &m.processing,
+    );
+    write_histogram(
+      out,
+      "pipeline_send_duration_seconds",
+      "Time spent handing one response to the outbound stream.",
+      &rpcs,
+      transport,
+      |m| &m.send,
+    );
+
+    write_counter(
+      out,
+      "pipeline_backpressure_stalls_total",
+      "Sends that waited for room in a full outbound queue.",
+      &rpcs,
+      |m| m.stalls.load(Ordering::Relaxed),
+    );
+    write_counter(
+      out,
+      "pipeline_backpressure_stall_seconds_total",
+      "Time spent waiting for room in a full outbound queue.",
+      &rpcs,
+      |m| nanos_to_millis(m.stall_nanos.load(Ordering::Relaxed)) / 1000.0,
+    );
+
+    write_counter(
+      out,
+      "pipeline_sequence_duplicates_total",
+      "Events dropped because their sequence was already delivered.",
+      &rpcs,
+      |m| m.duplicates.load(Ordering::Relaxed),
+    );
+    write_counter(
+      out,
+      "pipeline_sequence_reordered_total",
+      "Events that arrived after a higher sequence.",
+      &rpcs,
+      |m| m.reordered.load(Ordering::Relaxed),
+    );
+    write_counter(
+      out,
+      "pipeline_sequence_gaps_total",
+      "Ranges of sequences given up as missing.",
+      &rpcs,
+      |m| m.gaps.load(Ordering::Relaxed),
+    );
+    write_counter(
+      out,
+      "pipeline_sequence_missing_total",
+      "Sequences given up as missing.",
+      &rpcs,
+      |m| m.missing.load(Ordering::Relaxed),
+    );
+
+    let mut queues = Vec::new();
+    for (rpc, metrics) in &rpcs {
+      for (stream, probe) in metrics.queues.read().unwrap().iter() {
+        if let Some((depth, capacity)) = probe() {
+          let labels = format!("rpc=\"{}\",stream=\"{}\"", escape_label(rpc), stream);
+          queues.push((labels, depth, capacity));
+        }
+      }
+    }
+    let name = "pipeline_stream_queue_depth";
+    write_header(out, name, "Responses waiting in the outbound queue of a live stream.", "gauge");
+    for (labels, depth, _) in &queues {
+      let _ = writeln!(out, "{}{{{}}} {}", name, labels, depth);
+    }
+    let name = "pipeline_stream_queue_capacity";
+    write_header(out, name, "Capacity of the outbound queue of a live stream.", "gauge");
+    for (labels, _, capacity) in &queues {
+      let _ = writeln!(out, "{}{{{}}} {}", name, labels, capacity);
+    }
+  }
+}
+
+type RpcEntries = [(&'static str, Arc<RpcMetrics>)];
+
+fn write_counter<V: Display>(
+  out: &mut String,
+  name: &str,
+  help: &str,
+  rpcs: &RpcEntries,
+  value: fn(&RpcMetrics) -> V,
+) {
+  write_header(out, name, help, "counter");
+  for (rpc, metrics) in rpcs {
+    let _ = writeln!(out, "{}{{rpc=\"{}\"}} {}", name, escape_label(rpc), value(metrics));
+  }
+}
+
+fn write_histogram(
+  out: &mut String,
+  name: &str,
+  help: &str,
+  rpcs: &RpcEntries,
+  transport: Transport,
+  histogram: fn(&RpcMetrics) -> &Histogram,
+) {
+  write_header(out, name, help, "histogram");
+  for (rpc, metrics) in rpcs {
+    let transport = metrics.transport.get().copied().unwrap_or(transport).as_str();
+    let labels = format!("rpc=\"{}\",transport=\"{}\"", escape_label(rpc), transport);
+    histogram(metrics).render(out, name, &labels);
+  }
+}
+
+/// The registry shared by all streams of the process.
+pub fn registry() -> &'static MetricsRegistry {
+  static REGISTRY: OnceLock<MetricsRegistry> = OnceLock::new();
+  REGISTRY.get_or_init(MetricsRegistry::default)
+}
+
+/// Records the transport of the process in the shared registry.
+pub fn set_transport(transport: Transport) {
+  registry().set_transport(transport);
+}
+
+/// Collector for `observability_rust::exposition::register_collector`.
+pub fn collect(out: &mut String) {
+  registry().render(out);
+}
+
+/// Totals of a single stream, used for its closing summary.
+#[derive(Default)]
+struct StreamTotals {
+  events: AtomicU64,
+  processing_nanos: AtomicU64,
+  send_nanos: AtomicU64,
+  recv_nanos: AtomicU64,
+  stalls: AtomicU64,
+  stall_nanos: AtomicU64,
+  max_queue_depth: AtomicU64,
+  duplicates: AtomicU64,
+  reordered: AtomicU64,
+  gaps: AtomicU64,
+  missing: AtomicU64,
+  rejected: ReasonCounters,
+}
+
+/// Metrics of one stream. Every record also feeds the process-wide metrics
+/// of the stream's RPC.
+#[derive(Clone)]
+pub struct ServiceMetrics {
+  id: u64,
+  stream: Arc<StreamTotals>,
+  rpc: Arc<RpcMetrics>,
+}
+
+/// Keeps the outbound queue of a stream in the depth gauges until dropped.
+pub struct QueueWatch {
+  id: u64,
+  rpc: Arc<RpcMetrics>,
+}
+
+impl Drop for QueueWatch {
+  fn drop(&mut self) {
+    self.rpc.queues.write().unwrap().remove(&self.id);
+  }
+}
+
+impl ServiceMetrics {
+  /// Starts the metrics of a new stream of `rpc`, e.g. "ParseService/ParseEvents".
+  pub fn new(rpc: &'static str) -> Self {
+    static NEXT_STREAM_ID: AtomicU64 = AtomicU64::new(1);
+    let rpc = registry().rpc(rpc);
+    rpc.streams.fetch_add(1, Ordering::Relaxed);
+    Self {
+      id: NEXT_STREAM_ID.fetch_add(1, Ordering::Relaxed),
+      stream: Arc::default(),
+      rpc,
+    }
+  }
+
+  /// Like `new`, for an RPC served over `transport` rather than the
+  /// transport of the process. Give it a name of its own, e.g.
+  /// "ParseService/ParseEventsBatch (shm)".
+  pub fn with_transport(rpc: &'static str, transport: Transport) -> Self {
+    let metrics = Self::new(rpc);
+    let _ = metrics.rpc.transport.set(transport);
+    metrics
+  }
+
+  /// Process-unique id of the stream, used as the `stream` label.
+  pub fn stream_id(&self) -> u64 {
+    self.id
+  }
+
+  pub fn record_recv(&self, duration_ms: f64) {
+    self.stream.recv_nanos.fetch_add(millis_to_nanos(duration_ms), Ordering::Relaxed);
+    self.rpc.recv.observe_ms(duration_ms);
+  }
+
+  pub fn record_processing(&self, duration_ms: f64) {
+    self.record_processing_count(duration_ms, 1);
+  }
+
+  pub fn record_processing_count(&self, duration_ms: f64, count: u64) {
+    self.stream.processing_nanos.fetch_add(millis_to_nanos(duration_ms), Ordering::Relaxed);
+    self.stream.events.fetch_add(count, Ordering::Relaxed);
+    self.rpc.processing.observe_ms(duration_ms);
+    self.rpc.events.fetch_add(count, Ordering::Relaxed);
+  }
+
+  pub fn record_send(&self, duration_ms: f64) {
+    self.stream.send_nanos.fetch_add(millis_to_nanos(duration_ms), Ordering::Relaxed);
+    self.rpc.send.observe_ms(duration_ms);
+  }
+
+  /// Counts a send that had to wait `duration_ms` for room in a full outbound queue.
+  pub fn record_stall(&self, duration_ms: f64) {
+    let nanos = millis_to_nanos(duration_ms);
+    self.stream.stalls.fetch_add(1, Ordering::Relaxed);
+    self.stream.stall_nanos.fetch_add(nanos, Ordering::Relaxed);
+    self.rpc.stalls.fetch_add(1, Ordering::Relaxed);
+    self.rpc.stall_nanos.fetch_add(nanos, Ordering::Relaxed);
+  }
+
+  /// Notes the outbound queue depth seen after a send, for the summary's maximum.
+  pub fn record_queue_depth(&self, depth: usize) {
+    self.stream.max_queue_depth.fetch_max(depth as u64, Ordering::Relaxed);
+  }
+
+  /// Publishes the live depth of the stream's outbound queue until the
+  /// returned watch is dropped.
+  pub fn watch_queue(&self, probe: QueueProbe) -> QueueWatch {
+    self.rpc.queues.write().unwrap().insert(self.id, probe);
+    QueueWatch {
+      id: self.id,
+      rpc: self.rpc.clone(),
+    }
+  }
+
+  /// Counts an event dropped as a duplicate of an already delivered sequence.
+  pub fn record_duplicate(&self) {
+    self.stream.duplicates.fetch_add(1, Ordering::Relaxed);
+    self.rpc.duplicates.fetch_add(1, Ordering::Relaxed);
+  }
+
+  /// Counts an event that arrived after a higher sequence.
+  pub fn record_reordered(&self) {
+    self.stream.reordered.fetch_add(1, Ordering::Relaxed);
+    self.rpc.reordered.fetch_add(1, Ordering::Relaxed);
+  }
+
+  /// Counts a gap of `missing` sequences that were given up.
+  pub fn record_gap(&self, missing: u64) {
+    self.stream.gaps.fetch_add(1, Ordering::Relaxed);
+    self.stream.missing.fetch_add(missing, Ordering::Relaxed);
+    self.rpc.gaps.fetch_add(1, Ordering::Relaxed);
+    self.rpc.missing.fetch_add(missing, Ordering::Relaxed);
+  }
+
+  /// Counts an event rejected for `reason`.
+  pub fn record_rejected(&self, reason: &'static str) {
+    self.stream.rejected.increment(reason);
+    self.rpc.rejected.increment(reason);
+  }
+
+  /// Logs the totals of the stream as one `Stream finished` event.
+  pub fn log_summary(&self, service_name: &str) {
+    let summary = self.summary();
+    let rejected: u64 = summary.rejected.iter().map(|(_, count)| count).sum();
+    let rejected_reasons = summary
+      .rejected
+      .iter()
+      .map(|(reason, count)| format!("{}={}", reason, count))
+      .collect::<Vec<_>>()
+      .join(",");
+    info!(
+      service = service_name,
+      stream = self.id,
+      transport = self.rpc.transport.get().copied().unwrap_or(registry().transport()).as_str(),
+      events = summary.events,
+      processing_ms = summary.processing_ms,
+      send_ms = summary.send_ms,
+      recv_ms = summary.recv_ms,
+      avg_processing_ms = summary.per_event(summary.processing_ms),
+      avg_send_ms = summary.per_event(summary.send_ms),
+      avg_recv_ms = summary.per_event(summary.recv_ms),
+      stalls = summary.stalls,
+      stall_ms = summary.stall_ms,
+      max_queue_depth = summary.max_queue_depth,
+      bottleneck = summary.bottleneck(),
+      duplicates = summary.duplicates,
+      reordered = summary.reordered,
+      gaps = summary.gaps,
+      missing = summary.missing,
+      rejected,
+      rejected_reasons,
+      "Stream finished"
+    );
+  }
+
+  pub(crate) fn summary(&self) -> StreamSummary {
+    StreamSummary {
+      events: self.stream.events.load(Ordering::Relaxed),
+      processing_ms: nanos_to_millis(self.stream.processing_nanos.load(Ordering::Relaxed)),
+      send_ms: nanos_to_millis(self.stream.send_nanos.load(Ordering::Relaxed)),
+      recv_ms: nanos_to_millis(self.stream.recv_nanos.load(Ordering::Relaxed)),
+      stalls: self.stream.stalls.load(Ordering::Relaxed),
+      stall_ms: nanos_to_millis(self.stream.stall_nanos.load(Ordering::Relaxed)),
+      max_queue_depth: self.stream.max_queue_depth.load(Ordering::Relaxed),
+      duplicates: self.stream.duplicates.load(Ordering::Relaxed),
+      reordered: self.stream.reordered.load(Ordering::Relaxed),
+      gaps: self.stream.gaps.load(Ordering::Relaxed),
+      missing: self.stream.missing.load(Ordering::Relaxed),
+      rejected: self.stream.rejected.snapshot(),
+    }
+  }
+}
+
+/// Totals of one stream at the time it is summarized.
+pub(crate) struct StreamSummary {
+  events: u64,
+  processing_ms: f64,
+  send_ms: f64,
+  recv_ms: f64,
+  stalls: u64,
+  stall_ms: f64,
+  max_queue_depth: u64,
+  pub(crate) duplicates: u64,
+  pub(crate) reordered: u64,
+  pub(crate) gaps: u64,
+  pub(crate) missing: u64,
+  rejected: Vec<(&'static str, u64)>,
+}
+
+impl StreamSummary {
+  /// Average of `total_ms` per event; zero for a stream without events.
+  fn per_event(&self, total_ms: f64) -> f64 {
+    if self.events > 0 {
+      total_ms / self.events as f64
+    } else {
+      0.0
+    }
+  }
+
+  /// Where the stream spent most of its time: waiting for input
+  /// (`upstream`), processing it (`processing`) or waiting for the consumer
+  /// to drain the outbound queue (`downstream`).
+  fn bottleneck(&self) -> &'static str {
+    let waits = [
+      ("upstream", self.recv_ms),
+      ("processing", self.processing_ms),
+      ("downstream", self.stall_ms),
+    ];
+    waits
+      .into_iter()
+      .fold(("none", 0.0), |slowest, wait| if wait.1 > slowest.1 { wait } else { slowest })
+      .0
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn summary_of_an_empty_stream_has_no_nan() {
+    let summary = ServiceMetrics::new("Test/EmptyStream").summary();
+    assert_eq!(summary.events, 0);
+    assert_eq!(summary.per_event(summary.processing_ms), 0.0);
+    assert_eq!(summary.bottleneck(), "none");
+  }
+
+  #[test]
+  fn streams_of_an_rpc_share_its_metrics() {
+    let first = ServiceMetrics::new("Test/SharedRpc");
+    let second = ServiceMetrics::new("Test/SharedRpc");
+    first.record_processing_count(2.0, 10);
+    second.record_processing_count(1.0, 5);
+    second.record_rejected("INVALID_JSON");
+
+    assert_eq!(first.summary().events, 10);
+    let mut rendered = String::new();
+    registry().render(&mut rendered);
+    assert!(rendered.contains("pipeline_streams_total{rpc=\"Test/SharedRpc\"} 2"));
+    assert!(rendered.contains("pipeline_events_processed_total{rpc=\"Test/SharedRpc\"} 15"));
+    assert!(rendered
+      .contains("pipeline_rejected_events_total{rpc=\"Test/SharedRpc\",reason=\"INVALID_JSON\"} 1"));
+  }
+
+  #[test]
+  fn latency_histograms_carry_the_transport() {
+    let registry = MetricsRegistry::default();
+    registry.rpc("Test/Transport").recv.observe_ms(1.0);
+    registry.set_transport(Transport::Unix);
+    registry.set_transport(Transport::Tcp);
+
+    let mut rendered = String::new();
+    registry.render(&mut rendered);
+    assert!(rendered
+      .contains("pipeline_recv_duration_seconds_count{rpc=\"Test/Transport\",transport=\"unix\"} 1"));
+
+    let shm = registry.rpc("Test/Transport (shm)");
+    let _ = shm.transport.set(Transport::Shm);
+    shm.recv.observe_ms(1.0);
+    let mut rendered = String::new();
+    registry.render(&mut rendered);
+    assert!(rendered.contains(
+      "pipeline_recv_duration_seconds_count{rpc=\"Test/Transport (shm)\",transport=\"shm\"} 1"
+    ));
+  }
+
+  #[test]
+  fn stalls_and_live_queues_are_rendered() {
+    let metrics = ServiceMetrics::new("Test/Backpressure");
+    metrics.record_stall(250.0);
+    metrics.record_queue_depth(3);
+    metrics.record_queue_depth(1);
+    let watch = metrics.watch_queue(Box::new(|| Some((2, 8))));
+
+    let summary = metrics.summary();
+    assert_eq!(summary.stalls, 1);
+    assert_eq!(summary.max_queue_depth, 3);
+    assert_eq!(summary.bottleneck(), "downstream");
+
+    let labels = format!("rpc=\"Test/Backpressure\",stream=\"{}\"", metrics.stream_id());
+    let mut rendered = String::new();
+    registry().render(&mut rendered);
+    assert!(rendered.contains("pipeline_backpressure_stall_seconds_total{rpc=\"Test/Backpressure\"} 0.25"));
+    assert!(rendered.contains(&format!("pipeline_stream_queue_depth{{{}}} 2", labels)));
+    assert!(rendered.contains(&format!("pipeline_stream_queue_capacity{{{}}} 8", labels)));
+
+    drop(watch);
+    let mut rendered = String::new();
+    registry().render(&mut rendered);
+    assert!(!rendered.contains(&labels));
+  }
+}
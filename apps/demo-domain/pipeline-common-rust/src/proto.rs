@@ -0,0 +1,14 @@
+// Generated files are produced via `pnpm -C packages/proto gen`.
+// The prost and tonic plugins write into the same files.
+
+// Shared with the registration crate, so its broker client takes these types.
+pub use service_registration_rust::proto::broker;
+
+pub mod pipeline {
+    pub mod v1 {
+        include!(concat!(
+            env!("CARGO_MANIFEST_DIR"),
+            "/../../../packages/proto/generated/rust/pipeline.v1.rs"
+        ));
+    }
+}
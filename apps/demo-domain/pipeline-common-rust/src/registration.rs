@@ -0,0 +1,88 @@
+use crate::service::ServiceOptions;
+use std::time::Duration;
+use tokio::sync::{mpsc, watch};
+use tracing::{info, warn};
+use topology_reporter_rust::{ActivityReport, TopologyProxyClient};
+
+pub use service_registration_rust::{
+  connect_broker, run_broker_registration, BrokerClient, BrokerRegistration, InstanceOptions,
+  RegistrationOptions, StreamActivity, DEFAULT_REGISTRATION_INTERVAL, DEFAULT_ROLE, LANGUAGE,
+};
+
+/// Topology heartbeat interval used when `heartbeat_interval_ms` is not set.
+pub const DEFAULT_HEARTBEAT_INTERVAL: Duration = Duration::from_secs(2);
+
+impl RegistrationOptions for ServiceOptions {
+  fn role(&self) -> &str {
+    &self.role
+  }
+
+  fn registration_interval_ms(&self) -> u64 {
+    self.registration_interval_ms
+  }
+}
+
+/// Keeps the topology registration alive with a heartbeat every
+/// `heartbeat_interval_ms` of the live `options` and forwards stream activity
+/// reports. While `topology_enabled` is off the service stays unregistered
+/// and reports are dropped; a reloaded role registers again under it.
+pub async fn run_topology_reporter(
+  mut topology: TopologyProxyClient,
+  mut activity: mpsc::Receiver<ActivityReport>,
+  mut options: watch::Receiver<ServiceOptions>,
+  mut shutdown: watch::Receiver<bool>,
+) {
+  let mut current = options.borrow_and_update().clone();
+  let mut heartbeat = heartbeat_timer(&current);
+  loop {
+    tokio::select! {
+      _ = shutdown.changed() => {
+        if *shutdown.borrow() {
+          break;
+        }
+      }
+      Ok(()) = options.changed() => {
+        let next = options.borrow_and_update().clone();
+        if next.heartbeat_interval_ms != current.heartbeat_interval_ms {
+          heartbeat = heartbeat_timer(&next);
+          topology.set_heartbeat_interval(Duration::from_millis(next.heartbeat_interval_ms));
+        }
+        if next.role != current.role {
+          if let Err(error) = topology.set_role(Some(next.role.clone())).await {
+            warn!(%error, "Topology unregister failed");
+          }
+        }
+        if current.topology_enabled && !next.topology_enabled {
+          info!("Topology reporting disabled");
+          if let Err(error) = topology.unregister().await {
+            warn!(%error, "Topology unregister failed");
+          }
+        } else if !current.topology_enabled && next.topology_enabled {
+          info!("Topology reporting enabled");
+        }
+        current = next;
+      }
+      _ = heartbeat.tick(), if current.topology_enabled => {
+        if let Err(error) = topology.ensure_registered().await {
+          warn!(%error, "Topology registration failed");
+        }
+      }
+      Some(report) = activity.recv() => {
+        if !current.topology_enabled {
+          continue;
+        }
+        if let Err(error) = topology.report_activity(report).await {
+          warn!(%error, "Topology activity report failed");
+        }
+      }
+    }
+  }
+
+  if let Err(error) = topology.unregister().await {
+    warn!(%error, "Topology unregister failed");
+  }
+}
+
+fn heartbeat_timer(options: &ServiceOptions) -> tokio::time::Interval {
+  tokio::time::interval(Duration::from_millis(options.heartbeat_interval_ms))
+}
@@ -0,0 +1,255 @@
+//! Sequence tracking of inbound streams. Every stage checks the `sequence` of
+//! the events it receives: duplicates are dropped, reordering is counted and
+//! holes that are never filled are reported as gaps. The commit offset, the
+//! highest sequence up to which every input is settled, goes back on the
+//! responses as `committed_sequence` and acknowledges the inputs. Streams
+//! start at sequence 0, or right after the point they resume from.
+//!
+//! Stages after parse see holes where events were rejected or filtered
+//! upstream. Their requests carry the upstream commit offset as
+//! `upstream_committed_sequence`; holes at or below it are settled rather
+//! than reported.
+
+use crate::metrics::ServiceMetrics;
+use std::collections::BTreeSet;
+use tracing::warn;
+
+/// Sequences a stream may run ahead of a hole before the hole is given up as
+/// a gap, used when `--sequence-window` is not given.
+pub const DEFAULT_SEQUENCE_WINDOW: usize = 1024;
+
+/// How an inbound sequence relates to the ones seen before.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Delivery {
+  /// Beyond every sequence seen so far.
+  InOrder,
+  /// Fills a hole below the highest sequence seen so far.
+  Reordered,
+  /// Seen before, or at or below the commit offset.
+  Duplicate,
+}
+
+/// Sequence state of one inbound stream.
+pub struct SequenceTracker {
+  window: i64,
+  /// Every sequence up to this one is settled; -1 while none is.
+  committed: i64,
+  highest: i64,
+  /// Delivered sequences above `committed`.
+  delivered: BTreeSet<i64>,
+  acked: Option<i64>,
+  metrics: ServiceMetrics,
+}
+
+impl SequenceTracker {
+  /// Tracks the stream measured by `metrics`, keeping holes open for at most
+  /// `window` sequences.
+  pub fn new(window: usize, metrics: &ServiceMetrics) -> Self {
+    Self {
+      window: window.max(1) as i64,
+      committed: -1,
+      highest: -1,
+      delivered: BTreeSet::new(),
+      acked: None,
+      metrics: metrics.clone(),
+    }
+  }
+
+  /// Checks `sequence` and returns whether its event should be processed;
+  /// duplicates are counted and must be dropped.
+  pub fn accept(&mut self, sequence: i64) -> bool {
+    match self.observe(sequence) {
+      Delivery::InOrder => true,
+      Delivery::Reordered => {
+        self.metrics.record_reordered();
+        true
+      }
+      Delivery::Duplicate => {
+        self.metrics.record_duplicate();
+        false
+      }
+    }
+  }
+
+  /// Records `sequence` as delivered. Sequences below the first one seen are
+  /// reordered, not duplicates, until the commit offset passes them.
+  pub fn observe(&mut self, sequence: i64) -> Delivery {
+    if sequence <= self.committed || !self.delivered.insert(sequence) {
+      return Delivery::Duplicate;
+    }
+    let delivery = if sequence > self.highest {
+      self.highest = sequence;
+      Delivery::InOrder
+    } else {
+      Delivery::Reordered
+    };
+    self.advance();
+    delivery
+  }
+
+  /// Settles every sequence up to `sequence`: the upstream stage has
+  /// acknowledged them, so holes there were dropped on purpose.
+  pub fn settle_through(&mut self, sequence: i64) {
+    if sequence <= self.committed {
+      return;
+    }
+    self.committed = sequence;
+    self.highest = self.highest.max(sequence);
+    self.delivered = self.delivered.split_off(&(sequence + 1));
+    self.advance();
+  }
+
+  /// Gives up the holes that are still open at the end of the stream.
+  pub fn finish(&mut self) {
+    while self.committed < self.highest {
+      self.close_oldest_hole();
+    }
+  }
+
+  /// Highest sequence up to which every input is settled; `None` while none
+  /// is.
+  pub fn committed(&self) -> Option<i64> {
+    (self.committed >= 0).then_some(self.committed)
+  }
+
+  /// Commit offset for the next response, which acknowledges it.
+  pub fn ack(&mut self) -> Option<i64> {
+    self.acked = self.committed();
+    self.acked
+  }
+
+  /// Whether the commit offset moved since the last `ack`.
+  pub fn has_unacked(&self) -> bool {
+    self.committed() != self.acked
+  }
+
+  /// Moves the commit offset over delivered sequences, and over holes that
+  /// fell out of the window.
+  fn advance(&mut self) {
+    self.pop_delivered();
+    while self.highest - self.committed > self.window {
+      self.close_oldest_hole();
+    }
+  }
+
+  fn pop_delivered(&mut self) {
+    while self.delivered.first() == Some(&(self.committed + 1)) {
+      self.delivered.pop_first();
+      self.committed += 1;
+    }
+  }
+
+  /// Reports the hole right above the commit offset as a gap and commits
+  /// past it.
+  fn close_oldest_hole(&mut self) {
+    let first = self.committed + 1;
+    let last = self.delivered.first().map_or(self.highest, |next| next - 1);
+    if last >= first {
+      let missing = (last - first + 1) as u64;
+      warn!(first, last, missing, "Sequence gap");
+      self.metrics.record_gap(missing);
+    }
+    self.committed = last;
+    self.pop_delivered();
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  fn tracker(window: usize) -> SequenceTracker {
+    SequenceTracker::new(window, &ServiceMetrics::new("Test/Sequence"))
+  }
+
+  #[test]
+  fn commits_contiguous_sequences_from_zero() {
+    let mut sequences = tracker(8);
+    assert_eq!(sequences.committed(), None);
+    for sequence in 0..3 {
+      assert_eq!(sequences.observe(sequence), Delivery::InOrder);
+    }
+    assert_eq!(sequences.committed(), Some(2));
+  }
+
+  #[test]
+  fn sequences_below_the_first_one_are_reordered() {
+    let mut sequences = tracker(8);
+    assert_eq!(sequences.observe(3), Delivery::InOrder);
+    assert_eq!(sequences.committed(), None);
+    assert!(sequences.accept(1));
+    assert_eq!(sequences.observe(0), Delivery::Reordered);
+    assert_eq!(sequences.committed(), Some(1));
+    assert_eq!(sequences.observe(2), Delivery::Reordered);
+    assert_eq!(sequences.committed(), Some(3));
+    assert_eq!(sequences.observe(0), Delivery::Duplicate);
+
+    // A resumed stream starts right after its resume point instead.
+    let mut resumed = tracker(8);
+    resumed.settle_through(41);
+    assert_eq!(resumed.observe(41), Delivery::Duplicate);
+    assert_eq!(resumed.observe(42), Delivery::InOrder);
+    assert_eq!(resumed.committed(), Some(42));
+  }
+
+  #[test]
+  fn drops_duplicates_and_accepts_reordered_events() {
+    let mut sequences = tracker(8);
+    assert!(sequences.accept(0));
+    assert_eq!(sequences.observe(2), Delivery::InOrder);
+    assert_eq!(sequences.committed(), Some(0));
+    assert_eq!(sequences.observe(2), Delivery::Duplicate);
+    assert_eq!(sequences.observe(1), Delivery::Reordered);
+    assert_eq!(sequences.committed(), Some(2));
+    assert!(!sequences.accept(1));
+  }
+
+  #[test]
+  fn gives_up_holes_that_leave_the_window() {
+    let metrics = ServiceMetrics::new("Test/SequenceGap");
+    let mut sequences = SequenceTracker::new(4, &metrics);
+    sequences.observe(0);
+    sequences.observe(3);
+    sequences.observe(4);
+    assert_eq!(sequences.committed(), Some(0));
+
+    sequences.observe(6);
+    assert_eq!(sequences.committed(), Some(4));
+    assert_eq!(sequences.observe(1), Delivery::Duplicate);
+
+    sequences.finish();
+    assert_eq!(sequences.committed(), Some(6));
+    let summary = metrics.summary();
+    assert_eq!(summary.gaps, 2);
+    assert_eq!(summary.missing, 3);
+  }
+
+  #[test]
+  fn upstream_commits_settle_holes() {
+    let metrics = ServiceMetrics::new("Test/SequenceSettled");
+    let mut sequences = SequenceTracker::new(8, &metrics);
+    sequences.observe(1);
+    sequences.observe(4);
+    sequences.settle_through(5);
+    assert_eq!(sequences.committed(), Some(5));
+    assert_eq!(sequences.observe(3), Delivery::Duplicate);
+
+    sequences.observe(7);
+    sequences.settle_through(6);
+    assert_eq!(sequences.committed(), Some(7));
+    sequences.finish();
+    assert_eq!(metrics.summary().gaps, 0);
+  }
+
+  #[test]
+  fn acks_only_when_the_commit_offset_moves() {
+    let mut sequences = tracker(8);
+    assert!(!sequences.has_unacked());
+    sequences.observe(0);
+    assert!(sequences.has_unacked());
+    assert_eq!(sequences.ack(), Some(0));
+    assert!(!sequences.has_unacked());
+    sequences.observe(2);
+    assert!(!sequences.has_unacked());
+  }
+}
@@ -0,0 +1,712 @@
+use crate::fast_path::shm_interface;
+use crate::metrics;
+use crate::sequence::DEFAULT_SEQUENCE_WINDOW;
+use crate::registration::{
+  run_broker_registration, run_topology_reporter, BrokerRegistration, InstanceOptions,
+  StreamActivity,
+  DEFAULT_HEARTBEAT_INTERVAL, DEFAULT_REGISTRATION_INTERVAL, DEFAULT_ROLE,
+};
+use serde::{Deserialize, Serialize};
+use std::error::Error;
+use std::net::SocketAddr;
+use std::path::PathBuf;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::net::TcpListener;
+use tokio::sync::{mpsc, watch};
+use tower::layer::util::{Identity, Stack};
+use tracing::{error, info, info_span, warn, Instrument, Span};
+use observability_rust::{
+  check_log_filter, exposition, init_logging, set_log_filter, spawn_metrics_server, trace_id,
+  GrpcMetricsLayer, GrpcTraceLayer, LogFormat, TracingGuard, LOG_FORMAT_ENV, OTLP_ENDPOINT_ENV,
+};
+use service_config_rust::EnvAlias;
+use std::future::Future;
+use tonic::transport::server::Router;
+use tonic::transport::Server;
+use tonic::Request;
+use transport_rust::shm::{DEFAULT_RING_BYTES, DEFAULT_SHM_DIR};
+use transport_rust::{
+  remove_socket, shm_url, tcp_incoming, tls_incoming, unix_incoming, unix_url, AuthConfig,
+  ShmConfig, ShmListener, ShmSession, TlsConfig, Transport,
+};
+use topology_reporter_rust::{
+  ActivityReport, ServiceLanguage, ServiceType, TopologyProxyClient, TopologyProxyConfig,
+};
+
+pub const DEFAULT_HOST: &str = "127.0.0.1";
+pub const BROKER_ADDRESS_ENV: &str = "BROKER_ADDRESS";
+pub const TOPOLOGY_PROXY_ENV: &str = "TOPOLOGY_PROXY_ADDRESS";
+pub const DEFAULT_BROKER_ADDRESS: &str = "127.0.0.1:50051";
+pub const DEFAULT_TOPOLOGY_PROXY: &str = "http://127.0.0.1:50055";
+/// Request metadata naming the calling service, so the stage it calls can
+/// report the edge from it.
+pub const SOURCE_SERVICE_METADATA: &str = "x-source-service";
+/// Source reported for streams whose caller did not name itself.
+pub const UNKNOWN_SOURCE: &str = "unknown";
+
+/// Host named in shared-memory URLs when the hostname cannot be read.
+pub const LOCAL_HOST: &str = "localhost";
+
+pub use service_config_rust::{project, ConfigSource, Reload, Validate};
+
+/// Environment variables the services read before `RUNTIME_*`; they still set
+/// the shared options of the same name.
+pub const ENV_ALIASES: &[EnvAlias] = &[
+  ("broker_address", BROKER_ADDRESS_ENV),
+  ("topology_proxy", TOPOLOGY_PROXY_ENV),
+  ("log_format", LOG_FORMAT_ENV),
+  ("otlp_endpoint", OTLP_ENDPOINT_ENV),
+];
+
+/// Sender for stream activity reports; `None` when nothing collects them. The
+/// topology reporter drops them while topology reporting is disabled.
+pub type ActivitySender = Option<mpsc::Sender<ActivityReport>>;
+
+/// Middleware applied to every pipeline gRPC server: the per-RPC span
+/// outside, metrics inside so they can record the outcome on the span.
+pub type ServiceLayer = Stack<GrpcMetricsLayer, Stack<GrpcTraceLayer, Identity>>;
+
+/// Server builder handed to the `run_service` build closure.
+pub type ServiceServer = Server<ServiceLayer>;
+
+/// Bind address and discovery settings shared by all pipeline service binaries.
+/// They are the top-level keys of every service's config file. `role`,
+/// `topology_enabled`, the two intervals and `log_level` are reloaded while
+/// the service runs.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct ServiceOptions {
+  pub host: String,
+  pub port: u16,
+  pub broker_address: String,
+  pub broker_enabled: bool,
+  pub topology_proxy: String,
+  pub topology_enabled: bool,
+  /// Role registered with the broker and reported to the topology proxy.
+  pub role: String,
+  /// gRPC service registered with the broker; the binary's own when `None`.
+  pub interface_name: Option<String>,
+  /// Topology heartbeat interval in milliseconds.
+  pub heartbeat_interval_ms: u64,
+  /// Interval in milliseconds at which a registered service checks that the
+  /// broker still lists it.
+  pub registration_interval_ms: u64,
+  /// Port of the Prometheus `/metrics` endpoint; disabled when `None`.
+  pub metrics_port: Option<u16>,
+  /// Log output format; pretty when `None`.
+  pub log_format: Option<LogFormat>,
+  /// Level filter in `RUST_LOG` syntax, e.g. `debug` or `info,h2=warn`;
+  /// `RUST_LOG`, then `info` when `None`.
+  pub log_level: Option<String>,
+  /// OTLP/gRPC collector for span export; disabled when `None`.
+  pub otlp_endpoint: Option<String>,
+  /// HTTP/2 flow-control window of each stream in bytes; 1 MiB when `None`.
+  pub stream_window: Option<u32>,
+  /// HTTP/2 flow-control window of each connection in bytes; 1 MiB when `None`.
+  pub connection_window: Option<u32>,
+  /// Sequences a stream may run ahead of a missing one before it is
+  /// reported as a gap.
+  pub sequence_window: usize,
+  /// TLS of the served endpoint and of the connections to the broker and the
+  /// topology proxy.
+  pub tls: TlsConfig,
+  /// Credentials presented to the broker when registering and looking up
+  /// stages.
+  pub auth: AuthConfig,
+  /// Instance id and metadata registered with the broker.
+  pub instance: InstanceOptions,
+  /// Serve on this Unix domain socket instead of `host` and `port`, for
+  /// peers on the same host. Registered as a `unix://` URL; never TLS.
+  pub unix_socket: Option<PathBuf>,
+  /// Shared-memory endpoint next to the gRPC one, for stages on the same
+  /// host; only served by binaries that start with `run_service_with_shm`.
+  pub shm: ShmConfig,
+}
+
+impl ServiceOptions {
+  pub fn new(default_port: u16) -> Self {
+    Self {
+      host: DEFAULT_HOST.to_string(),
+      port: default_port,
+      broker_address: DEFAULT_BROKER_ADDRESS.to_string(),
+      broker_enabled: true,
+      topology_proxy: DEFAULT_TOPOLOGY_PROXY.to_string(),
+      topology_enabled: true,
+      role: DEFAULT_ROLE.to_string(),
+      interface_name: None,
+      heartbeat_interval_ms: DEFAULT_HEARTBEAT_INTERVAL.as_millis() as u64,
+      registration_interval_ms: DEFAULT_REGISTRATION_INTERVAL.as_millis() as u64,
+      metrics_port: None,
+      log_format: None,
+      log_level: None,
+      otlp_endpoint: None,
+      stream_window: None,
+      connection_window: None,
+      sequence_window: DEFAULT_SEQUENCE_WINDOW,
+      tls: TlsConfig::default(),
+      auth: AuthConfig::default(),
+      instance: InstanceOptions::default(),
+      unix_socket: None,
+      shm: ShmConfig::default(),
+    }
+  }
+
+  /// Consumes a shared option and its value. Returns `Ok(false)` when the
+  /// argument is not a shared option and must be handled by the caller.
+  /// `--config` and `--print-config` are only skipped; `ConfigSource` reads
+  /// them before the command line is applied.
+  pub fn parse_arg(
+    &mut self,
+    arg: &str,
+    args: &mut impl Iterator<Item = String>,
+  ) -> Result<bool, Box<dyn Error>> {
+    match arg {
+      "--config" => {
+        args.next().ok_or("Missing value for --config")?;
+      }
+      "--print-config" => {}
+      "--host" => {
+        self.host = args.next().ok_or("Missing value for --host")?;
+      }
+      "--port" => {
+        let value = args.next().ok_or("Missing value for --port")?;
+        self.port = value.parse()?;
+      }
+      "--broker-address" => {
+        self.broker_address = args.next().ok_or("Missing value for --broker-address")?;
+      }
+      "--no-broker" => {
+        self.broker_enabled = false;
+      }
+      "--topology-proxy" => {
+        self.topology_proxy = args.next().ok_or("Missing value for --topology-proxy")?;
+      }
+      "--no-topology" => {
+        self.topology_enabled = false;
+      }
+      "--role" => {
+        self.role = args.next().ok_or("Missing value for --role")?;
+      }
+      "--metrics-port" => {
+        let value = args.next().ok_or("Missing value for --metrics-port")?;
+        self.metrics_port = Some(value.parse()?);
+      }
+      "--log-format" => {
+        let value = args.next().ok_or("Missing value for --log-format")?;
+        self.log_format = Some(value.parse()?);
+      }
+      "--log-level" => {
+        self.log_level = Some(args.next().ok_or("Missing value for --log-level")?);
+      }
+      "--otlp-endpoint" => {
+        self.otlp_endpoint = Some(args.next().ok_or("Missing value for --otlp-endpoint")?);
+      }
+      "--stream-window" => {
+        let value = args.next().ok_or("Missing value for --stream-window")?;
+        self.stream_window = Some(value.parse()?);
+      }
+      "--connection-window" => {
+        let value = args.next().ok_or("Missing value for --connection-window")?;
+        self.connection_window = Some(value.parse()?);
+      }
+      "--sequence-window" => {
+        let value = args.next().ok_or("Missing value for --sequence-window")?;
+        self.sequence_window = value.parse()?;
+      }
+      "--tls" => {
+        self.tls.enabled = true;
+      }
+      "--tls-cert" => {
+        self.tls.cert_file = Some(args.next().ok_or("Missing value for --tls-cert")?.into());
+      }
+      "--tls-key" => {
+        self.tls.key_file = Some(args.next().ok_or("Missing value for --tls-key")?.into());
+      }
+      "--tls-ca" => {
+        self.tls.ca_file = Some(args.next().ok_or("Missing value for --tls-ca")?.into());
+      }
+      "--tls-require-client-cert" => {
+        self.tls.require_client_cert = true;
+      }
+      "--tls-server-name" => {
+        self.tls.server_name = Some(args.next().ok_or("Missing value for --tls-server-name")?);
+      }
+      "--broker-token-file" => {
+        let path = args.next().ok_or("Missing value for --broker-token-file")?;
+        self.auth.token_file = Some(path.into());
+      }
+      "--instance-id" => {
+        self.instance.id = Some(args.next().ok_or("Missing value for --instance-id")?);
+      }
+      "--weight" => {
+        let value = args.next().ok_or("Missing value for --weight")?;
+        self.instance.weight = Some(value.parse()?);
+      }
+      "--tag" => {
+        self.instance.tags.push(args.next().ok_or("Missing value for --tag")?);
+      }
+      "--zone" => {
+        self.instance.zone = Some(args.next().ok_or("Missing value for --zone")?);
+      }
+      "--unix-socket" => {
+        self.unix_socket = Some(args.next().ok_or("Missing value for --unix-socket")?.into());
+      }
+      "--shm-socket" => {
+        self.shm.socket = Some(args.next().ok_or("Missing value for --shm-socket")?.into());
+      }
+      "--shm-dir" => {
+        self.shm.dir = args.next().ok_or("Missing value for --shm-dir")?.into();
+      }
+      "--shm-ring-bytes" => {
+        let value = args.next().ok_or("Missing value for --shm-ring-bytes")?;
+        self.shm.ring_bytes = value.parse()?;
+      }
+      _ => return Ok(false),
+    }
+    Ok(true)
+  }
+
+  /// Installs the `tracing` subscriber; call once, right after parsing, and
+  /// keep the returned guard until the service exits.
+  pub fn init_logging(
+    &self,
+    descriptor: &ServiceDescriptor,
+  ) -> Result<TracingGuard, Box<dyn Error>> {
+    let guard =
+      init_logging(descriptor.program_name, self.log_format, self.otlp_endpoint.as_deref())?;
+    if let Some(log_level) = self.log_level.as_deref() {
+      set_log_filter(Some(log_level))?;
+    }
+    Ok(guard)
+  }
+
+  /// Help text for the shared options, one line per option.
+  pub fn usage(&self) -> String {
+    format!(
+      "{}  --host <host>               Bind host (default: {})\n  --port <port>               Bind port (default: {})\n  --broker-address <address>  Broker address (default: {})\n  --no-broker                 Disable broker registration\n  --topology-proxy <url>      Topology proxy address (default: {})\n  --no-topology               Disable topology reporting\n  --role <role>               Role registered with the broker (default: {})\n  --metrics-port <port>       Serve Prometheus metrics on /metrics (default: disabled)\n  --log-format <format>       Log format: pretty or json (default: pretty)\n  --log-level <filter>        Level filter in RUST_LOG syntax (default: $RUST_LOG or info)\n  --otlp-endpoint <url>       Export spans via OTLP/gRPC (default: disabled)\n  --stream-window <bytes>     HTTP/2 flow-control window per stream (default: 1 MiB)\n  --connection-window <bytes> HTTP/2 flow-control window per connection (default: 1 MiB)\n  --sequence-window <n>       Sequences a stream may run ahead of a missing one (default: {})\n  --tls                       Serve TLS and reach the broker over https (default: disabled)\n  --tls-cert <file>           PEM certificate chain presented to peers\n  --tls-key <file>            PEM private key of --tls-cert\n  --tls-ca <file>             PEM CA bundle that peer certificates are verified against\n  --tls-require-client-cert   Reject clients without a certificate signed by --tls-ca\n  --tls-server-name <name>    Name expected in server certificates instead of the host\n  --broker-token-file <file>  File holding the bearer token presented to the broker\n  --instance-id <id>          Instance id registered with the broker (default: program@hostname:port)\n  --weight <n>                Traffic weight registered with the broker (default: 100)\n  --tag <tag>                 Tag registered with the broker; repeatable\n  --zone <zone>               Zone registered with the broker\n  --unix-socket <path>        Serve on a Unix domain socket instead of host and port\n  --shm-socket <path>         Also serve shared-memory sessions, controlled on this socket\n  --shm-dir <dir>             Directory of the shared-memory rings (default: {})\n  --shm-ring-bytes <bytes>    Capacity of each shared-memory ring (default: {})\n",
+      service_config_rust::usage(),
+      self.host,
+      self.port,
+      self.broker_address,
+      self.topology_proxy,
+      self.role,
+      DEFAULT_SEQUENCE_WINDOW,
+      DEFAULT_SHM_DIR,
+      DEFAULT_RING_BYTES
+    )
+  }
+}
+
+impl Validate for ServiceOptions {
+  fn validate(&self, errors: &mut Vec<String>) {
+    if self.role.is_empty() {
+      errors.push("role must not be empty".to_string());
+    }
+    if self.heartbeat_interval_ms == 0 {
+      errors.push("heartbeat_interval_ms must be at least 1".to_string());
+    }
+    if self.registration_interval_ms == 0 {
+      errors.push("registration_interval_ms must be at least 1".to_string());
+    }
+    if self.sequence_window == 0 {
+      errors.push("sequence_window must be at least 1".to_string());
+    }
+    if let Some(Err(error)) = self.log_level.as_deref().map(check_log_filter) {
+      errors.push(error);
+    }
+    self.tls.validate_server(errors);
+    self.auth.validate(errors);
+    self.instance.validate(errors);
+    self.shm.validate(errors);
+  }
+}
+
+impl Reload for ServiceOptions {
+  fn reload_from(&mut self, next: &Self) {
+    self.role = next.role.clone();
+    self.topology_enabled = next.topology_enabled;
+    self.heartbeat_interval_ms = next.heartbeat_interval_ms;
+    self.registration_interval_ms = next.registration_interval_ms;
+    self.log_level = next.log_level.clone();
+  }
+}
+
+/// Static identity of a pipeline service binary.
+#[derive(Clone, Copy, Debug)]
+pub struct ServiceDescriptor {
+  /// Binary name, also used as the topology service name.
+  pub program_name: &'static str,
+  /// Fully qualified gRPC service registered with the broker.
+  pub interface_name: &'static str,
+  /// Human-readable name for log lines, e.g. "Parse service".
+  pub display_name: &'static str,
+  pub version: &'static str,
+}
+
+/// Creates the activity aggregator for one incoming stream. Reports draw the
+/// edge from the caller named in `SOURCE_SERVICE_METADATA` to this service and
+/// carry the trace of the RPC that opened the stream.
+pub fn stream_activity<T>(
+  sender: &ActivitySender,
+  descriptor: &ServiceDescriptor,
+  request: &Request<T>,
+  method: &'static str,
+) -> StreamActivity {
+  let source = request
+    .metadata()
+    .get(SOURCE_SERVICE_METADATA)
+    .and_then(|value| value.to_str().ok())
+    .filter(|source| !source.is_empty())
+    .unwrap_or(UNKNOWN_SOURCE);
+  StreamActivity::new(sender.clone(), descriptor.program_name.to_string(), method)
+    .with_source(source.to_string())
+    .with_trace_id(trace_id(&Span::current()))
+}
+
+/// Names `service_name` as the caller of an outgoing request; see
+/// `stream_activity`.
+pub fn name_source<T>(request: &mut Request<T>, service_name: &str) {
+  if let Ok(value) = service_name.parse() {
+    request.metadata_mut().insert(SOURCE_SERVICE_METADATA, value);
+  }
+}
+
+/// Spawns the processing task of a stream in a `stream` span nested under
+/// the span of the RPC that opened it.
+pub fn spawn_stream<F>(task: F)
+where
+  F: Future<Output = ()> + Send + 'static,
+{
+  tokio::spawn(task.instrument(info_span!("stream")));
+}
+
+/// Span of one streamed batch message, nested under the `stream` span.
+pub fn batch_span(events: usize) -> Span {
+  info_span!("batch", events)
+}
+
+/// Serves one shared-memory session. Called on the accept task, so the
+/// handler spawns the work.
+pub type ShmHandler = Arc<dyn Fn(ShmSession) + Send + Sync>;
+
+/// Serves the router built by `build` until SIGINT/SIGTERM, keeping the
+/// broker registration and topology reporting alive in the background.
+/// `build` adds its services to a server that already records gRPC metrics.
+/// Reloads of `options` apply the reloadable settings; the rest are read
+/// once at startup.
+pub async fn run_service<F>(
+  live_options: watch::Receiver<ServiceOptions>,
+  descriptor: ServiceDescriptor,
+  build: F,
+) -> Result<(), Box<dyn Error>>
+where
+  F: FnOnce(ServiceServer, ActivitySender) -> Router<ServiceLayer>,
+{
+  run_service_with_shm(live_options, descriptor, |server, activity| {
+    (build(server, activity), None)
+  })
+  .await
+}
+
+/// `run_service` for binaries that also serve shared-memory sessions. When
+/// `shm.socket` is set, the sessions accepted on it go to the handler `build`
+/// returns, and the endpoint is registered with the broker under
+/// `shm_interface` of the gRPC interface.
+pub async fn run_service_with_shm<F>(
+  live_options: watch::Receiver<ServiceOptions>,
+  descriptor: ServiceDescriptor,
+  build: F,
+) -> Result<(), Box<dyn Error>>
+where
+  F: FnOnce(ServiceServer, ActivitySender) -> (Router<ServiceLayer>, Option<ShmHandler>),
+{
+  let options = live_options.borrow().clone();
+  let (address, registered_host, registered_port, transport) = match &options.unix_socket {
+    Some(path) => (unix_url(path), unix_url(path), 0, Transport::Unix),
+    None => {
+      let addr: SocketAddr = format!("{}:{}", options.host, options.port).parse()?;
+      let transport = if options.tls.enabled { Transport::Tls } else { Transport::Tcp };
+      (addr.to_string(), options.host.clone(), i32::from(options.port), transport)
+    }
+  };
+  let listener = match &options.unix_socket {
+    Some(path) => Listener::Unix(unix_incoming(path)?),
+    None => Listener::Tcp(TcpListener::bind(&address).await?),
+  };
+  let shm_listener = match &options.shm.socket {
+    Some(_) => Some(ShmListener::bind(&options.shm)?),
+    None => None,
+  };
+  metrics::set_transport(transport);
+
+  let (shutdown_tx, shutdown_rx) = watch::channel(false);
+  let interface_name = options
+    .interface_name
+    .clone()
+    .unwrap_or_else(|| descriptor.interface_name.to_string());
+  let host = hostname::get().ok().and_then(|h| h.into_string().ok());
+  let metadata = options.instance.metadata(
+    descriptor.program_name,
+    descriptor.version,
+    host.as_deref().unwrap_or(LOCAL_HOST),
+    &registered_host,
+    registered_port,
+  );
+
+  let register = |interface_name: String, host: String, port: i32| {
+    let registration = BrokerRegistration {
+      interface_name,
+      role: options.role.clone(),
+      host,
+      port,
+      metadata: metadata.clone(),
+    };
+    let span = info_span!(
+      "broker_registration",
+      broker = %options.broker_address,
+      interface = %registration.interface_name,
+      role = %registration.role,
+    );
+    tokio::spawn(
+      run_broker_registration(
+        options.tls.url(&options.broker_address),
+        options.tls.clone(),
+        options.auth.clone(),
+        registration,
+        live_options.clone(),
+        shutdown_rx.clone(),
+      )
+      .instrument(span),
+    )
+  };
+  let mut broker_tasks = Vec::new();
+  if options.broker_enabled {
+    broker_tasks.push(register(interface_name.clone(), registered_host, registered_port));
+  }
+
+  let (activity_tx, activity_rx) = mpsc::channel(256);
+  let topology_task = {
+    let mut topology_config = TopologyProxyConfig::with_defaults(
+      options.topology_proxy.clone(),
+      descriptor.program_name.to_string(),
+      ServiceType::Server,
+      ServiceLanguage::Rust,
+    );
+    topology_config.version = Some(descriptor.version.to_string());
+    topology_config.address = Some(address.clone());
+    topology_config.host = host.clone();
+    topology_config.service_interface = Some(interface_name.clone());
+    topology_config.service_role = Some(options.role.clone());
+    topology_config.program_name = Some(descriptor.program_name.to_string());
+    topology_config.heartbeat_interval = Duration::from_millis(options.heartbeat_interval_ms);
+    topology_config.tls = options.tls.clone();
+    let span = info_span!("topology_heartbeat", proxy = %options.topology_proxy);
+    tokio::spawn(
+      run_topology_reporter(
+        TopologyProxyClient::new(topology_config),
+        activity_rx,
+        live_options.clone(),
+        shutdown_rx.clone(),
+      )
+      .instrument(span),
+    )
+  };
+  let log_level_task = tokio::spawn(follow_log_level(live_options.clone()));
+
+  let metrics_task = match options.metrics_port {
+    Some(port) => {
+      exposition::register_collector(metrics::collect);
+      Some(spawn_metrics_server((options.host.as_str(), port), shutdown_rx.clone()).await?)
+    }
+    None => None,
+  };
+
+  let server = Server::builder()
+    .initial_stream_window_size(options.stream_window)
+    .initial_connection_window_size(options.connection_window)
+    .layer(GrpcTraceLayer::server())
+    .layer(GrpcMetricsLayer::server());
+  let (router, shm_handler) = build(server, Some(activity_tx));
+
+  let shm_task = match (shm_listener, shm_handler, &options.shm.socket) {
+    (Some(listener), Some(handler), Some(socket)) => {
+      let url = shm_url(host.as_deref().unwrap_or(LOCAL_HOST), socket);
+      info!("{} serving shared memory on {}", descriptor.display_name, url);
+      if options.broker_enabled {
+        broker_tasks.push(register(shm_interface(&interface_name), url, 0));
+      }
+      Some(tokio::spawn(serve_shm(listener, handler, shutdown_rx.clone())))
+    }
+    (Some(_), None, _) => {
+      warn!("{} does not serve shared memory, ignoring shm.socket", descriptor.display_name);
+      None
+    }
+    _ => None,
+  };
+
+  let shutdown = wait_for_shutdown(shutdown_rx);
+  let server_task = match listener {
+    Listener::Unix(incoming) => {
+      info!("{} listening on {}", descriptor.display_name, address);
+      tokio::spawn(router.serve_with_incoming_shutdown(incoming, shutdown))
+    }
+    Listener::Tcp(listener) if options.tls.enabled => {
+      info!("{} listening on {} (TLS)", descriptor.display_name, address);
+      let incoming = tls_incoming(listener, &options.tls)?;
+      tokio::spawn(router.serve_with_incoming_shutdown(incoming, shutdown))
+    }
+    Listener::Tcp(listener) => {
+      info!("{} listening on {}", descriptor.display_name, address);
+      tokio::spawn(router.serve_with_incoming_shutdown(tcp_incoming(listener), shutdown))
+    }
+  };
+
+  wait_for_signal().await;
+  let _ = shutdown_tx.send(true);
+
+  for task in broker_tasks {
+    if let Err(error) = task.await {
+      error!(%error, "Broker task failed");
+    }
+  }
+  if let Some(task) = shm_task {
+    if let Err(error) = task.await {
+      error!(%error, "Shared-memory task failed");
+    }
+  }
+  if let Err(error) = topology_task.await {
+    error!(%error, "Topology task failed");
+  }
+  log_level_task.abort();
+  if let Some(task) = metrics_task {
+    if let Err(error) = task.await {
+      error!(%error, "Metrics task failed");
+    }
+  }
+  match server_task.await {
+    Ok(Ok(())) => {}
+    Ok(Err(error)) => error!(%error, "Server failed"),
+    Err(error) => error!(%error, "Server task failed"),
+  }
+  for path in options.unix_socket.iter().chain(&options.shm.socket) {
+    remove_socket(path);
+  }
+
+  Ok(())
+}
+
+/// Accepts shared-memory sessions until shutdown. Each handshake runs on its
+/// own task so a client that never maps its rings does not block the others.
+async fn serve_shm(listener: ShmListener, handler: ShmHandler, mut shutdown: watch::Receiver<bool>) {
+  loop {
+    tokio::select! {
+      _ = shutdown.changed() => {
+        if *shutdown.borrow() {
+          break;
+        }
+      }
+      accepted = listener.accept() => match accepted {
+        Ok(handshake) => {
+          let handler = handler.clone();
+          tokio::spawn(async move {
+            match handshake.complete().await {
+              Ok(session) => handler(session),
+              Err(error) => warn!(%error, "Shared-memory handshake failed"),
+            }
+          });
+        }
+        Err(error) => warn!(%error, "Shared-memory accept failed"),
+      }
+    }
+  }
+}
+
+/// Where `run_service` accepts connections.
+enum Listener {
+  Tcp(TcpListener),
+  Unix(transport_rust::UnixIncoming),
+}
+
+/// Applies reloaded log levels to the global subscriber.
+async fn follow_log_level(mut options: watch::Receiver<ServiceOptions>) {
+  let mut current = options.borrow_and_update().log_level.clone();
+  while options.changed().await.is_ok() {
+    let next = options.borrow_and_update().log_level.clone();
+    if next == current {
+      continue;
+    }
+    match set_log_filter(next.as_deref()) {
+      Ok(()) => info!(log_level = next.as_deref().unwrap_or("default"), "Log level changed"),
+      Err(error) => warn!(%error, "Log level change failed"),
+    }
+    current = next;
+  }
+}
+
+async fn wait_for_signal() {
+  let mut sigterm =
+    match tokio::signal::unix::signal(tokio::signal::unix::SignalKind::terminate()) {
+      Ok(signal) => signal,
+      Err(_) => return,
+    };
+  let mut sigint =
+    match tokio::signal::unix::signal(tokio::signal::unix::SignalKind::interrupt()) {
+      Ok(signal) => signal,
+      Err(_) => return,
+    };
+
+  tokio::select! {
+    _ = sigterm.recv() => {
+      info!("Received SIGTERM, shutting down.");
+    }
+    _ = sigint.recv() => {
+      info!("Received SIGINT, shutting down.");
+    }
+  }
+}
+
+async fn wait_for_shutdown(mut shutdown: watch::Receiver<bool>) {
+  while !*shutdown.borrow() {
+    if shutdown.changed().await.is_err() {
+      break;
+    }
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  const PARSE: ServiceDescriptor = ServiceDescriptor {
+    program_name: "parse-service-rust",
+    interface_name: "pipeline.v1.ParseService",
+    display_name: "Parse service",
+    version: "0.1.0",
+  };
+
+  fn reported_edge(request: &Request<()>) -> (Option<String>, String) {
+    let (sender, mut reports) = mpsc::channel(1);
+    let mut activity =
+      stream_activity(&Some(sender), &PARSE, request, "ParseService/ParseEvents");
+    activity.record(3, 1.0);
+    drop(activity);
+    let report = reports.try_recv().expect("the stream flushes on drop");
+    (report.source_service, report.target_service)
+  }
+
+  #[test]
+  fn streams_report_the_edge_from_their_caller_to_this_service() {
+    let mut named = Request::new(());
+    name_source(&mut named, "ingest-service-rust");
+    assert_eq!(
+      reported_edge(&named),
+      (Some("ingest-service-rust".to_string()), "parse-service-rust".to_string())
+    );
+
+    let anonymous = Request::new(());
+    assert_eq!(
+      reported_edge(&anonymous),
+      (Some(UNKNOWN_SOURCE.to_string()), "parse-service-rust".to_string())
+    );
+  }
+}
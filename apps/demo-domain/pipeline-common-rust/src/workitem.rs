@@ -0,0 +1,361 @@
+use crate::proto::pipeline::v1 as pb;
+use crate::proto::pipeline::v1::{PayloadSize, WorkloadConfig};
+use serde::{Deserialize, Serialize};
+
+/// `ParsedEvent.type` of events that carry a work item, either as a typed
+/// `payload` or as JSON in their `user` field.
+pub const WORK_ITEM_EVENT_TYPE: &str = "work-item";
+
+/// Compute iterations used when the workload config does not set any.
+pub const DEFAULT_COMPUTE_ITERATIONS: i32 = 500;
+const ENRICH_ITERATIONS: i32 = 500;
+const FINALIZE_ITERATIONS: i32 = 50;
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct WorkItem {
+    pub id: String,
+    pub vectors: Vec<Vector>,
+    pub matrix: Matrix,
+    pub text: String,
+    pub iterations: i32,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Vector {
+    pub values: Vec<f64>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Matrix {
+    pub rows: Vec<Vector>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ProcessedWorkItem {
+    pub id: String,
+    pub normalized_vectors: Vec<Vector>,
+    pub transposed_matrix: Matrix,
+    pub checksum: f64,
+}
+
+/// Processes a WorkItem: vector normalization, matrix transpose, CPU work
+pub fn process_work_item(item: &WorkItem) -> ProcessedWorkItem {
+    // Vector normalization
+    let normalized_vectors: Vec<Vector> = item.vectors.iter().map(normalize_vector).collect();
+
+    // Matrix transpose
+    let transposed_matrix = transpose_matrix(&item.matrix);
+
+    // CPU-intensive work
+    let mut checksum = 0.0;
+    for i in 0..item.iterations {
+        checksum += compute_checksum(&normalized_vectors, i as f64);
+    }
+
+    ProcessedWorkItem {
+        id: item.id.clone(),
+        normalized_vectors,
+        transposed_matrix,
+        checksum,
+    }
+}
+
+/// Normalizes a vector (L2 norm)
+fn normalize_vector(vec: &Vector) -> Vector {
+    let values = &vec.values;
+    let magnitude: f64 = values.iter().map(|v| v * v).sum::<f64>().sqrt();
+
+    if magnitude == 0.0 {
+        return Vector {
+            values: values.clone(),
+        };
+    }
+
+    Vector {
+        values: values.iter().map(|v| v / magnitude).collect(),
+    }
+}
+
+/// Transposes a matrix
+fn transpose_matrix(matrix: &Matrix) -> Matrix {
+    let rows = &matrix.rows;
+    if rows.is_empty() {
+        return Matrix { rows: vec![] };
+    }
+
+    let num_rows = rows.len();
+    let num_cols = rows[0].values.len();
+
+    let mut transposed = vec![];
+    for col in 0..num_cols {
+        let mut new_row = vec![];
+        for row in rows.iter().take(num_rows) {
+            new_row.push(row.values.get(col).copied().unwrap_or(0.0));
+        }
+        transposed.push(Vector { values: new_row });
+    }
+
+    Matrix { rows: transposed }
+}
+
+/// Computes a checksum over normalized vectors (simulates CPU work)
+fn compute_checksum(vectors: &[Vector], iteration: f64) -> f64 {
+    let mut sum = 0.0;
+    for vec in vectors {
+        for &v in &vec.values {
+            sum += v * (iteration + 1.0) * 0.001;
+        }
+    }
+    sum
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct EnrichedWorkItem {
+    pub id: String,
+    pub eigenvalues: Vec<f64>,
+    pub score: f64,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct WorkItemResult {
+    pub id: String,
+    pub final_score: f64,
+    pub processed_count: i64,
+}
+
+/// Generates a random WorkItem sized by the workload's payload size (ingest stage)
+pub fn generate_work_item(id: String, config: &WorkloadConfig) -> WorkItem {
+    let payload_size = PayloadSize::try_from(config.payload_size).unwrap_or(PayloadSize::Medium);
+    let size = match payload_size {
+        PayloadSize::Small => 5,
+        PayloadSize::Medium => 10,
+        PayloadSize::Large => 20,
+    };
+    let repetitions = match payload_size {
+        PayloadSize::Small => 5,
+        PayloadSize::Medium => 20,
+        PayloadSize::Large => 50,
+    };
+    let iterations = if config.compute_iterations > 0 {
+        config.compute_iterations
+    } else {
+        DEFAULT_COMPUTE_ITERATIONS
+    };
+
+    WorkItem {
+        id,
+        vectors: (0..2).map(|_| random_vector(size)).collect(),
+        matrix: Matrix {
+            rows: (0..size).map(|_| random_vector(size)).collect(),
+        },
+        text: "Lorem ipsum dolor sit amet, consectetur adipiscing elit. ".repeat(repetitions),
+        iterations,
+    }
+}
+
+/// Derives eigenvalues and a feature score from a ProcessedWorkItem (rules stage)
+pub fn enrich_work_item(item: &ProcessedWorkItem) -> EnrichedWorkItem {
+    let all_values: Vec<f64> = item
+        .normalized_vectors
+        .iter()
+        .flat_map(|vec| vec.values.iter().copied())
+        .collect();
+
+    let mean = if all_values.is_empty() {
+        0.0
+    } else {
+        all_values.iter().sum::<f64>() / all_values.len() as f64
+    };
+
+    // Use first 5 values as "eigenvalues" (simplified)
+    let eigenvalues: Vec<f64> = all_values.iter().take(5).copied().collect();
+
+    let matrix_score: f64 = item
+        .transposed_matrix
+        .rows
+        .iter()
+        .map(|row| row.values.iter().sum::<f64>())
+        .sum();
+
+    // CPU-intensive feature engineering
+    let mut score = 0.0;
+    for i in 0..ENRICH_ITERATIONS {
+        score += (item.checksum + matrix_score + mean) * (i + 1) as f64 * 0.001;
+    }
+
+    EnrichedWorkItem {
+        id: item.id.clone(),
+        eigenvalues,
+        score,
+    }
+}
+
+/// Computes the final score of an EnrichedWorkItem (aggregate stage)
+pub fn finalize_work_item(item: &EnrichedWorkItem) -> WorkItemResult {
+    let eigen_sum: f64 = item.eigenvalues.iter().sum();
+
+    let mut final_score = 0.0;
+    for i in 0..FINALIZE_ITERATIONS {
+        final_score += (item.score + eigen_sum) * (i + 1) as f64 * 0.001;
+    }
+
+    // Dot products of neighbouring eigenvalues
+    for pair in item.eigenvalues.windows(2) {
+        final_score += (pair[0] * pair[1]).abs() * 0.1;
+    }
+
+    if !final_score.is_finite() {
+        final_score = 0.0;
+    }
+
+    WorkItemResult {
+        id: item.id.clone(),
+        final_score,
+        processed_count: 1,
+    }
+}
+
+// Conversions to and from the protobuf messages carried in `ParsedEvent.payload`
+// and `EnrichedEvent.payload`. They move the vectors instead of re-encoding them.
+
+impl From<Vector> for pb::Vector {
+    fn from(vector: Vector) -> Self {
+        pb::Vector { values: vector.values }
+    }
+}
+
+impl From<pb::Vector> for Vector {
+    fn from(vector: pb::Vector) -> Self {
+        Vector { values: vector.values }
+    }
+}
+
+impl From<Matrix> for pb::Matrix {
+    fn from(matrix: Matrix) -> Self {
+        pb::Matrix {
+            rows: matrix.rows.into_iter().map(Into::into).collect(),
+        }
+    }
+}
+
+impl From<pb::Matrix> for Matrix {
+    fn from(matrix: pb::Matrix) -> Self {
+        Matrix {
+            rows: matrix.rows.into_iter().map(Into::into).collect(),
+        }
+    }
+}
+
+impl From<ProcessedWorkItem> for pb::ProcessedWorkItem {
+    fn from(item: ProcessedWorkItem) -> Self {
+        pb::ProcessedWorkItem {
+            id: item.id,
+            normalized_vectors: item.normalized_vectors.into_iter().map(Into::into).collect(),
+            transposed_matrix: Some(item.transposed_matrix.into()),
+            checksum: item.checksum,
+        }
+    }
+}
+
+impl From<pb::ProcessedWorkItem> for ProcessedWorkItem {
+    fn from(item: pb::ProcessedWorkItem) -> Self {
+        ProcessedWorkItem {
+            id: item.id,
+            normalized_vectors: item.normalized_vectors.into_iter().map(Into::into).collect(),
+            transposed_matrix: item
+                .transposed_matrix
+                .map(Into::into)
+                .unwrap_or(Matrix { rows: vec![] }),
+            checksum: item.checksum,
+        }
+    }
+}
+
+impl From<EnrichedWorkItem> for pb::EnrichedWorkItem {
+    fn from(item: EnrichedWorkItem) -> Self {
+        pb::EnrichedWorkItem {
+            id: item.id,
+            eigenvalues: item.eigenvalues,
+            score: item.score,
+        }
+    }
+}
+
+impl From<pb::EnrichedWorkItem> for EnrichedWorkItem {
+    fn from(item: pb::EnrichedWorkItem) -> Self {
+        EnrichedWorkItem {
+            id: item.id,
+            eigenvalues: item.eigenvalues,
+            score: item.score,
+        }
+    }
+}
+
+fn random_vector(size: usize) -> Vector {
+    Vector {
+        values: (0..size).map(|_| fastrand::f64()).collect(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn generated_item_matches_payload_size() {
+        let config = WorkloadConfig {
+            work_ratio: 1.0,
+            payload_size: PayloadSize::Small as i32,
+            compute_iterations: 0,
+        };
+        let item = generate_work_item("w-000001".to_string(), &config);
+        assert_eq!(item.vectors.len(), 2);
+        assert_eq!(item.vectors[0].values.len(), 5);
+        assert_eq!(item.matrix.rows.len(), 5);
+        assert_eq!(item.iterations, DEFAULT_COMPUTE_ITERATIONS);
+    }
+
+    #[test]
+    fn work_item_flows_through_all_stages() {
+        let item = WorkItem {
+            id: "w-1".to_string(),
+            vectors: vec![Vector { values: vec![3.0, 4.0] }],
+            matrix: Matrix {
+                rows: vec![Vector { values: vec![1.0, 2.0] }],
+            },
+            text: String::new(),
+            iterations: 2,
+        };
+        let processed = process_work_item(&item);
+        assert_eq!(processed.normalized_vectors[0].values, vec![0.6, 0.8]);
+        assert_eq!(processed.transposed_matrix.rows.len(), 2);
+
+        let enriched = enrich_work_item(&processed);
+        assert_eq!(enriched.eigenvalues, vec![0.6, 0.8]);
+        assert!(enriched.score > 0.0);
+
+        let result = finalize_work_item(&enriched);
+        assert_eq!(result.id, "w-1");
+        assert_eq!(result.processed_count, 1);
+        assert!(result.final_score > 0.0);
+    }
+
+    #[test]
+    fn processed_item_survives_the_protobuf_round_trip() {
+        let processed = ProcessedWorkItem {
+            id: "w-2".to_string(),
+            normalized_vectors: vec![Vector { values: vec![0.6, 0.8] }],
+            transposed_matrix: Matrix {
+                rows: vec![Vector { values: vec![1.0] }, Vector { values: vec![2.0] }],
+            },
+            checksum: 1.5,
+        };
+        let message = pb::ProcessedWorkItem::from(processed.clone());
+        assert_eq!(message.transposed_matrix.as_ref().map(|m| m.rows.len()), Some(2));
+
+        let restored = ProcessedWorkItem::from(message);
+        assert_eq!(restored.normalized_vectors[0].values, processed.normalized_vectors[0].values);
+        assert_eq!(restored.transposed_matrix.rows[1].values, vec![2.0]);
+        assert_eq!(restored.checksum, 1.5);
+    }
+}
@@ -170,6 +170,10 @@ const parseArgs = (argv: string[]): PipelineConfig => {
   return config
 }
 
+// Stages report their streams as coming from the service named in this header
+const SOURCE_SERVICE_METADATA = 'x-source-service'
+const SERVICE_NAME = 'pipeline-orchestrator'
+
 const createClient = <T>(
   ServiceClient: new (address: string, credentials: grpc.ChannelCredentials) => T,
   host: string,
@@ -223,7 +227,10 @@ const runPipeline = async (config: PipelineConfig): Promise<void> => {
   const startTime = Date.now()
   let firstEventTime: number | null = null
 
-  const ingestStream = ingestClient.streamEvents(request)
+  const metadata = new grpc.Metadata()
+  metadata.set(SOURCE_SERVICE_METADATA, SERVICE_NAME)
+
+  const ingestStream = ingestClient.streamEvents(request, metadata)
   // eslint-disable-next-line @typescript-eslint/no-explicit-any
   let parseStream: any
   // eslint-disable-next-line @typescript-eslint/no-explicit-any
@@ -232,16 +239,17 @@ const runPipeline = async (config: PipelineConfig): Promise<void> => {
   let aggregateStream: any
 
   if (config.enableBatching) {
-    parseStream = parseClient.parseEventsBatch()
-    rulesStream = rulesClient.applyRulesBatch()
-    aggregateStream = aggregateClient.aggregateBatch()
+    parseStream = parseClient.parseEventsBatch(metadata)
+    rulesStream = rulesClient.applyRulesBatch(metadata)
+    aggregateStream = aggregateClient.aggregateBatch(metadata)
   } else {
-    parseStream = parseClient.parseEvents()
-    rulesStream = rulesClient.applyRules()
-    aggregateStream = aggregateClient.aggregate()
+    parseStream = parseClient.parseEvents(metadata)
+    rulesStream = rulesClient.applyRules(metadata)
+    aggregateStream = aggregateClient.aggregate(metadata)
   }
 
   const sinkStream = sinkClient.writeResults(
+    metadata,
     (err: Error | null, response: WriteResultsResponse | undefined) => {
       const endTime = Date.now()
       const totalDuration = endTime - startTime
@@ -315,21 +323,29 @@ const runPipeline = async (config: PipelineConfig): Promise<void> => {
       process.exit(1)
     })
 
-    // Parse receives batches
+    // Parse receives batches. Its commit offset is forwarded so rules can
+    // tell rejected events from lost ones.
     let parseBatch: ParsedEvent[] = []
+    let parseCommitted: string | undefined
+    let parseCommittedForwarded: string | undefined
 
     const flushParseBatch = () => {
-      if (parseBatch.length === 0) return
+      if (parseBatch.length === 0 && parseCommitted === parseCommittedForwarded) return
 
       const batchRequest = {
         events: parseBatch,
         batchSize: parseBatch.length,
+        upstreamCommittedSequence: parseCommitted,
       }
       rulesStream.write(batchRequest)
       parseBatch = []
+      parseCommittedForwarded = parseCommitted
     }
 
     parseStream.on('data', (response: ParseEventsBatchResponse) => {
+      if (response.committedSequence !== undefined) {
+        parseCommitted = response.committedSequence
+      }
       if (response.events) {
         parseCount += response.events.length
         parseBatch.push(...response.events)
@@ -351,21 +367,28 @@ const runPipeline = async (config: PipelineConfig): Promise<void> => {
       process.exit(1)
     })
 
-    // Rules receives batches
+    // Rules receives batches; filtered events are settled by its commit offset
     let rulesBatch: EnrichedEvent[] = []
+    let rulesCommitted: string | undefined
+    let rulesCommittedForwarded: string | undefined
 
     const flushRulesBatch = () => {
-      if (rulesBatch.length === 0) return
+      if (rulesBatch.length === 0 && rulesCommitted === rulesCommittedForwarded) return
 
       const batchRequest = {
         events: rulesBatch,
         batchSize: rulesBatch.length,
+        upstreamCommittedSequence: rulesCommitted,
       }
       aggregateStream.write(batchRequest)
       rulesBatch = []
+      rulesCommittedForwarded = rulesCommitted
     }
 
     rulesStream.on('data', (response: ApplyRulesBatchResponse) => {
+      if (response.committedSequence !== undefined) {
+        rulesCommitted = response.committedSequence
+      }
       if (response.events) {
         rulesCount += response.events.length
         rulesBatch.push(...response.events)
@@ -432,8 +455,13 @@ const runPipeline = async (config: PipelineConfig): Promise<void> => {
     })
 
     parseStream.on('data', (response: ParseEventsResponse) => {
-      parseCount += 1
-      const rulesRequest: ApplyRulesRequest = { event: response.event }
+      if (response.event) {
+        parseCount += 1
+      }
+      const rulesRequest: ApplyRulesRequest = {
+        event: response.event,
+        upstreamCommittedSequence: response.committedSequence,
+      }
       rulesStream.write(rulesRequest)
     })
 
@@ -448,8 +476,13 @@ const runPipeline = async (config: PipelineConfig): Promise<void> => {
     })
 
     rulesStream.on('data', (response: ApplyRulesResponse) => {
-      rulesCount += 1
-      const aggregateRequest: AggregateRequest = { event: response.event }
+      if (response.event) {
+        rulesCount += 1
+      }
+      const aggregateRequest: AggregateRequest = {
+        event: response.event,
+        upstreamCommittedSequence: response.committedSequence,
+      }
       aggregateStream.write(aggregateRequest)
     })
 
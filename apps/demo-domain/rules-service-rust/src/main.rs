@@ -0,0 +1,244 @@
+mod rules;
+
+use pipeline_common_rust::metrics::ServiceMetrics;
+use pipeline_common_rust::proto::pipeline::v1::rules_service_server::{
+  RulesService,
+  RulesServiceServer,
+};
+use pipeline_common_rust::proto::pipeline::v1::{
+  ApplyRulesBatchRequest,
+  ApplyRulesBatchResponse,
+  ApplyRulesRequest,
+  ApplyRulesResponse,
+  EnrichedEvent,
+};
+use pipeline_common_rust::sequence::SequenceTracker;
+use pipeline_common_rust::service::{
+  batch_span, run_service, spawn_stream, stream_activity, ActivitySender, ConfigSource,
+  ServiceDescriptor, ServiceOptions, ENV_ALIASES,
+};
+use rules::process_event;
+use std::error::Error;
+use std::time::Instant;
+use tokio::sync::mpsc;
+use tokio_stream::wrappers::ReceiverStream;
+use tonic::{Request, Response, Status};
+use tracing::warn;
+
+const DEFAULT_PORT: u16 = 6003;
+
+const DESCRIPTOR: ServiceDescriptor = ServiceDescriptor {
+  program_name: "rules-service-rust",
+  interface_name: "pipeline.v1.RulesService",
+  display_name: "Rules service",
+  version: env!("CARGO_PKG_VERSION"),
+};
+
+struct RulesServiceImpl {
+  activity: ActivitySender,
+  sequence_window: usize,
+}
+
+#[tonic::async_trait]
+impl RulesService for RulesServiceImpl {
+  type ApplyRulesStream = ReceiverStream<Result<ApplyRulesResponse, Status>>;
+  type ApplyRulesBatchStream = ReceiverStream<Result<ApplyRulesBatchResponse, Status>>;
+
+  async fn apply_rules(
+    &self,
+    request: Request<tonic::Streaming<ApplyRulesRequest>>,
+  ) -> Result<Response<Self::ApplyRulesStream>, Status> {
+    let mut activity =
+      stream_activity(&self.activity, &DESCRIPTOR, &request, "RulesService/ApplyRules");
+    let mut input = request.into_inner();
+    let (tx, rx) = mpsc::channel(128);
+    let metrics = ServiceMetrics::new("RulesService/ApplyRules");
+    let mut sequences = SequenceTracker::new(self.sequence_window, &metrics);
+
+    spawn_stream(async move {
+      loop {
+        let recv_start = Instant::now();
+        match input.message().await {
+          Ok(Some(message)) => {
+            metrics.record_recv(recv_start.elapsed().as_secs_f64() * 1000.0);
+
+            let enriched = match message.event {
+              Some(event) if sequences.accept(event.sequence) => {
+                let process_start = Instant::now();
+                let enriched = process_event(event);
+                let processing_ms = process_start.elapsed().as_secs_f64() * 1000.0;
+                metrics.record_processing(processing_ms);
+                activity.record(1, processing_ms);
+                enriched
+              }
+              _ => None,
+            };
+            if let Some(upstream) = message.upstream_committed_sequence {
+              sequences.settle_through(upstream);
+            }
+
+            if let Some(enriched) = enriched {
+              let send_start = Instant::now();
+              let send_result = tx
+                .send(Ok(ApplyRulesResponse {
+                  event: Some(enriched),
+                  committed_sequence: sequences.ack(),
+                }))
+                .await;
+              metrics.record_send(send_start.elapsed().as_secs_f64() * 1000.0);
+
+              if send_result.is_err() {
+                break;
+              }
+            }
+          }
+          Ok(None) => {
+            // Acknowledges trailing events that were filtered out.
+            sequences.finish();
+            if sequences.has_unacked() {
+              let ack = ApplyRulesResponse {
+                event: None,
+                committed_sequence: sequences.ack(),
+              };
+              let _ = tx.send(Ok(ack)).await;
+            }
+            metrics.log_summary("rules-service");
+            break;
+          }
+          Err(error) => {
+            warn!(%error, "Stream failed");
+            activity.record_error(error.to_string());
+            let _ = tx.send(Err(Status::internal(error.to_string()))).await;
+            break;
+          }
+        }
+      }
+    });
+
+    Ok(Response::new(ReceiverStream::new(rx)))
+  }
+
+  async fn apply_rules_batch(
+    &self,
+    request: Request<tonic::Streaming<ApplyRulesBatchRequest>>,
+  ) -> Result<Response<Self::ApplyRulesBatchStream>, Status> {
+    let mut activity =
+      stream_activity(&self.activity, &DESCRIPTOR, &request, "RulesService/ApplyRulesBatch");
+    let mut input = request.into_inner();
+    let (tx, rx) = mpsc::channel(128);
+    let metrics = ServiceMetrics::new("RulesService/ApplyRulesBatch");
+    let mut sequences = SequenceTracker::new(self.sequence_window, &metrics);
+
+    spawn_stream(async move {
+      loop {
+        let recv_start = Instant::now();
+        match input.message().await {
+          Ok(Some(mut message)) => {
+            metrics.record_recv(recv_start.elapsed().as_secs_f64() * 1000.0);
+
+            message.events.retain(|event| sequences.accept(event.sequence));
+            let enriched: Vec<EnrichedEvent> = if message.events.is_empty() {
+              Vec::new()
+            } else {
+              let process_start = Instant::now();
+              let count = message.events.len();
+              let batch = batch_span(count);
+              let enriched = batch.in_scope(|| {
+                message
+                  .events
+                  .into_iter()
+                  .filter_map(process_event)
+                  .collect()
+              });
+              let processing_ms = process_start.elapsed().as_secs_f64() * 1000.0;
+              metrics.record_processing_count(processing_ms, count as u64);
+              activity.record(count, processing_ms);
+              enriched
+            };
+            if let Some(upstream) = message.upstream_committed_sequence {
+              sequences.settle_through(upstream);
+            }
+
+            // Batches that were filtered out entirely still acknowledge
+            // their sequences.
+            if !enriched.is_empty() || sequences.has_unacked() {
+              let send_start = Instant::now();
+              let send_result = tx
+                .send(Ok(ApplyRulesBatchResponse {
+                  events: enriched,
+                  committed_sequence: sequences.ack(),
+                }))
+                .await;
+              metrics.record_send(send_start.elapsed().as_secs_f64() * 1000.0);
+
+              if send_result.is_err() {
+                break;
+              }
+            }
+          }
+          Ok(None) => {
+            sequences.finish();
+            if sequences.has_unacked() {
+              let ack = ApplyRulesBatchResponse {
+                events: Vec::new(),
+                committed_sequence: sequences.ack(),
+              };
+              let _ = tx.send(Ok(ack)).await;
+            }
+            metrics.log_summary("rules-service");
+            break;
+          }
+          Err(error) => {
+            warn!(%error, "Stream failed");
+            activity.record_error(error.to_string());
+            let _ = tx.send(Err(Status::internal(error.to_string()))).await;
+            break;
+          }
+        }
+      }
+    });
+
+    Ok(Response::new(ReceiverStream::new(rx)))
+  }
+}
+
+/// Returns the options and where they came from, so they can be reloaded.
+fn parse_args() -> Result<(ServiceOptions, ConfigSource), Box<dyn Error>> {
+  let args: Vec<String> = std::env::args().skip(1).collect();
+  let source = ConfigSource::from_args(&args)?;
+  let mut options = source.load(&ServiceOptions::new(DEFAULT_PORT), ENV_ALIASES)?;
+  let mut args = args.into_iter();
+
+  while let Some(arg) = args.next() {
+    if options.parse_arg(&arg, &mut args)? {
+      continue;
+    }
+    match arg.as_str() {
+      "-h" | "--help" => {
+        println!(
+          "Usage: rules-service-rust [options]\n\nOptions:\n{}  -h, --help                  Show this help message",
+          ServiceOptions::new(DEFAULT_PORT).usage()
+        );
+        std::process::exit(0);
+      }
+      _ => return Err(format!("Unknown argument: {}", arg).into()),
+    }
+  }
+
+  Ok((source.finish(options)?, source))
+}
+
+#[tokio::main]
+async fn main() -> Result<(), Box<dyn Error>> {
+  let (options, source) = parse_args()?;
+  let _tracing = options.init_logging(&DESCRIPTOR)?;
+  let sequence_window = options.sequence_window;
+  let options = source.watch(options, || Ok(parse_args()?.0));
+  run_service(options, DESCRIPTOR, |mut server, activity| {
+    server.add_service(RulesServiceServer::new(RulesServiceImpl {
+      activity,
+      sequence_window,
+    }))
+  })
+  .await
+}
@@ -0,0 +1,141 @@
+use pipeline_common_rust::proto::pipeline::v1::{enriched_event, parsed_event};
+use pipeline_common_rust::proto::pipeline::v1::{EnrichedEvent, ParsedEvent};
+use pipeline_common_rust::workitem::{enrich_work_item, ProcessedWorkItem, WORK_ITEM_EVENT_TYPE};
+use std::collections::HashMap;
+use tracing::warn;
+
+const MIN_VALUE: i64 = 10;
+
+/// Applies the demo rules, or work-item enrichment for work-item events.
+/// Returns `None` for events that are filtered out or cannot be processed.
+pub fn process_event(mut event: ParsedEvent) -> Option<EnrichedEvent> {
+  if let Some(parsed_event::Payload::WorkItem(item)) = event.payload.take() {
+    return Some(enrich_typed_work_item(event, item.into()));
+  }
+  if event.r#type != WORK_ITEM_EVENT_TYPE {
+    return apply_rules(&event);
+  }
+
+  match enrich_work_item_event(&event) {
+    Ok(enriched) => Some(enriched),
+    Err(error) => {
+      warn!(%error, "Failed to process WorkItem");
+      None
+    }
+  }
+}
+
+/// Passes events with a value of at least `MIN_VALUE` that are not views.
+pub fn apply_rules(event: &ParsedEvent) -> Option<EnrichedEvent> {
+  if event.value < MIN_VALUE || event.r#type == "view" {
+    return None;
+  }
+
+  Some(EnrichedEvent {
+    event: Some(event.clone()),
+    metadata: HashMap::from([("rule".to_string(), "min_value_and_type".to_string())]),
+    passed_rules: true,
+    payload: None,
+  })
+}
+
+/// Attaches the EnrichedWorkItem of a typed work-item payload.
+fn enrich_typed_work_item(event: ParsedEvent, item: ProcessedWorkItem) -> EnrichedEvent {
+  let enriched = enrich_work_item(&item);
+  EnrichedEvent {
+    event: Some(event),
+    metadata: work_item_metadata(),
+    passed_rules: true,
+    payload: Some(enriched_event::Payload::WorkItem(enriched.into())),
+  }
+}
+
+/// Replaces the ProcessedWorkItem in `user` with its EnrichedWorkItem, for
+/// parse stages that still send work items as JSON.
+fn enrich_work_item_event(event: &ParsedEvent) -> Result<EnrichedEvent, serde_json::Error> {
+  let processed: ProcessedWorkItem = serde_json::from_str(&event.user)?;
+  let enriched = enrich_work_item(&processed);
+
+  let mut event = event.clone();
+  event.user = serde_json::to_string(&enriched)?;
+  Ok(EnrichedEvent {
+    event: Some(event),
+    metadata: work_item_metadata(),
+    passed_rules: true,
+    payload: None,
+  })
+}
+
+fn work_item_metadata() -> HashMap<String, String> {
+  HashMap::from([("workload".to_string(), "compute-heavy".to_string())])
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use pipeline_common_rust::proto::pipeline::v1 as pb;
+
+  fn event(event_type: &str, value: i64) -> ParsedEvent {
+    ParsedEvent {
+      r#type: event_type.to_string(),
+      user: "u1".to_string(),
+      value,
+      timestamp: 0,
+      sequence: 1,
+      payload: None,
+    }
+  }
+
+  #[test]
+  fn passes_events_above_the_minimum() {
+    let enriched = apply_rules(&event("purchase", 10)).expect("event should pass");
+    assert!(enriched.passed_rules);
+    assert_eq!(enriched.metadata["rule"], "min_value_and_type");
+  }
+
+  #[test]
+  fn filters_low_values_and_views() {
+    assert!(apply_rules(&event("click", 9)).is_none());
+    assert!(apply_rules(&event("view", 100)).is_none());
+  }
+
+  #[test]
+  fn enriches_work_items_regardless_of_value() {
+    let mut work_item = event(WORK_ITEM_EVENT_TYPE, 0);
+    work_item.user = r#"{"id":"w-000001","normalized_vectors":[{"values":[0.6,0.8]}],"transposed_matrix":{"rows":[]},"checksum":1.0}"#.to_string();
+
+    let enriched = process_event(work_item).expect("work item should pass");
+    assert_eq!(enriched.metadata["workload"], "compute-heavy");
+    let user = &enriched.event.expect("event").user;
+    assert!(user.contains(r#""eigenvalues":[0.6,0.8]"#));
+  }
+
+  #[test]
+  fn enriches_typed_work_item_payloads() {
+    let mut work_item = event(WORK_ITEM_EVENT_TYPE, 0);
+    work_item.user.clear();
+    work_item.payload = Some(parsed_event::Payload::WorkItem(pb::ProcessedWorkItem {
+      id: "w-000002".to_string(),
+      normalized_vectors: vec![pb::Vector { values: vec![0.6, 0.8] }],
+      transposed_matrix: None,
+      checksum: 1.0,
+    }));
+
+    let enriched = process_event(work_item).expect("work item should pass");
+    assert!(enriched.event.expect("event").payload.is_none());
+    match enriched.payload {
+      Some(enriched_event::Payload::WorkItem(item)) => {
+        assert_eq!(item.id, "w-000002");
+        assert_eq!(item.eigenvalues, vec![0.6, 0.8]);
+      }
+      None => panic!("typed work item should stay typed"),
+    }
+  }
+
+  #[test]
+  fn drops_malformed_work_items() {
+    let mut work_item = event(WORK_ITEM_EVENT_TYPE, 0);
+    work_item.user = "not json".to_string();
+    assert!(process_event(work_item).is_none());
+  }
+}
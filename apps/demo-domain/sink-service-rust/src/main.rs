@@ -0,0 +1,167 @@
+mod output;
+
+use output::format_result;
+use pipeline_common_rust::metrics::ServiceMetrics;
+use pipeline_common_rust::proto::pipeline::v1::sink_service_server::{
+  SinkService,
+  SinkServiceServer,
+};
+use pipeline_common_rust::proto::pipeline::v1::{WriteResultsRequest, WriteResultsResponse};
+use pipeline_common_rust::service::{
+  project, run_service, stream_activity, ActivitySender, ConfigSource, ServiceDescriptor, ServiceOptions,
+  Reload, Validate, ENV_ALIASES,
+};
+use serde::{Deserialize, Serialize};
+use std::error::Error;
+use std::time::Instant;
+use tokio::fs::File;
+use tokio::io::{AsyncWriteExt, BufWriter};
+use tonic::{Request, Response, Status};
+use tracing::warn;
+
+const DEFAULT_PORT: u16 = 6005;
+const DEFAULT_OUTPUT_FILE: &str = "aggregate-results.ndjson";
+
+const DESCRIPTOR: ServiceDescriptor = ServiceDescriptor {
+  program_name: "sink-service-rust",
+  interface_name: "pipeline.v1.SinkService",
+  display_name: "Sink service",
+  version: env!("CARGO_PKG_VERSION"),
+};
+
+#[derive(Clone, Serialize, Deserialize)]
+struct SinkConfig {
+  #[serde(flatten)]
+  service: ServiceOptions,
+  output_file: String,
+}
+
+impl Reload for SinkConfig {
+  fn reload_from(&mut self, next: &Self) {
+    self.service.reload_from(&next.service);
+  }
+}
+
+impl Validate for SinkConfig {
+  fn validate(&self, errors: &mut Vec<String>) {
+    self.service.validate(errors);
+  }
+}
+
+struct SinkServiceImpl {
+  output_file: String,
+  activity: ActivitySender,
+}
+
+#[tonic::async_trait]
+impl SinkService for SinkServiceImpl {
+  async fn write_results(
+    &self,
+    request: Request<tonic::Streaming<WriteResultsRequest>>,
+  ) -> Result<Response<WriteResultsResponse>, Status> {
+    let mut activity =
+      stream_activity(&self.activity, &DESCRIPTOR, &request, "SinkService/WriteResults");
+    let mut input = request.into_inner();
+    let metrics = ServiceMetrics::new("SinkService/WriteResults");
+
+    let file = File::create(&self.output_file).await.map_err(|error| {
+      Status::internal(format!("Cannot create {}: {}", self.output_file, error))
+    })?;
+    let mut output = BufWriter::new(file);
+    let mut written: i64 = 0;
+
+    loop {
+      let recv_start = Instant::now();
+      let message = match input.message().await {
+        Ok(Some(message)) => message,
+        Ok(None) => break,
+        Err(error) => {
+          warn!(%error, "Stream failed");
+          activity.record_error(error.to_string());
+          return Err(Status::internal(error.to_string()));
+        }
+      };
+      metrics.record_recv(recv_start.elapsed().as_secs_f64() * 1000.0);
+
+      let Some(result) = message.result else {
+        continue;
+      };
+
+      let process_start = Instant::now();
+      let mut line = format_result(&result);
+      line.push('\n');
+      let processing_ms = process_start.elapsed().as_secs_f64() * 1000.0;
+      metrics.record_processing(processing_ms);
+      activity.record(1, processing_ms);
+
+      let send_start = Instant::now();
+      if let Err(error) = output.write_all(line.as_bytes()).await {
+        warn!(%error, "Writing results failed");
+        activity.record_error(error.to_string());
+        return Err(Status::internal(error.to_string()));
+      }
+      metrics.record_send(send_start.elapsed().as_secs_f64() * 1000.0);
+      written += 1;
+    }
+
+    if let Err(error) = output.flush().await {
+      warn!(%error, "Flushing results failed");
+      activity.record_error(error.to_string());
+      return Err(Status::internal(error.to_string()));
+    }
+
+    metrics.log_summary("sink-service");
+    Ok(Response::new(WriteResultsResponse { written }))
+  }
+}
+
+/// Returns the config and where it came from, so it can be reloaded.
+fn parse_args() -> Result<(SinkConfig, ConfigSource), Box<dyn Error>> {
+  let defaults = SinkConfig {
+    service: ServiceOptions::new(DEFAULT_PORT),
+    output_file: DEFAULT_OUTPUT_FILE.to_string(),
+  };
+  let args: Vec<String> = std::env::args().skip(1).collect();
+  let source = ConfigSource::from_args(&args)?;
+  let mut config = source.load(&defaults, ENV_ALIASES)?;
+  let mut args = args.into_iter();
+
+  while let Some(arg) = args.next() {
+    if config.service.parse_arg(&arg, &mut args)? {
+      continue;
+    }
+    match arg.as_str() {
+      "--output" => {
+        config.output_file = args.next().ok_or("Missing value for --output")?;
+      }
+      "-h" | "--help" => {
+        println!(
+          "Usage: sink-service-rust [options]\n\nOptions:\n{}  --output <file>             Output NDJSON file (default: {})\n  -h, --help                  Show this help message",
+          ServiceOptions::new(DEFAULT_PORT).usage(),
+          DEFAULT_OUTPUT_FILE
+        );
+        std::process::exit(0);
+      }
+      _ => return Err(format!("Unknown argument: {}", arg).into()),
+    }
+  }
+
+  Ok((source.finish(config)?, source))
+}
+
+#[tokio::main]
+async fn main() -> Result<(), Box<dyn Error>> {
+  let (config, source) = parse_args()?;
+  let _tracing = config.service.init_logging(&DESCRIPTOR)?;
+  let output_file = config.output_file.clone();
+  let config = source.watch(config, || Ok(parse_args()?.0));
+  let options = project(config, |config| config.service.clone());
+  run_service(options, DESCRIPTOR, |mut server, activity| {
+    let sink_service = SinkServiceImpl {
+      output_file,
+      activity,
+    };
+    server.add_service(SinkServiceServer::new(sink_service))
+  })
+  .await
+}
@@ -0,0 +1,71 @@
+use pipeline_common_rust::proto::pipeline::v1::AggregateResult;
+use serde::Serialize;
+use serde_json::json;
+
+/// Prefix of aggregate keys that carry a work-item result.
+const WORK_ITEM_KEY_PREFIX: &str = "w-";
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+struct WorkItemLine<'a> {
+  work_item_id: &'a str,
+  vector_checksum: i64,
+  final_score: f64,
+  timestamp: i64,
+}
+
+/// Formats one aggregate result as an NDJSON line (without the newline).
+pub fn format_result(result: &AggregateResult) -> String {
+  if result.key.starts_with(WORK_ITEM_KEY_PREFIX) {
+    let line = WorkItemLine {
+      work_item_id: &result.key,
+      vector_checksum: result.sum,
+      final_score: result.avg,
+      // The aggregate stage reuses `count` as a timestamp for work items.
+      timestamp: result.count / 1000,
+    };
+    return serde_json::to_string(&line).unwrap_or_default();
+  }
+
+  format!(
+    "{{\"key\":{},\"count\":{},\"sum\":{},\"avg\":{}}}",
+    json!(result.key),
+    result.count,
+    result.sum,
+    result.avg
+  )
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  fn result(key: &str, count: i64, sum: i64, avg: f64) -> AggregateResult {
+    AggregateResult {
+      key: key.to_string(),
+      count,
+      sum,
+      avg,
+    }
+  }
+
+  #[test]
+  fn formats_event_aggregates_like_the_node_sink() {
+    assert_eq!(
+      format_result(&result("purchase", 2, 25, 12.5)),
+      r#"{"key":"purchase","count":2,"sum":25,"avg":12.5}"#
+    );
+    assert_eq!(
+      format_result(&result("click", 1, 20, 20.0)),
+      r#"{"key":"click","count":1,"sum":20,"avg":20}"#
+    );
+  }
+
+  #[test]
+  fn formats_work_item_results() {
+    assert_eq!(
+      format_result(&result("w-000001", 0, 3, 2.5)),
+      r#"{"workItemId":"w-000001","vectorChecksum":3,"finalScore":2.5,"timestamp":0}"#
+    );
+  }
+}
@@ -0,0 +1,39 @@
+//! Prometheus text exposition helpers and the process-wide list of
+//! collectors rendered by the metrics endpoint.
+
+use std::fmt::Write;
+use std::sync::RwLock;
+
+/// Renders one group of metric families into the exposition output.
+pub type Collector = fn(&mut String);
+
+static COLLECTORS: RwLock<Vec<Collector>> = RwLock::new(Vec::new());
+
+/// Adds `collector` to the metrics endpoint output; register each one once.
+/// The gRPC metrics of [`crate::grpc`] are always included.
+pub fn register_collector(collector: Collector) {
+  COLLECTORS.write().unwrap().push(collector);
+}
+
+/// Renders the gRPC metrics followed by every registered collector.
+pub fn render() -> String {
+  let mut out = String::new();
+  crate::grpc::registry().render(&mut out);
+  for collector in COLLECTORS.read().unwrap().iter() {
+    collector(&mut out);
+  }
+  out
+}
+
+pub fn write_header(out: &mut String, name: &str, help: &str, kind: &str) {
+  let _ = writeln!(out, "# HELP {} {}", name, help);
+  let _ = writeln!(out, "# TYPE {} {}", name, kind);
+}
+
+/// Escapes a label value for use inside double quotes.
+pub fn escape_label(value: &str) -> String {
+  value
+    .replace('\\', "\\\\")
+    .replace('"', "\\\"")
+    .replace('\n', "\\n")
+}
@@ -0,0 +1,223 @@
+//! Per-method gRPC metrics, named after the `grpc_server_*` / `grpc_client_*`
+//! families of go-grpc-prometheus so existing dashboards apply.
+
+use crate::exposition::{escape_label, write_header};
+use crate::histogram::{Histogram, RPC_BUCKETS};
+use std::collections::BTreeMap;
+use std::fmt::Write;
+use std::sync::atomic::{AtomicI64, AtomicU64, Ordering};
+use std::sync::{Arc, OnceLock, RwLock};
+use std::time::Duration;
+use tonic::Code;
+
+/// Label values of `grpc_code`, indexed by the numeric status code.
+const CODE_NAMES: [&str; 17] = [
+  "OK",
+  "Canceled",
+  "Unknown",
+  "InvalidArgument",
+  "DeadlineExceeded",
+  "NotFound",
+  "AlreadyExists",
+  "PermissionDenied",
+  "ResourceExhausted",
+  "FailedPrecondition",
+  "Aborted",
+  "OutOfRange",
+  "Unimplemented",
+  "Internal",
+  "Unavailable",
+  "DataLoss",
+  "Unauthenticated",
+];
+
+/// Whether calls are handled by this process or made by it.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Side {
+  Server,
+  Client,
+}
+
+impl Side {
+  fn prefix(self) -> &'static str {
+    match self {
+      Side::Server => "grpc_server",
+      Side::Client => "grpc_client",
+    }
+  }
+}
+
+/// Counters of one gRPC method on one side.
+pub struct MethodMetrics {
+  started: AtomicU64,
+  handled: [AtomicU64; CODE_NAMES.len()],
+  in_flight: AtomicI64,
+  duration: Histogram,
+}
+
+impl Default for MethodMetrics {
+  fn default() -> Self {
+    Self {
+      started: AtomicU64::new(0),
+      handled: Default::default(),
+      in_flight: AtomicI64::new(0),
+      duration: Histogram::new(RPC_BUCKETS),
+    }
+  }
+}
+
+impl MethodMetrics {
+  pub fn start(&self) {
+    self.started.fetch_add(1, Ordering::Relaxed);
+    self.in_flight.fetch_add(1, Ordering::Relaxed);
+  }
+
+  pub fn finish(&self, code: Code, duration: Duration) {
+    self.in_flight.fetch_sub(1, Ordering::Relaxed);
+    self.handled[code as usize].fetch_add(1, Ordering::Relaxed);
+    self.duration.observe_ms(duration.as_secs_f64() * 1000.0);
+  }
+
+  pub fn started(&self) -> u64 {
+    self.started.load(Ordering::Relaxed)
+  }
+
+  pub fn handled(&self, code: Code) -> u64 {
+    self.handled[code as usize].load(Ordering::Relaxed)
+  }
+
+  pub fn in_flight(&self) -> i64 {
+    self.in_flight.load(Ordering::Relaxed)
+  }
+}
+
+/// All method metrics of the process, keyed by side and gRPC path.
+#[derive(Default)]
+pub struct GrpcRegistry {
+  methods: RwLock<BTreeMap<(Side, String), Arc<MethodMetrics>>>,
+}
+
+impl GrpcRegistry {
+  /// Returns the metrics of `path` (`/pkg.Service/Method`), creating them on first use.
+  pub fn method(&self, side: Side, path: &str) -> Arc<MethodMetrics> {
+    let key = (side, path.to_string());
+    if let Some(metrics) = self.methods.read().unwrap().get(&key) {
+      return metrics.clone();
+    }
+    self.methods.write().unwrap().entry(key).or_default().clone()
+  }
+
+  /// Appends all families of both sides in the Prometheus text format.
+  pub fn render(&self, out: &mut String) {
+    let methods = self.methods.read().unwrap();
+    for side in [Side::Server, Side::Client] {
+      let entries: Vec<(String, &MethodMetrics)> = methods
+        .iter()
+        .filter(|((entry_side, _), _)| *entry_side == side)
+        .map(|((_, path), metrics)| (method_labels(path), metrics.as_ref()))
+        .collect();
+      if entries.is_empty() {
+        continue;
+      }
+      render_side(out, side, &entries);
+    }
+  }
+}
+
+fn render_side(out: &mut String, side: Side, entries: &[(String, &MethodMetrics)]) {
+  let prefix = side.prefix();
+
+  let name = format!("{}_started_total", prefix);
+  write_header(out, &name, "Total number of RPCs started.", "counter");
+  for (labels, metrics) in entries {
+    let _ = writeln!(out, "{}{{{}}} {}", name, labels, metrics.started());
+  }
+
+  let name = format!("{}_handled_total", prefix);
+  write_header(out, &name, "Total number of RPCs completed, by status code.", "counter");
+  for (labels, metrics) in entries {
+    for (code, count) in metrics.handled.iter().enumerate() {
+      let count = count.load(Ordering::Relaxed);
+      if count > 0 {
+        let _ = writeln!(
+          out,
+          "{}{{{},grpc_code=\"{}\"}} {}",
+          name, labels, CODE_NAMES[code], count
+        );
+      }
+    }
+  }
+
+  let name = format!("{}_handling_seconds", prefix);
+  write_header(out, &name, "Latency of RPCs until the response stream ended.", "histogram");
+  for (labels, metrics) in entries {
+    metrics.duration.render(out, &name, labels);
+  }
+
+  let name = format!("{}_in_flight", prefix);
+  write_header(out, &name, "Number of RPCs currently in progress.", "gauge");
+  for (labels, metrics) in entries {
+    let _ = writeln!(out, "{}{{{}}} {}", name, labels, metrics.in_flight());
+  }
+}
+
+/// Splits `/pkg.Service/Method` into service and method name.
+pub(crate) fn split_path(path: &str) -> (&str, &str) {
+  path
+    .trim_start_matches('/')
+    .split_once('/')
+    .unwrap_or(("unknown", path))
+}
+
+/// Renders the `grpc_service` and `grpc_method` labels of a path.
+fn method_labels(path: &str) -> String {
+  let (service, method) = split_path(path);
+  format!(
+    "grpc_service=\"{}\",grpc_method=\"{}\"",
+    escape_label(service),
+    escape_label(method)
+  )
+}
+
+/// The registry shared by all layers of the process.
+pub fn registry() -> &'static GrpcRegistry {
+  static REGISTRY: OnceLock<GrpcRegistry> = OnceLock::new();
+  REGISTRY.get_or_init(GrpcRegistry::default)
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn renders_each_side_with_method_labels() {
+    let registry = GrpcRegistry::default();
+    let server = registry.method(Side::Server, "/calculator.v1.CalculatorService/Calculate");
+    server.start();
+    server.finish(Code::Ok, Duration::from_millis(3));
+    server.start();
+    server.finish(Code::InvalidArgument, Duration::from_millis(1));
+    server.start();
+
+    let mut out = String::new();
+    registry.render(&mut out);
+    let labels = "grpc_service=\"calculator.v1.CalculatorService\",grpc_method=\"Calculate\"";
+    assert!(out.contains(&format!("grpc_server_started_total{{{}}} 3\n", labels)));
+    assert!(out.contains(&format!("grpc_server_handled_total{{{},grpc_code=\"OK\"}} 1\n", labels)));
+    assert!(out.contains(&format!(
+      "grpc_server_handled_total{{{},grpc_code=\"InvalidArgument\"}} 1\n",
+      labels
+    )));
+    assert!(out.contains(&format!("grpc_server_handling_seconds_count{{{}}} 2\n", labels)));
+    assert!(out.contains(&format!("grpc_server_in_flight{{{}}} 1\n", labels)));
+    assert!(!out.contains("grpc_client_"));
+  }
+
+  #[test]
+  fn sides_are_tracked_separately() {
+    let registry = GrpcRegistry::default();
+    registry.method(Side::Client, "/a.B/C").start();
+    assert_eq!(registry.method(Side::Client, "/a.B/C").started(), 1);
+    assert_eq!(registry.method(Side::Server, "/a.B/C").started(), 0);
+  }
+}
@@ -0,0 +1,118 @@
+use std::fmt::Write;
+use std::sync::atomic::{AtomicU64, Ordering};
+
+/// Bucket bounds in seconds for in-process work, 10µs to 250ms.
+pub const LATENCY_BUCKETS: &[f64] = &[
+  0.00001, 0.000025, 0.00005, 0.0001, 0.00025, 0.0005, 0.001, 0.0025, 0.005, 0.01, 0.025, 0.05,
+  0.1, 0.25,
+];
+
+/// Bucket bounds in seconds for whole RPCs including the network, 500µs to 60s.
+pub const RPC_BUCKETS: &[f64] = &[
+  0.0005, 0.001, 0.0025, 0.005, 0.01, 0.025, 0.05, 0.1, 0.25, 0.5, 1.0, 2.5, 5.0, 10.0, 30.0, 60.0,
+];
+
+fn millis_to_nanos(duration_ms: f64) -> u64 {
+  (duration_ms.max(0.0) * 1_000_000.0) as u64
+}
+
+/// Lock-free latency histogram.
+pub struct Histogram {
+  bounds: &'static [f64],
+  /// Non-cumulative counts per bucket; the last slot is the `+Inf` overflow.
+  buckets: Box<[AtomicU64]>,
+  count: AtomicU64,
+  sum_nanos: AtomicU64,
+}
+
+impl Default for Histogram {
+  fn default() -> Self {
+    Self::new(LATENCY_BUCKETS)
+  }
+}
+
+impl Histogram {
+  /// Creates a histogram with the given ascending bucket bounds in seconds.
+  pub fn new(bounds: &'static [f64]) -> Self {
+    Self {
+      bounds,
+      buckets: (0..=bounds.len()).map(|_| AtomicU64::new(0)).collect(),
+      count: AtomicU64::new(0),
+      sum_nanos: AtomicU64::new(0),
+    }
+  }
+
+  pub fn observe_ms(&self, duration_ms: f64) {
+    let seconds = duration_ms / 1000.0;
+    let index = self
+      .bounds
+      .iter()
+      .position(|bound| seconds <= *bound)
+      .unwrap_or(self.bounds.len());
+    self.buckets[index].fetch_add(1, Ordering::Relaxed);
+    self.count.fetch_add(1, Ordering::Relaxed);
+    self.sum_nanos.fetch_add(millis_to_nanos(duration_ms), Ordering::Relaxed);
+  }
+
+  pub fn count(&self) -> u64 {
+    self.count.load(Ordering::Relaxed)
+  }
+
+  pub fn sum_ms(&self) -> f64 {
+    self.sum_nanos.load(Ordering::Relaxed) as f64 / 1_000_000.0
+  }
+
+  /// Appends the `_bucket`, `_sum` and `_count` series of `name`. `labels`
+  /// is the rendered label list without braces, e.g. `rpc="Parse"`.
+  pub fn render(&self, out: &mut String, name: &str, labels: &str) {
+    let separator = if labels.is_empty() { "" } else { "," };
+    let mut cumulative = 0;
+    for (index, bound) in self.bounds.iter().enumerate() {
+      cumulative += self.buckets[index].load(Ordering::Relaxed);
+      let _ = writeln!(
+        out,
+        "{}_bucket{{{}{}le=\"{}\"}} {}",
+        name, labels, separator, bound, cumulative
+      );
+    }
+    cumulative += self.buckets[self.bounds.len()].load(Ordering::Relaxed);
+    let _ = writeln!(out, "{}_bucket{{{}{}le=\"+Inf\"}} {}", name, labels, separator, cumulative);
+    let labels = if labels.is_empty() { String::new() } else { format!("{{{}}}", labels) };
+    let _ = writeln!(out, "{}_sum{} {}", name, labels, self.sum_ms() / 1000.0);
+    let _ = writeln!(out, "{}_count{} {}", name, labels, cumulative);
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn buckets_are_cumulative() {
+    let histogram = Histogram::default();
+    histogram.observe_ms(0.05);
+    histogram.observe_ms(2.0);
+    histogram.observe_ms(1000.0);
+
+    let mut out = String::new();
+    histogram.render(&mut out, "latency_seconds", "rpc=\"x\"");
+    assert!(out.contains("latency_seconds_bucket{rpc=\"x\",le=\"0.0001\"} 1\n"));
+    assert!(out.contains("latency_seconds_bucket{rpc=\"x\",le=\"0.0025\"} 2\n"));
+    assert!(out.contains("latency_seconds_bucket{rpc=\"x\",le=\"0.25\"} 2\n"));
+    assert!(out.contains("latency_seconds_bucket{rpc=\"x\",le=\"+Inf\"} 3\n"));
+    assert!(out.contains("latency_seconds_count{rpc=\"x\"} 3\n"));
+    assert_eq!(histogram.count(), 3);
+  }
+
+  #[test]
+  fn custom_bounds_without_labels() {
+    let histogram = Histogram::new(RPC_BUCKETS);
+    histogram.observe_ms(3000.0);
+
+    let mut out = String::new();
+    histogram.render(&mut out, "rpc_seconds", "");
+    assert!(out.contains("rpc_seconds_bucket{le=\"2.5\"} 0\n"));
+    assert!(out.contains("rpc_seconds_bucket{le=\"5\"} 1\n"));
+    assert!(out.contains("rpc_seconds_sum 3\n"));
+  }
+}
@@ -0,0 +1,306 @@
+use crate::grpc::{registry, MethodMetrics, Side};
+use http::StatusCode;
+use http_body::{Body, Frame, SizeHint};
+use pin_project_lite::pin_project;
+use std::future::Future;
+use std::pin::Pin;
+use std::sync::Arc;
+use std::task::{ready, Context, Poll};
+use std::time::Instant;
+use tonic::Code;
+use tower::{Layer, Service};
+use tracing::{debug, Span};
+
+/// Tower layer that records per-method gRPC metrics. Works for tonic servers
+/// (`Server::builder().layer(..)`) and for client channels.
+#[derive(Clone, Copy, Debug)]
+pub struct GrpcMetricsLayer {
+  side: Side,
+}
+
+impl GrpcMetricsLayer {
+  pub fn server() -> Self {
+    Self { side: Side::Server }
+  }
+
+  pub fn client() -> Self {
+    Self { side: Side::Client }
+  }
+}
+
+impl<S> Layer<S> for GrpcMetricsLayer {
+  type Service = GrpcMetricsService<S>;
+
+  fn layer(&self, inner: S) -> Self::Service {
+    GrpcMetricsService {
+      inner,
+      side: self.side,
+    }
+  }
+}
+
+/// Service produced by [`GrpcMetricsLayer`].
+#[derive(Clone, Debug)]
+pub struct GrpcMetricsService<S> {
+  inner: S,
+  side: Side,
+}
+
+impl<S, ReqBody, ResBody> Service<http::Request<ReqBody>> for GrpcMetricsService<S>
+where
+  S: Service<http::Request<ReqBody>, Response = http::Response<ResBody>>,
+{
+  type Response = http::Response<MetricsBody<ResBody>>;
+  type Error = S::Error;
+  type Future = ResponseFuture<S::Future>;
+
+  fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+    self.inner.poll_ready(cx)
+  }
+
+  fn call(&mut self, request: http::Request<ReqBody>) -> Self::Future {
+    let call = Call::start(registry().method(self.side, request.uri().path()));
+    ResponseFuture {
+      inner: self.inner.call(request),
+      call: Some(call),
+    }
+  }
+}
+
+/// One call in progress. Dropping it records the outcome in the metrics and
+/// on the span that was current at the start, i.e. the `GrpcTraceLayer`
+/// span when that layer is outside this one. A call dropped before its
+/// status was seen counts as cancelled.
+struct Call {
+  metrics: Arc<MethodMetrics>,
+  span: Span,
+  started_at: Instant,
+  code: Option<Code>,
+}
+
+impl Call {
+  fn start(metrics: Arc<MethodMetrics>) -> Self {
+    metrics.start();
+    Self {
+      metrics,
+      span: Span::current(),
+      started_at: Instant::now(),
+      code: None,
+    }
+  }
+}
+
+impl Drop for Call {
+  fn drop(&mut self) {
+    let code = self.code.unwrap_or(Code::Cancelled);
+    let elapsed = self.started_at.elapsed();
+    self.metrics.finish(code, elapsed);
+    self.span.record("rpc.grpc.status_code", code as i32);
+    if code != Code::Ok {
+      self.span.record("otel.status_code", "ERROR");
+    }
+    debug!(
+      parent: &self.span,
+      grpc_code = ?code,
+      elapsed_ms = elapsed.as_secs_f64() * 1000.0,
+      "RPC finished"
+    );
+  }
+}
+
+pin_project! {
+  /// Response future of [`GrpcMetricsService`].
+  pub struct ResponseFuture<F> {
+    #[pin]
+    inner: F,
+    call: Option<Call>,
+  }
+}
+
+impl<F, B, E> Future for ResponseFuture<F>
+where
+  F: Future<Output = Result<http::Response<B>, E>>,
+{
+  type Output = Result<http::Response<MetricsBody<B>>, E>;
+
+  fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+    let this = self.project();
+    let result = ready!(this.inner.poll(cx));
+    let mut call = this.call.take();
+    Poll::Ready(match result {
+      Ok(response) => {
+        // Trailers-only responses are complete once the headers arrive.
+        if let Some(code) = header_code(&response) {
+          if let Some(call) = call.as_mut() {
+            call.code = Some(code);
+          }
+          call = None;
+        }
+        Ok(response.map(|inner| MetricsBody { inner, call }))
+      }
+      Err(error) => {
+        // Transport failures count as UNAVAILABLE, as in the retry layer.
+        if let Some(call) = call.as_mut() {
+          call.code = Some(Code::Unavailable);
+        }
+        Err(error)
+      }
+    })
+  }
+}
+
+pin_project! {
+  /// Response body that records the call once the stream ends, so streaming
+  /// RPCs are measured until their last message.
+  pub struct MetricsBody<B> {
+    #[pin]
+    inner: B,
+    call: Option<Call>,
+  }
+}
+
+impl<B: Body> Body for MetricsBody<B> {
+  type Data = B::Data;
+  type Error = B::Error;
+
+  fn poll_frame(
+    self: Pin<&mut Self>,
+    cx: &mut Context<'_>,
+  ) -> Poll<Option<Result<Frame<Self::Data>, Self::Error>>> {
+    let this = self.project();
+    let frame = ready!(this.inner.poll_frame(cx));
+    let code = match &frame {
+      Some(Ok(frame)) => frame.trailers_ref().map(|trailers| {
+        trailers
+          .get("grpc-status")
+          .map(|value| Code::from_bytes(value.as_bytes()))
+          .unwrap_or(Code::Unknown)
+      }),
+      // A stream that errors or ends without a status has no valid outcome.
+      Some(Err(_)) | None => Some(Code::Unknown),
+    };
+    if let Some(code) = code {
+      if let Some(mut call) = this.call.take() {
+        call.code = Some(code);
+      }
+    }
+    Poll::Ready(frame)
+  }
+
+  fn is_end_stream(&self) -> bool {
+    self.inner.is_end_stream()
+  }
+
+  fn size_hint(&self) -> SizeHint {
+    self.inner.size_hint()
+  }
+}
+
+/// Status carried by the response headers: `grpc-status` of a trailers-only
+/// response, or the gRPC mapping of a non-200 HTTP status.
+fn header_code<B>(response: &http::Response<B>) -> Option<Code> {
+  if let Some(value) = response.headers().get("grpc-status") {
+    return Some(Code::from_bytes(value.as_bytes()));
+  }
+  match response.status() {
+    StatusCode::OK => None,
+    StatusCode::BAD_REQUEST => Some(Code::Internal),
+    StatusCode::UNAUTHORIZED => Some(Code::Unauthenticated),
+    StatusCode::FORBIDDEN => Some(Code::PermissionDenied),
+    StatusCode::NOT_FOUND => Some(Code::Unimplemented),
+    StatusCode::TOO_MANY_REQUESTS
+    | StatusCode::BAD_GATEWAY
+    | StatusCode::SERVICE_UNAVAILABLE
+    | StatusCode::GATEWAY_TIMEOUT => Some(Code::Unavailable),
+    _ => Some(Code::Unknown),
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use bytes::Bytes;
+  use http::{HeaderMap, HeaderValue};
+  use http_body_util::BodyExt;
+  use std::collections::VecDeque;
+  use std::convert::Infallible;
+  use tower::{service_fn, ServiceExt};
+
+  /// Body replaying a fixed list of frames.
+  struct Frames(VecDeque<Frame<Bytes>>);
+
+  impl Body for Frames {
+    type Data = Bytes;
+    type Error = Infallible;
+
+    fn poll_frame(
+      mut self: Pin<&mut Self>,
+      _cx: &mut Context<'_>,
+    ) -> Poll<Option<Result<Frame<Bytes>, Infallible>>> {
+      Poll::Ready(self.0.pop_front().map(Ok))
+    }
+  }
+
+  fn trailers(code: &'static str) -> Frame<Bytes> {
+    let mut trailers = HeaderMap::new();
+    trailers.insert("grpc-status", HeaderValue::from_static(code));
+    Frame::trailers(trailers)
+  }
+
+  async fn call(path: &str, response: fn() -> http::Response<Frames>) -> MetricsBody<Frames> {
+    let service = GrpcMetricsLayer::client().layer(service_fn(move |_| async move {
+      Ok::<_, Infallible>(response())
+    }));
+    let request = http::Request::builder().uri(path).body(()).unwrap();
+    service.oneshot(request).await.unwrap().into_body()
+  }
+
+  #[tokio::test]
+  async fn status_is_taken_from_trailers_at_end_of_stream() {
+    let path = "/test.Layer/Trailers";
+    let body = call(path, || {
+      http::Response::new(Frames(VecDeque::from([
+        Frame::data(Bytes::from_static(b"message")),
+        trailers("5"),
+      ])))
+    })
+    .await;
+
+    let metrics = registry().method(Side::Client, path);
+    assert_eq!(metrics.in_flight(), 1);
+    body.collect().await.unwrap();
+    assert_eq!(metrics.in_flight(), 0);
+    assert_eq!(metrics.handled(Code::NotFound), 1);
+  }
+
+  #[tokio::test]
+  async fn trailers_only_response_finishes_without_reading_the_body() {
+    let path = "/test.Layer/TrailersOnly";
+    let _body = call(path, || {
+      let mut response = http::Response::new(Frames(VecDeque::new()));
+      response
+        .headers_mut()
+        .insert("grpc-status", HeaderValue::from_static("14"));
+      response
+    })
+    .await;
+
+    let metrics = registry().method(Side::Client, path);
+    assert_eq!(metrics.in_flight(), 0);
+    assert_eq!(metrics.handled(Code::Unavailable), 1);
+  }
+
+  #[tokio::test]
+  async fn dropped_stream_counts_as_cancelled() {
+    let path = "/test.Layer/Dropped";
+    let body = call(path, || {
+      http::Response::new(Frames(VecDeque::from([trailers("0")])))
+    })
+    .await;
+    drop(body);
+
+    let metrics = registry().method(Side::Client, path);
+    assert_eq!(metrics.started(), 1);
+    assert_eq!(metrics.handled(Code::Cancelled), 1);
+  }
+}
@@ -0,0 +1,22 @@
+//! Observability shared by the Rust services: tower layers that record
+//! per-method gRPC metrics and spans for tonic servers and clients, the HTTP
+//! endpoint that serves the metrics in the Prometheus text format, and the
+//! `tracing` subscriber setup with W3C trace context propagation and OTLP
+//! span export.
+
+pub mod exposition;
+pub mod grpc;
+pub mod histogram;
+pub mod layer;
+pub mod logging;
+pub mod otel;
+pub mod server;
+pub mod trace;
+
+pub use grpc::Side;
+pub use histogram::Histogram;
+pub use layer::{GrpcMetricsLayer, GrpcMetricsService};
+pub use logging::{check_log_filter, init_logging, set_log_filter, LogFormat, LOG_FORMAT_ENV};
+pub use otel::{trace_id, TracingGuard, OTLP_ENDPOINT_ENV};
+pub use server::{serve_metrics, spawn_metrics_server};
+pub use trace::{GrpcTraceLayer, GrpcTraceService};
@@ -0,0 +1,149 @@
+//! Global `tracing` subscriber for the Rust binaries. The level filter comes
+//! from `RUST_LOG` (default `info`); warnings and errors go to stderr, the
+//! rest to stdout, so process supervisors can still tell them apart. With an
+//! OTLP endpoint the spans are exported as well. The filter can be replaced
+//! while running, see `set_log_filter`.
+
+use crate::otel::{otlp_layer, TracingGuard, OTLP_ENDPOINT_ENV};
+use serde::{Deserialize, Serialize};
+use std::error::Error;
+use std::io::IsTerminal;
+use std::str::FromStr;
+use std::sync::OnceLock;
+use tracing::{info, Level};
+use tracing_subscriber::filter::LevelFilter;
+use tracing_subscriber::fmt::writer::MakeWriterExt;
+use tracing_subscriber::layer::SubscriberExt;
+use tracing_subscriber::util::SubscriberInitExt;
+use tracing_subscriber::{reload, EnvFilter, Layer, Registry};
+
+/// Selects the output format when `--log-format` is not given.
+pub const LOG_FORMAT_ENV: &str = "LOG_FORMAT";
+
+const DEFAULT_FILTER: &str = "info";
+
+/// Handle to the level filter of the installed subscriber.
+static FILTER: OnceLock<reload::Handle<EnvFilter, Registry>> = OnceLock::new();
+
+/// Output format of log lines.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum LogFormat {
+  /// Human-readable lines with span context.
+  #[default]
+  Pretty,
+  /// One JSON object per line, including the current span and its parents.
+  Json,
+}
+
+impl FromStr for LogFormat {
+  type Err = String;
+
+  fn from_str(value: &str) -> Result<Self, Self::Err> {
+    match value.to_ascii_lowercase().as_str() {
+      "pretty" => Ok(LogFormat::Pretty),
+      "json" => Ok(LogFormat::Json),
+      _ => Err(format!("Unknown log format '{}', expected pretty or json", value)),
+    }
+  }
+}
+
+/// Installs the global subscriber. An explicit `format` wins over
+/// `LOG_FORMAT`, an explicit `otlp_endpoint` over
+/// `OTEL_EXPORTER_OTLP_ENDPOINT`; spans are exported as `service_name`.
+pub fn init_logging(
+  service_name: &str,
+  format: Option<LogFormat>,
+  otlp_endpoint: Option<&str>,
+) -> Result<TracingGuard, Box<dyn Error>> {
+  let format = match format {
+    Some(format) => format,
+    None => match std::env::var(LOG_FORMAT_ENV) {
+      Ok(value) => value.parse()?,
+      Err(_) => LogFormat::default(),
+    },
+  };
+  let otlp_endpoint = match otlp_endpoint {
+    Some(endpoint) => Some(endpoint.to_string()),
+    None => std::env::var(OTLP_ENDPOINT_ENV).ok().filter(|value| !value.is_empty()),
+  };
+  let (filter, filter_handle) = reload::Layer::new(default_filter());
+  let writer = std::io::stderr
+    .with_max_level(Level::WARN)
+    .or_else(std::io::stdout);
+  let output = tracing_subscriber::fmt::layer().with_writer(writer);
+  let output = match format {
+    LogFormat::Pretty => output.with_ansi(std::io::stdout().is_terminal()).boxed(),
+    LogFormat::Json => output
+      .json()
+      .with_current_span(true)
+      .with_span_list(true)
+      .boxed(),
+  };
+
+  let (export, guard) = match otlp_endpoint.as_deref() {
+    Some(endpoint) => {
+      let (layer, guard) = otlp_layer(service_name, endpoint)?;
+      // Debug spans of the transport crates would be exported by the very
+      // exporter they trace, so only INFO and above leave the process.
+      (Some(layer.with_filter(LevelFilter::INFO)), guard)
+    }
+    None => (None, TracingGuard::default()),
+  };
+
+  tracing_subscriber::registry()
+    .with(filter)
+    .with(output)
+    .with(export)
+    .try_init()?;
+  let _ = FILTER.set(filter_handle);
+  if let Some(endpoint) = otlp_endpoint {
+    info!("Exporting spans to {}", endpoint);
+  }
+  Ok(guard)
+}
+
+/// Replaces the level filter of the installed subscriber with `directives`
+/// in `RUST_LOG` syntax, e.g. `debug` or `info,h2=warn`. `None` goes back to
+/// `RUST_LOG`, or `info` without it.
+pub fn set_log_filter(directives: Option<&str>) -> Result<(), Box<dyn Error>> {
+  let filter = match directives {
+    Some(directives) => EnvFilter::try_new(directives)?,
+    None => default_filter(),
+  };
+  FILTER
+    .get()
+    .ok_or("Logging is not initialized")?
+    .reload(filter)?;
+  Ok(())
+}
+
+/// Checks `directives` for `set_log_filter` without applying them.
+pub fn check_log_filter(directives: &str) -> Result<(), String> {
+  EnvFilter::try_new(directives)
+    .map(drop)
+    .map_err(|error| format!("Invalid log filter '{}': {}", directives, error))
+}
+
+fn default_filter() -> EnvFilter {
+  EnvFilter::try_from_default_env().unwrap_or_else(|_| EnvFilter::new(DEFAULT_FILTER))
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn parses_formats_case_insensitively() {
+    assert_eq!("json".parse::<LogFormat>(), Ok(LogFormat::Json));
+    assert_eq!("Pretty".parse::<LogFormat>(), Ok(LogFormat::Pretty));
+    assert!("xml".parse::<LogFormat>().is_err());
+  }
+
+  #[test]
+  fn checks_filter_directives() {
+    assert!(check_log_filter("info,h2=warn").is_ok());
+    assert!(check_log_filter("info,h2=loud").is_err());
+    assert!(set_log_filter(Some("debug")).is_err());
+  }
+}
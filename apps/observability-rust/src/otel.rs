@@ -0,0 +1,151 @@
+//! OpenTelemetry integration: W3C trace context (`traceparent`) carried in
+//! gRPC metadata, and OTLP export of the `tracing` spans. Without an OTLP
+//! endpoint no OpenTelemetry layer is installed and propagation is a no-op.
+
+use http::header::{HeaderMap, HeaderName, HeaderValue};
+use opentelemetry::propagation::{Extractor, Injector, TextMapPropagator};
+use opentelemetry::trace::{TraceContextExt, TraceError, TracerProvider as _};
+use opentelemetry::{Context, KeyValue};
+use opentelemetry_otlp::{SpanExporter, WithExportConfig};
+use opentelemetry_sdk::propagation::TraceContextPropagator;
+use opentelemetry_sdk::trace::{Tracer, TracerProvider};
+use opentelemetry_sdk::{runtime, Resource};
+use tracing::{warn, Span};
+use tracing_opentelemetry::{OpenTelemetryLayer, OpenTelemetrySpanExt};
+use tracing_subscriber::registry::LookupSpan;
+
+/// OTLP/gRPC collector endpoint used when `--otlp-endpoint` is not given,
+/// e.g. `http://127.0.0.1:4317`.
+pub const OTLP_ENDPOINT_ENV: &str = "OTEL_EXPORTER_OTLP_ENDPOINT";
+
+/// Keeps the OTLP exporter alive. Dropping it flushes the spans that are
+/// still buffered, so hold it until the end of `main`.
+#[must_use = "dropping the guard shuts down span export"]
+#[derive(Debug, Default)]
+pub struct TracingGuard {
+  provider: Option<TracerProvider>,
+}
+
+impl Drop for TracingGuard {
+  fn drop(&mut self) {
+    if let Some(provider) = self.provider.take() {
+      if let Err(error) = provider.shutdown() {
+        warn!(%error, "Flushing spans failed");
+      }
+    }
+  }
+}
+
+/// Builds the layer that exports spans of `service_name` to `endpoint` in
+/// batches. Must be called inside a Tokio runtime.
+pub(crate) fn otlp_layer<S>(
+  service_name: &str,
+  endpoint: &str,
+) -> Result<(OpenTelemetryLayer<S, Tracer>, TracingGuard), TraceError>
+where
+  S: tracing::Subscriber + for<'span> LookupSpan<'span>,
+{
+  let exporter = SpanExporter::builder()
+    .with_tonic()
+    .with_endpoint(endpoint)
+    .build()?;
+  let provider = TracerProvider::builder()
+    .with_batch_exporter(exporter, runtime::Tokio)
+    .with_resource(Resource::new([KeyValue::new(
+      "service.name",
+      service_name.to_string(),
+    )]))
+    .build();
+  let layer = tracing_opentelemetry::layer().with_tracer(provider.tracer(env!("CARGO_PKG_NAME")));
+  Ok((
+    layer,
+    TracingGuard {
+      provider: Some(provider),
+    },
+  ))
+}
+
+/// Makes `span` a child of the remote span in the `traceparent` header, if any.
+pub(crate) fn extract_parent(span: &Span, headers: &HeaderMap) {
+  let parent = TraceContextPropagator::new().extract(&HeaderExtractor(headers));
+  if parent.span().span_context().is_valid() {
+    span.set_parent(parent);
+  }
+}
+
+/// Writes `traceparent` (and `tracestate`) of `span` into `headers`.
+pub(crate) fn inject_context(span: &Span, headers: &mut HeaderMap) {
+  TraceContextPropagator::new().inject_context(&span.context(), &mut HeaderInjector(headers));
+}
+
+/// Hex trace id of `span`, or `None` when spans are not exported.
+pub fn trace_id(span: &Span) -> Option<String> {
+  span_trace_id(&span.context())
+}
+
+fn span_trace_id(context: &Context) -> Option<String> {
+  let span_context = context.span().span_context().clone();
+  span_context
+    .is_valid()
+    .then(|| span_context.trace_id().to_string())
+}
+
+struct HeaderExtractor<'a>(&'a HeaderMap);
+
+impl Extractor for HeaderExtractor<'_> {
+  fn get(&self, key: &str) -> Option<&str> {
+    self.0.get(key).and_then(|value| value.to_str().ok())
+  }
+
+  fn keys(&self) -> Vec<&str> {
+    self.0.keys().map(HeaderName::as_str).collect()
+  }
+}
+
+struct HeaderInjector<'a>(&'a mut HeaderMap);
+
+impl Injector for HeaderInjector<'_> {
+  fn set(&mut self, key: &str, value: String) {
+    let name = HeaderName::from_bytes(key.as_bytes());
+    let value = HeaderValue::from_str(&value);
+    if let (Ok(name), Ok(value)) = (name, value) {
+      self.0.insert(name, value);
+    }
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  const TRACEPARENT: &str = "00-4bf92f3577b34da6a3ce929d0e0e4736-00f067aa0ba902b7-01";
+
+  #[test]
+  fn extracts_and_injects_traceparent() {
+    let mut headers = HeaderMap::new();
+    headers.insert("traceparent", HeaderValue::from_static(TRACEPARENT));
+    let context = TraceContextPropagator::new().extract(&HeaderExtractor(&headers));
+    assert_eq!(
+      span_trace_id(&context).as_deref(),
+      Some("4bf92f3577b34da6a3ce929d0e0e4736")
+    );
+
+    let mut injected = HeaderMap::new();
+    TraceContextPropagator::new().inject_context(&context, &mut HeaderInjector(&mut injected));
+    assert_eq!(injected.get("traceparent").unwrap(), TRACEPARENT);
+  }
+
+  #[test]
+  fn missing_or_malformed_header_has_no_trace() {
+    let mut headers = HeaderMap::new();
+    assert_eq!(
+      span_trace_id(&TraceContextPropagator::new().extract(&HeaderExtractor(&headers))),
+      None
+    );
+    headers.insert("traceparent", HeaderValue::from_static("00-zz-00f067aa0ba902b7-01"));
+    assert_eq!(
+      span_trace_id(&TraceContextPropagator::new().extract(&HeaderExtractor(&headers))),
+      None
+    );
+  }
+}
@@ -0,0 +1,67 @@
+use crate::exposition::render;
+use bytes::Bytes;
+use http_body_util::Full;
+use hyper::body::Incoming;
+use hyper::header::CONTENT_TYPE;
+use hyper::server::conn::http1;
+use hyper::service::service_fn;
+use hyper::{Method, Request, Response, StatusCode};
+use hyper_util::rt::TokioIo;
+use std::convert::Infallible;
+use std::io;
+use tokio::net::{TcpListener, ToSocketAddrs};
+use tokio::sync::watch;
+use tokio::task::JoinHandle;
+use tracing::{info, info_span, warn, Instrument};
+
+const PROMETHEUS_CONTENT_TYPE: &str = "text/plain; version=0.0.4; charset=utf-8";
+
+/// Binds `addr` and serves `/metrics` in the background until `shutdown` is set.
+pub async fn spawn_metrics_server(
+  addr: impl ToSocketAddrs,
+  shutdown: watch::Receiver<bool>,
+) -> io::Result<JoinHandle<()>> {
+  let listener = TcpListener::bind(addr).await?;
+  let local_addr = listener.local_addr()?;
+  info!("Metrics available at http://{}/metrics", local_addr);
+  let span = info_span!("metrics_server", address = %local_addr);
+  Ok(tokio::spawn(serve_metrics(listener, shutdown).instrument(span)))
+}
+
+/// Serves `GET /metrics` from the registered collectors until `shutdown` is set.
+pub async fn serve_metrics(listener: TcpListener, mut shutdown: watch::Receiver<bool>) {
+  loop {
+    tokio::select! {
+      accepted = listener.accept() => match accepted {
+        Ok((stream, _)) => {
+          tokio::spawn(async move {
+            let connection = http1::Builder::new()
+              .serve_connection(TokioIo::new(stream), service_fn(handle));
+            if let Err(error) = connection.await {
+              warn!(%error, "Metrics connection failed");
+            }
+          }.in_current_span());
+        }
+        Err(error) => warn!(%error, "Metrics accept failed"),
+      },
+      changed = shutdown.changed() => {
+        if changed.is_err() || *shutdown.borrow() {
+          break;
+        }
+      }
+    }
+  }
+}
+
+async fn handle(request: Request<Incoming>) -> Result<Response<Full<Bytes>>, Infallible> {
+  let response = if request.method() == Method::GET && request.uri().path() == "/metrics" {
+    Response::builder()
+      .header(CONTENT_TYPE, PROMETHEUS_CONTENT_TYPE)
+      .body(Full::from(render()))
+  } else {
+    Response::builder()
+      .status(StatusCode::NOT_FOUND)
+      .body(Full::from("Not found\n"))
+  };
+  Ok(response.expect("static response parts are valid"))
+}
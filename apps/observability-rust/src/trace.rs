@@ -0,0 +1,179 @@
+use crate::grpc::{split_path, Side};
+use crate::otel::{extract_parent, inject_context, trace_id};
+use tonic::transport::server::TcpConnectInfo;
+use tower::{Layer, Service};
+use tracing::instrument::Instrumented;
+use tracing::{field, info_span, Instrument, Span};
+
+/// Tower layer that runs each gRPC call inside a `grpc_server` or
+/// `grpc_client` span. Handler code and tasks spawned with the current span
+/// nest under it; the metrics layer records the final status on it. Server
+/// spans continue the trace of an incoming `traceparent` header, client
+/// spans send theirs along with the request.
+#[derive(Clone, Copy, Debug)]
+pub struct GrpcTraceLayer {
+  side: Side,
+}
+
+impl GrpcTraceLayer {
+  pub fn server() -> Self {
+    Self { side: Side::Server }
+  }
+
+  pub fn client() -> Self {
+    Self { side: Side::Client }
+  }
+}
+
+impl<S> Layer<S> for GrpcTraceLayer {
+  type Service = GrpcTraceService<S>;
+
+  fn layer(&self, inner: S) -> Self::Service {
+    GrpcTraceService {
+      inner,
+      side: self.side,
+    }
+  }
+}
+
+/// Service produced by [`GrpcTraceLayer`].
+#[derive(Clone, Debug)]
+pub struct GrpcTraceService<S> {
+  inner: S,
+  side: Side,
+}
+
+impl<S, ReqBody> Service<http::Request<ReqBody>> for GrpcTraceService<S>
+where
+  S: Service<http::Request<ReqBody>>,
+{
+  type Response = S::Response;
+  type Error = S::Error;
+  type Future = Instrumented<S::Future>;
+
+  fn poll_ready(
+    &mut self,
+    cx: &mut std::task::Context<'_>,
+  ) -> std::task::Poll<Result<(), Self::Error>> {
+    self.inner.poll_ready(cx)
+  }
+
+  fn call(&mut self, mut request: http::Request<ReqBody>) -> Self::Future {
+    let span = rpc_span(self.side, &request);
+    match self.side {
+      Side::Server => extract_parent(&span, request.headers()),
+      Side::Client => inject_context(&span, request.headers_mut()),
+    }
+    if let Some(trace_id) = trace_id(&span) {
+      span.record("trace_id", trace_id);
+    }
+    let future = span.in_scope(|| self.inner.call(request));
+    future.instrument(span)
+  }
+}
+
+fn rpc_span<B>(side: Side, request: &http::Request<B>) -> Span {
+  let (service, method) = split_path(request.uri().path());
+  match side {
+    Side::Server => {
+      let span = info_span!(
+        "grpc_server",
+        otel.kind = "server",
+        rpc.service = service,
+        rpc.method = method,
+        peer.address = field::Empty,
+        rpc.grpc.status_code = field::Empty,
+        otel.status_code = field::Empty,
+        trace_id = field::Empty,
+      );
+      let peer = request
+        .extensions()
+        .get::<TcpConnectInfo>()
+        .and_then(|info| info.remote_addr());
+      if let Some(peer) = peer {
+        span.record("peer.address", field::display(peer));
+      }
+      span
+    }
+    Side::Client => info_span!(
+      "grpc_client",
+      otel.kind = "client",
+      rpc.service = service,
+      rpc.method = method,
+      server.address = request.uri().authority().map(|a| a.as_str()).unwrap_or(""),
+      rpc.grpc.status_code = field::Empty,
+      otel.status_code = field::Empty,
+      trace_id = field::Empty,
+    ),
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use opentelemetry::trace::TracerProvider as _;
+  use opentelemetry_sdk::trace::TracerProvider;
+  use std::convert::Infallible;
+  use std::future::{ready, Ready};
+  use std::sync::{Arc, Mutex};
+  use tower::service_fn;
+  use tracing_subscriber::layer::SubscriberExt;
+
+  const TRACEPARENT: &str = "00-4bf92f3577b34da6a3ce929d0e0e4736-00f067aa0ba902b7-01";
+
+  /// Runs `test` with an OpenTelemetry layer that records but exports nothing.
+  fn with_tracer(test: impl FnOnce()) {
+    let provider = TracerProvider::builder().build();
+    let subscriber = tracing_subscriber::registry()
+      .with(tracing_opentelemetry::layer().with_tracer(provider.tracer("test")));
+    tracing::subscriber::with_default(subscriber, test);
+  }
+
+  fn request(traceparent: Option<&'static str>) -> http::Request<()> {
+    let mut builder = http::Request::builder().uri("/test.Trace/Call");
+    if let Some(traceparent) = traceparent {
+      builder = builder.header("traceparent", traceparent);
+    }
+    builder.body(()).unwrap()
+  }
+
+  fn respond() -> Ready<Result<(), Infallible>> {
+    ready(Ok(()))
+  }
+
+  #[test]
+  fn server_span_continues_the_incoming_trace() {
+    with_tracer(|| {
+      let seen = Arc::new(Mutex::new(None));
+      let captured = seen.clone();
+      let mut service = GrpcTraceLayer::server().layer(service_fn(move |_| {
+        *captured.lock().unwrap() = trace_id(&Span::current());
+        respond()
+      }));
+
+      drop(service.call(request(Some(TRACEPARENT))));
+      assert_eq!(
+        seen.lock().unwrap().as_deref(),
+        Some("4bf92f3577b34da6a3ce929d0e0e4736")
+      );
+    });
+  }
+
+  #[test]
+  fn client_span_sends_its_trace_context() {
+    with_tracer(|| {
+      let seen = Arc::new(Mutex::new(None));
+      let captured = seen.clone();
+      let mut service = GrpcTraceLayer::client().layer(service_fn(move |request: http::Request<()>| {
+        *captured.lock().unwrap() = request.headers().get("traceparent").cloned();
+        respond()
+      }));
+
+      let root = info_span!("root");
+      drop(root.in_scope(|| service.call(request(None))));
+      let traceparent = seen.lock().unwrap().clone().expect("traceparent header");
+      let expected = format!("00-{}-", trace_id(&root).unwrap());
+      assert!(traceparent.to_str().unwrap().starts_with(&expected));
+    });
+  }
+}
@@ -0,0 +1,68 @@
+use std::error::Error;
+use std::fmt;
+use std::io;
+use std::path::PathBuf;
+
+/// Why the configuration of a binary could not be loaded.
+pub enum ConfigError {
+  /// `--config` without a file name.
+  MissingFile,
+  /// The config file could not be read.
+  Read { path: PathBuf, source: io::Error },
+  /// The extension is neither `.toml` nor `.yaml`/`.yml`.
+  UnsupportedFormat(PathBuf),
+  /// The config file is not valid TOML or YAML.
+  Parse { path: PathBuf, message: String },
+  /// The config file sets a key the binary does not have, as a dotted path.
+  UnknownKey { path: PathBuf, key: String },
+  /// An environment variable does not hold a value of the key's type.
+  Env { variable: String, message: String },
+  /// Settings out of range, one message each.
+  Invalid(Vec<String>),
+}
+
+impl ConfigError {
+  pub(crate) fn invalid(error: impl fmt::Display) -> Self {
+    ConfigError::Invalid(vec![error.to_string()])
+  }
+}
+
+impl fmt::Display for ConfigError {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    match self {
+      ConfigError::MissingFile => write!(f, "Missing value for --config"),
+      ConfigError::Read { path, source } => {
+        write!(f, "Cannot read config file {}: {}", path.display(), source)
+      }
+      ConfigError::UnsupportedFormat(path) => write!(
+        f,
+        "Unsupported config file {}, expected .toml, .yaml or .yml",
+        path.display()
+      ),
+      ConfigError::Parse { path, message } => {
+        write!(f, "Invalid config file {}: {}", path.display(), message)
+      }
+      ConfigError::UnknownKey { path, key } => {
+        write!(f, "Unknown key '{}' in config file {}", key, path.display())
+      }
+      ConfigError::Env { variable, message } => write!(f, "Invalid {}: {}", variable, message),
+      ConfigError::Invalid(errors) => write!(f, "Invalid configuration: {}", errors.join("; ")),
+    }
+  }
+}
+
+// `main` reports returned errors with `Debug`; show the message there too.
+impl fmt::Debug for ConfigError {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    fmt::Display::fmt(self, f)
+  }
+}
+
+impl Error for ConfigError {
+  fn source(&self) -> Option<&(dyn Error + 'static)> {
+    match self {
+      ConfigError::Read { source, .. } => Some(source),
+      _ => None,
+    }
+  }
+}
@@ -0,0 +1,155 @@
+//! Merging of the config layers on the JSON form of the configuration. The
+//! serialized defaults give every key and its type, so the file can be
+//! checked for unknown keys and environment strings can be typed. Reloads
+//! compare configurations in the same form.
+
+use crate::{ConfigError, EnvAlias, ENV_PREFIX};
+use serde_json::{Number, Value};
+use std::collections::BTreeSet;
+use std::path::Path;
+
+/// Reads a TOML or YAML config file. An empty file sets nothing.
+pub(crate) fn read_file(path: &Path) -> Result<Value, ConfigError> {
+  let extension = path
+    .extension()
+    .and_then(|extension| extension.to_str())
+    .map(str::to_ascii_lowercase);
+  let text = std::fs::read_to_string(path).map_err(|source| ConfigError::Read {
+    path: path.to_path_buf(),
+    source,
+  })?;
+  let parsed = match extension.as_deref() {
+    Some("toml") => toml::from_str::<Value>(&text).map_err(|error| error.to_string()),
+    Some("yaml" | "yml") => serde_yaml::from_str::<Value>(&text).map_err(|error| error.to_string()),
+    _ => return Err(ConfigError::UnsupportedFormat(path.to_path_buf())),
+  };
+  parsed.map_err(|message| ConfigError::Parse {
+    path: path.to_path_buf(),
+    message,
+  })
+}
+
+/// Merges `overlay` into `base` key by key. Tables the defaults leave empty
+/// are free-form maps and take any key; elsewhere a key missing from `base`
+/// is returned as the error, as a dotted path below `prefix`.
+pub(crate) fn merge(base: &mut Value, overlay: Value, prefix: &str) -> Result<(), String> {
+  match (base, overlay) {
+    (_, Value::Null) if prefix.is_empty() => {}
+    (Value::Object(base), Value::Object(overlay)) => {
+      let open = base.is_empty();
+      for (key, value) in overlay {
+        let path = if prefix.is_empty() {
+          key.clone()
+        } else {
+          format!("{}.{}", prefix, key)
+        };
+        match base.get_mut(&key) {
+          Some(slot) => merge(slot, value, &path)?,
+          None if open => {
+            base.insert(key, value);
+          }
+          None => return Err(path),
+        }
+      }
+    }
+    (slot, value) => *slot = value,
+  }
+  Ok(())
+}
+
+/// Applies the legacy `aliases`, then the `RUNTIME_` variables of every key.
+pub(crate) fn apply_env(
+  config: &mut Value,
+  aliases: &[EnvAlias],
+  env: &dyn Fn(&str) -> Option<String>,
+) -> Result<(), ConfigError> {
+  if let Value::Object(entries) = config {
+    for (key, variable) in aliases {
+      let (Some(slot), Some(raw)) = (entries.get_mut(*key), env(variable)) else {
+        continue;
+      };
+      *slot = coerce(slot, &raw).map_err(|message| ConfigError::Env {
+        variable: variable.to_string(),
+        message,
+      })?;
+    }
+  }
+  apply_prefixed(config, ENV_PREFIX, env)
+}
+
+fn apply_prefixed(
+  value: &mut Value,
+  prefix: &str,
+  env: &dyn Fn(&str) -> Option<String>,
+) -> Result<(), ConfigError> {
+  let Value::Object(entries) = value else {
+    return Ok(());
+  };
+  for (key, slot) in entries.iter_mut() {
+    let variable = format!("{}{}", prefix, key.to_ascii_uppercase());
+    if matches!(slot, Value::Object(nested) if !nested.is_empty()) {
+      apply_prefixed(slot, &format!("{}__", variable), env)?;
+    } else if let Some(raw) = env(&variable) {
+      *slot = coerce(slot, &raw).map_err(|message| ConfigError::Env { variable, message })?;
+    }
+  }
+  Ok(())
+}
+
+/// Types an environment string like the value it replaces. Keys without a
+/// default are read as a YAML scalar; an empty string clears them.
+fn coerce(current: &Value, raw: &str) -> Result<Value, String> {
+  match current {
+    Value::String(_) => Ok(Value::String(raw.to_string())),
+    Value::Bool(_) => match raw.trim().to_ascii_lowercase().as_str() {
+      "true" | "1" | "yes" | "on" => Ok(Value::Bool(true)),
+      "false" | "0" | "no" | "off" => Ok(Value::Bool(false)),
+      _ => Err(format!("expected true or false, got '{}'", raw)),
+    },
+    Value::Number(_) => serde_json::from_str::<Number>(raw.trim())
+      .map(Value::Number)
+      .map_err(|_| format!("expected a number, got '{}'", raw)),
+    _ if raw.is_empty() => Ok(Value::Null),
+    _ => serde_yaml::from_str::<Value>(raw).map_err(|error| error.to_string()),
+  }
+}
+
+/// A setting that differs between two configurations, as a dotted key.
+#[derive(Debug, PartialEq)]
+pub(crate) struct Change {
+  pub key: String,
+  pub from: Value,
+  pub to: Value,
+}
+
+/// Settings that differ between `from` and `to`, nested tables compared key
+/// by key.
+pub(crate) fn diff(from: &Value, to: &Value) -> Vec<Change> {
+  let mut changes = Vec::new();
+  diff_into(from, to, "", &mut changes);
+  changes
+}
+
+fn diff_into(from: &Value, to: &Value, prefix: &str, changes: &mut Vec<Change>) {
+  match (from, to) {
+    (Value::Object(from), Value::Object(to)) => {
+      let keys: BTreeSet<&String> = from.keys().chain(to.keys()).collect();
+      for key in keys {
+        let path = if prefix.is_empty() {
+          key.clone()
+        } else {
+          format!("{}.{}", prefix, key)
+        };
+        let from = from.get(key).unwrap_or(&Value::Null);
+        let to = to.get(key).unwrap_or(&Value::Null);
+        diff_into(from, to, &path, changes);
+      }
+    }
+    _ if from != to => changes.push(Change {
+      key: prefix.to_string(),
+      from: from.clone(),
+      to: to.clone(),
+    }),
+    _ => {}
+  }
+}
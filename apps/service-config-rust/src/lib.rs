@@ -0,0 +1,293 @@
+//! Layered configuration shared by the Rust binaries. Every setting starts
+//! from the compiled-in default and is overridden, key by key, by the config
+//! file (TOML or YAML, picked by the extension), then by the environment,
+//! then by command-line flags. `--print-config` dumps the merged result in
+//! the shape of the config file, so it can seed one. Running binaries reload
+//! the settings that allow it, see `reload`.
+
+mod error;
+mod layer;
+pub mod reload;
+
+pub use error::ConfigError;
+pub use reload::{project, Reload};
+
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+use std::path::PathBuf;
+
+/// Config file used when `--config` is not given.
+pub const CONFIG_FILE_ENV: &str = "RUNTIME_CONFIG";
+
+/// Prefix of the environment variables that override single settings, e.g.
+/// `RUNTIME_PORT=6002`. Nested keys are joined with `__`, as in
+/// `RUNTIME_DEAD_LETTER__FILE`.
+pub const ENV_PREFIX: &str = "RUNTIME_";
+
+/// Environment variable that sets a top-level key in addition to its
+/// `RUNTIME_` variable, as `(key, variable)`. Used for the variables the
+/// binaries read before the shared layer existed; the `RUNTIME_` variable
+/// wins when both are set.
+pub type EnvAlias = (&'static str, &'static str);
+
+/// Range and consistency checks of a configuration, run once every layer
+/// including the command line is applied.
+pub trait Validate {
+  /// Appends one message per invalid setting.
+  fn validate(&self, errors: &mut Vec<String>);
+}
+
+/// The config file and dump request of one binary run.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct ConfigSource {
+  /// TOML or YAML file layered over the defaults; `None` for none.
+  pub file: Option<PathBuf>,
+  /// Print the effective configuration and exit.
+  pub print: bool,
+}
+
+impl ConfigSource {
+  /// Uses `file`, falling back to `RUNTIME_CONFIG`.
+  pub fn new(file: Option<PathBuf>, print: bool) -> Self {
+    let file = file.or_else(|| {
+      std::env::var_os(CONFIG_FILE_ENV)
+        .filter(|value| !value.is_empty())
+        .map(PathBuf::from)
+    });
+    Self { file, print }
+  }
+
+  /// Picks `--config <file>` and `--print-config` out of hand-parsed
+  /// arguments; the caller still sees and skips them.
+  pub fn from_args(args: &[String]) -> Result<Self, ConfigError> {
+    let mut file = None;
+    let mut print = false;
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+      match arg.as_str() {
+        "--config" => file = Some(args.next().ok_or(ConfigError::MissingFile)?.into()),
+        "--print-config" => print = true,
+        _ => {}
+      }
+    }
+    Ok(Self::new(file, print))
+  }
+
+  /// Layers the config file and the environment over `defaults`. Keys the
+  /// defaults do not have are rejected, so typos do not go unnoticed.
+  pub fn load<T>(&self, defaults: &T, aliases: &[EnvAlias]) -> Result<T, ConfigError>
+  where
+    T: Serialize + DeserializeOwned,
+  {
+    self.load_with(defaults, aliases, |variable| std::env::var(variable).ok())
+  }
+
+  fn load_with<T>(
+    &self,
+    defaults: &T,
+    aliases: &[EnvAlias],
+    env: impl Fn(&str) -> Option<String>,
+  ) -> Result<T, ConfigError>
+  where
+    T: Serialize + DeserializeOwned,
+  {
+    let mut config = serde_json::to_value(defaults).map_err(ConfigError::invalid)?;
+    if let Some(path) = &self.file {
+      let file = layer::read_file(path)?;
+      layer::merge(&mut config, file, "").map_err(|key| ConfigError::UnknownKey {
+        path: path.clone(),
+        key,
+      })?;
+    }
+    layer::apply_env(&mut config, aliases, &env)?;
+    serde_json::from_value(config).map_err(ConfigError::invalid)
+  }
+
+  /// Validates `config` once the command line is applied. With
+  /// `--print-config` it is printed as YAML and the process exits.
+  pub fn finish<T>(&self, config: T) -> Result<T, ConfigError>
+  where
+    T: Serialize + Validate,
+  {
+    let mut errors = Vec::new();
+    config.validate(&mut errors);
+    if !errors.is_empty() {
+      return Err(ConfigError::Invalid(errors));
+    }
+    if self.print {
+      print!("{}", serde_yaml::to_string(&config).map_err(ConfigError::invalid)?);
+      std::process::exit(0);
+    }
+    Ok(config)
+  }
+}
+
+/// Help text for the options every binary shares, in the layout of the
+/// pipeline services' usage.
+pub fn usage() -> String {
+  format!(
+    "  --config <file>             TOML or YAML config file (default: ${})\n  --print-config              Print the effective configuration and exit\n",
+    CONFIG_FILE_ENV
+  )
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use serde::Deserialize;
+  use std::collections::HashMap;
+
+  #[derive(Debug, PartialEq, Serialize, Deserialize)]
+  struct Settings {
+    port: u16,
+    broker_address: String,
+    broker_enabled: bool,
+    metrics_port: Option<u16>,
+    dead_letter: DeadLetter,
+  }
+
+  #[derive(Debug, PartialEq, Serialize, Deserialize)]
+  struct DeadLetter {
+    file: Option<String>,
+    stream: bool,
+  }
+
+  impl Validate for Settings {
+    fn validate(&self, errors: &mut Vec<String>) {
+      if self.port == 0 {
+        errors.push("port must not be 0".to_string());
+      }
+    }
+  }
+
+  fn defaults() -> Settings {
+    Settings {
+      port: 6002,
+      broker_address: "127.0.0.1:50051".to_string(),
+      broker_enabled: true,
+      metrics_port: None,
+      dead_letter: DeadLetter {
+        file: None,
+        stream: false,
+      },
+    }
+  }
+
+  fn config_file(name: &str, text: &str) -> ConfigSource {
+    let path = std::env::temp_dir().join(format!("service-config-{}-{}", std::process::id(), name));
+    std::fs::write(&path, text).unwrap();
+    ConfigSource {
+      file: Some(path),
+      print: false,
+    }
+  }
+
+  fn no_env(_: &str) -> Option<String> {
+    None
+  }
+
+  #[test]
+  fn file_overrides_only_the_keys_it_sets() {
+    let yaml = config_file("partial.yaml", "port: 7002\ndead_letter:\n  file: dead.jsonl\n");
+    let settings = yaml.load_with(&defaults(), &[], no_env).unwrap();
+    assert_eq!(settings.port, 7002);
+    assert_eq!(settings.dead_letter.file.as_deref(), Some("dead.jsonl"));
+    assert_eq!(settings.broker_address, "127.0.0.1:50051");
+
+    let toml = config_file(
+      "partial.toml",
+      "broker_enabled = false\nmetrics_port = 9102\n[dead_letter]\nstream = true\n",
+    );
+    let settings = toml.load_with(&defaults(), &[], no_env).unwrap();
+    assert!(!settings.broker_enabled);
+    assert_eq!(settings.metrics_port, Some(9102));
+    assert!(settings.dead_letter.stream);
+    assert_eq!(settings.port, 6002);
+  }
+
+  #[test]
+  fn unknown_keys_and_formats_are_rejected() {
+    let typo = config_file("typo.yaml", "dead_letter:\n  fiel: dead.jsonl\n");
+    match typo.load_with(&defaults(), &[], no_env) {
+      Err(ConfigError::UnknownKey { key, .. }) => assert_eq!(key, "dead_letter.fiel"),
+      other => panic!("unexpected result {:?}", other),
+    }
+    let ini = config_file("settings.ini", "port=1\n");
+    assert!(matches!(
+      ini.load_with(&defaults(), &[], no_env),
+      Err(ConfigError::UnsupportedFormat(_))
+    ));
+  }
+
+  #[test]
+  fn environment_overrides_the_file() {
+    let source = config_file("env.yaml", "port: 7002\nbroker_address: broker:50051\n");
+    let env: HashMap<&str, &str> = HashMap::from([
+      ("RUNTIME_PORT", "8002"),
+      ("RUNTIME_BROKER_ENABLED", "false"),
+      ("RUNTIME_METRICS_PORT", "9102"),
+      ("RUNTIME_DEAD_LETTER__FILE", "env.jsonl"),
+      ("BROKER_ADDRESS", "legacy:50051"),
+    ]);
+    let aliases = [("broker_address", "BROKER_ADDRESS")];
+    let lookup = |variable: &str| env.get(variable).map(|value| value.to_string());
+    let settings = source.load_with(&defaults(), &aliases, lookup).unwrap();
+    assert_eq!(
+      settings,
+      Settings {
+        port: 8002,
+        broker_address: "legacy:50051".to_string(),
+        broker_enabled: false,
+        metrics_port: Some(9102),
+        dead_letter: DeadLetter {
+          file: Some("env.jsonl".to_string()),
+          stream: false,
+        },
+      }
+    );
+
+    let prefixed = |variable: &str| match variable {
+      "RUNTIME_BROKER_ADDRESS" => Some("runtime:50051".to_string()),
+      other => lookup(other),
+    };
+    let settings = source.load_with(&defaults(), &aliases, prefixed).unwrap();
+    assert_eq!(settings.broker_address, "runtime:50051");
+  }
+
+  #[test]
+  fn malformed_values_name_their_source() {
+    let env = |variable: &str| (variable == "RUNTIME_PORT").then(|| "many".to_string());
+    match ConfigSource::default().load_with(&defaults(), &[], env) {
+      Err(ConfigError::Env { variable, .. }) => assert_eq!(variable, "RUNTIME_PORT"),
+      other => panic!("unexpected result {:?}", other),
+    }
+
+    let source = config_file("range.yaml", "port: 70000\n");
+    assert!(matches!(
+      source.load_with(&defaults(), &[], no_env),
+      Err(ConfigError::Invalid(_))
+    ));
+  }
+
+  #[test]
+  fn finish_reports_every_invalid_setting() {
+    let mut settings = defaults();
+    settings.port = 0;
+    match ConfigSource::default().finish(settings) {
+      Err(ConfigError::Invalid(errors)) => assert_eq!(errors, ["port must not be 0"]),
+      other => panic!("unexpected result {:?}", other),
+    }
+  }
+
+  #[test]
+  fn scans_hand_parsed_arguments() {
+    let args: Vec<String> = ["--port", "1", "--config", "svc.toml", "--print-config"]
+      .iter()
+      .map(|arg| arg.to_string())
+      .collect();
+    let source = ConfigSource::from_args(&args).unwrap();
+    assert_eq!(source.file, Some(PathBuf::from("svc.toml")));
+    assert!(source.print);
+    assert!(ConfigSource::from_args(&["--config".to_string()]).is_err());
+  }
+}
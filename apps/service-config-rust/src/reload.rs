@@ -0,0 +1,211 @@
+//! Reloading of the configuration while a binary runs. SIGHUP reloads it, and
+//! so does a change of the config file or of another file the configuration
+//! names; files are polled for their modification time. Only the settings a
+//! `Reload` impl takes over change; the others keep their startup values and
+//! changes to them are logged as needing a restart.
+
+use crate::layer::{diff, Change};
+use crate::ConfigSource;
+use serde::Serialize;
+use std::error::Error;
+use std::path::PathBuf;
+use std::time::{Duration, SystemTime};
+use tokio::signal::unix::{signal, Signal, SignalKind};
+use tokio::sync::watch;
+use tracing::{info, warn};
+
+/// How often watched files are checked for changes.
+pub const RELOAD_POLL_INTERVAL: Duration = Duration::from_secs(1);
+
+/// A configuration that can be partially reloaded while running.
+pub trait Reload: Clone + Serialize + Send + Sync + 'static {
+  /// Takes over the settings that may change at runtime from `next`.
+  fn reload_from(&mut self, next: &Self);
+
+  /// Files besides the config file whose changes trigger a reload.
+  fn watched_files(&self) -> Vec<PathBuf> {
+    Vec::new()
+  }
+}
+
+impl ConfigSource {
+  /// Publishes `current` and reloads it with `load` on SIGHUP and whenever a
+  /// watched file changes. `load` runs every layer again, command line
+  /// included, and validates the result; on failure the current
+  /// configuration stays. Every successful reload is published, even without
+  /// changed settings, so consumers can re-read files the configuration
+  /// names. Must be called inside a Tokio runtime.
+  pub fn watch<T, F>(&self, current: T, load: F) -> watch::Receiver<T>
+  where
+    T: Reload,
+    F: Fn() -> Result<T, Box<dyn Error>> + Send + 'static,
+  {
+    let (tx, rx) = watch::channel(current);
+    tokio::spawn(reload_on_change(self.file.clone(), tx, load));
+    rx
+  }
+}
+
+/// Follows one part of a reloaded configuration, e.g. the options shared by
+/// all services within a service's own configuration.
+pub fn project<T, U, F>(mut source: watch::Receiver<T>, part: F) -> watch::Receiver<U>
+where
+  T: Send + Sync + 'static,
+  U: Send + Sync + 'static,
+  F: Fn(&T) -> U + Send + 'static,
+{
+  let (tx, rx) = watch::channel(part(&source.borrow_and_update()));
+  tokio::spawn(async move {
+    loop {
+      tokio::select! {
+        changed = source.changed() => {
+          if changed.is_err() {
+            break;
+          }
+          let next = part(&source.borrow_and_update());
+          tx.send_replace(next);
+        }
+        _ = tx.closed() => break,
+      }
+    }
+  });
+  rx
+}
+
+async fn reload_on_change<T, F>(file: Option<PathBuf>, tx: watch::Sender<T>, load: F)
+where
+  T: Reload,
+  F: Fn() -> Result<T, Box<dyn Error>>,
+{
+  let mut hangup = match signal(SignalKind::hangup()) {
+    Ok(hangup) => Some(hangup),
+    Err(error) => {
+      warn!(%error, "Reloading on SIGHUP is unavailable");
+      None
+    }
+  };
+  let mut poll = tokio::time::interval(RELOAD_POLL_INTERVAL);
+  let mut stamps = modified(&file, &*tx.borrow());
+
+  loop {
+    let trigger = tokio::select! {
+      _ = next_hangup(&mut hangup) => "SIGHUP",
+      _ = poll.tick() => {
+        if modified(&file, &*tx.borrow()) == stamps {
+          continue;
+        }
+        "file change"
+      }
+      _ = tx.closed() => break,
+    };
+    match try_load(&load) {
+      Ok(next) => tx.send_modify(|current| apply(current, next, trigger)),
+      Err(error) => warn!(trigger, %error, "Config reload failed, keeping the current configuration"),
+    }
+    stamps = modified(&file, &*tx.borrow());
+  }
+}
+
+async fn next_hangup(hangup: &mut Option<Signal>) {
+  match hangup {
+    Some(hangup) => {
+      hangup.recv().await;
+    }
+    None => std::future::pending().await,
+  }
+}
+
+fn try_load<T>(load: &impl Fn() -> Result<T, Box<dyn Error>>) -> Result<T, String> {
+  load().map_err(|error| error.to_string())
+}
+
+/// Modification times of the config file and the files `config` watches.
+fn modified<T: Reload>(file: &Option<PathBuf>, config: &T) -> Vec<Option<SystemTime>> {
+  file
+    .iter()
+    .cloned()
+    .chain(config.watched_files())
+    .map(|path| std::fs::metadata(path).and_then(|metadata| metadata.modified()).ok())
+    .collect()
+}
+
+/// Takes over the reloadable settings of `next` and logs what changed and
+/// what was left for a restart.
+fn apply<T: Reload>(current: &mut T, next: T, trigger: &str) {
+  let mut applied = current.clone();
+  applied.reload_from(&next);
+  let changed = changes(current, &applied);
+  let ignored = changes(&applied, &next);
+  if changed.is_empty() && ignored.is_empty() {
+    info!(trigger, "Config reloaded without changes");
+  }
+  for Change { key, from, to } in &changed {
+    info!(trigger, key = %key, from = %from, to = %to, "Config changed");
+  }
+  for Change { key, from, to } in &ignored {
+    warn!(trigger, key = %key, from = %from, to = %to, "Config change needs a restart, ignored");
+  }
+  *current = applied;
+}
+
+fn changes<T: Serialize>(from: &T, to: &T) -> Vec<Change> {
+  match (serde_json::to_value(from), serde_json::to_value(to)) {
+    (Ok(from), Ok(to)) => diff(&from, &to),
+    _ => Vec::new(),
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use serde::Deserialize;
+
+  #[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+  struct Settings {
+    port: u16,
+    role: String,
+    heartbeat_interval_ms: u64,
+  }
+
+  impl Reload for Settings {
+    fn reload_from(&mut self, next: &Self) {
+      self.role = next.role.clone();
+      self.heartbeat_interval_ms = next.heartbeat_interval_ms;
+    }
+  }
+
+  fn settings(port: u16, role: &str, heartbeat_interval_ms: u64) -> Settings {
+    Settings {
+      port,
+      role: role.to_string(),
+      heartbeat_interval_ms,
+    }
+  }
+
+  #[test]
+  fn applies_only_reloadable_settings() {
+    let mut current = settings(6002, "default", 2000);
+    apply(&mut current, settings(7002, "canary", 500), "SIGHUP");
+    assert_eq!(current, settings(6002, "canary", 500));
+
+    let changed = changes(&settings(6002, "default", 2000), &current);
+    let keys: Vec<&str> = changed.iter().map(|change| change.key.as_str()).collect();
+    assert_eq!(keys, ["heartbeat_interval_ms", "role"]);
+  }
+
+  #[tokio::test]
+  async fn projection_follows_every_reload() {
+    let (tx, rx) = watch::channel(settings(6002, "default", 2000));
+    let mut roles = project(rx, |settings: &Settings| settings.role.clone());
+    assert_eq!(*roles.borrow_and_update(), "default");
+
+    tx.send_modify(|current| apply(current, settings(6002, "canary", 2000), "test"));
+    roles.changed().await.unwrap();
+    assert_eq!(*roles.borrow_and_update(), "canary");
+    tx.send_modify(|current| apply(current, settings(6002, "canary", 2000), "test"));
+    roles.changed().await.unwrap();
+
+    drop(tx);
+    assert!(roles.changed().await.is_err());
+  }
+}
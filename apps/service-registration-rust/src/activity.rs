@@ -0,0 +1,136 @@
+use std::time::Duration;
+use tokio::sync::mpsc;
+use tokio::time::Instant;
+use topology_reporter_rust::{ActivityReport, ActivityType};
+
+const ACTIVITY_FLUSH_INTERVAL: Duration = Duration::from_secs(1);
+
+/// Aggregates the activity of one stream into periodic topology reports so
+/// that high event rates do not turn into one HTTP request per message.
+pub struct StreamActivity {
+  sender: Option<mpsc::Sender<ActivityReport>>,
+  source_service: Option<String>,
+  target_service: String,
+  method: &'static str,
+  trace_id: Option<String>,
+  events: u64,
+  messages: u64,
+  processing_ms: f64,
+  stall_ms: f64,
+  last_flush: Instant,
+}
+
+impl StreamActivity {
+  pub fn new(
+    sender: Option<mpsc::Sender<ActivityReport>>,
+    target_service: String,
+    method: &'static str,
+  ) -> Self {
+    Self {
+      sender,
+      source_service: None,
+      target_service,
+      method,
+      trace_id: None,
+      events: 0,
+      messages: 0,
+      processing_ms: 0.0,
+      stall_ms: 0.0,
+      last_flush: Instant::now(),
+    }
+  }
+
+  /// Reports the activity as coming from `source_service` rather than from
+  /// this service, e.g. for a stream this service serves.
+  pub fn with_source(mut self, source_service: String) -> Self {
+    self.source_service = Some(source_service);
+    self
+  }
+
+  /// Attaches the trace the reports belong to.
+  pub fn with_trace_id(mut self, trace_id: Option<String>) -> Self {
+    self.trace_id = trace_id;
+    self
+  }
+
+  /// Records one processed request message carrying `events` events.
+  pub fn record(&mut self, events: usize, processing_ms: f64) {
+    if self.sender.is_none() {
+      return;
+    }
+    self.events += events as u64;
+    self.messages += 1;
+    self.processing_ms += processing_ms;
+    if self.last_flush.elapsed() >= ACTIVITY_FLUSH_INTERVAL {
+      self.flush();
+    }
+  }
+
+  /// Adds time a send waited for the target to drain the stream.
+  pub fn record_stall(&mut self, stall: Duration) {
+    if self.sender.is_some() {
+      self.stall_ms += stall.as_secs_f64() * 1000.0;
+    }
+  }
+
+  /// Reports a stream failure immediately.
+  pub fn record_error(&mut self, message: String) {
+    self.flush();
+    self.send(ActivityReport {
+      source_service: self.source_service.clone(),
+      target_service: self.target_service.clone(),
+      activity_type: ActivityType::Error,
+      timestamp_ms: None,
+      latency_ms: None,
+      method: Some(self.method.to_string()),
+      success: Some(false),
+      batch_size: None,
+      error_message: Some(message),
+      connection_state: None,
+      trace_id: self.trace_id.clone(),
+      stall_ms: None,
+    });
+  }
+
+  /// Sends the pending aggregate, if any.
+  pub fn flush(&mut self) {
+    self.last_flush = Instant::now();
+    if self.messages == 0 {
+      return;
+    }
+
+    let latency_ms = self.processing_ms / self.messages as f64;
+    let report = ActivityReport {
+      source_service: self.source_service.clone(),
+      target_service: self.target_service.clone(),
+      activity_type: ActivityType::ResponseReceived,
+      timestamp_ms: None,
+      latency_ms: Some(latency_ms.round() as i32),
+      method: Some(self.method.to_string()),
+      success: Some(true),
+      batch_size: Some(self.events.min(i32::MAX as u64) as i32),
+      error_message: None,
+      connection_state: None,
+      trace_id: self.trace_id.clone(),
+      stall_ms: Some(self.stall_ms.round().min(i32::MAX as f64) as i32),
+    };
+    self.events = 0;
+    self.messages = 0;
+    self.processing_ms = 0.0;
+    self.stall_ms = 0.0;
+    self.send(report);
+  }
+
+  fn send(&self, report: ActivityReport) {
+    if let Some(sender) = self.sender.as_ref() {
+      // Dropping a report is preferable to stalling the stream on a slow proxy.
+      let _ = sender.try_send(report);
+    }
+  }
+}
+
+impl Drop for StreamActivity {
+  fn drop(&mut self) {
+    self.flush();
+  }
+}
@@ -0,0 +1,309 @@
+//! Registration of a Rust service with the broker: the endpoint and the
+//! instance metadata it is published with, kept registered across broker
+//! restarts and role reloads, plus the per-stream activity aggregation the
+//! services report to topology.
+
+mod activity;
+pub mod proto;
+
+pub use activity::StreamActivity;
+
+use proto::broker::v1::{
+  broker_service_client::BrokerServiceClient, GetAvailableServicesRequest, InstanceMetadata,
+  RegisterServiceRequest, ServiceInfo, UnregisterServiceRequest,
+};
+use serde::{Deserialize, Serialize};
+use service_config_rust::Validate;
+use std::error::Error;
+use std::time::Duration;
+use tokio::sync::watch;
+use tokio::time::sleep;
+use tonic::service::interceptor::InterceptedService;
+use tonic::transport::Channel;
+use tonic::Status;
+use tracing::{info, warn};
+use transport_rust::{connect, AuthConfig, AuthInterceptor, TlsConfig};
+
+pub const DEFAULT_ROLE: &str = "default";
+
+/// Interval of the broker registration check once registered, used when
+/// `registration_interval_ms` is not set.
+pub const DEFAULT_REGISTRATION_INTERVAL: Duration = Duration::from_secs(5);
+
+/// Language every service built on this crate registers with.
+pub const LANGUAGE: &str = "rust";
+
+/// Broker entry published by a service.
+#[derive(Clone, Debug)]
+pub struct BrokerRegistration {
+  pub interface_name: String,
+  pub role: String,
+  pub host: String,
+  pub port: i32,
+  pub metadata: InstanceMetadata,
+}
+
+/// Identity and attributes registered with the broker next to the endpoint,
+/// the `instance` table of a service's config file.
+#[derive(Clone, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct InstanceOptions {
+  /// Stable id of the instance; `<program>@<hostname>:<port>` when `None`.
+  pub id: Option<String>,
+  /// Share of traffic relative to the other instances of the interface and
+  /// role; the broker's default when `None`, no traffic at all when 0.
+  pub weight: Option<u32>,
+  /// Free-form labels lookups can require, e.g. `canary`.
+  pub tags: Vec<String>,
+  /// Availability zone or rack of the instance.
+  pub zone: Option<String>,
+}
+
+impl InstanceOptions {
+  /// Metadata of an instance of `program` at version `version`, running on
+  /// `hostname` and serving at `url` and `port`.
+  pub fn metadata(
+    &self,
+    program: &str,
+    version: &str,
+    hostname: &str,
+    url: &str,
+    port: i32,
+  ) -> InstanceMetadata {
+    let endpoint = if port > 0 { port.to_string() } else { url.to_string() };
+    InstanceMetadata {
+      instance_id: self
+        .id
+        .clone()
+        .unwrap_or_else(|| format!("{}@{}:{}", program, hostname, endpoint)),
+      version: version.to_string(),
+      language: LANGUAGE.to_string(),
+      host: hostname.to_string(),
+      weight: self.weight,
+      tags: self.tags.clone(),
+      zone: self.zone.clone().unwrap_or_default(),
+    }
+  }
+}
+
+impl Validate for InstanceOptions {
+  fn validate(&self, errors: &mut Vec<String>) {
+    if self.id.as_deref().is_some_and(str::is_empty) {
+      errors.push("instance.id must not be empty".to_string());
+    }
+    if self.tags.iter().any(String::is_empty) {
+      errors.push("instance.tags must not contain empty tags".to_string());
+    }
+  }
+}
+
+/// Reloadable settings `run_broker_registration` follows, so services with
+/// their own config type can share it.
+pub trait RegistrationOptions {
+  /// Role the endpoint is registered under.
+  fn role(&self) -> &str;
+  /// Interval in milliseconds at which a registered entry is checked again.
+  fn registration_interval_ms(&self) -> u64;
+}
+
+/// Keeps a service registered with the broker at `broker_url` until
+/// shutdown, then unregisters its endpoint alone; `https://` URLs connect
+/// with `tls`, and calls carry the token of `auth`. Once registered, the
+/// entry is checked again every `registration_interval_ms` of the live
+/// `options`. A reloaded role is registered first, then the entry under the
+/// previous role is removed.
+pub async fn run_broker_registration<O: RegistrationOptions>(
+  broker_url: String,
+  tls: TlsConfig,
+  auth: AuthConfig,
+  mut registration: BrokerRegistration,
+  mut options: watch::Receiver<O>,
+  mut shutdown: watch::Receiver<bool>,
+) {
+  let mut interval = Duration::from_millis(options.borrow_and_update().registration_interval_ms());
+  let mut delay = Duration::from_secs(1);
+  let mut registered = false;
+  // Entry under a role the service no longer has, removed once the new one is in.
+  let mut retired: Option<BrokerRegistration> = None;
+
+  loop {
+    if *shutdown.borrow() {
+      break;
+    }
+
+    match connect_broker(&broker_url, &tls, &auth).await {
+      Ok(mut client) => {
+        match ensure_broker_registration(&mut client, &registration).await {
+          Ok(is_registered) => {
+            if is_registered && !registered {
+              info!(
+                role = %registration.role,
+                "Registered {} with broker at {}", registration.interface_name, broker_url
+              );
+            }
+            registered = is_registered;
+            delay = interval;
+            if let Some(previous) = retired.take() {
+              unregister(&mut client, &previous).await;
+            }
+          }
+          Err(error) => {
+            warn!(%error, "Broker registration failed");
+            delay = next_backoff(delay);
+          }
+        }
+      }
+      Err(error) => {
+        warn!(%error, "Broker connection failed");
+        delay = next_backoff(delay);
+      }
+    }
+
+    tokio::select! {
+      _ = shutdown.changed() => {
+        if *shutdown.borrow() {
+          break;
+        }
+      }
+      Ok(()) = options.changed() => {
+        let (role, interval_ms) = {
+          let options = options.borrow_and_update();
+          (options.role().to_string(), options.registration_interval_ms())
+        };
+        interval = Duration::from_millis(interval_ms);
+        if role != registration.role {
+          info!(from = %registration.role, to = %role, "Re-registering with the broker");
+          let previous = BrokerRegistration {
+            role: std::mem::replace(&mut registration.role, role),
+            ..registration.clone()
+          };
+          if registered {
+            retired.get_or_insert(previous);
+          }
+          registered = false;
+        }
+      }
+      _ = sleep(delay) => {}
+    }
+  }
+
+  if !registered && retired.is_none() {
+    return;
+  }
+
+  if let Ok(mut client) = connect_broker(&broker_url, &tls, &auth).await {
+    for entry in retired.iter().chain(registered.then_some(&registration)) {
+      unregister(&mut client, entry).await;
+    }
+  }
+}
+pub type BrokerClient = BrokerServiceClient<InterceptedService<Channel, AuthInterceptor>>;
+
+/// Connects to the broker at `broker_url`; calls carry the token of `auth`.
+pub async fn connect_broker(
+  broker_url: &str,
+  tls: &TlsConfig,
+  auth: &AuthConfig,
+) -> Result<BrokerClient, Box<dyn Error + Send + Sync>> {
+  let interceptor = auth.interceptor()?;
+  Ok(BrokerServiceClient::with_interceptor(connect(broker_url, tls).await?, interceptor))
+}
+
+async fn unregister(
+  client: &mut BrokerClient,
+  registration: &BrokerRegistration,
+) {
+  let request = UnregisterServiceRequest {
+    interface_name: registration.interface_name.clone(),
+    role: registration.role.clone(),
+    url: registration.host.clone(),
+    port: registration.port,
+  };
+  match client.unregister_service(request).await {
+    Ok(_) => info!(
+      role = %registration.role,
+      "Unregistered {} from broker", registration.interface_name
+    ),
+    Err(error) => warn!(%error, "Broker unregister failed"),
+  }
+}
+
+async fn ensure_broker_registration(
+  client: &mut BrokerClient,
+  registration: &BrokerRegistration,
+) -> Result<bool, Status> {
+  if is_registered(client, registration).await? {
+    return Ok(true);
+  }
+
+  let request = RegisterServiceRequest {
+    info: Some(ServiceInfo {
+      interface_name: registration.interface_name.clone(),
+      role: registration.role.clone(),
+    }),
+    url: registration.host.clone(),
+    port: registration.port,
+    metadata: Some(registration.metadata.clone()),
+  };
+  client.register_service(request).await?;
+  Ok(true)
+}
+
+async fn is_registered(
+  client: &mut BrokerClient,
+  registration: &BrokerRegistration,
+) -> Result<bool, Status> {
+  let response = client
+    .get_available_services(GetAvailableServicesRequest::default())
+    .await?
+    .into_inner();
+
+  for service in response.services {
+    let info = match service.info {
+      Some(info) => info,
+      None => continue,
+    };
+    if info.interface_name != registration.interface_name || info.role != registration.role {
+      continue;
+    }
+    if service.url == registration.host && service.port == registration.port {
+      return Ok(true);
+    }
+  }
+
+  Ok(false)
+}
+
+fn next_backoff(current: Duration) -> Duration {
+  let next = current.as_secs().saturating_mul(2).clamp(1, 15);
+  Duration::from_secs(next)
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn instances_default_to_an_id_from_their_endpoint() {
+    let options = InstanceOptions::default();
+    let tcp = options.metadata("parse-service-rust", "0.1.0", "node-1", "127.0.0.1", 6002);
+    assert_eq!(tcp.instance_id, "parse-service-rust@node-1:6002");
+    assert_eq!(tcp.language, LANGUAGE);
+    assert_eq!(tcp.weight, None);
+
+    let socket = "unix:///run/parse.sock";
+    let unix = options.metadata("parse-service-rust", "0.1.0", "node-1", socket, 0);
+    assert_eq!(unix.instance_id, "parse-service-rust@node-1:unix:///run/parse.sock");
+
+    let canary = InstanceOptions {
+      id: Some("parse-canary".to_string()),
+      weight: Some(5),
+      tags: vec!["canary".to_string()],
+      zone: Some("eu-1a".to_string()),
+    };
+    let metadata = canary.metadata("parse-service-rust", "0.2.0", "node-2", "127.0.0.1", 6002);
+    assert_eq!(metadata.instance_id, "parse-canary");
+    assert_eq!(metadata.weight, Some(5));
+    assert_eq!(metadata.tags, vec!["canary".to_string()]);
+    assert_eq!(metadata.zone, "eu-1a");
+  }
+}
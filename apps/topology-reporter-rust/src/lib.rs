@@ -1,6 +1,8 @@
 use serde::{Deserialize, Serialize};
 use std::fmt;
+use std::time::SystemTime;
 use tokio::time::{Duration, Instant};
+use transport_rust::{TlsConfig, TlsError};
 
 /// Service type for topology registration.
 #[derive(Clone, Copy, Debug)]
@@ -58,6 +60,24 @@ impl ActivityType {
   }
 }
 
+/// Connection state reported for the edge to a target service.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ConnectionState {
+  Idle,
+  Active,
+  Failed,
+}
+
+impl ConnectionState {
+  fn as_str(self) -> &'static str {
+    match self {
+      ConnectionState::Idle => "CONNECTION_STATE_IDLE",
+      ConnectionState::Active => "CONNECTION_STATE_ACTIVE",
+      ConnectionState::Failed => "CONNECTION_STATE_FAILED",
+    }
+  }
+}
+
 /// Configuration for the topology proxy client.
 #[derive(Clone, Debug)]
 pub struct TopologyProxyConfig {
@@ -73,6 +93,10 @@ pub struct TopologyProxyConfig {
   pub host: Option<String>,
   pub enable_activity: bool,
   pub heartbeat_interval: Duration,
+  /// CA bundle and client certificate for an `https://` proxy address;
+  /// `enabled` and `server_name` do not apply. Renewed files are picked up
+  /// with the next request.
+  pub tls: TlsConfig,
 }
 
 impl TopologyProxyConfig {
@@ -96,6 +120,7 @@ impl TopologyProxyConfig {
       host: None,
       enable_activity: true,
       heartbeat_interval: Duration::from_secs(5),
+      tls: TlsConfig::default(),
     }
   }
 }
@@ -103,13 +128,24 @@ impl TopologyProxyConfig {
 /// Activity report payload.
 #[derive(Clone, Debug)]
 pub struct ActivityReport {
+  /// Service the activity came from when it is not the reporter, e.g. the
+  /// caller of a stream the reporter serves.
+  pub source_service: Option<String>,
   pub target_service: String,
   pub activity_type: ActivityType,
   pub timestamp_ms: Option<i64>,
   pub latency_ms: Option<i32>,
   pub method: Option<String>,
   pub success: Option<bool>,
+  /// Number of events covered by this report, for aggregated stream activity.
+  pub batch_size: Option<i32>,
   pub error_message: Option<String>,
+  /// Overrides the edge state, e.g. `Failed` while a circuit breaker is open.
+  pub connection_state: Option<ConnectionState>,
+  /// Trace the activity belongs to, so the edge can link to it.
+  pub trace_id: Option<String>,
+  /// Time spent blocked on the target's backpressure since the last report.
+  pub stall_ms: Option<i32>,
 }
 
 /// Errors emitted by the topology proxy client.
@@ -117,6 +153,7 @@ pub struct ActivityReport {
 pub enum TopologyProxyError {
   Http(reqwest::Error),
   InvalidResponse(String),
+  Tls(TlsError),
 }
 
 impl fmt::Display for TopologyProxyError {
@@ -124,6 +161,7 @@ impl fmt::Display for TopologyProxyError {
     match self {
       TopologyProxyError::Http(error) => write!(formatter, "{error}"),
       TopologyProxyError::InvalidResponse(message) => write!(formatter, "{message}"),
+      TopologyProxyError::Tls(error) => write!(formatter, "{error}"),
     }
   }
 }
@@ -136,6 +174,12 @@ impl From<reqwest::Error> for TopologyProxyError {
   }
 }
 
+impl From<TlsError> for TopologyProxyError {
+  fn from(error: TlsError) -> Self {
+    TopologyProxyError::Tls(error)
+  }
+}
+
 #[derive(Serialize)]
 struct RegisterRequest {
   #[serde(rename = "serviceName")]
@@ -172,6 +216,8 @@ struct HeartbeatRequest {
 struct ActivityRequest {
   #[serde(rename = "serviceId")]
   service_id: String,
+  #[serde(rename = "sourceService")]
+  source_service: Option<String>,
   #[serde(rename = "targetService")]
   target_service: String,
   #[serde(rename = "type")]
@@ -182,8 +228,16 @@ struct ActivityRequest {
   latency_ms: Option<i32>,
   method: Option<String>,
   success: Option<bool>,
+  #[serde(rename = "batchSize")]
+  batch_size: Option<i32>,
   #[serde(rename = "errorMessage")]
   error_message: Option<String>,
+  #[serde(rename = "connectionState")]
+  connection_state: Option<String>,
+  #[serde(rename = "traceId")]
+  trace_id: Option<String>,
+  #[serde(rename = "stallMs")]
+  stall_ms: Option<i32>,
 }
 
 #[derive(Serialize)]
@@ -195,7 +249,9 @@ struct UnregisterRequest {
 /// Client for the topology HTTP proxy with retry and heartbeat.
 pub struct TopologyProxyClient {
   config: TopologyProxyConfig,
-  client: reqwest::Client,
+  /// Built on first use and again when the TLS files change.
+  client: Option<reqwest::Client>,
+  tls_stamps: Vec<Option<SystemTime>>,
   service_id: Option<String>,
   next_retry_at: Instant,
   retry_delay: Duration,
@@ -207,7 +263,8 @@ impl TopologyProxyClient {
   pub fn new(config: TopologyProxyConfig) -> Self {
     Self {
       config,
-      client: reqwest::Client::new(),
+      client: None,
+      tls_stamps: Vec::new(),
       service_id: None,
       next_retry_at: Instant::now(),
       retry_delay: Duration::from_secs(1),
@@ -220,6 +277,21 @@ impl TopologyProxyClient {
     self.service_id.as_deref()
   }
 
+  /// Changes the heartbeat interval, effective from the next heartbeat.
+  pub fn set_heartbeat_interval(&mut self, interval: Duration) {
+    self.config.heartbeat_interval = interval;
+  }
+
+  /// Changes the reported role. A registered service is unregistered and
+  /// registers again under the new role on the next `ensure_registered`.
+  pub async fn set_role(&mut self, role: Option<String>) -> Result<(), TopologyProxyError> {
+    if self.config.service_role == role {
+      return Ok(());
+    }
+    self.config.service_role = role;
+    self.unregister().await
+  }
+
   /// Ensures the service is registered and sends periodic heartbeats.
   pub async fn ensure_registered(&mut self) -> Result<bool, TopologyProxyError> {
     if let Some(service_id) = self.service_id.clone() {
@@ -256,17 +328,22 @@ impl TopologyProxyClient {
 
     let request = ActivityRequest {
       service_id,
+      source_service: report.source_service,
       target_service: report.target_service,
       activity_type: report.activity_type.as_str().to_string(),
       timestamp_ms: report.timestamp_ms,
       latency_ms: report.latency_ms,
       method: report.method,
       success: report.success,
+      batch_size: report.batch_size,
       error_message: report.error_message,
+      connection_state: report.connection_state.map(|state| state.as_str().to_string()),
+      trace_id: report.trace_id,
+      stall_ms: report.stall_ms,
     };
 
     let response = match self
-      .client
+      .http()?
       .post(format!("{}/activity", self.config.proxy_address))
       .json(&request)
       .send()
@@ -299,7 +376,7 @@ impl TopologyProxyClient {
 
     let request = UnregisterRequest { service_id };
     let response = self
-      .client
+      .http()?
       .post(format!("{}/unregister", self.config.proxy_address))
       .json(&request)
       .send()
@@ -330,7 +407,7 @@ impl TopologyProxyClient {
     };
 
     let response = self
-      .client
+      .http()?
       .post(format!("{}/register", self.config.proxy_address))
       .json(&request)
       .send()
@@ -370,7 +447,7 @@ impl TopologyProxyClient {
 
     let request = HeartbeatRequest { service_id };
     let response = match self
-      .client
+      .http()?
       .post(format!("{}/heartbeat", self.config.proxy_address))
       .json(&request)
       .send()
@@ -395,6 +472,20 @@ impl TopologyProxyClient {
     Ok(())
   }
 
+  /// The HTTP client, built again when one of the TLS files changed.
+  fn http(&mut self) -> Result<reqwest::Client, TopologyProxyError> {
+    let stamps = self.config.tls.modified();
+    if let Some(client) = &self.client {
+      if stamps == self.tls_stamps {
+        return Ok(client.clone());
+      }
+    }
+    let client = http_client(&self.config.tls)?;
+    self.client = Some(client.clone());
+    self.tls_stamps = stamps;
+    Ok(client)
+  }
+
   fn schedule_retry(&mut self) {
     self.next_retry_at = Instant::now() + self.retry_delay;
     let next_delay = self.retry_delay.as_secs().saturating_mul(2).min(15);
@@ -412,3 +503,18 @@ impl TopologyProxyClient {
     self.schedule_retry();
   }
 }
+
+fn http_client(tls: &TlsConfig) -> Result<reqwest::Client, TopologyProxyError> {
+  let mut builder = reqwest::Client::builder().use_rustls_tls();
+  if let Some(ca) = tls.ca_pem()? {
+    builder = builder
+      .tls_built_in_root_certs(false)
+      .add_root_certificate(reqwest::Certificate::from_pem(&ca)?);
+  }
+  if let Some(identity) = tls.identity_pem()? {
+    let mut pem = identity.cert;
+    pem.extend_from_slice(&identity.key);
+    builder = builder.identity(reqwest::Identity::from_pem(&pem)?);
+  }
+  Ok(builder.build()?)
+}
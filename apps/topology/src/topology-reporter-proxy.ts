@@ -2,6 +2,7 @@
 import { createServer, type IncomingMessage, type ServerResponse } from 'node:http'
 import {
   ActivityType,
+  ConnectionState,
   ServiceLanguage,
   ServiceType,
 } from '../../../packages/proto/generated/ts/runtime/v1/topology.js'
@@ -56,13 +57,18 @@ interface HeartbeatRequest {
  */
 interface ActivityRequest {
   serviceId: string
+  sourceService?: string | null
   targetService: string
   type: keyof typeof ActivityType
   timestampMs?: number
   latencyMs?: number
   method?: string
   success?: boolean
+  batchSize?: number
   errorMessage?: string
+  connectionState?: keyof typeof ConnectionState | null
+  traceId?: string | null
+  stallMs?: number | null
 }
 
 /**
@@ -271,19 +277,33 @@ export const startTopologyReporterProxy = async (
         return
       }
 
+      // Rust reporters send absent optional fields as null.
+      const connectionState =
+        body.connectionState != null ? ConnectionState[body.connectionState] : undefined
+      if (body.connectionState != null && connectionState === undefined) {
+        sendError(response, 400, 'Invalid connection state')
+        return
+      }
+
       const report: ActivityReport = {
+        sourceService: body.sourceService ?? undefined,
         targetService: body.targetService,
         type: activityType,
         timestampMs: body.timestampMs,
         latencyMs: body.latencyMs,
         method: body.method,
         success: body.success,
+        batchSize: body.batchSize,
         errorMessage: body.errorMessage,
+        connectionState,
+        traceId: body.traceId ?? undefined,
+        stallMs: body.stallMs ?? undefined,
       }
 
       service.reporter.reportActivity(report)
 
-      console.log(`[activity] ${service.serviceName} -> ${body.targetService}`)
+      const source = body.sourceService ?? service.serviceName
+      console.log(`[activity] ${source} -> ${body.targetService}`)
 
       sendJson(response, 200, { status: 'ok' })
     } catch (error) {
@@ -128,4 +128,209 @@ describe('TopologyStore', () => {
     expect(updates.some((update) => update.type === UpdateType.UPDATE_TYPE_EDGE_REMOVED)).toBe(true)
     expect(store.snapshot().edges).toHaveLength(0)
   })
+
+  it('draws activity reported by the target of a stream from its source', () => {
+    let now = 0
+    const ids = ['ingest', 'parse']
+    const store = new TopologyStore({
+      generateId: () => ids.shift() ?? 'unexpected',
+      now: () => now,
+      idleTimeoutMs: 1000,
+      unknownEdgeTimeoutMs: 1500,
+    })
+
+    for (const serviceName of ['ingest-service-rust', 'parse-service-rust']) {
+      store.registerService({
+        serviceName,
+        serviceType: ServiceType.SERVICE_TYPE_SERVER,
+        language: ServiceLanguage.SERVICE_LANGUAGE_RUST,
+      })
+    }
+
+    now = 10
+    for (const sourceService of ['ingest-service-rust', 'pipeline-orchestrator']) {
+      store.recordActivity({
+        serviceId: 'parse',
+        sourceService,
+        targetService: 'parse-service-rust',
+        type: ActivityType.ACTIVITY_TYPE_RESPONSE_RECEIVED,
+        batchSize: 1,
+        success: true,
+      })
+    }
+
+    const edges = () => store.snapshot().edges.map((edge) => edge.sourceServiceId)
+    expect(edges()).toEqual(['ingest', 'pipeline-orchestrator'])
+
+    // Callers that never registered are dropped once idle, registered ones stay
+    now = 2000
+    store.sweep()
+    expect(edges()).toEqual(['ingest'])
+  })
+
+  it('keeps a reported failed edge state until it is cleared', () => {
+    let now = 0
+    const store = new TopologyStore({
+      generateId: () => 'service-5',
+      now: () => now,
+    })
+
+    const registerResult = store.registerService({
+      serviceName: 'calculator-client-rust',
+      serviceType: ServiceType.SERVICE_TYPE_CLIENT,
+      language: ServiceLanguage.SERVICE_LANGUAGE_RUST,
+    })
+
+    const serviceId = registerResult.handle.serviceId
+    now = 10
+    const failedUpdates = store.recordActivity({
+      serviceId,
+      targetService: 'calculator-server',
+      type: ActivityType.ACTIVITY_TYPE_ERROR,
+      success: false,
+      connectionState: ConnectionState.CONNECTION_STATE_FAILED,
+    })
+    expect(
+      failedUpdates.some(
+        (update) =>
+          update.type === UpdateType.UPDATE_TYPE_EDGE_UPDATED &&
+          update.edge?.state === ConnectionState.CONNECTION_STATE_FAILED
+      )
+    ).toBe(true)
+
+    now = 20
+    store.recordActivity({
+      serviceId,
+      targetService: 'calculator-server',
+      type: ActivityType.ACTIVITY_TYPE_REQUEST_SENT,
+      success: true,
+    })
+    expect(store.snapshot().edges[0].state).toBe(ConnectionState.CONNECTION_STATE_FAILED)
+
+    now = 30
+    store.recordActivity({
+      serviceId,
+      targetService: 'calculator-server',
+      type: ActivityType.ACTIVITY_TYPE_REQUEST_SENT,
+      success: true,
+      connectionState: ConnectionState.CONNECTION_STATE_ACTIVE,
+    })
+    expect(store.snapshot().edges[0].state).toBe(ConnectionState.CONNECTION_STATE_ACTIVE)
+  })
+
+  it('expires a failed edge state that is not reported again', () => {
+    let now = 0
+    const store = new TopologyStore({
+      generateId: () => 'service-5b',
+      now: () => now,
+      idleTimeoutMs: 1000,
+      unknownEdgeTimeoutMs: 0,
+    })
+
+    const registerResult = store.registerService({
+      serviceName: 'calculator-client-rust',
+      serviceType: ServiceType.SERVICE_TYPE_CLIENT,
+      language: ServiceLanguage.SERVICE_LANGUAGE_RUST,
+    })
+
+    const serviceId = registerResult.handle.serviceId
+    const report = (connectionState?: ConnectionState) =>
+      store.recordActivity({
+        serviceId,
+        targetService: 'calculator-server',
+        type: ActivityType.ACTIVITY_TYPE_REQUEST_SENT,
+        success: connectionState === undefined,
+        connectionState,
+      })
+    const state = () => store.snapshot().edges[0].state
+
+    now = 10
+    report(ConnectionState.CONNECTION_STATE_FAILED)
+    now = 800
+    report(ConnectionState.CONNECTION_STATE_FAILED)
+    now = 1500
+    report()
+    store.sweep()
+    expect(state()).toBe(ConnectionState.CONNECTION_STATE_FAILED)
+
+    // Traffic without the failure for the idle timeout clears it
+    now = 1900
+    const updates = store.sweep()
+    expect(updates.some((update) => update.type === UpdateType.UPDATE_TYPE_EDGE_UPDATED)).toBe(true)
+    expect(state()).toBe(ConnectionState.CONNECTION_STATE_ACTIVE)
+
+    // A reporter that went quiet leaves the edge idle
+    now = 2000
+    report(ConnectionState.CONNECTION_STATE_FAILED)
+    now = 3100
+    store.sweep()
+    expect(state()).toBe(ConnectionState.CONNECTION_STATE_IDLE)
+  })
+
+  it('keeps the latest reported trace id on the edge', () => {
+    let now = 0
+    const store = new TopologyStore({
+      generateId: () => 'service-6',
+      now: () => now,
+    })
+
+    const registerResult = store.registerService({
+      serviceName: 'calculator-client-rust',
+      serviceType: ServiceType.SERVICE_TYPE_CLIENT,
+      language: ServiceLanguage.SERVICE_LANGUAGE_RUST,
+    })
+
+    const serviceId = registerResult.handle.serviceId
+    now = 10
+    store.recordActivity({
+      serviceId,
+      targetService: 'calculator-server',
+      type: ActivityType.ACTIVITY_TYPE_REQUEST_SENT,
+      traceId: '4bf92f3577b34da6a3ce929d0e0e4736',
+    })
+    expect(store.snapshot().edges[0].lastTraceId).toBe('4bf92f3577b34da6a3ce929d0e0e4736')
+
+    now = 20
+    store.recordActivity({
+      serviceId,
+      targetService: 'calculator-server',
+      type: ActivityType.ACTIVITY_TYPE_REQUEST_SENT,
+    })
+    expect(store.snapshot().edges[0].lastTraceId).toBe('4bf92f3577b34da6a3ce929d0e0e4736')
+  })
+
+  it('reports stall time per flush window as edge backpressure', () => {
+    let now = 0
+    const store = new TopologyStore({
+      generateId: () => 'service-7',
+      now: () => now,
+    })
+
+    const registerResult = store.registerService({
+      serviceName: 'parse-service-rust',
+      serviceType: ServiceType.SERVICE_TYPE_SERVER,
+      language: ServiceLanguage.SERVICE_LANGUAGE_RUST,
+    })
+
+    const serviceId = registerResult.handle.serviceId
+    now = 500
+    store.recordActivity({
+      serviceId,
+      targetService: '127.0.0.1:40000',
+      type: ActivityType.ACTIVITY_TYPE_RESPONSE_RECEIVED,
+      batchSize: 100,
+      stallMs: 300,
+    })
+    now = 1000
+    store.recordActivity({
+      serviceId,
+      targetService: '127.0.0.1:40000',
+      type: ActivityType.ACTIVITY_TYPE_RESPONSE_RECEIVED,
+      batchSize: 100,
+      stallMs: 200,
+    })
+
+    store.flushActivity()
+    expect(store.snapshot().edges[0].backpressureRatio).toBeCloseTo(0.5)
+  })
 })
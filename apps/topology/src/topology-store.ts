@@ -33,9 +33,11 @@ interface EdgeRecord {
   edge: ServiceEdge
   lastActivityMs: number
   lastFlushMs: number
+  lastReportedStateMs: number
   pendingCount: number
   pendingErrorCount: number
   pendingLatencyTotal: number
+  pendingStallMs: number
   totalCount: number
   totalErrorCount: number
   avgLatencyMs: number
@@ -79,7 +81,7 @@ export interface TopologyStoreOptions {
   timeoutMultiplier?: number
   /** Idle timeout before active services become idle. */
   idleTimeoutMs?: number
-  /** Timeout before removing idle edges with unresolved targets. */
+  /** Timeout before removing idle edges with unresolved sources or targets. */
   unknownEdgeTimeoutMs?: number
   /** Window size in milliseconds for RPS averaging. */
   rpsWindowMs?: number
@@ -255,12 +257,16 @@ export class TopologyStore {
       this.queueNodeUpdate(record, updates, now)
     }
 
-    const edgeKey = this.edgeKey(event.serviceId, event.targetService)
+    // A served stream names its caller; unregistered callers keep their name as the source
+    const sourceServiceId = event.sourceService
+      ? (this.serviceNameIndex.get(event.sourceService) ?? event.sourceService)
+      : event.serviceId
+    const edgeKey = this.edgeKey(sourceServiceId, event.targetService)
     const edgeRecord = this.edges.get(edgeKey)
 
     if (!edgeRecord) {
       const edge: ServiceEdge = {
-        sourceServiceId: event.serviceId,
+        sourceServiceId,
         targetService: event.targetService,
         state: ConnectionState.CONNECTION_STATE_ACTIVE,
         lastActivityMs: String(now),
@@ -268,15 +274,19 @@ export class TopologyStore {
         totalErrors: '0',
         avgLatencyMs: 0,
         rps: 0,
+        lastTraceId: '',
+        backpressureRatio: 0,
       }
 
       const created: EdgeRecord = {
         edge,
         lastActivityMs: now,
         lastFlushMs: now,
+        lastReportedStateMs: now,
         pendingCount: 0,
         pendingErrorCount: 0,
         pendingLatencyTotal: 0,
+        pendingStallMs: 0,
         totalCount: 0,
         totalErrorCount: 0,
         avgLatencyMs: 0,
@@ -300,7 +310,27 @@ export class TopologyStore {
     }
     activeEdge.lastActivityMs = now
     activeEdge.edge.lastActivityMs = String(now)
-    activeEdge.edge.state = ConnectionState.CONNECTION_STATE_ACTIVE
+    if (event.traceId) {
+      activeEdge.edge.lastTraceId = event.traceId
+    }
+    activeEdge.pendingStallMs += Math.max(0, event.stallMs ?? 0)
+
+    // An explicit state (e.g. FAILED from an open circuit breaker) wins and is
+    // published immediately; FAILED sticks until the reporter clears it or
+    // stops repeating it for the idle timeout.
+    const reportedState = event.connectionState
+    if (
+      reportedState !== undefined &&
+      reportedState !== ConnectionState.CONNECTION_STATE_UNSPECIFIED
+    ) {
+      activeEdge.lastReportedStateMs = now
+      if (activeEdge.edge.state !== reportedState) {
+        activeEdge.edge.state = reportedState
+        updates.push(this.createEdgeUpdate(UpdateType.UPDATE_TYPE_EDGE_UPDATED, activeEdge.edge))
+      }
+    } else if (activeEdge.edge.state !== ConnectionState.CONNECTION_STATE_FAILED) {
+      activeEdge.edge.state = ConnectionState.CONNECTION_STATE_ACTIVE
+    }
 
     return updates
   }
@@ -335,11 +365,13 @@ export class TopologyStore {
       edgeRecord.edge.totalRequests = String(edgeRecord.totalCount)
       edgeRecord.edge.totalErrors = String(edgeRecord.totalErrorCount)
       edgeRecord.edge.avgLatencyMs = edgeRecord.avgLatencyMs
+      edgeRecord.edge.backpressureRatio = edgeRecord.pendingStallMs / elapsedMs
       this.updateEdgeRps(edgeRecord, now, elapsedSeconds)
 
       edgeRecord.pendingCount = 0
       edgeRecord.pendingErrorCount = 0
       edgeRecord.pendingLatencyTotal = 0
+      edgeRecord.pendingStallMs = 0
       edgeRecord.lastFlushMs = now
 
       updates.push(this.createEdgeUpdate(UpdateType.UPDATE_TYPE_EDGE_UPDATED, edgeRecord.edge))
@@ -389,13 +421,27 @@ export class TopologyStore {
       if (
         this.unknownEdgeTimeoutMs > 0 &&
         idleElapsed > this.unknownEdgeTimeoutMs &&
-        !knownTargets.has(edgeRecord.edge.targetService)
+        (!knownTargets.has(edgeRecord.edge.targetService) ||
+          !this.services.has(edgeRecord.edge.sourceServiceId))
       ) {
         this.edges.delete(edgeKey)
         updates.push(this.createEdgeUpdate(UpdateType.UPDATE_TYPE_EDGE_REMOVED, edgeRecord.edge))
         continue
       }
 
+      // A FAILED state nobody reported again expires like any other
+      if (
+        edgeRecord.edge.state === ConnectionState.CONNECTION_STATE_FAILED &&
+        now - edgeRecord.lastReportedStateMs > this.idleTimeoutMs
+      ) {
+        edgeRecord.edge.state =
+          idleElapsed > this.idleTimeoutMs
+            ? ConnectionState.CONNECTION_STATE_IDLE
+            : ConnectionState.CONNECTION_STATE_ACTIVE
+        updates.push(this.createEdgeUpdate(UpdateType.UPDATE_TYPE_EDGE_UPDATED, edgeRecord.edge))
+        continue
+      }
+
       if (edgeRecord.edge.state === ConnectionState.CONNECTION_STATE_ACTIVE) {
         if (idleElapsed > this.idleTimeoutMs) {
           edgeRecord.edge.state = ConnectionState.CONNECTION_STATE_IDLE
@@ -0,0 +1,153 @@
+//! Credentials presented to the broker. A bearer token goes into the
+//! `authorization` metadata of every call; over mutual TLS the client
+//! certificate of `TlsConfig` identifies the caller instead. The broker maps
+//! either to an identity and checks it against its ACL of interfaces and
+//! roles.
+
+use crate::AuthError;
+use serde::{Deserialize, Serialize, Serializer};
+use service_config_rust::Validate;
+use std::path::PathBuf;
+use tonic::metadata::{Ascii, MetadataValue};
+use tonic::service::Interceptor;
+use tonic::{Request, Status};
+
+/// Metadata carrying the bearer token.
+pub const AUTHORIZATION_METADATA: &str = "authorization";
+
+/// Broker credentials of a binary, the `auth` table of its config file.
+#[derive(Clone, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct AuthConfig {
+  /// Bearer token, e.g. from `RUNTIME_AUTH__TOKEN`. Redacted when the
+  /// config is printed.
+  #[serde(serialize_with = "redact")]
+  pub token: Option<String>,
+  /// File holding the bearer token. Read on every connection, so a rotated
+  /// token applies to the next one.
+  pub token_file: Option<PathBuf>,
+}
+
+impl AuthConfig {
+  /// The configured token, trimmed; `None` when neither field is set.
+  pub fn token(&self) -> Result<Option<String>, AuthError> {
+    let token = match (&self.token, &self.token_file) {
+      (Some(token), _) => token.trim().to_string(),
+      (None, Some(path)) => std::fs::read_to_string(path)
+        .map_err(|source| AuthError::Read {
+          path: path.clone(),
+          source,
+        })?
+        .trim()
+        .to_string(),
+      (None, None) => return Ok(None),
+    };
+    if token.is_empty() {
+      return Err(AuthError::Invalid("the broker token is empty".to_string()));
+    }
+    Ok(Some(token))
+  }
+
+  /// Interceptor adding the token to every call of a client.
+  pub fn interceptor(&self) -> Result<AuthInterceptor, AuthError> {
+    let authorization = match self.token()? {
+      Some(token) => Some(format!("Bearer {}", token).parse().map_err(|_| {
+        AuthError::Invalid("the broker token contains control characters".to_string())
+      })?),
+      None => None,
+    };
+    Ok(AuthInterceptor { authorization })
+  }
+}
+
+fn redact<S: Serializer>(token: &Option<String>, serializer: S) -> Result<S::Ok, S::Error> {
+  token.as_ref().map(|_| "<redacted>").serialize(serializer)
+}
+
+impl Validate for AuthConfig {
+  fn validate(&self, errors: &mut Vec<String>) {
+    if self.token.is_some() && self.token_file.is_some() {
+      errors.push("auth.token and auth.token_file are mutually exclusive".to_string());
+    }
+    if self.token.as_deref().is_some_and(|token| token.trim().is_empty()) {
+      errors.push("auth.token must not be empty".to_string());
+    }
+  }
+}
+
+/// Adds the bearer token of `AuthConfig` to outgoing calls; calls pass
+/// unchanged without one.
+#[derive(Clone, Debug, Default)]
+pub struct AuthInterceptor {
+  authorization: Option<MetadataValue<Ascii>>,
+}
+
+impl Interceptor for AuthInterceptor {
+  fn call(&mut self, mut request: Request<()>) -> Result<Request<()>, Status> {
+    if let Some(authorization) = &self.authorization {
+      request
+        .metadata_mut()
+        .insert(AUTHORIZATION_METADATA, authorization.clone());
+    }
+    Ok(request)
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  fn authorization(auth: &AuthConfig) -> Option<String> {
+    let request = auth.interceptor().unwrap().call(Request::new(())).unwrap();
+    request
+      .metadata()
+      .get(AUTHORIZATION_METADATA)
+      .map(|value| value.to_str().unwrap().to_string())
+  }
+
+  #[test]
+  fn tokens_are_sent_as_bearer_credentials() {
+    assert_eq!(authorization(&AuthConfig::default()), None);
+
+    let inline = AuthConfig {
+      token: Some("s3cret\n".to_string()),
+      token_file: None,
+    };
+    assert_eq!(authorization(&inline).as_deref(), Some("Bearer s3cret"));
+    let printed = serde_json::to_string(&inline).unwrap();
+    assert!(!printed.contains("s3cret"), "{}", printed);
+
+    let path = std::env::temp_dir().join(format!("transport-auth-{}.token", std::process::id()));
+    std::fs::write(&path, "from-file\n").unwrap();
+    let file = AuthConfig {
+      token: None,
+      token_file: Some(path.clone()),
+    };
+    assert_eq!(authorization(&file).as_deref(), Some("Bearer from-file"));
+    std::fs::write(&path, "rotated").unwrap();
+    assert_eq!(authorization(&file).as_deref(), Some("Bearer rotated"));
+    std::fs::remove_file(&path).unwrap();
+    assert!(matches!(file.interceptor(), Err(AuthError::Read { .. })));
+  }
+
+  #[test]
+  fn unusable_tokens_are_rejected() {
+    let both = AuthConfig {
+      token: Some("a".to_string()),
+      token_file: Some(PathBuf::from("/run/secrets/broker")),
+    };
+    let mut errors = Vec::new();
+    both.validate(&mut errors);
+    assert_eq!(errors.len(), 1);
+
+    let blank = AuthConfig {
+      token: Some("  ".to_string()),
+      token_file: None,
+    };
+    assert!(matches!(blank.interceptor(), Err(AuthError::Invalid(_))));
+    let control = AuthConfig {
+      token: Some("to\u{7}ken".to_string()),
+      token_file: None,
+    };
+    assert!(matches!(control.interceptor(), Err(AuthError::Invalid(_))));
+  }
+}
@@ -0,0 +1,174 @@
+use std::error::Error;
+use std::fmt;
+use std::io;
+use std::path::PathBuf;
+
+/// Why a TLS setup could not be built from the configured files.
+pub enum TlsError {
+  /// A certificate, key or CA file could not be read.
+  Read { path: PathBuf, source: io::Error },
+  /// A file holds no usable PEM item of the expected kind.
+  Pem { path: PathBuf, message: String },
+  /// The certificates or key were rejected by the TLS stack.
+  Invalid(String),
+  /// The endpoint URL is malformed.
+  Url { url: String, message: String },
+}
+
+impl fmt::Display for TlsError {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    match self {
+      TlsError::Read { path, source } => write!(f, "Cannot read {}: {}", path.display(), source),
+      TlsError::Pem { path, message } => write!(f, "Invalid PEM file {}: {}", path.display(), message),
+      TlsError::Invalid(message) => write!(f, "Invalid TLS setup: {}", message),
+      TlsError::Url { url, message } => write!(f, "Invalid endpoint {}: {}", url, message),
+    }
+  }
+}
+
+// `main` reports returned errors with `Debug`; show the message there too.
+impl fmt::Debug for TlsError {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    fmt::Display::fmt(self, f)
+  }
+}
+
+impl Error for TlsError {
+  fn source(&self) -> Option<&(dyn Error + 'static)> {
+    match self {
+      TlsError::Read { source, .. } => Some(source),
+      _ => None,
+    }
+  }
+}
+
+/// Why the broker credentials could not be loaded.
+pub enum AuthError {
+  /// The token file could not be read.
+  Read { path: PathBuf, source: io::Error },
+  /// The token cannot be sent as metadata.
+  Invalid(String),
+}
+
+impl fmt::Display for AuthError {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    match self {
+      AuthError::Read { path, source } => write!(f, "Cannot read {}: {}", path.display(), source),
+      AuthError::Invalid(message) => write!(f, "Invalid broker credentials: {}", message),
+    }
+  }
+}
+
+impl fmt::Debug for AuthError {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    fmt::Display::fmt(self, f)
+  }
+}
+
+impl Error for AuthError {
+  fn source(&self) -> Option<&(dyn Error + 'static)> {
+    match self {
+      AuthError::Read { source, .. } => Some(source),
+      AuthError::Invalid(_) => None,
+    }
+  }
+}
+
+/// Why `connect` could not open a channel.
+pub enum ConnectError {
+  /// The TLS setup of the endpoint could not be built.
+  Tls(TlsError),
+  /// The peer could not be reached.
+  Transport(tonic::transport::Error),
+}
+
+impl fmt::Display for ConnectError {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    match self {
+      ConnectError::Tls(error) => write!(f, "{}", error),
+      ConnectError::Transport(error) => write!(f, "Connection failed: {}", error),
+    }
+  }
+}
+
+impl fmt::Debug for ConnectError {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    fmt::Display::fmt(self, f)
+  }
+}
+
+impl Error for ConnectError {
+  fn source(&self) -> Option<&(dyn Error + 'static)> {
+    match self {
+      ConnectError::Tls(error) => Some(error),
+      ConnectError::Transport(error) => Some(error),
+    }
+  }
+}
+
+impl From<TlsError> for ConnectError {
+  fn from(error: TlsError) -> Self {
+    ConnectError::Tls(error)
+  }
+}
+
+impl From<tonic::transport::Error> for ConnectError {
+  fn from(error: tonic::transport::Error) -> Self {
+    ConnectError::Transport(error)
+  }
+}
+
+/// Why a shared-memory session failed.
+pub enum ShmError {
+  /// The control socket or a ring file failed.
+  Io(io::Error),
+  /// The peer broke the session setup protocol.
+  Handshake(String),
+  /// An encoded message does not fit into a ring.
+  FrameTooLarge { len: usize, capacity: usize },
+  /// A frame did not decode as the expected message.
+  Decode(prost::DecodeError),
+  /// The peer wrote a cursor or frame header outside the ring.
+  InvalidFrame(String),
+  /// The peer went away without ending the stream.
+  Closed,
+}
+
+impl fmt::Display for ShmError {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    match self {
+      ShmError::Io(error) => write!(f, "Shared-memory I/O failed: {}", error),
+      ShmError::Handshake(message) => write!(f, "Shared-memory handshake failed: {}", message),
+      ShmError::FrameTooLarge { len, capacity } => write!(
+        f,
+        "Message of {} bytes does not fit into a {} byte ring",
+        len, capacity
+      ),
+      ShmError::Decode(error) => write!(f, "Invalid shared-memory frame: {}", error),
+      ShmError::InvalidFrame(message) => write!(f, "Corrupt shared-memory ring: {}", message),
+      ShmError::Closed => write!(f, "Shared-memory peer closed the session"),
+    }
+  }
+}
+
+impl fmt::Debug for ShmError {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    fmt::Display::fmt(self, f)
+  }
+}
+
+impl Error for ShmError {
+  fn source(&self) -> Option<&(dyn Error + 'static)> {
+    match self {
+      ShmError::Io(error) => Some(error),
+      ShmError::Decode(error) => Some(error),
+      _ => None,
+    }
+  }
+}
+
+impl From<io::Error> for ShmError {
+  fn from(error: io::Error) -> Self {
+    ShmError::Io(error)
+  }
+}
@@ -0,0 +1,26 @@
+//! Transport setup shared by the Rust gRPC servers and clients. With `tls`
+//! enabled, servers accept TLS connections, optionally verifying client
+//! certificates (mutual TLS), and clients reach their peers over `https://`.
+//! Servers pick up renewed certificate, key and CA files without a restart;
+//! clients read them on every new connection. Peers on the same host can use
+//! Unix domain sockets instead of TCP, or shared-memory rings that bypass
+//! gRPC altogether. Calls to the broker can carry a bearer token.
+
+pub mod auth;
+mod error;
+pub mod shm;
+pub mod tcp;
+pub mod tls;
+pub mod uds;
+
+pub use auth::{AuthConfig, AuthInterceptor};
+pub use error::{AuthError, ConnectError, ShmError, TlsError};
+pub use shm::{
+  parse_shm_url, shm_connect, shm_url, ShmConfig, ShmHandshake, ShmListener, ShmReceiver,
+  ShmSender, ShmSession,
+};
+pub use tcp::tcp_incoming;
+pub use tls::{tls_incoming, PemIdentity, TlsConfig, TlsIncoming};
+pub use uds::{
+  connect, remove_socket, socket_path, unix_incoming, unix_url, Transport, UnixIncoming,
+};
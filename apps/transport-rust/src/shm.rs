@@ -0,0 +1,762 @@
+//! Shared-memory rings between stages on the same host. A session is a pair
+//! of single-producer single-consumer byte rings in files under a tmpfs, one
+//! per direction, plus a Unix domain socket that carries the handshake and
+//! wake-ups. Messages are protobuf-encoded straight into the ring and decoded
+//! from it: a batch costs one encode and one decode, but no socket copies and
+//! no HTTP/2 framing. The socket is only written to while the peer waits.
+//!
+//! The server binds the control socket and registers it with the broker as
+//! `shm://<host>/<socket path>`; clients on another host must not use it.
+
+use crate::error::ShmError;
+use crate::uds::bind_unix;
+use prost::Message;
+use serde::{Deserialize, Serialize};
+use service_config_rust::Validate;
+use std::fs::{File, OpenOptions};
+use std::io::{self, ErrorKind};
+use std::os::fd::AsRawFd;
+use std::os::unix::fs::OpenOptionsExt;
+use std::path::{Path, PathBuf};
+use std::ptr::{self, NonNull};
+use std::sync::atomic::{fence, AtomicBool, AtomicU32, AtomicU64, Ordering};
+use std::sync::{Arc, Weak};
+use std::time::Duration;
+use tokio::io::{AsyncBufReadExt, AsyncReadExt, AsyncWriteExt, BufReader};
+use tokio::net::unix::{OwnedReadHalf, OwnedWriteHalf};
+use tokio::net::{UnixListener, UnixStream};
+use tokio::sync::Notify;
+use tokio::task::AbortHandle;
+
+/// Scheme of shared-memory endpoint URLs.
+pub const SHM_SCHEME: &str = "shm://";
+
+/// Directory of the ring files when `ShmConfig::dir` is not set.
+pub const DEFAULT_SHM_DIR: &str = "/dev/shm";
+
+/// Capacity of each ring when `ShmConfig::ring_bytes` is not set.
+pub const DEFAULT_RING_BYTES: usize = 8 << 20;
+
+/// Smallest ring `ShmConfig` accepts.
+const MIN_RING_BYTES: usize = 4096;
+
+/// Time a client gets to map the rings after connecting.
+const HANDSHAKE_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Ring file layout: a header with the cursors on their own cache lines,
+/// then the data area. Frames are an 8-byte header (payload length) and the
+/// payload, padded to 8 bytes; they never wrap around the end of the ring.
+const MAGIC: u64 = 0x6d6f_6473_686d_0001;
+const HEADER_BYTES: usize = 256;
+const CAPACITY_OFFSET: usize = 8;
+const TAIL_OFFSET: usize = 64;
+const HEAD_OFFSET: usize = 128;
+const READER_PARKED_OFFSET: usize = 192;
+const WRITER_PARKED_OFFSET: usize = 196;
+const FRAME_HEADER: usize = 8;
+/// Frame length marking the rest of the ring as unused.
+const WRAP: u32 = u32::MAX;
+/// Frame length marking the end of the stream.
+const END: u32 = u32::MAX - 1;
+
+/// Doorbell bytes on the control socket.
+const DATA: u8 = b'd';
+const SPACE: u8 = b's';
+const READY: u8 = b'r';
+
+/// Shared-memory endpoint of a server, the `shm` table of its config file.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ShmConfig {
+  /// Control socket of the endpoint; disabled when `None`.
+  pub socket: Option<PathBuf>,
+  /// Directory of the ring files, a tmpfs so the rings stay in memory.
+  pub dir: PathBuf,
+  /// Capacity of each ring in bytes. A single encoded message must fit.
+  pub ring_bytes: usize,
+}
+
+impl Default for ShmConfig {
+  fn default() -> Self {
+    Self {
+      socket: None,
+      dir: PathBuf::from(DEFAULT_SHM_DIR),
+      ring_bytes: DEFAULT_RING_BYTES,
+    }
+  }
+}
+
+impl Validate for ShmConfig {
+  fn validate(&self, errors: &mut Vec<String>) {
+    if self.ring_bytes < MIN_RING_BYTES {
+      errors.push(format!("shm.ring_bytes must be at least {}", MIN_RING_BYTES));
+    }
+    if self.socket.as_deref().is_some_and(Path::is_relative) {
+      errors.push("shm.socket must be an absolute path".to_string());
+    }
+  }
+}
+
+/// `shm://` URL of the control socket at `socket` on `host`.
+pub fn shm_url(host: &str, socket: &Path) -> String {
+  format!("{}{}{}", SHM_SCHEME, host, socket.display())
+}
+
+/// Host and control socket of an `shm://` URL.
+pub fn parse_shm_url(url: &str) -> Option<(&str, &Path)> {
+  let rest = url.strip_prefix(SHM_SCHEME)?;
+  let slash = rest.find('/')?;
+  let (host, socket) = rest.split_at(slash);
+  (!host.is_empty() && socket.len() > 1).then(|| (host, Path::new(socket)))
+}
+
+/// A memory-mapped ring file.
+struct Ring {
+  base: NonNull<u8>,
+  len: usize,
+  capacity: u64,
+}
+
+// The mapping is only reached through atomics and the frames they publish;
+// each ring has one writer and one reader.
+unsafe impl Send for Ring {}
+unsafe impl Sync for Ring {}
+
+impl Ring {
+  fn create(path: &Path, capacity: usize) -> io::Result<Self> {
+    let capacity = capacity.div_ceil(8) * 8;
+    let file = OpenOptions::new()
+      .read(true)
+      .write(true)
+      .create_new(true)
+      .mode(0o600)
+      .open(path)?;
+    let len = HEADER_BYTES + capacity;
+    file.set_len(len as u64)?;
+    let ring = Self::map(&file, len, capacity as u64)?;
+    // SAFETY: the header lies within the fresh mapping, nobody else has it yet.
+    unsafe {
+      ptr::write(ring.base.as_ptr().add(CAPACITY_OFFSET) as *mut u64, capacity as u64);
+      ptr::write(ring.base.as_ptr() as *mut u64, MAGIC);
+    }
+    Ok(ring)
+  }
+
+  fn open(path: &Path) -> io::Result<Self> {
+    let file = OpenOptions::new().read(true).write(true).open(path)?;
+    let len = file.metadata()?.len() as usize;
+    if len <= HEADER_BYTES {
+      return Err(io::Error::new(ErrorKind::InvalidData, "ring file too small"));
+    }
+    let ring = Self::map(&file, len, (len - HEADER_BYTES) as u64)?;
+    // SAFETY: the header lies within the mapping and was written by the creator.
+    let (magic, capacity) = unsafe {
+      (
+        ptr::read(ring.base.as_ptr() as *const u64),
+        ptr::read(ring.base.as_ptr().add(CAPACITY_OFFSET) as *const u64),
+      )
+    };
+    if magic != MAGIC || capacity != ring.capacity {
+      return Err(io::Error::new(ErrorKind::InvalidData, "not a ring file of this version"));
+    }
+    Ok(ring)
+  }
+
+  fn map(file: &File, len: usize, capacity: u64) -> io::Result<Self> {
+    // SAFETY: maps `len` bytes of a file that is at least that long.
+    let base = unsafe {
+      libc::mmap(
+        ptr::null_mut(),
+        len,
+        libc::PROT_READ | libc::PROT_WRITE,
+        libc::MAP_SHARED,
+        file.as_raw_fd(),
+        0,
+      )
+    };
+    if base == libc::MAP_FAILED {
+      return Err(io::Error::last_os_error());
+    }
+    let base = NonNull::new(base as *mut u8).ok_or_else(io::Error::last_os_error)?;
+    Ok(Self { base, len, capacity })
+  }
+
+  fn cursor(&self, offset: usize) -> &AtomicU64 {
+    // SAFETY: header offsets are 8-byte aligned and within the mapping.
+    unsafe { &*(self.base.as_ptr().add(offset) as *const AtomicU64) }
+  }
+
+  fn flag(&self, offset: usize) -> &AtomicU32 {
+    // SAFETY: header offsets are 4-byte aligned and within the mapping.
+    unsafe { &*(self.base.as_ptr().add(offset) as *const AtomicU32) }
+  }
+
+  fn tail(&self) -> &AtomicU64 {
+    self.cursor(TAIL_OFFSET)
+  }
+
+  fn head(&self) -> &AtomicU64 {
+    self.cursor(HEAD_OFFSET)
+  }
+
+  fn reader_parked(&self) -> &AtomicU32 {
+    self.flag(READER_PARKED_OFFSET)
+  }
+
+  fn writer_parked(&self) -> &AtomicU32 {
+    self.flag(WRITER_PARKED_OFFSET)
+  }
+
+  /// Pointer to byte `index` of the data area.
+  fn data(&self, index: u64) -> *mut u8 {
+    // SAFETY: callers pass indexes below `capacity`.
+    unsafe { self.base.as_ptr().add(HEADER_BYTES + index as usize) }
+  }
+
+  fn frame_len(&self, index: u64) -> u32 {
+    // SAFETY: frame headers are 8-byte aligned within the data area and were
+    // published by the writer's release store of the tail.
+    unsafe { ptr::read(self.data(index) as *const u32) }
+  }
+
+  fn set_frame_len(&self, index: u64, len: u32) {
+    // SAFETY: as for `frame_len`; the writer owns the bytes until it publishes.
+    unsafe { ptr::write(self.data(index) as *mut u32, len) }
+  }
+}
+
+impl Drop for Ring {
+  fn drop(&mut self) {
+    // SAFETY: unmaps the region mapped in `map`.
+    unsafe {
+      libc::munmap(self.base.as_ptr() as *mut libc::c_void, self.len);
+    }
+  }
+}
+
+/// Bytes a frame with `len` payload bytes takes up.
+fn frame_bytes(len: usize) -> u64 {
+  (FRAME_HEADER + len).div_ceil(8) as u64 * 8
+}
+
+/// Wake-ups shared by both halves of a session.
+struct Doorbell {
+  socket: OwnedWriteHalf,
+  /// The receive ring has new frames, or the peer is gone.
+  data: Notify,
+  /// The send ring has room again, or the peer is gone.
+  space: Notify,
+  closed: AtomicBool,
+  listener: AbortHandle,
+}
+
+impl Doorbell {
+  fn start(socket: UnixStream) -> Arc<Self> {
+    let (read, write) = socket.into_split();
+    Arc::new_cyclic(|doorbell| Self {
+      socket: write,
+      data: Notify::new(),
+      space: Notify::new(),
+      closed: AtomicBool::new(false),
+      listener: tokio::spawn(listen(read, doorbell.clone())).abort_handle(),
+    })
+  }
+
+  /// Wakes the peer; a peer that is gone is noticed by the listener.
+  async fn ring(&self, byte: u8) {
+    loop {
+      match self.socket.try_write(&[byte]) {
+        Ok(_) => return,
+        Err(error) if error.kind() == ErrorKind::WouldBlock => {
+          if self.socket.writable().await.is_err() {
+            return;
+          }
+        }
+        Err(_) => return,
+      }
+    }
+  }
+
+  fn is_closed(&self) -> bool {
+    self.closed.load(Ordering::Acquire)
+  }
+}
+
+impl Drop for Doorbell {
+  fn drop(&mut self) {
+    self.listener.abort();
+  }
+}
+
+async fn listen(mut socket: OwnedReadHalf, doorbell: Weak<Doorbell>) {
+  let mut buffer = [0u8; 64];
+  loop {
+    let read = socket.read(&mut buffer).await.unwrap_or(0);
+    let Some(doorbell) = doorbell.upgrade() else {
+      return;
+    };
+    if read == 0 {
+      doorbell.closed.store(true, Ordering::Release);
+      doorbell.data.notify_one();
+      doorbell.space.notify_one();
+      return;
+    }
+    let bytes = &buffer[..read];
+    if bytes.contains(&DATA) {
+      doorbell.data.notify_one();
+    }
+    if bytes.contains(&SPACE) {
+      doorbell.space.notify_one();
+    }
+  }
+}
+
+/// Both directions of an established session.
+pub struct ShmSession {
+  pub sender: ShmSender,
+  pub receiver: ShmReceiver,
+}
+
+impl ShmSession {
+  fn new(send: Ring, receive: Ring, socket: UnixStream) -> Self {
+    let doorbell = Doorbell::start(socket);
+    Self {
+      sender: ShmSender {
+        tail: send.tail().load(Ordering::Acquire),
+        ring: send,
+        doorbell: doorbell.clone(),
+      },
+      receiver: ShmReceiver {
+        head: receive.head().load(Ordering::Acquire),
+        ring: receive,
+        doorbell,
+      },
+    }
+  }
+}
+
+/// Writing half of a session.
+pub struct ShmSender {
+  ring: Ring,
+  tail: u64,
+  doorbell: Arc<Doorbell>,
+}
+
+impl ShmSender {
+  /// Encodes `message` into the ring, waiting while the ring is full.
+  pub async fn send<M: Message>(&mut self, message: &M) -> Result<(), ShmError> {
+    let len = message.encoded_len();
+    let index = self.reserve(len).await?;
+    // SAFETY: `reserve` handed out `len` contiguous bytes after the header.
+    let mut payload =
+      unsafe { std::slice::from_raw_parts_mut(self.ring.data(index + FRAME_HEADER as u64), len) };
+    message
+      .encode(&mut payload)
+      .expect("the reserved frame fits the encoded message");
+    self.publish(index, len as u32).await;
+    Ok(())
+  }
+
+  /// Ends the stream; the receiver gets `None` once it has read everything
+  /// before.
+  pub async fn finish(&mut self) -> Result<(), ShmError> {
+    let index = self.reserve(0).await?;
+    self.publish(index, END).await;
+    Ok(())
+  }
+
+  /// Index of a contiguous frame for `len` payload bytes.
+  async fn reserve(&mut self, len: usize) -> Result<u64, ShmError> {
+    let capacity = self.ring.capacity;
+    let frame = frame_bytes(len);
+    if len >= END as usize || frame > capacity {
+      return Err(ShmError::FrameTooLarge {
+        len,
+        capacity: capacity as usize,
+      });
+    }
+    let contiguous = capacity - self.tail % capacity;
+    if frame > contiguous {
+      // Pad to the end of the ring and publish the padding on its own: the
+      // frame may not fit into the free space before and after it at once.
+      self.wait_for_room(contiguous).await?;
+      self.ring.set_frame_len(self.tail % capacity, WRAP);
+      self.tail += contiguous;
+      self.ring.tail().store(self.tail, Ordering::SeqCst);
+      self.wake_reader().await;
+    }
+    self.wait_for_room(frame).await?;
+    Ok(self.tail % capacity)
+  }
+
+  /// Waits until the reader has freed `bytes` after the tail.
+  async fn wait_for_room(&mut self, bytes: u64) -> Result<(), ShmError> {
+    let capacity = self.ring.capacity;
+    loop {
+      if self.doorbell.is_closed() {
+        return Err(ShmError::Closed);
+      }
+      let head = self.ring.head().load(Ordering::Acquire);
+      if self.tail + bytes <= head + capacity {
+        return Ok(());
+      }
+
+      self.ring.writer_parked().store(1, Ordering::SeqCst);
+      fence(Ordering::SeqCst);
+      if self.ring.head().load(Ordering::SeqCst) == head && !self.doorbell.is_closed() {
+        self.doorbell.space.notified().await;
+      }
+      self.ring.writer_parked().store(0, Ordering::SeqCst);
+    }
+  }
+
+  async fn publish(&mut self, index: u64, len: u32) {
+    self.ring.set_frame_len(index, len);
+    self.tail += frame_bytes(if len == END { 0 } else { len as usize });
+    self.ring.tail().store(self.tail, Ordering::SeqCst);
+    self.wake_reader().await;
+  }
+
+  async fn wake_reader(&self) {
+    fence(Ordering::SeqCst);
+    if self.ring.reader_parked().load(Ordering::SeqCst) == 1 {
+      self.doorbell.ring(DATA).await;
+    }
+  }
+}
+
+/// Reading half of a session.
+pub struct ShmReceiver {
+  ring: Ring,
+  head: u64,
+  doorbell: Arc<Doorbell>,
+}
+
+impl ShmReceiver {
+  /// Decodes the next message, waiting while the ring is empty. Returns
+  /// `None` once the sender has finished the stream.
+  pub async fn recv<M: Message + Default>(&mut self) -> Result<Option<M>, ShmError> {
+    let capacity = self.ring.capacity;
+    loop {
+      let tail = self.ring.tail().load(Ordering::Acquire);
+      if tail != self.head {
+        // The peer writes the tail and the frame headers; nothing is read
+        // before they are checked against the ring.
+        let published = tail.wrapping_sub(self.head);
+        if published > capacity {
+          return Err(ShmError::InvalidFrame(format!(
+            "tail {} is outside the ring at head {}",
+            tail, self.head
+          )));
+        }
+        let index = self.head % capacity;
+        let contiguous = capacity - index;
+        let len = self.ring.frame_len(index);
+        if len == WRAP {
+          // Padding is only written where a frame did not fit before the end.
+          if index == 0 || published < contiguous {
+            return Err(ShmError::InvalidFrame(format!("padding at {}", index)));
+          }
+          self.head += contiguous;
+          self.release().await;
+          continue;
+        }
+        let frame = frame_bytes(if len == END { 0 } else { len as usize });
+        if frame > contiguous || frame > published {
+          return Err(ShmError::InvalidFrame(format!(
+            "{} byte frame at {} exceeds the ring",
+            len, index
+          )));
+        }
+        if len == END {
+          self.head += frame;
+          self.release().await;
+          return Ok(None);
+        }
+        // SAFETY: the checks above keep `len` payload bytes after the header
+        // within the published part of the data area.
+        let payload = unsafe {
+          std::slice::from_raw_parts(self.ring.data(index + FRAME_HEADER as u64), len as usize)
+        };
+        let message = M::decode(payload).map_err(ShmError::Decode)?;
+        self.head += frame_bytes(len as usize);
+        self.release().await;
+        return Ok(Some(message));
+      }
+      if self.doorbell.is_closed() {
+        if self.ring.tail().load(Ordering::Acquire) == self.head {
+          return Err(ShmError::Closed);
+        }
+        continue;
+      }
+
+      self.ring.reader_parked().store(1, Ordering::SeqCst);
+      fence(Ordering::SeqCst);
+      if self.ring.tail().load(Ordering::SeqCst) == self.head && !self.doorbell.is_closed() {
+        self.doorbell.data.notified().await;
+      }
+      self.ring.reader_parked().store(0, Ordering::SeqCst);
+    }
+  }
+
+  async fn release(&mut self) {
+    self.ring.head().store(self.head, Ordering::SeqCst);
+    fence(Ordering::SeqCst);
+    if self.ring.writer_parked().load(Ordering::SeqCst) == 1 {
+      self.doorbell.ring(SPACE).await;
+    }
+  }
+}
+
+/// Server side: accepts sessions on a control socket.
+pub struct ShmListener {
+  listener: UnixListener,
+  dir: PathBuf,
+  ring_bytes: usize,
+}
+
+impl ShmListener {
+  /// Binds the control socket of `config`, which must be set.
+  pub fn bind(config: &ShmConfig) -> io::Result<Self> {
+    let socket = config
+      .socket
+      .as_deref()
+      .ok_or_else(|| io::Error::new(ErrorKind::InvalidInput, "shm.socket is not set"))?;
+    Ok(Self {
+      listener: bind_unix(socket)?,
+      dir: config.dir.clone(),
+      ring_bytes: config.ring_bytes,
+    })
+  }
+
+  /// Waits for the next client. The session is set up by
+  /// `ShmHandshake::complete`, so a slow client does not hold up others.
+  pub async fn accept(&self) -> io::Result<ShmHandshake> {
+    let (socket, _) = self.listener.accept().await?;
+    Ok(ShmHandshake {
+      socket,
+      dir: self.dir.clone(),
+      ring_bytes: self.ring_bytes,
+    })
+  }
+}
+
+/// A client connection whose rings are not set up yet.
+pub struct ShmHandshake {
+  socket: UnixStream,
+  dir: PathBuf,
+  ring_bytes: usize,
+}
+
+impl ShmHandshake {
+  /// Creates the rings, hands their paths to the client and removes the
+  /// files once the client has mapped them.
+  pub async fn complete(mut self) -> Result<ShmSession, ShmError> {
+    static NEXT_SESSION: AtomicU64 = AtomicU64::new(1);
+    let session = NEXT_SESSION.fetch_add(1, Ordering::Relaxed);
+    let name = format!("modular-shm-{}-{}", std::process::id(), session);
+    let up = self.dir.join(format!("{}-up", name));
+    let down = self.dir.join(format!("{}-down", name));
+
+    let rings = async {
+      let receive = Ring::create(&up, self.ring_bytes)?;
+      let send = Ring::create(&down, self.ring_bytes)?;
+      let paths = format!("{}\n{}\n", up.display(), down.display());
+      self.socket.write_all(paths.as_bytes()).await?;
+      match self.socket.read_u8().await? {
+        READY => Ok((send, receive)),
+        other => Err(ShmError::Handshake(format!("unexpected byte {:#04x}", other))),
+      }
+    };
+    let outcome = tokio::time::timeout(HANDSHAKE_TIMEOUT, rings).await;
+    let _ = std::fs::remove_file(&up);
+    let _ = std::fs::remove_file(&down);
+    let (send, receive) = outcome.map_err(|_| ShmError::Handshake("timed out".to_string()))??;
+    Ok(ShmSession::new(send, receive, self.socket))
+  }
+}
+
+/// Client side: opens a session with the server at the `shm://` URL `url`.
+/// The URL's host is not checked; callers make sure it is this host.
+pub async fn shm_connect(url: &str) -> Result<ShmSession, ShmError> {
+  let (_, socket) =
+    parse_shm_url(url).ok_or_else(|| ShmError::Handshake(format!("invalid URL {}", url)))?;
+  let mut socket = UnixStream::connect(socket).await?;
+  let rings = async {
+    let mut lines = BufReader::new(&mut socket);
+    let mut up = String::new();
+    let mut down = String::new();
+    lines.read_line(&mut up).await?;
+    lines.read_line(&mut down).await?;
+    if up.is_empty() || down.is_empty() {
+      return Err(ShmError::Handshake("server sent no rings".to_string()));
+    }
+    let send = Ring::open(Path::new(up.trim_end()))?;
+    let receive = Ring::open(Path::new(down.trim_end()))?;
+    Ok::<_, ShmError>((send, receive))
+  };
+  let (send, receive) = tokio::time::timeout(HANDSHAKE_TIMEOUT, rings)
+    .await
+    .map_err(|_| ShmError::Handshake("timed out".to_string()))??;
+  socket.write_u8(READY).await?;
+  Ok(ShmSession::new(send, receive, socket))
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  /// Stands in for the pipeline messages, which live in the generated crates.
+  #[derive(Clone, PartialEq, Message)]
+  struct Frame {
+    #[prost(string, tag = "1")]
+    text: String,
+    #[prost(int64, tag = "2")]
+    sequence: i64,
+  }
+
+  fn frame(sequence: i64, len: usize) -> Frame {
+    Frame {
+      text: "x".repeat(len),
+      sequence,
+    }
+  }
+
+  async fn session_pair(name: &str, ring_bytes: usize) -> (ShmSession, ShmSession) {
+    let socket = std::env::temp_dir().join(format!("transport-shm-{}-{}.sock", std::process::id(), name));
+    let config = ShmConfig {
+      socket: Some(socket.clone()),
+      dir: std::env::temp_dir(),
+      ring_bytes,
+    };
+    let listener = ShmListener::bind(&config).unwrap();
+    let server = tokio::spawn(async move { listener.accept().await.unwrap().complete().await });
+    let client = shm_connect(&shm_url("localhost", &socket)).await.unwrap();
+    let server = server.await.unwrap().unwrap();
+    crate::remove_socket(&socket);
+    (client, server)
+  }
+
+  #[test]
+  fn urls_name_host_and_socket() {
+    let url = shm_url("node-1", Path::new("/run/modular/parse.shm"));
+    assert_eq!(url, "shm://node-1/run/modular/parse.shm");
+    assert_eq!(parse_shm_url(&url), Some(("node-1", Path::new("/run/modular/parse.shm"))));
+    assert_eq!(parse_shm_url("shm:///run/parse.shm"), None);
+    assert_eq!(parse_shm_url("shm://node-1"), None);
+    assert_eq!(parse_shm_url("unix:///run/parse.sock"), None);
+  }
+
+  #[tokio::test]
+  async fn frames_wrap_around_a_small_ring_in_order() {
+    let (client, server) = session_pair("wrap", MIN_RING_BYTES).await;
+    let (mut tx, mut rx) = (client.sender, server.receiver);
+    let writer = tokio::spawn(async move {
+      for sequence in 0..500 {
+        tx.send(&frame(sequence, (sequence as usize * 37) % 1500)).await.unwrap();
+      }
+      tx.finish().await.unwrap();
+      tx
+    });
+    for sequence in 0..500 {
+      let received: Frame = rx.recv().await.unwrap().unwrap();
+      assert_eq!(received, frame(sequence, (sequence as usize * 37) % 1500));
+    }
+    assert_eq!(rx.recv::<Frame>().await.unwrap(), None);
+    writer.await.unwrap();
+  }
+
+  #[tokio::test]
+  async fn both_directions_share_one_session() {
+    let (client, server) = session_pair("echo", 1 << 16).await;
+    let ShmSession { sender: mut server_tx, receiver: mut server_rx } = server;
+    tokio::spawn(async move {
+      while let Some(request) = server_rx.recv::<Frame>().await.unwrap() {
+        server_tx.send(&frame(request.sequence * 2, 8)).await.unwrap();
+      }
+      server_tx.finish().await.unwrap();
+    });
+    let ShmSession { sender: mut tx, receiver: mut rx } = client;
+    for sequence in 0..100 {
+      tx.send(&frame(sequence, 64)).await.unwrap();
+      let response: Frame = rx.recv().await.unwrap().unwrap();
+      assert_eq!(response.sequence, sequence * 2);
+    }
+    tx.finish().await.unwrap();
+    assert_eq!(rx.recv::<Frame>().await.unwrap(), None);
+  }
+
+  #[tokio::test]
+  async fn oversized_frames_and_vanished_peers_are_errors() {
+    let (client, server) = session_pair("errors", MIN_RING_BYTES).await;
+    let mut tx = client.sender;
+    assert!(matches!(
+      tx.send(&frame(1, MIN_RING_BYTES)).await,
+      Err(ShmError::FrameTooLarge { .. })
+    ));
+
+    tx.send(&frame(1, 16)).await.unwrap();
+    drop(tx);
+    drop(client.receiver);
+    let mut rx = server.receiver;
+    assert_eq!(rx.recv::<Frame>().await.unwrap(), Some(frame(1, 16)));
+    assert!(matches!(rx.recv::<Frame>().await, Err(ShmError::Closed)));
+  }
+
+  #[tokio::test]
+  async fn frames_over_half_the_ring_fit_after_a_partial_fill() {
+    let (client, server) = session_pair("half", MIN_RING_BYTES).await;
+    let (mut tx, mut rx) = (client.sender, server.receiver);
+    tx.send(&frame(1, 2000)).await.unwrap();
+    assert_eq!(rx.recv::<Frame>().await.unwrap(), Some(frame(1, 2000)));
+
+    // Neither the 2 KiB before nor the 2 KiB after the tail hold this frame.
+    let large = frame(2, 3000);
+    let send = tokio::time::timeout(Duration::from_secs(5), tx.send(&large));
+    let received = tokio::time::timeout(Duration::from_secs(5), rx.recv::<Frame>());
+    let (sent, received) = tokio::join!(send, received);
+    sent.expect("the sender waits forever").unwrap();
+    assert_eq!(received.unwrap().unwrap(), Some(frame(2, 3000)));
+  }
+
+  #[tokio::test]
+  async fn corrupt_frame_headers_are_errors() {
+    let (client, server) = session_pair("corrupt", MIN_RING_BYTES).await;
+    let (tx, mut rx) = (client.sender, server.receiver);
+    tx.ring.set_frame_len(0, 1 << 20);
+    tx.ring.tail().store(frame_bytes(16), Ordering::SeqCst);
+    assert!(matches!(rx.recv::<Frame>().await, Err(ShmError::InvalidFrame(_))));
+
+    tx.ring.set_frame_len(0, WRAP);
+    assert!(matches!(rx.recv::<Frame>().await, Err(ShmError::InvalidFrame(_))));
+
+    tx.ring.tail().store(u64::MAX, Ordering::SeqCst);
+    assert!(matches!(rx.recv::<Frame>().await, Err(ShmError::InvalidFrame(_))));
+  }
+
+  #[test]
+  fn ring_files_are_private() {
+    use std::os::unix::fs::PermissionsExt;
+    let path = std::env::temp_dir().join(format!("transport-ring-{}", std::process::id()));
+    let ring = Ring::create(&path, MIN_RING_BYTES).unwrap();
+    let mode = std::fs::metadata(&path).unwrap().permissions().mode();
+    std::fs::remove_file(&path).unwrap();
+    drop(ring);
+    assert_eq!(mode & 0o777, 0o600);
+  }
+
+  #[test]
+  fn ring_files_are_removed_after_the_handshake() {
+    let runtime = tokio::runtime::Runtime::new().unwrap();
+    let before = ring_files();
+    let (_client, _server) = runtime.block_on(session_pair("files", MIN_RING_BYTES));
+    assert_eq!(ring_files(), before);
+  }
+
+  fn ring_files() -> usize {
+    let prefix = format!("modular-shm-{}-", std::process::id());
+    std::fs::read_dir(std::env::temp_dir())
+      .unwrap()
+      .filter(|entry| entry.as_ref().unwrap().file_name().to_string_lossy().starts_with(&prefix))
+      .count()
+  }
+}
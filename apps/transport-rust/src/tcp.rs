@@ -0,0 +1,17 @@
+//! Plain TCP connections for `serve_with_incoming`. Tonic only disables
+//! Nagle's algorithm on listeners it binds itself; small stream messages on
+//! accepted sockets would otherwise wait for the peer's delayed ACK.
+
+use std::io;
+use tokio::net::{TcpListener, TcpStream};
+use tokio_stream::wrappers::TcpListenerStream;
+use tokio_stream::{Stream, StreamExt};
+
+/// Connections accepted on `listener`, with `TCP_NODELAY` set.
+pub fn tcp_incoming(listener: TcpListener) -> impl Stream<Item = io::Result<TcpStream>> {
+  TcpListenerStream::new(listener).map(|stream| {
+    let stream = stream?;
+    stream.set_nodelay(true)?;
+    Ok(stream)
+  })
+}
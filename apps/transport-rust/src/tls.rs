@@ -0,0 +1,452 @@
+//! TLS settings, the server side acceptor and client endpoints. The acceptor
+//! polls the certificate, key and CA files and swaps in a new server setup
+//! when one of them changes; connections already open keep theirs.
+
+use crate::TlsError;
+use serde::{Deserialize, Serialize};
+use service_config_rust::reload::RELOAD_POLL_INTERVAL;
+use service_config_rust::Validate;
+use std::io::{self, BufReader};
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::{Duration, SystemTime};
+use tokio::net::{TcpListener, TcpStream};
+use tokio::sync::mpsc;
+use tokio_rustls::rustls::pki_types::{CertificateDer, PrivateKeyDer};
+use tokio_rustls::rustls::server::WebPkiClientVerifier;
+use tokio_rustls::rustls::{RootCertStore, ServerConfig};
+use tokio_rustls::server::TlsStream;
+use tokio_rustls::TlsAcceptor;
+use tokio_stream::wrappers::ReceiverStream;
+use tonic::transport::{Certificate, ClientTlsConfig, Endpoint, Identity};
+use tracing::{info, warn};
+
+/// Time a client gets to complete the TLS handshake.
+const HANDSHAKE_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Handshaken connections waiting for the server to pick them up.
+const ACCEPT_BACKLOG: usize = 64;
+
+/// TLS connections accepted by `tls_incoming`, for `serve_with_incoming`.
+pub type TlsIncoming = ReceiverStream<Result<TlsStream<TcpStream>, io::Error>>;
+
+/// Certificate chain and private key of `TlsConfig`, as PEM.
+pub struct PemIdentity {
+  pub cert: Vec<u8>,
+  pub key: Vec<u8>,
+}
+
+/// TLS settings of a binary, the `tls` table of its config file. All files
+/// are PEM.
+#[derive(Clone, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct TlsConfig {
+  /// Serve TLS and reach peers over `https://`. Addresses given with an
+  /// explicit `http://` stay plaintext.
+  pub enabled: bool,
+  /// Certificate chain presented to peers. Servers need one; clients only
+  /// for mutual TLS.
+  pub cert_file: Option<PathBuf>,
+  /// Private key of `cert_file`.
+  pub key_file: Option<PathBuf>,
+  /// CA bundle peer certificates are verified against. Clients fall back to
+  /// the system roots; servers check client certificates only with a bundle.
+  pub ca_file: Option<PathBuf>,
+  /// Reject clients without a certificate signed by `ca_file`.
+  pub require_client_cert: bool,
+  /// Name expected in server certificates instead of the address' host.
+  pub server_name: Option<String>,
+}
+
+impl TlsConfig {
+  /// `address` as a URL, with `https://` when TLS is enabled and the address
+  /// has no scheme of its own. `unix://` socket URLs are kept as they are.
+  pub fn url(&self, address: &str) -> String {
+    if address.starts_with("http://")
+      || address.starts_with("https://")
+      || crate::uds::socket_path(address).is_some()
+    {
+      address.to_string()
+    } else if self.enabled {
+      format!("https://{}", address)
+    } else {
+      format!("http://{}", address)
+    }
+  }
+
+  /// URL of a service the broker lists at `url` and `port`; sockets are
+  /// registered with their full `unix://` URL.
+  pub fn service_url(&self, url: &str, port: i32) -> String {
+    if crate::uds::socket_path(url).is_some() {
+      url.to_string()
+    } else {
+      self.url(&format!("{}:{}", url, port))
+    }
+  }
+
+  /// Endpoint for `url`, set up for TLS when it is an `https://` URL. The
+  /// files are read on every call, so renewed ones apply to new connections.
+  pub fn endpoint(&self, url: &str) -> Result<Endpoint, TlsError> {
+    let endpoint = Endpoint::from_shared(url.to_string()).map_err(|error| TlsError::Url {
+      url: url.to_string(),
+      message: error.to_string(),
+    })?;
+    if !url.starts_with("https://") {
+      return Ok(endpoint);
+    }
+
+    let mut client = ClientTlsConfig::new();
+    client = match self.ca_pem()? {
+      Some(ca) => client.ca_certificate(Certificate::from_pem(ca)),
+      None => client.with_native_roots(),
+    };
+    if let Some(identity) = self.identity_pem()? {
+      client = client.identity(Identity::from_pem(identity.cert, identity.key));
+    }
+    if let Some(server_name) = &self.server_name {
+      client = client.domain_name(server_name.clone());
+    }
+    endpoint
+      .tls_config(client)
+      .map_err(|error| TlsError::Invalid(error.to_string()))
+  }
+
+  /// Contents of `ca_file`, if set.
+  pub fn ca_pem(&self) -> Result<Option<Vec<u8>>, TlsError> {
+    self.ca_file.as_deref().map(read).transpose()
+  }
+
+  /// Contents of `cert_file` and `key_file`, if set.
+  pub fn identity_pem(&self) -> Result<Option<PemIdentity>, TlsError> {
+    match (&self.cert_file, &self.key_file) {
+      (Some(cert), Some(key)) => Ok(Some(PemIdentity {
+        cert: read(cert)?,
+        key: read(key)?,
+      })),
+      _ => Ok(None),
+    }
+  }
+
+  /// Modification times of the configured files, to notice renewals.
+  pub fn modified(&self) -> Vec<Option<SystemTime>> {
+    [&self.cert_file, &self.key_file, &self.ca_file]
+      .into_iter()
+      .flatten()
+      .map(|path| std::fs::metadata(path).and_then(|metadata| metadata.modified()).ok())
+      .collect()
+  }
+
+  /// The checks of `validate` plus a server certificate when TLS is enabled.
+  pub fn validate_server(&self, errors: &mut Vec<String>) {
+    self.validate(errors);
+    if self.enabled && (self.cert_file.is_none() || self.key_file.is_none()) {
+      errors.push("tls.enabled needs tls.cert_file and tls.key_file".to_string());
+    }
+  }
+}
+
+impl Validate for TlsConfig {
+  fn validate(&self, errors: &mut Vec<String>) {
+    if self.cert_file.is_some() != self.key_file.is_some() {
+      errors.push("tls.cert_file and tls.key_file must be set together".to_string());
+    }
+    if self.require_client_cert && self.ca_file.is_none() {
+      errors.push("tls.require_client_cert needs tls.ca_file".to_string());
+    }
+  }
+}
+
+/// Accepts TLS connections on `listener` until the returned stream is
+/// dropped. Fails when the certificate files do not load; later changes to
+/// them are applied to new connections, and a renewal that does not load is
+/// logged and keeps the current certificates.
+pub fn tls_incoming(listener: TcpListener, tls: &TlsConfig) -> Result<TlsIncoming, TlsError> {
+  let server = server_config(tls)?;
+  let (tx, rx) = mpsc::channel(ACCEPT_BACKLOG);
+  tokio::spawn(accept(listener, tls.clone(), server, tx));
+  Ok(ReceiverStream::new(rx))
+}
+
+type IncomingSender = mpsc::Sender<Result<TlsStream<TcpStream>, io::Error>>;
+
+async fn accept(
+  listener: TcpListener,
+  tls: TlsConfig,
+  mut server: Arc<ServerConfig>,
+  tx: IncomingSender,
+) {
+  let mut poll = tokio::time::interval(RELOAD_POLL_INTERVAL);
+  let mut stamps = tls.modified();
+  loop {
+    tokio::select! {
+      accepted = listener.accept() => match accepted {
+        Ok((stream, peer)) => {
+          if let Err(error) = stream.set_nodelay(true) {
+            warn!(%error, %peer, "Cannot disable Nagle's algorithm");
+          }
+          tokio::spawn(handshake(TlsAcceptor::from(server.clone()), stream, peer, tx.clone()));
+        }
+        Err(error) => {
+          if tx.send(Err(error)).await.is_err() {
+            break;
+          }
+        }
+      },
+      _ = poll.tick() => {
+        let next = tls.modified();
+        if next == stamps {
+          continue;
+        }
+        stamps = next;
+        match server_config(&tls) {
+          Ok(reloaded) => {
+            server = reloaded;
+            info!("TLS certificates reloaded");
+          }
+          Err(error) => warn!(%error, "TLS certificate reload failed, keeping the current certificates"),
+        }
+      }
+      _ = tx.closed() => break,
+    }
+  }
+}
+
+async fn handshake(
+  acceptor: TlsAcceptor,
+  stream: TcpStream,
+  peer: std::net::SocketAddr,
+  tx: IncomingSender,
+) {
+  match tokio::time::timeout(HANDSHAKE_TIMEOUT, acceptor.accept(stream)).await {
+    Ok(Ok(stream)) => {
+      let _ = tx.send(Ok(stream)).await;
+    }
+    Ok(Err(error)) => warn!(%peer, %error, "TLS handshake failed"),
+    Err(_) => warn!(%peer, "TLS handshake timed out"),
+  }
+}
+
+fn server_config(tls: &TlsConfig) -> Result<Arc<ServerConfig>, TlsError> {
+  let (Some(cert_file), Some(key_file)) = (&tls.cert_file, &tls.key_file) else {
+    return Err(TlsError::Invalid(
+      "a server needs tls.cert_file and tls.key_file".to_string(),
+    ));
+  };
+  let certs = load_certs(cert_file)?;
+  let key = load_key(key_file)?;
+
+  let builder = ServerConfig::builder();
+  let builder = match &tls.ca_file {
+    Some(ca_file) => {
+      let mut roots = RootCertStore::empty();
+      for cert in load_certs(ca_file)? {
+        roots
+          .add(cert)
+          .map_err(|error| TlsError::Invalid(format!("CA {}: {}", ca_file.display(), error)))?;
+      }
+      let verifier = WebPkiClientVerifier::builder(Arc::new(roots));
+      let verifier = if tls.require_client_cert {
+        verifier.build()
+      } else {
+        verifier.allow_unauthenticated().build()
+      };
+      builder.with_client_cert_verifier(verifier.map_err(|error| TlsError::Invalid(error.to_string()))?)
+    }
+    None => builder.with_no_client_auth(),
+  };
+  let mut config = builder
+    .with_single_cert(certs, key)
+    .map_err(|error| TlsError::Invalid(error.to_string()))?;
+  config.alpn_protocols = vec![b"h2".to_vec()];
+  Ok(Arc::new(config))
+}
+
+fn read(path: &Path) -> Result<Vec<u8>, TlsError> {
+  std::fs::read(path).map_err(|source| TlsError::Read {
+    path: path.to_path_buf(),
+    source,
+  })
+}
+
+fn load_certs(path: &Path) -> Result<Vec<CertificateDer<'static>>, TlsError> {
+  let pem = read(path)?;
+  let certs = rustls_pemfile::certs(&mut BufReader::new(pem.as_slice()))
+    .collect::<Result<Vec<_>, _>>()
+    .map_err(|error| pem_error(path, error.to_string()))?;
+  if certs.is_empty() {
+    return Err(pem_error(path, "no certificate found".to_string()));
+  }
+  Ok(certs)
+}
+
+fn load_key(path: &Path) -> Result<PrivateKeyDer<'static>, TlsError> {
+  let pem = read(path)?;
+  rustls_pemfile::private_key(&mut BufReader::new(pem.as_slice()))
+    .map_err(|error| pem_error(path, error.to_string()))?
+    .ok_or_else(|| pem_error(path, "no private key found".to_string()))
+}
+
+fn pem_error(path: &Path, message: String) -> TlsError {
+  TlsError::Pem {
+    path: path.to_path_buf(),
+    message,
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use rcgen::{BasicConstraints, CertificateParams, CertifiedKey, IsCa, KeyPair};
+  use tokio::io::{AsyncReadExt, AsyncWriteExt};
+  use tokio_rustls::rustls::pki_types::ServerName;
+  use tokio_rustls::rustls::ClientConfig;
+  use tokio_rustls::TlsConnector;
+  use tokio_stream::StreamExt;
+
+  struct Authority {
+    cert: rcgen::Certificate,
+    key: KeyPair,
+  }
+
+  impl Authority {
+    fn new() -> Self {
+      let mut params = CertificateParams::new(Vec::<String>::new()).unwrap();
+      params.is_ca = IsCa::Ca(BasicConstraints::Unconstrained);
+      let key = KeyPair::generate().unwrap();
+      let cert = params.self_signed(&key).unwrap();
+      Self { cert, key }
+    }
+
+    fn issue(&self, name: &str) -> CertifiedKey {
+      let params = CertificateParams::new(vec![name.to_string()]).unwrap();
+      let key_pair = KeyPair::generate().unwrap();
+      let cert = params.signed_by(&key_pair, &self.cert, &self.key).unwrap();
+      CertifiedKey { cert, key_pair }
+    }
+  }
+
+  fn write(dir: &Path, name: &str, pem: &str) -> PathBuf {
+    let path = dir.join(name);
+    std::fs::write(&path, pem).unwrap();
+    path
+  }
+
+  fn temp_dir(name: &str) -> PathBuf {
+    let dir = std::env::temp_dir().join(format!("transport-tls-{}-{}", std::process::id(), name));
+    std::fs::create_dir_all(&dir).unwrap();
+    dir
+  }
+
+  fn server_tls(dir: &Path, ca: &Authority, require_client_cert: bool) -> TlsConfig {
+    let server = ca.issue("localhost");
+    TlsConfig {
+      enabled: true,
+      cert_file: Some(write(dir, "server.pem", &server.cert.pem())),
+      key_file: Some(write(dir, "server.key", &server.key_pair.serialize_pem())),
+      ca_file: Some(write(dir, "ca.pem", &ca.cert.pem())),
+      require_client_cert,
+      server_name: None,
+    }
+  }
+
+  fn connector(ca: &Authority, client: Option<&CertifiedKey>) -> TlsConnector {
+    let mut roots = RootCertStore::empty();
+    roots.add(ca.cert.der().clone()).unwrap();
+    let builder = ClientConfig::builder().with_root_certificates(roots);
+    let config = match client {
+      Some(client) => builder
+        .with_client_auth_cert(
+          vec![client.cert.der().clone()],
+          PrivateKeyDer::try_from(client.key_pair.serialize_der()).unwrap(),
+        )
+        .unwrap(),
+      None => builder.with_no_client_auth(),
+    };
+    TlsConnector::from(Arc::new(config))
+  }
+
+  /// Serves `incoming` by echoing one byte per connection.
+  fn echo(mut incoming: TlsIncoming) {
+    tokio::spawn(async move {
+      while let Some(Ok(mut stream)) = incoming.next().await {
+        tokio::spawn(async move {
+          let mut byte = [0u8; 1];
+          if stream.read_exact(&mut byte).await.is_ok() {
+            let _ = stream.write_all(&byte).await;
+          }
+        });
+      }
+    });
+  }
+
+  async fn round_trip(addr: std::net::SocketAddr, connector: &TlsConnector) -> io::Result<()> {
+    let tcp = TcpStream::connect(addr).await?;
+    let name = ServerName::try_from("localhost").unwrap();
+    let mut stream = connector.connect(name, tcp).await?;
+    stream.write_all(&[7]).await?;
+    let mut byte = [0u8; 1];
+    stream.read_exact(&mut byte).await?;
+    Ok(())
+  }
+
+  #[test]
+  fn urls_follow_the_tls_setting() {
+    let mut tls = TlsConfig::default();
+    assert_eq!(tls.url("127.0.0.1:50051"), "http://127.0.0.1:50051");
+    tls.enabled = true;
+    assert_eq!(tls.url("127.0.0.1:50051"), "https://127.0.0.1:50051");
+    assert_eq!(tls.url("http://broker:50051"), "http://broker:50051");
+  }
+
+  #[test]
+  fn validation_requires_complete_settings() {
+    let tls = TlsConfig {
+      enabled: true,
+      key_file: Some("server.key".into()),
+      require_client_cert: true,
+      ..TlsConfig::default()
+    };
+    let mut errors = Vec::new();
+    tls.validate_server(&mut errors);
+    assert_eq!(
+      errors,
+      [
+        "tls.cert_file and tls.key_file must be set together",
+        "tls.require_client_cert needs tls.ca_file",
+        "tls.enabled needs tls.cert_file and tls.key_file",
+      ]
+    );
+  }
+
+  #[tokio::test]
+  async fn mutual_tls_rejects_clients_without_certificate() {
+    let dir = temp_dir("mutual");
+    let ca = Authority::new();
+    let tls = server_tls(&dir, &ca, true);
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap();
+    echo(tls_incoming(listener, &tls).unwrap());
+
+    let client = ca.issue("client");
+    assert!(round_trip(addr, &connector(&ca, Some(&client))).await.is_ok());
+    assert!(round_trip(addr, &connector(&ca, None)).await.is_err());
+  }
+
+  #[tokio::test]
+  async fn renewed_certificates_apply_to_new_connections() {
+    let dir = temp_dir("renewal");
+    let old_ca = Authority::new();
+    let tls = server_tls(&dir, &old_ca, false);
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap();
+    echo(tls_incoming(listener, &tls).unwrap());
+    assert!(round_trip(addr, &connector(&old_ca, None)).await.is_ok());
+
+    // Modification times can be coarse; make sure the renewal is newer.
+    tokio::time::sleep(Duration::from_millis(50)).await;
+    let new_ca = Authority::new();
+    server_tls(&dir, &new_ca, false);
+    tokio::time::sleep(RELOAD_POLL_INTERVAL * 2).await;
+    assert!(round_trip(addr, &connector(&new_ca, None)).await.is_ok());
+    assert!(round_trip(addr, &connector(&old_ca, None)).await.is_err());
+  }
+}
@@ -0,0 +1,171 @@
+//! Unix domain sockets for peers on the same host. A server bound to a
+//! socket registers it with the broker as a `unix://` URL and port 0;
+//! `connect` dials such URLs over the socket and everything else over TCP.
+//! Socket connections skip TLS: who may connect is decided by the
+//! permissions of the socket file.
+
+use crate::{ConnectError, TlsConfig};
+use hyper_util::rt::TokioIo;
+use std::io;
+use std::os::unix::fs::FileTypeExt;
+use std::path::{Path, PathBuf};
+use tokio::net::{UnixListener, UnixStream};
+use tokio_stream::wrappers::UnixListenerStream;
+use tonic::transport::{Channel, Endpoint, Uri};
+use tower::service_fn;
+
+/// Scheme of socket URLs, e.g. `unix:///run/modular/parse.sock`.
+pub const UNIX_SCHEME: &str = "unix://";
+
+/// Placeholder authority of socket connections; the connector ignores it.
+const UNIX_AUTHORITY: &str = "http://localhost";
+
+/// Connections accepted by `unix_incoming`, for `serve_with_incoming`.
+pub type UnixIncoming = UnixListenerStream;
+
+/// How a server is reached, for metrics and log lines.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Transport {
+  Tcp,
+  Tls,
+  Unix,
+  /// Shared-memory rings, see `shm`.
+  Shm,
+}
+
+impl Transport {
+  pub fn as_str(self) -> &'static str {
+    match self {
+      Transport::Tcp => "tcp",
+      Transport::Tls => "tls",
+      Transport::Unix => "unix",
+      Transport::Shm => "shm",
+    }
+  }
+}
+
+/// Socket path of a `unix://` URL. The `unix:<path>` form of other gRPC
+/// stacks is accepted too.
+pub fn socket_path(url: &str) -> Option<&Path> {
+  url
+    .strip_prefix(UNIX_SCHEME)
+    .or_else(|| url.strip_prefix("unix:"))
+    .filter(|path| !path.is_empty())
+    .map(Path::new)
+}
+
+/// `unix://` URL of the socket at `path`.
+pub fn unix_url(path: &Path) -> String {
+  format!("{}{}", UNIX_SCHEME, path.display())
+}
+
+/// Binds a socket at `path`. A socket file left behind by a server that did
+/// not shut down cleanly is replaced; one that still accepts connections is
+/// not.
+pub fn unix_incoming(path: &Path) -> io::Result<UnixIncoming> {
+  Ok(UnixListenerStream::new(bind_unix(path)?))
+}
+
+/// `UnixListener` at `path`, replacing a stale socket file as described for
+/// `unix_incoming`.
+pub(crate) fn bind_unix(path: &Path) -> io::Result<UnixListener> {
+  if is_socket(path) {
+    if std::os::unix::net::UnixStream::connect(path).is_ok() {
+      return Err(io::Error::new(
+        io::ErrorKind::AddrInUse,
+        format!("{} is served by another process", path.display()),
+      ));
+    }
+    std::fs::remove_file(path)?;
+  }
+  UnixListener::bind(path)
+}
+
+/// Removes the socket file at `path` once its server has stopped. Files that
+/// are not sockets are left alone.
+pub fn remove_socket(path: &Path) {
+  if is_socket(path) {
+    let _ = std::fs::remove_file(path);
+  }
+}
+
+fn is_socket(path: &Path) -> bool {
+  std::fs::symlink_metadata(path)
+    .map(|metadata| metadata.file_type().is_socket())
+    .unwrap_or(false)
+}
+
+/// Connects to `url`: over the socket for `unix://` URLs, otherwise through
+/// `TlsConfig::endpoint`.
+pub async fn connect(url: &str, tls: &TlsConfig) -> Result<Channel, ConnectError> {
+  let Some(path) = socket_path(url) else {
+    return Ok(tls.endpoint(url)?.connect().await?);
+  };
+  let path = PathBuf::from(path);
+  let channel = Endpoint::from_static(UNIX_AUTHORITY)
+    .connect_with_connector(service_fn(move |_: Uri| {
+      let path = path.clone();
+      async move { Ok::<_, io::Error>(TokioIo::new(UnixStream::connect(path).await?)) }
+    }))
+    .await?;
+  Ok(channel)
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use tokio_stream::StreamExt;
+
+  fn socket(name: &str) -> PathBuf {
+    std::env::temp_dir().join(format!("transport-uds-{}-{}.sock", std::process::id(), name))
+  }
+
+  #[test]
+  fn socket_urls_round_trip() {
+    let path = Path::new("/run/modular/parse.sock");
+    assert_eq!(unix_url(path), "unix:///run/modular/parse.sock");
+    assert_eq!(socket_path(&unix_url(path)), Some(path));
+    assert_eq!(socket_path("unix:parse.sock"), Some(Path::new("parse.sock")));
+    assert_eq!(socket_path("http://127.0.0.1:6002"), None);
+    assert_eq!(socket_path("unix://"), None);
+  }
+
+  #[test]
+  fn service_urls_keep_sockets() {
+    let tls = TlsConfig::default();
+    assert_eq!(tls.service_url("127.0.0.1", 6002), "http://127.0.0.1:6002");
+    assert_eq!(tls.service_url("unix:///tmp/parse.sock", 0), "unix:///tmp/parse.sock");
+    assert_eq!(tls.url("unix:///tmp/parse.sock"), "unix:///tmp/parse.sock");
+  }
+
+  #[tokio::test]
+  async fn stale_sockets_are_replaced_and_live_ones_kept() {
+    let path = socket("stale");
+    drop(std::os::unix::net::UnixListener::bind(&path).unwrap());
+    let mut incoming = unix_incoming(&path).unwrap();
+
+    let error = unix_incoming(&path).unwrap_err();
+    assert_eq!(error.kind(), io::ErrorKind::AddrInUse);
+
+    let client = tokio::spawn(UnixStream::connect(path.clone()));
+    assert!(incoming.next().await.unwrap().is_ok());
+    assert!(client.await.unwrap().is_ok());
+
+    drop(incoming);
+    remove_socket(&path);
+    assert!(!path.exists());
+  }
+
+  #[tokio::test]
+  async fn connect_dials_the_socket() {
+    let path = socket("connect");
+    let url = unix_url(&path);
+    assert!(connect(&url, &TlsConfig::default()).await.is_err());
+
+    let mut incoming = unix_incoming(&path).unwrap();
+    let server = tokio::spawn(async move { incoming.next().await.unwrap().unwrap() });
+    assert!(connect(&url, &TlsConfig::default()).await.is_ok());
+    assert!(server.await.is_ok());
+    remove_socket(&path);
+  }
+}
@@ -61,7 +61,7 @@ const defaultConfig = {
   workload: 'events',
   payloadSize: 'medium',
   iterations: 500,
-  implementation: 'ts', // ts | polyglot
+  implementation: 'ts', // ts | polyglot | rust
 }
 
 const usage = `Usage: run-split-pipeline.mjs [options]
@@ -78,7 +78,7 @@ Options:
   --workload <mode>      Workload mode: events|work-items|mixed (default: ${defaultConfig.workload})
   --payload-size <size>  Payload size: small|medium|large (default: ${defaultConfig.payloadSize})
   --iterations <number>  Compute iterations (default: ${defaultConfig.iterations})
-  --impl <mode>          Service implementation: ts|polyglot (default: ${defaultConfig.implementation})
+  --impl <mode>          Service implementation: ts|polyglot|rust (default: ${defaultConfig.implementation})
   --no-build             Skip build step
   --no-generate          Skip generator step
   -h, --help             Show this help message
@@ -178,7 +178,7 @@ const parseArgs = (argv) => {
 
     if (arg === '--impl') {
       const value = getValue(i + 1)
-      if (value !== 'ts' && value !== 'polyglot') {
+      if (!['ts', 'polyglot', 'rust'].includes(value)) {
         throw new Error(`Invalid implementation: ${value}`)
       }
       config.implementation = value
@@ -291,9 +291,45 @@ const signalProcess = (child, signal) => {
   }
 }
 
+const rustServices = [
+  'ingest-service-rust',
+  'parse-service-rust',
+  'rules-service-rust',
+  'aggregate-service-rust',
+  'sink-service-rust',
+]
+
+const rustManifest = (name) => `apps/demo-domain/${name}/Cargo.toml`
+
+const rustService = (name, args = []) => ({
+  command: 'cargo',
+  args: ['run', '--release', '--quiet', '--manifest-path', rustManifest(name), '--', ...args],
+})
+
 const resolveServiceCommands = (config) => {
+  const ingest = {
+    command: 'node',
+    args: ['apps/demo-domain/ingest-service/dist/ingest-service.js', '--input', config.input],
+  }
+  const sink = {
+    command: 'node',
+    args: ['apps/demo-domain/sink-service/dist/sink-service.js', '--output', config.output],
+  }
+
+  if (config.implementation === 'rust') {
+    return {
+      ingest: rustService('ingest-service-rust', ['--input', config.input]),
+      parse: rustService('parse-service-rust'),
+      rules: rustService('rules-service-rust'),
+      aggregate: rustService('aggregate-service-rust'),
+      sink: rustService('sink-service-rust', ['--output', config.output]),
+    }
+  }
+
   if (config.implementation === 'polyglot') {
     return {
+      ingest,
+      sink,
       parse: {
         command: 'cargo',
         args: [
@@ -301,6 +337,9 @@ const resolveServiceCommands = (config) => {
           '--release',
           '--manifest-path',
           'apps/demo-domain/parse-service-rust/Cargo.toml',
+          '--',
+          // The Python rules service reads work items from `user`.
+          '--work-item-json',
         ],
       },
       rules: {
@@ -318,6 +357,8 @@ const resolveServiceCommands = (config) => {
   }
 
   return {
+    ingest,
+    sink,
     parse: {
       command: 'node',
       args: ['apps/demo-domain/parse-service/dist/parse-service.js'],
@@ -389,6 +430,15 @@ const main = async () => {
         ],
         { name: 'build' }
       )
+      if (config.implementation === 'rust') {
+        for (const name of rustServices) {
+          await execCommand(
+            'cargo',
+            ['build', '--release', '--manifest-path', rustManifest(name)],
+            { name: 'build' }
+          )
+        }
+      }
       logRunner('✓ Build complete\n')
     }
 
@@ -422,11 +472,11 @@ const main = async () => {
 
     const serviceCommands = resolveServiceCommands(config)
 
-    const ingest = startService('ingest', 'node', [
-      'apps/demo-domain/ingest-service/dist/ingest-service.js',
-      '--input',
-      config.input,
-    ])
+    const ingest = startService(
+      'ingest',
+      serviceCommands.ingest.command,
+      serviceCommands.ingest.args
+    )
     services.push(ingest.child)
     await sleep(1000)
 
@@ -460,11 +510,7 @@ const main = async () => {
     services.push(aggregate.child)
     await sleep(1000)
 
-    const sink = startService('sink', 'node', [
-      'apps/demo-domain/sink-service/dist/sink-service.js',
-      '--output',
-      config.output,
-    ])
+    const sink = startService('sink', serviceCommands.sink.command, serviceCommands.sink.args)
     services.push(sink.child)
     await sleep(1000)
 
@@ -168,7 +168,8 @@ export class BrokerClientManager extends ClientManager<BrokerServiceClient> {
    */
   private async unregisterService(info: ExtServiceInfo): Promise<void> {
     return new Promise((resolve, reject) => {
-      this.client?.unregisterService({ interfaceName: info.name, role: info.role }, (error) => {
+      const request = { interfaceName: info.name, role: info.role, url: info.url, port: info.port }
+      this.client?.unregisterService(request, (error) => {
         if (error) {
           console.error(`Failed to unregister service ${JSON.stringify(info)}:`)
           reject(error)
@@ -0,0 +1,120 @@
+import * as grpc from '@grpc/grpc-js'
+import { mkdtempSync, writeFileSync } from 'node:fs'
+import { tmpdir } from 'node:os'
+import { join } from 'node:path'
+import { describe, expect, it } from 'vitest'
+import { AuthFailure, BrokerAuthConfig, authorize, identify, loadAuthConfig } from './auth'
+
+const auth: BrokerAuthConfig = {
+  tokens: { 'token-parse': 'parse', 'token-admin': 'admin' },
+  acl: [
+    { identity: 'parse', interfaces: ['pipeline.v1.ParseService*'], roles: ['default', 'canary'] },
+    { identity: 'admin', interfaces: ['*'], roles: ['*'] },
+  ],
+}
+
+const call = (options: { token?: string; commonName?: string } = {}): grpc.ServerSurfaceCall => {
+  const metadata = new grpc.Metadata()
+  if (options.token !== undefined) metadata.set('authorization', `Bearer ${options.token}`)
+  const sslPeerCertificate =
+    options.commonName === undefined ? undefined : { subject: { CN: options.commonName } }
+  const surface = { metadata, getAuthContext: () => ({ sslPeerCertificate }) }
+  return surface as unknown as grpc.ServerSurfaceCall
+}
+
+const failure = (action: () => unknown): AuthFailure => {
+  try {
+    action()
+  } catch (error) {
+    expect(error).toBeInstanceOf(AuthFailure)
+    return error as AuthFailure
+  }
+  throw new Error('expected an AuthFailure')
+}
+
+const writeAuthFile = (contents: unknown): string => {
+  const path = join(mkdtempSync(join(tmpdir(), 'broker-auth-')), 'auth.json')
+  writeFileSync(path, JSON.stringify(contents))
+  return path
+}
+
+describe('identify', () => {
+  it('maps bearer tokens to their identity', () => {
+    expect(identify(auth, call({ token: 'token-parse' }))).toBe('parse')
+  })
+
+  it('prefers the token over the client certificate', () => {
+    expect(identify(auth, call({ token: 'token-admin', commonName: 'parse' }))).toBe('admin')
+  })
+
+  it('falls back to the common name of the client certificate', () => {
+    expect(identify(auth, call({ commonName: 'ingest' }))).toBe('ingest')
+  })
+
+  it('leaves anonymous callers unidentified', () => {
+    expect(identify(auth, call())).toBeUndefined()
+  })
+
+  it('rejects unknown tokens', () => {
+    const error = failure(() => identify(auth, call({ token: 'guess' })))
+    expect(error.code).toBe(grpc.status.UNAUTHENTICATED)
+  })
+})
+
+describe('authorize', () => {
+  it('allows interfaces and roles covered by the caller ACL entry', () => {
+    const parse = call({ token: 'token-parse' })
+    expect(authorize(auth, parse, 'pipeline.v1.ParseService', 'canary')).toBe('parse')
+    expect(authorize(auth, parse, 'pipeline.v1.ParseService/shm', 'default')).toBe('parse')
+    expect(authorize(auth, call({ token: 'token-admin' }), 'any.Service', 'any')).toBe('admin')
+  })
+
+  it('denies interfaces and roles outside the ACL entry', () => {
+    const parse = call({ token: 'token-parse' })
+    expect(failure(() => authorize(auth, parse, 'pipeline.v1.SinkService', 'default')).code).toBe(
+      grpc.status.PERMISSION_DENIED
+    )
+    expect(failure(() => authorize(auth, parse, 'pipeline.v1.ParseService', 'blue')).code).toBe(
+      grpc.status.PERMISSION_DENIED
+    )
+  })
+
+  it('denies identities without an ACL entry', () => {
+    const ingest = call({ commonName: 'ingest' })
+    expect(failure(() => authorize(auth, ingest, 'any.Service', 'default')).code).toBe(
+      grpc.status.PERMISSION_DENIED
+    )
+  })
+
+  it('requires credentials', () => {
+    expect(failure(() => authorize(auth, call(), 'any.Service', 'default')).code).toBe(
+      grpc.status.UNAUTHENTICATED
+    )
+  })
+})
+
+describe('loadAuthConfig', () => {
+  it('reads tokens and ACL entries', () => {
+    expect(loadAuthConfig(writeAuthFile(auth))).toEqual(auth)
+  })
+
+  it('defaults missing sections to empty', () => {
+    expect(loadAuthConfig(writeAuthFile({}))).toEqual({ tokens: {}, acl: [] })
+  })
+
+  it('rejects tokens that do not map to identities', () => {
+    const path = writeAuthFile({ tokens: { 'token-parse': 7 } })
+    expect(() => loadAuthConfig(path)).toThrow('"tokens" must map tokens to identities')
+  })
+
+  it('rejects incomplete ACL entries', () => {
+    const path = writeAuthFile({ acl: [{ identity: 'parse', interfaces: ['*'] }] })
+    expect(() => loadAuthConfig(path)).toThrow('every "acl" entry needs')
+  })
+
+  it('rejects malformed JSON', () => {
+    const path = writeAuthFile({})
+    writeFileSync(path, '{ "tokens": ')
+    expect(() => loadAuthConfig(path)).toThrow()
+  })
+})
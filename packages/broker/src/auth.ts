@@ -0,0 +1,114 @@
+import * as grpc from '@grpc/grpc-js'
+import { readFileSync } from 'node:fs'
+
+/**
+ * Which identity may register which interfaces and roles. `*` matches any
+ * name, a trailing `*` any name with that prefix.
+ */
+export interface AclEntry {
+  identity: string
+  interfaces: string[]
+  roles: string[]
+}
+
+/**
+ * Contents of the broker auth file. `tokens` maps bearer tokens to
+ * identities; callers over mutual TLS are identified by the common name of
+ * their certificate instead.
+ */
+export interface BrokerAuthConfig {
+  tokens: Record<string, string>
+  acl: AclEntry[]
+}
+
+/** Error with the gRPC status code to send back to the caller. */
+export class AuthFailure extends Error {
+  constructor(
+    readonly code: grpc.status,
+    message: string
+  ) {
+    super(message)
+  }
+}
+
+const isStringArray = (value: unknown): value is string[] =>
+  Array.isArray(value) && value.every((item) => typeof item === 'string')
+
+/**
+ * Reads and checks the auth file at `path`.
+ * @throws {Error} If the file cannot be read or is malformed.
+ */
+export const loadAuthConfig = (path: string): BrokerAuthConfig => {
+  const raw = JSON.parse(readFileSync(path, 'utf8')) as Partial<BrokerAuthConfig>
+  const tokens = raw.tokens ?? {}
+  if (typeof tokens !== 'object' || Object.values(tokens).some((id) => typeof id !== 'string')) {
+    throw new Error(`${path}: "tokens" must map tokens to identities`)
+  }
+  const acl = raw.acl ?? []
+  if (
+    !Array.isArray(acl) ||
+    !acl.every(
+      (entry) =>
+        typeof entry.identity === 'string' &&
+        isStringArray(entry.interfaces) &&
+        isStringArray(entry.roles)
+    )
+  ) {
+    throw new Error(`${path}: every "acl" entry needs an identity, interfaces and roles`)
+  }
+  return { tokens, acl }
+}
+
+const matches = (pattern: string, name: string): boolean =>
+  pattern.endsWith('*') ? name.startsWith(pattern.slice(0, -1)) : pattern === name
+
+/**
+ * Identity of the caller: the owner of its bearer token, else the common
+ * name of its client certificate; `undefined` for anonymous callers.
+ * @throws {AuthFailure} If the call carries an unknown token.
+ */
+export const identify = (
+  auth: BrokerAuthConfig,
+  call: grpc.ServerSurfaceCall
+): string | undefined => {
+  const [authorization] = call.metadata.get('authorization')
+  if (typeof authorization === 'string') {
+    const token = authorization.replace(/^Bearer\s+/i, '')
+    if (!Object.hasOwn(auth.tokens, token)) {
+      throw new AuthFailure(grpc.status.UNAUTHENTICATED, 'Unknown broker token')
+    }
+    return auth.tokens[token]
+  }
+  const commonName = call.getAuthContext().sslPeerCertificate?.subject?.CN
+  return commonName || undefined
+}
+
+/**
+ * Checks that the caller may register `interfaceName` under `role` and
+ * returns its identity.
+ * @throws {AuthFailure} If the caller is anonymous or not allowed.
+ */
+export const authorize = (
+  auth: BrokerAuthConfig,
+  call: grpc.ServerSurfaceCall,
+  interfaceName: string,
+  role: string
+): string => {
+  const identity = identify(auth, call)
+  if (identity === undefined) {
+    throw new AuthFailure(grpc.status.UNAUTHENTICATED, 'Broker credentials required')
+  }
+  const allowed = auth.acl.some(
+    (entry) =>
+      entry.identity === identity &&
+      entry.interfaces.some((pattern) => matches(pattern, interfaceName)) &&
+      entry.roles.some((pattern) => matches(pattern, role))
+  )
+  if (!allowed) {
+    throw new AuthFailure(
+      grpc.status.PERMISSION_DENIED,
+      `${identity} may not register ${interfaceName} (${role})`
+    )
+  }
+  return identity
+}
@@ -0,0 +1,217 @@
+import * as grpc from '@grpc/grpc-js'
+import { describe, expect, it } from 'vitest'
+import {
+  BrokerServiceServer,
+  NotifyServiceChangesRequest,
+  NotifyServiceChangesResponse,
+  RegisterServiceRequest,
+  UnregisterServiceRequest,
+} from '../../proto/generated/ts/broker/v1/broker'
+import { BrokerAuthConfig } from './auth'
+import { createBrokerService } from './broker'
+
+const auth: BrokerAuthConfig = {
+  tokens: { 'token-a': 'parse-a', 'token-b': 'parse-b' },
+  acl: [
+    { identity: 'parse-a', interfaces: ['*'], roles: ['*'] },
+    { identity: 'parse-b', interfaces: ['*'], roles: ['*'] },
+  ],
+}
+
+const PARSE = 'pipeline.v1.ParseService'
+
+interface Outcome<T> {
+  error: Partial<grpc.ServiceError> | null
+  value?: T
+}
+
+// Calls a unary handler with `request`, presenting `token` when given
+const invoke = <Req, Res>(
+  handler: grpc.handleUnaryCall<Req, Res>,
+  request: Req,
+  token?: string
+): Promise<Outcome<Res>> => {
+  const metadata = new grpc.Metadata()
+  if (token !== undefined) metadata.set('authorization', `Bearer ${token}`)
+  const call = { request, metadata, getAuthContext: () => ({}) }
+  return new Promise((resolve) => {
+    handler(call as unknown as grpc.ServerUnaryCall<Req, Res>, (error, value) =>
+      resolve({ error: error as Partial<grpc.ServiceError> | null, value: value ?? undefined })
+    )
+  })
+}
+
+const registration = (port: number, role = 'default'): RegisterServiceRequest => ({
+  info: { interfaceName: PARSE, role },
+  url: '127.0.0.1',
+  port,
+})
+
+const unregistration = (port?: number, role = 'default'): UnregisterServiceRequest => ({
+  interfaceName: PARSE,
+  role,
+  url: port === undefined ? '' : '127.0.0.1',
+  port: port ?? 0,
+})
+
+const ports = async (broker: BrokerServiceServer): Promise<number[]> => {
+  const { value } = await invoke(broker.getAvailableServices, {})
+  return (value?.services ?? []).map((service) => service.port).sort((a, b) => a - b)
+}
+
+// Subscribes to service changes and returns the notifications as they arrive
+const listen = (broker: BrokerServiceServer): NotifyServiceChangesResponse[] => {
+  const notifications: NotifyServiceChangesResponse[] = []
+  const stream = {
+    write: (notification: NotifyServiceChangesResponse) => notifications.push(notification),
+    on: () => stream,
+  }
+  broker.notifyServiceChanges(
+    stream as unknown as grpc.ServerWritableStream<
+      NotifyServiceChangesRequest,
+      NotifyServiceChangesResponse
+    >
+  )
+  return notifications
+}
+
+describe('BrokerService endpoints', () => {
+  it('unregisters exactly the named one of two instances', async () => {
+    const broker = createBrokerService()
+    await invoke(broker.registerService, registration(6002))
+    await invoke(broker.registerService, registration(6003))
+
+    const removed = await invoke(broker.unregisterService, unregistration(6003))
+    expect(removed.error).toBeNull()
+    expect(await ports(broker)).toEqual([6002])
+
+    // An endpoint that is not registered leaves the other instance alone
+    await invoke(broker.unregisterService, unregistration(6004))
+    expect(await ports(broker)).toEqual([6002])
+  })
+
+  it('keeps one entry when an endpoint registers again', async () => {
+    const broker = createBrokerService()
+    const notifications = listen(broker)
+    await invoke(broker.registerService, registration(6002))
+    await invoke(broker.registerService, registration(6002))
+    expect(await ports(broker)).toEqual([6002])
+    expect(notifications.map((notification) => notification.changeType)).toEqual(['added'])
+
+    const metadata = {
+      instanceId: 'parse-1',
+      version: '1.5.0',
+      language: 'rust',
+      host: 'node-1',
+      tags: [],
+      zone: '',
+    }
+    await invoke(broker.registerService, { ...registration(6002), metadata })
+    expect(await ports(broker)).toEqual([6002])
+    expect(notifications.map((notification) => notification.changeType)).toEqual([
+      'added',
+      'updated',
+    ])
+  })
+
+  it('reports the instances of the interface and role left after each change', async () => {
+    const broker = createBrokerService()
+    const notifications = listen(broker)
+    await invoke(broker.registerService, registration(6002))
+    await invoke(broker.registerService, registration(6003))
+    await invoke(broker.registerService, registration(7002, 'canary'))
+    await invoke(broker.unregisterService, unregistration(6002))
+    await invoke(broker.unregisterService, unregistration(6003))
+
+    const changes = notifications.map(({ changeType, port, remaining }) => [
+      changeType,
+      port,
+      remaining,
+    ])
+    expect(changes).toEqual([
+      ['added', 6002, 1],
+      ['added', 6003, 2],
+      ['added', 7002, 1],
+      ['removed', 6002, 1],
+      ['removed', 6003, 0],
+    ])
+  })
+})
+
+describe('BrokerService with auth', () => {
+  it('requires credentials the ACL allows to register', async () => {
+    const broker = createBrokerService(auth)
+    const anonymous = await invoke(broker.registerService, registration(6002))
+    expect(anonymous.error?.code).toBe(grpc.status.UNAUTHENTICATED)
+    const unknown = await invoke(broker.registerService, registration(6002), 'token-x')
+    expect(unknown.error?.code).toBe(grpc.status.UNAUTHENTICATED)
+    expect(await ports(broker)).toEqual([])
+  })
+
+  it('only lets the owner unregister an entry', async () => {
+    const broker = createBrokerService(auth)
+    await invoke(broker.registerService, registration(6002), 'token-a')
+
+    const other = await invoke(broker.unregisterService, unregistration(6002), 'token-b')
+    expect(other.error?.code).toBe(grpc.status.PERMISSION_DENIED)
+    const anonymous = await invoke(broker.unregisterService, unregistration(6002))
+    expect(anonymous.error?.code).toBe(grpc.status.UNAUTHENTICATED)
+    expect(await ports(broker)).toEqual([6002])
+
+    const owner = await invoke(broker.unregisterService, unregistration(6002), 'token-a')
+    expect(owner.error).toBeNull()
+    expect(await ports(broker)).toEqual([])
+  })
+
+  it('only lets the owner register an endpoint again', async () => {
+    const broker = createBrokerService(auth)
+    const notifications = listen(broker)
+    await invoke(broker.registerService, registration(6002), 'token-a')
+
+    const metadata = {
+      instanceId: 'parse-1',
+      version: '2.0.0-canary',
+      language: 'rust',
+      host: 'node-1',
+      weight: 0,
+      tags: [],
+      zone: '',
+    }
+    const hijack = { ...registration(6002), metadata }
+    const other = await invoke(broker.registerService, hijack, 'token-b')
+    expect(other.error?.code).toBe(grpc.status.PERMISSION_DENIED)
+    const { value } = await invoke(broker.getAvailableServices, {})
+    expect(value?.services[0].metadata).toBeUndefined()
+
+    const owner = await invoke(broker.registerService, hijack, 'token-a')
+    expect(owner.error).toBeNull()
+    expect(notifications.map((notification) => notification.changeType)).toEqual([
+      'added',
+      'updated',
+    ])
+  })
+
+  it('removes only the caller entries of a shared interface and role', async () => {
+    const broker = createBrokerService(auth)
+    await invoke(broker.registerService, registration(6002), 'token-a')
+    await invoke(broker.registerService, registration(6003), 'token-b')
+
+    const own = await invoke(broker.unregisterService, unregistration(), 'token-b')
+    expect(own.error).toBeNull()
+    expect(await ports(broker)).toEqual([6002])
+  })
+
+  it('rejects unregistering without an endpoint when the caller has several', async () => {
+    const broker = createBrokerService(auth)
+    await invoke(broker.registerService, registration(6002), 'token-a')
+    await invoke(broker.registerService, registration(6003), 'token-a')
+
+    const ambiguous = await invoke(broker.unregisterService, unregistration(), 'token-a')
+    expect(ambiguous.error?.code).toBe(grpc.status.INVALID_ARGUMENT)
+    expect(await ports(broker)).toEqual([6002, 6003])
+
+    const named = await invoke(broker.unregisterService, unregistration(6003), 'token-a')
+    expect(named.error).toBeNull()
+    expect(await ports(broker)).toEqual([6002])
+  })
+})
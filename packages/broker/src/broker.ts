@@ -10,122 +10,240 @@ import {
   LookupServiceResponse,
   GetAvailableServicesRequest,
   GetAvailableServicesResponse,
+  InstanceMetadata,
+  MetadataFilter,
   UnregisterServiceRequest,
   UnregisterServiceResponse,
   NotifyServiceChangesRequest,
 } from '../../proto/generated/ts/broker/v1/broker'
+import { AuthFailure, BrokerAuthConfig, authorize, identify } from './auth'
 
 interface IService {
   name: string
   role: string
   url: string
   port: number
+  owner?: string // Identity that registered the service, when auth is enabled
+  metadata?: InstanceMetadata
 }
 
-const services: IService[] = []
-const serviceChangeListeners: grpc.ServerWritableStream<unknown, unknown>[] = []
+/** TLS of the broker endpoint, PEM file contents. */
+export interface BrokerTlsOptions {
+  cert: Buffer
+  key: Buffer
+  ca?: Buffer
+  requireClientCert: boolean
+}
 
-// Function to notify all listeners about a service change
-function notifyAllListeners(notification: NotifyServiceChangesResponse) {
-  serviceChangeListeners.forEach((listener) => {
-    listener.write(notification)
-  })
+/** Optional security of the broker server. */
+export interface BrokerServerOptions {
+  /** Serve TLS, or mutual TLS with `ca` and `requireClientCert`. */
+  tls?: BrokerTlsOptions
+  /** Require credentials for registration and check them against the ACL. */
+  auth?: BrokerAuthConfig
 }
 
-// Example usage of notifyAllListeners
-function serviceChange(s: IService, change: string) {
-  const notification: NotifyServiceChangesResponse = {
-    info: { interfaceName: s.name, role: s.role },
-    url: s.url,
-    port: s.port,
-    changeType: change,
-  }
-  notifyAllListeners(notification)
+const sameEndpoint = (a: IService, b: IService): boolean =>
+  a.name === b.name && a.role === b.role && a.url === b.url && a.port === b.port
+
+// Empty filter fields match any instance; entries without metadata only match an empty filter
+const matchesFilter = (s: IService, filter?: MetadataFilter): boolean => {
+  if (filter == null) return true
+  const m = s.metadata
+  const matches = (wanted: string, actual?: string) => wanted === '' || wanted === actual
+  return (
+    matches(filter.version, m?.version) &&
+    matches(filter.language, m?.language) &&
+    matches(filter.zone, m?.zone) &&
+    filter.tags.every((tag) => m?.tags.includes(tag) === true)
+  )
 }
 
-// Implement the BrokerService
-const brokerService: BrokerServiceServer = {
-  registerService: (
-    call: grpc.ServerUnaryCall<RegisterServiceRequest, RegisterServiceResponse>,
-    callback: grpc.sendUnaryData<RegisterServiceResponse>
-  ) => {
-    const rq = call.request
-    console.log(`registerService: ${JSON.stringify(rq)}`)
-    if (!rq.info) {
-      callback(new Error('Invalid request'))
-      return
-    }
-    const sv = {
-      name: rq.info.interfaceName,
-      role: rq.info.role,
-      url: rq.url,
-      port: rq.port,
-    }
-    services.push(sv)
-    callback(null)
-    serviceChange(sv, 'added')
-  },
-  lookupService: (
-    call: grpc.ServerUnaryCall<LookupServiceRequest, LookupServiceResponse>,
-    callback: grpc.sendUnaryData<LookupServiceResponse>
-  ) => {
-    const rq = call.request
-    console.log(`lookupService: ${JSON.stringify(rq)}`)
-    const s = services.find((s) => s.name === rq.interfaceName)
-    if (s == null) callback(null, { url: '', port: 0, error: 'Service not found' })
-    else callback(null, { url: s.url, port: s.port, error: '' })
-  },
-  getAvailableServices: (
-    call: grpc.ServerUnaryCall<GetAvailableServicesRequest, GetAvailableServicesResponse>,
-    callback: grpc.sendUnaryData<GetAvailableServicesResponse>
-  ) => {
-    const rq = call.request
-    console.log(`getAvailableServices: ${JSON.stringify(rq)}`)
-    callback(null, {
-      services: services.map((s) => ({
-        info: { interfaceName: s.name, role: s.role },
-        url: s.url,
-        port: s.port,
-      })),
+// Sends an AuthFailure back as its status; anything else is rethrown
+function rejectCall(error: unknown, callback: grpc.sendUnaryData<never>) {
+  if (!(error instanceof AuthFailure)) throw error
+  console.log(`rejected: ${error.message}`)
+  callback({ code: error.code, details: error.message })
+}
+
+/**
+ * Creates the BrokerService handlers over an empty registry of their own.
+ * @param {BrokerAuthConfig} [auth] - Credentials and ACL for registrations; open when omitted.
+ * @returns {BrokerServiceServer} The service implementation.
+ */
+export const createBrokerService = (auth?: BrokerAuthConfig): BrokerServiceServer => {
+  const services: IService[] = []
+  const serviceChangeListeners: grpc.ServerWritableStream<unknown, unknown>[] = []
+
+  // Function to notify all listeners about a service change
+  function notifyAllListeners(notification: NotifyServiceChangesResponse) {
+    serviceChangeListeners.forEach((listener) => {
+      listener.write(notification)
     })
-  },
-  unregisterService: (
-    call: grpc.ServerUnaryCall<UnregisterServiceRequest, UnregisterServiceResponse>,
-    callback: grpc.sendUnaryData<UnregisterServiceResponse>
-  ) => {
-    const rq = call.request
-    console.log(`unregisterService: ${JSON.stringify(rq)}`)
-    const i = services.findIndex((s) => s.name === rq.interfaceName)
-    if (i >= 0) {
-      const s = services.splice(i, 1)
-      callback(null)
-      serviceChange(s[0], 'removed')
+  }
+
+  // Announces an added or removed entry with the number of entries of its interface and role left
+  function serviceChange(s: IService, change: string) {
+    const notification: NotifyServiceChangesResponse = {
+      info: { interfaceName: s.name, role: s.role, url: s.url, port: s.port },
+      url: s.url,
+      port: s.port,
+      changeType: change,
+      remaining: services.filter((other) => other.name === s.name && other.role === s.role).length,
+      metadata: s.metadata,
     }
-  },
-  notifyServiceChanges: (
-    call: grpc.ServerWritableStream<NotifyServiceChangesRequest, NotifyServiceChangesResponse>
-  ) => {
-    serviceChangeListeners.push(call)
-    console.log('Listener added, listeners count:', serviceChangeListeners.length)
-    call.on('cancelled', () => {
-      const index = serviceChangeListeners.indexOf(call)
-      if (index !== -1) {
-        /*const _deleted =*/ serviceChangeListeners.splice(index, 1)
-        console.log('Listener cancelled, listeners count:', serviceChangeListeners.length)
+    notifyAllListeners(notification)
+  }
+
+  return {
+    registerService: (
+      call: grpc.ServerUnaryCall<RegisterServiceRequest, RegisterServiceResponse>,
+      callback: grpc.sendUnaryData<RegisterServiceResponse>
+    ) => {
+      const rq = call.request
+      console.log(`registerService: ${JSON.stringify(rq)}`)
+      if (!rq.info) {
+        callback(new Error('Invalid request'))
+        return
       }
-    })
-  },
+      let owner: string | undefined
+      try {
+        owner = auth && authorize(auth, call, rq.info.interfaceName, rq.info.role)
+      } catch (error) {
+        rejectCall(error, callback)
+        return
+      }
+      const sv = {
+        name: rq.info.interfaceName,
+        role: rq.info.role,
+        url: rq.url,
+        port: rq.port,
+        owner,
+        metadata: rq.metadata,
+      }
+      // Registering an endpoint again keeps the existing entry and takes the new metadata;
+      // only its owner may do that
+      const existing = services.find((s) => sameEndpoint(s, sv))
+      if (existing != null) {
+        if (auth && existing.owner !== owner) {
+          rejectCall(
+            new AuthFailure(
+              grpc.status.PERMISSION_DENIED,
+              `${sv.url}:${sv.port} is registered by another identity`
+            ),
+            callback
+          )
+          return
+        }
+        callback(null)
+        if (JSON.stringify(existing.metadata) !== JSON.stringify(sv.metadata)) {
+          existing.metadata = sv.metadata
+          serviceChange(existing, 'updated')
+        }
+        return
+      }
+      services.push(sv)
+      callback(null)
+      serviceChange(sv, 'added')
+    },
+    lookupService: (
+      call: grpc.ServerUnaryCall<LookupServiceRequest, LookupServiceResponse>,
+      callback: grpc.sendUnaryData<LookupServiceResponse>
+    ) => {
+      const rq = call.request
+      console.log(`lookupService: ${JSON.stringify(rq)}`)
+      const s = services.find((s) => s.name === rq.interfaceName && matchesFilter(s, rq.filter))
+      if (s == null) callback(null, { url: '', port: 0, error: 'Service not found' })
+      else callback(null, { url: s.url, port: s.port, error: '', metadata: s.metadata })
+    },
+    getAvailableServices: (
+      call: grpc.ServerUnaryCall<GetAvailableServicesRequest, GetAvailableServicesResponse>,
+      callback: grpc.sendUnaryData<GetAvailableServicesResponse>
+    ) => {
+      const rq = call.request
+      console.log(`getAvailableServices: ${JSON.stringify(rq)}`)
+      callback(null, {
+        services: services
+          .filter((s) => matchesFilter(s, rq.filter))
+          .map((s) => ({
+            info: { interfaceName: s.name, role: s.role },
+            url: s.url,
+            port: s.port,
+            metadata: s.metadata,
+          })),
+      })
+    },
+    unregisterService: (
+      call: grpc.ServerUnaryCall<UnregisterServiceRequest, UnregisterServiceResponse>,
+      callback: grpc.sendUnaryData<UnregisterServiceResponse>
+    ) => {
+      const rq = call.request
+      console.log(`unregisterService: ${JSON.stringify(rq)}`)
+      let caller: string | undefined
+      try {
+        caller = auth && identify(auth, call)
+      } catch (error) {
+        rejectCall(error, callback)
+        return
+      }
+      // With url and port exactly that endpoint, else the caller's entries of the interface
+      // and role
+      const matching = services.filter(
+        (s) =>
+          s.name === rq.interfaceName &&
+          s.role === rq.role &&
+          (rq.url === '' || (s.url === rq.url && s.port === rq.port))
+      )
+      // Callers only remove their own registrations
+      const own = matching.filter((s) => !auth || s.owner === caller)
+      if (own.length === 0 && matching.length > 0) {
+        rejectCall(
+          new AuthFailure(
+            caller === undefined ? grpc.status.UNAUTHENTICATED : grpc.status.PERMISSION_DENIED,
+            `${rq.interfaceName} (${rq.role}) is registered by another identity`
+          ),
+          callback
+        )
+        return
+      }
+      // Never pick one of several instances at random
+      if (own.length > 1) {
+        const details = `${rq.interfaceName} (${rq.role}) has ${own.length} instances; name one`
+        callback({ code: grpc.status.INVALID_ARGUMENT, details })
+        return
+      }
+      callback(null)
+      if (own.length === 1) {
+        services.splice(services.indexOf(own[0]), 1)
+        serviceChange(own[0], 'removed')
+      }
+    },
+    notifyServiceChanges: (
+      call: grpc.ServerWritableStream<NotifyServiceChangesRequest, NotifyServiceChangesResponse>
+    ) => {
+      serviceChangeListeners.push(call)
+      console.log('Listener added, listeners count:', serviceChangeListeners.length)
+      call.on('cancelled', () => {
+        const index = serviceChangeListeners.indexOf(call)
+        if (index !== -1) {
+          /*const _deleted =*/ serviceChangeListeners.splice(index, 1)
+          console.log('Listener cancelled, listeners count:', serviceChangeListeners.length)
+        }
+      })
+    },
+  }
 }
 
 /**
  * Creates a Broker gRPC server with all services registered.
+ * @param {BrokerAuthConfig} [auth] - Credentials and ACL for registrations; open when omitted.
  * @returns {grpc.Server} The configured gRPC server instance.
  */
-export const createBrokerServer = (): grpc.Server => {
+export const createBrokerServer = (auth?: BrokerAuthConfig): grpc.Server => {
   const server = new grpc.Server()
   server.addService(
     BrokerServiceService,
-    brokerService as unknown as grpc.UntypedServiceImplementation
+    createBrokerService(auth) as unknown as grpc.UntypedServiceImplementation
   )
   return server
 }
@@ -133,15 +251,25 @@ export const createBrokerServer = (): grpc.Server => {
 /**
  * Starts the Broker gRPC server.
  * @param {string} [address="127.0.0.1:50051"] - The address to bind.
+ * @param {BrokerServerOptions} [options] - TLS and auth; plaintext and open when omitted.
  * @returns {Promise<grpc.Server>} The running gRPC server.
  * @throws {Error} If binding fails.
  */
 export const startBrokerServer = async (
-  address: string = '127.0.0.1:50051'
+  address: string = '127.0.0.1:50051',
+  options: BrokerServerOptions = {}
 ): Promise<grpc.Server> => {
-  const server = createBrokerServer()
+  const server = createBrokerServer(options.auth)
+  const { tls } = options
+  const credentials = tls
+    ? grpc.ServerCredentials.createSsl(
+        tls.ca ?? null,
+        [{ cert_chain: tls.cert, private_key: tls.key }],
+        tls.requireClientCert
+      )
+    : grpc.ServerCredentials.createInsecure()
   await new Promise<void>((resolve, reject) => {
-    server.bindAsync(address, grpc.ServerCredentials.createInsecure(), (err) => {
+    server.bindAsync(address, credentials, (err) => {
       if (err) {
         reject(err)
         return
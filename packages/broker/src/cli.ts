@@ -1,22 +1,76 @@
 /* eslint-disable no-console */
-import { startBrokerServer } from './broker'
+import { readFileSync } from 'node:fs'
+import { loadAuthConfig } from './auth'
+import { BrokerServerOptions, startBrokerServer } from './broker'
 
-const parseArgs = () => {
-  const args = process.argv.slice(2)
+const VALUE_FLAGS = ['--address', '--auth-file', '--tls-cert', '--tls-key', '--tls-ca']
+
+/**
+ * Reads the command line over the environment.
+ * @throws {Error} If a flag that takes a value is not followed by one.
+ */
+const parseArgs = (args: string[]) => {
   let address = process.env.BROKER_ADDRESS ?? '127.0.0.1:50051'
+  let authFile = process.env.BROKER_AUTH_FILE
+  let tlsCert: string | undefined
+  let tlsKey: string | undefined
+  let tlsCa: string | undefined
+  let requireClientCert = false
   for (let i = 0; i < args.length; i++) {
-    if (args[i] === '--address' && i + 1 < args.length) {
-      address = args[i + 1]
-      i++
+    const flag = args[i]
+    if (flag === '--tls-require-client-cert') {
+      requireClientCert = true
+      continue
+    }
+    if (!VALUE_FLAGS.includes(flag)) continue
+    const value = args[++i]
+    if (value === undefined || value.startsWith('--')) {
+      throw new Error(`Missing value for ${flag}`)
     }
+    if (flag === '--address') address = value
+    else if (flag === '--auth-file') authFile = value
+    else if (flag === '--tls-cert') tlsCert = value
+    else if (flag === '--tls-key') tlsKey = value
+    else tlsCa = value
   }
-  return { address }
+  return { address, authFile, tlsCert, tlsKey, tlsCa, requireClientCert }
 }
 
-const { address } = parseArgs()
+type CliArgs = ReturnType<typeof parseArgs>
+
+const loadOptions = ({
+  authFile,
+  tlsCert,
+  tlsKey,
+  tlsCa,
+  requireClientCert,
+}: CliArgs): BrokerServerOptions => {
+  const options: BrokerServerOptions = {}
+  if (tlsCert !== undefined || tlsKey !== undefined) {
+    if (tlsCert === undefined || tlsKey === undefined) {
+      throw new Error('--tls-cert and --tls-key must be given together')
+    }
+    options.tls = {
+      cert: readFileSync(tlsCert),
+      key: readFileSync(tlsKey),
+      ca: tlsCa === undefined ? undefined : readFileSync(tlsCa),
+      requireClientCert,
+    }
+  } else if (tlsCa !== undefined || requireClientCert) {
+    throw new Error('--tls-ca and --tls-require-client-cert need --tls-cert and --tls-key')
+  }
+  if (authFile !== undefined) {
+    options.auth = loadAuthConfig(authFile)
+    console.log(`Registrations are checked against ${authFile}`)
+  } else {
+    console.warn('No --auth-file given: any caller may register and unregister services')
+  }
+  return options
+}
 
 const main = async (): Promise<void> => {
-  const server = await startBrokerServer(address)
+  const args = parseArgs(process.argv.slice(2))
+  const server = await startBrokerServer(args.address, loadOptions(args))
 
   const shutdown = (signal: NodeJS.Signals): void => {
     console.log(`Received ${signal}, shutting down gracefully...`)
@@ -1,6 +1,7 @@
 import {
   ActivityType,
   type ApplicationHealth,
+  type ConnectionState,
   type HeartbeatRequest,
   type HeartbeatResponse,
   type RegisterServiceRequest,
@@ -57,6 +58,8 @@ export interface TopologyReporterOptions {
  * Activity report payload to record a service interaction.
  */
 export interface ActivityReport {
+  /** Source service name when the activity did not come from the reporter. */
+  sourceService?: string
   /** Target service name. */
   targetService: string
   /** Activity type. */
@@ -73,6 +76,12 @@ export interface ActivityReport {
   batchSize?: number
   /** Optional error message. */
   errorMessage?: string
+  /** Optional edge state override, e.g. FAILED while a circuit breaker is open. */
+  connectionState?: ConnectionState
+  /** Optional trace id of the request or stream behind this activity. */
+  traceId?: string
+  /** Optional time blocked on the target's backpressure since the previous report. */
+  stallMs?: number
 }
 
 /**
@@ -186,6 +195,7 @@ export class TopologyReporter {
 
     const event: ReportActivityRequest = {
       serviceId: this.serviceId,
+      sourceService: report.sourceService,
       targetService: report.targetService,
       type: report.type,
       timestampMs: String(report.timestampMs ?? Date.now()),
@@ -194,6 +204,9 @@ export class TopologyReporter {
       success: report.success,
       batchSize: report.batchSize,
       errorMessage: report.errorMessage,
+      connectionState: report.connectionState,
+      traceId: report.traceId,
+      stallMs: report.stallMs,
     }
 
     try {